@@ -0,0 +1,61 @@
+//! Measures the time taken to dispatch on string values using `case`.
+//!
+//! A `case` expression whose patterns are all strings is compiled using a
+//! jump table. Adding a single pattern of another type causes the value to be
+//! compared to each pattern in turn, which provides a point of comparison.
+//!
+//! Run with `cargo run --release --example case_dispatch`.
+
+extern crate ketos;
+
+use std::time::Instant;
+
+use ketos::{Interpreter, FromValueRef};
+
+const ARMS: usize = 200;
+const LOOKUPS: u32 = 1_000_000;
+
+fn dispatch_fn(name: &str, extra_arm: &str) -> String {
+    let mut s = format!("(define ({} s) (case s", name);
+
+    for i in 0..ARMS {
+        s.push_str(&format!(r#" (("command-{}") {})"#, i, i));
+    }
+
+    s.push_str(extra_arm);
+    s.push_str("))");
+    s
+}
+
+fn main() {
+    let interp = Interpreter::new();
+
+    let words = (0..ARMS).map(|i| format!(r#""command-{}""#, i))
+        .collect::<Vec<_>>().join(" ");
+
+    interp.run_code(&dispatch_fn("table", ""), None).unwrap();
+    interp.run_code(&dispatch_fn("chain", " ((:none) -1)"), None).unwrap();
+    interp.run_code(&format!("(define words '({}))", words), None).unwrap();
+
+    interp.run_code(r#"
+        (define (run f n li acc)
+          (cond
+            ((= n 0) acc)
+            ((null li) (run f n words acc))
+            (else (run f (- n 1) (tail li) (+ acc (f (first li)))))))
+        "#, None).unwrap();
+
+    let expected = (0..LOOKUPS as u64).map(|i| i % ARMS as u64).sum::<u64>();
+
+    for name in &["table", "chain"] {
+        let start = Instant::now();
+        let v = interp.run_single_expr(
+            &format!("(run {} {} words 0)", name, LOOKUPS), None).unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(u64::from_value_ref(&v).unwrap(), expected);
+
+        println!("{}: {} lookups over {} arms in {}.{:03}s",
+            name, LOOKUPS, ARMS, elapsed.as_secs(), elapsed.subsec_nanos() / 1_000_000);
+    }
+}
@@ -1,7 +1,12 @@
 //! Performs name-based text completion using the current `GlobalScope`.
 
+use ketos::name::get_standard_name_for;
 use ketos::scope::{GlobalScope, MasterScope};
 
+/// Characters which end a name; these match the word break characters
+/// given to readline.
+const NAME_BREAK_CHARS: &'static str = " \t\n#\"'(),:;@[\\]`{}";
+
 /// Returns common prefix and possible completion suffixes for a given input.
 pub fn complete(text: &str, start: usize, end: usize, scope: &GlobalScope) -> Option<(String, Vec<String>)> {
     // Don't attempt to complete when the input is empty
@@ -9,10 +14,49 @@ pub fn complete(text: &str, start: usize, end: usize, scope: &GlobalScope) -> Op
         return None;
     }
 
+    let alias = qualified_alias(&text[..start]);
     let text = &text[start..end];
     let prefix_len = text.len();
     let mut results = Vec::new();
 
+    if let Some(alias) = alias {
+        // Complete qualified names from the exports of an aliased module
+        let alias = get_standard_name_for(alias)
+            .or_else(|| scope.borrow_names().get_name(alias));
+        let m = alias.and_then(|a| scope.get_module_alias(a))
+            .and_then(|name| scope.get_modules().get_loaded_module(name));
+
+        if let Some(m) = m {
+            let mut exports = m.scope.with_exports(
+                |e| e.map_or_else(Vec::new, |e| e.iter().collect::<Vec<_>>()));
+            m.scope.with_export_renames(
+                |r| exports.extend(r.iter().flat_map(|r| r.iter()).map(|&(name, _)| name)));
+
+            for name in exports {
+                scope.with_name(name, |name| {
+                    if name.starts_with(text) {
+                        results.push(name[prefix_len..].to_owned());
+                    }
+                });
+            }
+        }
+
+        return complete_results(results);
+    }
+
+    if text.starts_with(':') {
+        // Complete namespaced keywords from all names seen so far
+        let kw = &text[1..];
+
+        for name in scope.borrow_names().iter() {
+            if name.contains('/') && name.starts_with(kw) {
+                results.push(name[kw.len()..].to_owned());
+            }
+        }
+
+        return complete_results(results);
+    }
+
     for name in MasterScope::get_names() {
         scope.with_name(name, |name| {
             if name.starts_with(text) {
@@ -21,29 +65,58 @@ pub fn complete(text: &str, start: usize, end: usize, scope: &GlobalScope) -> Op
         });
     }
 
-    scope.with_values(|values| {
-        for &(name, _) in values {
-            scope.with_name(name, |name| {
-                if name.starts_with(text) {
-                    results.push(name[prefix_len..].to_owned());
-                }
-            });
-        }
-    });
-
-    scope.with_macros(|macros| {
-        for &(name, _) in macros {
-            scope.with_name(name, |name| {
-                if name.starts_with(text) {
-                    results.push(name[prefix_len..].to_owned());
-                }
-            });
-        }
-    });
+    // Complete names from this scope and any parent or prelude scopes
+    let mut cur = Some(scope);
 
+    while let Some(s) = cur {
+        s.with_values(|values| {
+            for &(name, _) in values {
+                scope.with_name(name, |name| {
+                    if name.starts_with(text) {
+                        results.push(name[prefix_len..].to_owned());
+                    }
+                });
+            }
+        });
+
+        s.with_macros(|macros| {
+            for &(name, _) in macros {
+                scope.with_name(name, |name| {
+                    if name.starts_with(text) {
+                        results.push(name[prefix_len..].to_owned());
+                    }
+                });
+            }
+        });
+
+        cur = s.parent().map(|p| &**p);
+    }
+
+    complete_results(results)
+}
+
+/// Returns the module alias preceding a partial qualified name, `alias:name`,
+/// given the input before the partial name.
+fn qualified_alias(input: &str) -> Option<&str> {
+    if !input.ends_with(':') {
+        return None;
+    }
+
+    let input = &input[..input.len() - 1];
+    let begin = input.rfind(|c| NAME_BREAK_CHARS.contains(c)).map_or(0, |pos| pos + 1);
+    let alias = &input[begin..];
+
+    if alias.is_empty() { None } else { Some(alias) }
+}
+
+fn complete_results(mut results: Vec<String>) -> Option<(String, Vec<String>)> {
     if results.is_empty() {
         None
     } else {
+        // A name may be defined in more than one scope
+        results.sort();
+        results.dedup();
+
         let prefix = common_prefix(&results);
         Some((prefix, results))
     }
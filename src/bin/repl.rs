@@ -2,11 +2,17 @@ extern crate getopts;
 extern crate ketos;
 extern crate libc;
 
-use std::io::{stderr, Write};
-use std::path::Path;
+use std::env;
+use std::fs::File;
+use std::io::{stderr, Read, Write};
+use std::path::{Path, PathBuf};
 
 use getopts::{Options, ParsingStyle};
-use ketos::{Interpreter, Error, ParseErrorKind};
+use ketos::{FileModuleLoader, Interpreter, Error, IoError, LogLevel, ParseErrorKind, Value};
+use ketos::compile::operator_usage;
+use ketos::io::IoMode;
+use ketos::name::{get_standard_name_for, intern_name};
+use ketos::scope::Scope;
 
 mod completion;
 mod readline;
@@ -23,10 +29,18 @@ fn run() -> i32 {
     // Allow arguments that appear to be options to be passed to scripts
     opts.parsing_style(ParsingStyle::StopAtFirstFree);
 
-    opts.optopt ("e", "", "Evaluate one expression and exit", "EXPR");
+    opts.optflag("", "check", "Compile each FILE without executing it and exit");
+    opts.optmulti("e", "", "Evaluate an expression; may be given more than once", "EXPR");
+    opts.optopt ("", "error-format",
+        "Format of error messages: `short` (default) or `full`, \
+         which includes the chain of causes", "FORMAT");
     opts.optflag("h", "help", "Print this help message and exit");
     opts.optflag("i", "interactive", "Run interactively even with a file");
-    opts.optflag("", "no-rc", "Do not run ~/.ketosrc.kts on startup");
+    opts.optopt ("m", "", "Load module NAME and call its exported `main` function", "NAME");
+    opts.optflag("q", "quiet", "Do not print the values of expressions given by -e");
+    opts.optflag("", "test", "Run the tests defined in each FILE and exit");
+    opts.optflag("", "no-rc",
+        "Do not load ~/.ketos_prelude.kts or run ~/.ketosrc.kts on startup");
     opts.optflag("V", "version", "Print version and exit");
 
     let matches = match opts.parse(&args[1..]) {
@@ -46,63 +60,264 @@ fn run() -> i32 {
         return 0;
     }
 
+    let error_format = match matches.opt_str("error-format").as_ref().map(|s| &s[..]) {
+        None | Some("short") => ErrorFormat::Short,
+        Some("full") => ErrorFormat::Full,
+        Some(fmt) => {
+            let _ = writeln!(stderr(), "{}: invalid error format: {}", args[0], fmt);
+            return 1;
+        }
+    };
+
+    if matches.opt_present("check") {
+        if matches.free.is_empty() {
+            let _ = writeln!(stderr(), "{}: --check requires at least one file", args[0]);
+            return 1;
+        }
+
+        let mut ok = true;
+
+        for file in &matches.free {
+            if !check_file(Path::new(file), error_format) {
+                ok = false;
+            }
+        }
+
+        return if ok { 0 } else { 1 };
+    }
+
+    if matches.opt_present("test") {
+        if matches.free.is_empty() {
+            let _ = writeln!(stderr(), "{}: --test requires at least one file", args[0]);
+            return 1;
+        }
+
+        let mut ok = true;
+
+        for file in &matches.free {
+            if !run_tests(Path::new(file), error_format) {
+                ok = false;
+            }
+        }
+
+        return if ok { 0 } else { 1 };
+    }
+
     let interactive = matches.opt_present("interactive") ||
-        (matches.free.is_empty() && !matches.opt_present("e"));
+        (matches.free.is_empty() && !matches.opt_present("e") &&
+            !matches.opt_present("m"));
 
-    let interp = Interpreter::new();
+    let mut interp = Interpreter::with_loader(Box::new(module_loader()));
+    set_log_handler(&interp);
 
     if !matches.opt_present("no-rc") {
         if let Some(p) = std::env::home_dir() {
+            let prelude = p.join(".ketos_prelude.kts");
+            if prelude.is_file() {
+                match load_prelude(&interp, &prelude) {
+                    Ok(i) => interp = i,
+                    Err(e) => {
+                        display_error(&interp, &e, error_format);
+                        if !interactive {
+                            return 1;
+                        }
+                    }
+                }
+            }
+
             let rc = p.join(".ketosrc.kts");
             if rc.is_file() {
-                if !run_file(&interp, &rc) && !interactive {
+                if !run_file(&interp, &rc, error_format) && !interactive {
                     return 1;
                 }
             }
         }
     }
 
-    if let Some(expr) = matches.opt_str("e") {
-        if !run_expr(&interp, &expr) && !interactive {
+    let exprs = matches.opt_strs("e");
+    let quiet = matches.opt_present("quiet");
+
+    for expr in &exprs {
+        if !run_expr(&interp, expr, quiet, error_format) && !interactive {
             return 1;
         }
-    } else if !matches.free.is_empty() {
+    }
+
+    if let Some(module) = matches.opt_str("m") {
+        interp.set_args(&matches.free);
+        if !run_module(&interp, &module, &matches.free, error_format) && !interactive {
+            return 1;
+        }
+    } else if exprs.is_empty() && !matches.free.is_empty() {
         interp.set_args(&matches.free[1..]);
-        if !run_file(&interp, Path::new(&matches.free[0])) && !interactive {
+        if !run_file(&interp, Path::new(&matches.free[0]), error_format) && !interactive {
             return 1;
         }
     }
 
     if interactive {
-        run_repl(&interp);
+        interp.get_scope().set_reuse_struct_defs(true);
+        run_repl(&interp, error_format);
     }
 
     0
 }
 
-fn run_expr(interp: &Interpreter, expr: &str) -> bool {
+#[derive(Copy, Clone)]
+enum ErrorFormat {
+    Short,
+    Full,
+}
+
+/// Prints interpreter warnings to `stderr`, highlighted when it is a terminal.
+fn set_log_handler(interp: &Interpreter) {
+    let color = unsafe { libc::isatty(2) != 0 };
+
+    interp.get_scope().set_log_handler(Box::new(move |level, msg| {
+        let label = match level {
+            LogLevel::Info => "info",
+            LogLevel::Warning => "warning",
+        };
+
+        let _ = if color && level == LogLevel::Warning {
+            writeln!(stderr(), "\x1b[33m{}:\x1b[0m {}", label, msg)
+        } else {
+            writeln!(stderr(), "{}: {}", label, msg)
+        };
+    }));
+}
+
+fn display_error(interp: &Interpreter, e: &Error, format: ErrorFormat) {
+    match format {
+        ErrorFormat::Short if unsafe { libc::isatty(2) != 0 } => {
+            let _ = stderr().write_all(interp.format_error_ansi(e).as_bytes());
+        }
+        ErrorFormat::Short => interp.display_error(e),
+        ErrorFormat::Full => interp.display_error_chain(e),
+    }
+}
+
+/// Returns a module loader which searches the current directory,
+/// followed by each directory listed in the `KETOS_PATH` environment variable.
+fn module_loader() -> FileModuleLoader {
+    let mut paths = vec![PathBuf::new()];
+
+    if let Some(path) = env::var_os("KETOS_PATH") {
+        paths.extend(env::split_paths(&path));
+    }
+
+    FileModuleLoader::with_search_paths(paths)
+}
+
+fn run_expr(interp: &Interpreter, expr: &str, quiet: bool,
+        error_format: ErrorFormat) -> bool {
     match interp.run_code(expr, None) {
         Ok(value) => {
-            interp.display_value(&value);
+            if !quiet {
+                interp.display_value(&value);
+            }
             true
         }
         Err(e) => {
-            interp.display_error(&e);
+            display_error(interp, &e, error_format);
             false
         }
     }
 }
 
-fn run_file(interp: &Interpreter, file: &Path) -> bool {
+/// Runs a prelude file, returning an interpreter whose scope inherits
+/// its definitions.
+fn load_prelude(interp: &Interpreter, file: &Path) -> Result<Interpreter, Error> {
+    let src = try!(read_file(file));
+    interp.with_prelude(&src, Some(file.display().to_string()))
+}
+
+fn run_file(interp: &Interpreter, file: &Path, error_format: ErrorFormat) -> bool {
     match interp.run_file(file) {
         Ok(()) => true,
         Err(e) => {
-            interp.display_error(&e);
+            display_error(interp, &e, error_format);
+            false
+        }
+    }
+}
+
+/// Loads a module and calls its exported `main` function, if it has one.
+/// `argv` is defined within the module scope before `main` is called.
+fn run_module(interp: &Interpreter, module: &str, args: &[String],
+        error_format: ErrorFormat) -> bool {
+    let scope = interp.get_scope();
+    let name = intern_name(scope, module);
+    let main = intern_name(scope, "main");
+
+    let r = scope.get_modules().get_module(name, scope).and_then(|m| {
+        match m.scope.get_export(main).and_then(|n| m.scope.get_value(n)) {
+            Some(f) => {
+                let args = args.iter().map(|s| s[..].into()).collect::<Vec<Value>>();
+                m.scope.add_named_value("argv", args.into());
+                interp.call_value(f, Vec::new()).map(|_| ())
+            }
+            None => Ok(())
+        }
+    });
+
+    match r {
+        Ok(()) => true,
+        Err(e) => {
+            display_error(interp, &e, error_format);
             false
         }
     }
 }
 
+fn run_tests(file: &Path, error_format: ErrorFormat) -> bool {
+    let interp = Interpreter::new();
+    set_log_handler(&interp);
+
+    let r = read_file(file)
+        .and_then(|src| interp.run_code(&src, Some(file.display().to_string())))
+        .and_then(|_| interp.run_tests());
+
+    match r {
+        Ok(passed) => passed,
+        Err(e) => {
+            display_error(&interp, &e, error_format);
+            false
+        }
+    }
+}
+
+/// Compiles a file, and any modules it imports, without executing any code
+/// other than macros.
+fn check_file(file: &Path, error_format: ErrorFormat) -> bool {
+    let interp = Interpreter::new();
+    set_log_handler(&interp);
+    interp.get_scope().set_compile_only(true);
+
+    match interp.compile_file(file) {
+        Ok(_) => true,
+        Err(e) => {
+            // Parse errors include the file name; others may not
+            match e {
+                Error::ParseError(_) => (),
+                _ => { let _ = write!(stderr(), "{}: ", file.display()); }
+            }
+            display_error(&interp, &e, error_format);
+            false
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, Error> {
+    let mut f = try!(File::open(path)
+        .map_err(|e| IoError::new(IoMode::Open, path, e)));
+    let mut buf = String::new();
+
+    try!(f.read_to_string(&mut buf)
+        .map_err(|e| IoError::new(IoMode::Read, path, e)));
+    Ok(buf)
+}
+
 #[derive(Copy, Clone)]
 enum Prompt {
     Normal,
@@ -122,7 +337,7 @@ fn read_line(interp: &Interpreter, prompt: Prompt) -> Option<String> {
     readline::read_line(prompt, interp.get_scope())
 }
 
-fn run_repl(interp: &Interpreter) {
+fn run_repl(interp: &Interpreter, error_format: ErrorFormat) {
     let mut buf = String::new();
     let mut prompt = Prompt::Normal;
 
@@ -132,6 +347,11 @@ fn run_repl(interp: &Interpreter) {
         }
 
         readline::push_history(&line);
+
+        if buf.is_empty() && run_command(interp, &line) {
+            continue;
+        }
+
         buf.push_str(&line);
         buf.push('\n');
 
@@ -141,7 +361,7 @@ fn run_repl(interp: &Interpreter) {
                 if !code.is_empty() {
                     match interp.execute_program(code) {
                         Ok(v) => interp.display_value(&v),
-                        Err(e) => interp.display_error(&e)
+                        Err(e) => display_error(interp, &e, error_format)
                     }
                 }
             }
@@ -157,7 +377,7 @@ fn run_repl(interp: &Interpreter) {
                 prompt = Prompt::OpenString;
                 continue;
             }
-            Err(ref e) => interp.display_error(e)
+            Err(ref e) => display_error(interp, e, error_format)
         }
 
         buf.clear();
@@ -167,6 +387,61 @@ fn run_repl(interp: &Interpreter) {
     println!("");
 }
 
+/// Runs a REPL command, such as `:doc let`.
+/// Returns `false` if the line does not contain a command.
+fn run_command(interp: &Interpreter, line: &str) -> bool {
+    let mut words = line.split_whitespace();
+
+    match words.next() {
+        Some(":doc") => {
+            match (words.next(), words.next()) {
+                (Some(name), None) => {
+                    match get_standard_name_for(name).and_then(operator_usage) {
+                        Some(usage) => println!("{}", usage),
+                        None => println!("no documentation for `{}`", name)
+                    }
+                }
+                _ => println!("usage: :doc name")
+            }
+        }
+        Some(":scope") => {
+            match words.next() {
+                None => print_scope(interp.get_scope()),
+                Some(_) => println!("usage: :scope")
+            }
+        }
+        _ => return false
+    }
+
+    true
+}
+
+/// Prints the names defined in the user scope and in any prelude scopes.
+fn print_scope(scope: &Scope) {
+    println!("user: {}", scope_names(scope));
+
+    let mut prelude = scope.parent();
+
+    while let Some(p) = prelude {
+        println!("prelude: {}", scope_names(p));
+        prelude = p.parent();
+    }
+}
+
+/// Returns the sorted names of values and macros defined in a scope,
+/// excluding those of its parent.
+fn scope_names(scope: &Scope) -> String {
+    let mut names = Vec::new();
+
+    scope.with_values(|values| names.extend(values.iter().map(|&(name, _)| name)));
+    scope.with_macros(|macros| names.extend(macros.iter().map(|&(name, _)| name)));
+
+    let store = scope.borrow_names();
+    let mut names = names.into_iter().map(|n| store.get(n)).collect::<Vec<_>>();
+    names.sort();
+    names.join(" ")
+}
+
 fn print_version() {
     println!("ketos {}", version());
 }
@@ -176,5 +451,9 @@ fn version() -> &'static str {
 }
 
 fn print_usage(arg0: &str, opts: &Options) {
-    print!("{}", opts.usage(&format!("Usage: {} [OPTIONS] [FILE]", arg0)));
+    print!("{}", opts.usage(&format!(
+        "Usage: {0} [OPTIONS] [FILE]\n       {0} [OPTIONS] -m NAME [ARGS]\n       \
+         {0} --test FILE...\n       {0} --check FILE...\n\n\
+         Modules are found in the current directory, then in each directory \
+         listed in KETOS_PATH.", arg0)));
 }
@@ -17,6 +17,8 @@
 //! with a single opcode, e.g. the `CONST_0` opcode replaces the two-byte
 //! sequence `CONST`, followed by operand `0`.
 
+use std::cmp::Ordering;
+
 use compile::CompileError;
 use exec::ExecError;
 use function::Arity;
@@ -27,7 +29,7 @@ use value::Value;
 /// change to the bytecode format. The version represents a `ketos` version
 /// number, e.g. `0x01_02_03_00` corresponds to version `1.2.3`.
 /// (The least significant 8 bits don't mean anything yet.)
-pub const BYTECODE_VERSION: u32 = 0x00_00_01_00;
+pub const BYTECODE_VERSION: u32 = 0x00_00_02_00;
 
 /// Maximum value of a short-encoded operand.
 pub const MAX_SHORT_OPERAND: u32 = 0x7f;
@@ -47,10 +49,28 @@ pub enum Instruction {
     LoadC(u32),
     /// If value *n* on the stack is `Unbound`, replace it with `()`.
     UnboundToUnit(u32),
+    /// Load `true` if value *n* on the stack is bound; otherwise, load `false`.
+    IsBound(u32),
     /// Load a named value from global scope
     GetDef(u32),
+    /// Load the active value of a dynamic variable named by const *n*
+    GetDynamic(u32),
+    /// Load a value defined in another module; const *n* is a list
+    /// of the module name and the name exported by the module
+    GetModuleDef(u32),
+    /// Load the name of a module, const *n*, defined by `define-module`
+    /// when the code was compiled. Code containing this instruction
+    /// cannot be encoded.
+    DefineModule(u32),
+    /// Replace the lambda in value with one bound to the global scope
+    /// of the module named by const *n*, loading the module if necessary
+    WithModule(u32),
     /// Push a value onto the stack; value is invalidated.
     Push,
+    /// Push a copy of the value on the top of the stack
+    Dup,
+    /// Exchange the top two values on the stack
+    Swap,
     /// Load `()` into value
     Unit,
     /// Load `true` into value
@@ -77,6 +97,27 @@ pub enum Instruction {
     ConstPush(u32),
     /// Assign a value to a name into global scope
     SetDef(u32),
+    /// Assign a function to a name into global scope, combining it with
+    /// an existing function of the same name which accepts a different
+    /// number of arguments
+    SetDefOverload(u32),
+    /// Assign value to the dynamic variable named by const *n*
+    SetDynamic(u32),
+    /// Bind value to the dynamic variable named by const *n*
+    /// until a corresponding `UnbindDynamic`
+    BindDynamic(u32),
+    /// Remove the *n* most recent dynamic variable bindings
+    UnbindDynamic(u32),
+    /// Install an error handler at a label, catching errors of the type
+    /// named by keyword const *n*; parameters are `(label, n)`
+    PushHandler(u32, u32),
+    /// Remove the *n* most recently installed error handlers
+    PopHandler(u32),
+    /// Begin capturing output written to the scope's output stream
+    /// until a corresponding `EndCapture`
+    BeginCapture,
+    /// End the most recent output capture and load captured output as a string
+    EndCapture,
     /// Build a list of *n* values from the stack
     List(u32),
     /// Transform value into an *n*-quoted value
@@ -110,6 +151,9 @@ pub enum Instruction {
     JumpIfEqConst(u32, u32),
     /// Jump if value is not equal to const; parameters are `(label, n)`
     JumpIfNotEqConst(u32, u32),
+    /// If value is a string, jump to the label paired with it in the jump
+    /// table in const *n*; see `jump_table_lookup`
+    JumpTable(u32),
     /// Test whether value is `()`
     Null,
     /// Test whether value is not `()`
@@ -138,6 +182,13 @@ pub enum Instruction {
     Init,
     /// Take last element of list and assign to value
     Last,
+    /// Begin iteration over a list, vector, or range in value;
+    /// an empty sequence is replaced with `()`
+    IterStart,
+    /// Take the current element of an iteration and assign to value
+    IterFirst,
+    /// Advance an iteration and assign to value; `()` when it is finished
+    IterRest,
     /// Push first value from list
     FirstPush,
     /// Push tail from list
@@ -160,15 +211,32 @@ pub enum Instruction {
     /// Call function on the stack with *n* stack arguments and additional
     /// arguments from list value
     Apply(u32),
+    /// Call function on the stack with keyword arguments;
+    /// parameters are `(n_kw_pairs, n_positional)`.
+    ///
+    /// The stack contains the function, *n_positional* positional arguments,
+    /// and *n_kw_pairs* keyword-value pairs. Additional positional arguments
+    /// are taken from the list value and passed before keyword arguments.
+    ApplyWithKeywords(u32, u32),
     /// Call current code object with *n* arguments from the top of the stack;
     /// this does not perform a tail call
     CallSelf(u32),
     /// Perform tail-recursive call with *n* arguments from the top of the stack
     TailCall(u32),
+    /// Call named function in tail position; parameters are `(const, n_args)`.
+    ///
+    /// If the function is a lambda, the current stack frame is reused.
+    /// Otherwise, the function is called and the current function returns.
+    TailCallConst(u32, u32),
+    /// Call function on the stack in tail position with *n* arguments
+    /// from the top of the stack
+    TailCallFunction(u32),
     /// Remove *n* values from the top of the stack
     Skip(u32),
     /// Return value from function
     Return,
+    /// Return `()` from function
+    ReturnUnit,
 }
 
 macro_rules! opcodes {
@@ -307,6 +375,29 @@ opcodes!{
     SKIP_3 = 119,
     SKIP_4 = 120,
     RETURN = 121,
+    IS_BOUND = 122,
+    RETURN_UNIT = 123,
+    GET_DYNAMIC = 124,
+    SET_DYNAMIC = 125,
+    BIND_DYNAMIC = 126,
+    UNBIND_DYNAMIC = 127,
+    PUSH_HANDLER = 128,
+    POP_HANDLER = 129,
+    APPLY_WITH_KEYWORDS = 130,
+    DUP = 131,
+    SWAP = 132,
+    GET_MODULE_DEF = 133,
+    BEGIN_CAPTURE = 134,
+    END_CAPTURE = 135,
+    JUMP_TABLE = 136,
+    TAIL_CALL_CONST = 137,
+    TAIL_CALL_FUNCTION = 138,
+    SET_DEF_OVERLOAD = 139,
+    DEFINE_MODULE = 140,
+    WITH_MODULE = 141,
+    ITER_START = 142,
+    ITER_FIRST = 143,
+    ITER_REST = 144,
 }
 
 impl Instruction {
@@ -337,8 +428,15 @@ impl Instruction {
             UNBOUND_TO_UNIT_1 => UnboundToUnit(1),
             UNBOUND_TO_UNIT_2 => UnboundToUnit(2),
             UNBOUND_TO_UNIT_3 => UnboundToUnit(3),
+            IS_BOUND => IsBound(operand!()),
             GET_DEF => GetDef(operand!()),
+            GET_DYNAMIC => GetDynamic(operand!()),
+            GET_MODULE_DEF => GetModuleDef(operand!()),
+            DEFINE_MODULE => DefineModule(operand!()),
+            WITH_MODULE => WithModule(operand!()),
             PUSH => Push,
+            DUP => Dup,
+            SWAP => Swap,
             UNIT => Unit,
             TRUE => True,
             FALSE => False,
@@ -380,6 +478,15 @@ impl Instruction {
             CONST_PUSH_6 => ConstPush(6),
             CONST_PUSH_7 => ConstPush(7),
             SET_DEF => SetDef(operand!()),
+            SET_DEF_OVERLOAD => SetDefOverload(operand!()),
+            SET_DYNAMIC => SetDynamic(operand!()),
+            BIND_DYNAMIC => BindDynamic(operand!()),
+            UNBIND_DYNAMIC => UnbindDynamic(operand!()),
+            PUSH_HANDLER => PushHandler(operand!(), operand!()),
+            POP_HANDLER => PopHandler(operand!()),
+            BEGIN_CAPTURE => BeginCapture,
+            END_CAPTURE => EndCapture,
+            JUMP_TABLE => JumpTable(operand!()),
             LIST => List(operand!()),
             QUOTE => Quote(operand!()),
             QUOTE_1 => Quote(1),
@@ -414,6 +521,9 @@ impl Instruction {
             TAIL => Tail,
             INIT => Init,
             LAST => Last,
+            ITER_START => IterStart,
+            ITER_FIRST => IterFirst,
+            ITER_REST => IterRest,
             FIRST_PUSH => FirstPush,
             TAIL_PUSH => TailPush,
             INIT_PUSH => InitPush,
@@ -431,14 +541,18 @@ impl Instruction {
             CALL_CONST_7 => CallConst(7, operand!()),
             CALL => Call(operand!()),
             APPLY => Apply(operand!()),
+            APPLY_WITH_KEYWORDS => ApplyWithKeywords(operand!(), operand!()),
             CALL_SELF => CallSelf(operand!()),
             TAIL_CALL => TailCall(operand!()),
+            TAIL_CALL_CONST => TailCallConst(operand!(), operand!()),
+            TAIL_CALL_FUNCTION => TailCallFunction(operand!()),
             SKIP => Skip(operand!()),
             SKIP_1 => Skip(1),
             SKIP_2 => Skip(2),
             SKIP_3 => Skip(3),
             SKIP_4 => Skip(4),
             RETURN => Return,
+            RETURN_UNIT => ReturnUnit,
             _ => return Err(ExecError::UnrecognizedOpCode(op))
         };
 
@@ -490,8 +604,15 @@ impl Instruction {
             UnboundToUnit(2) => op!(UNBOUND_TO_UNIT_2),
             UnboundToUnit(3) => op!(UNBOUND_TO_UNIT_3),
             UnboundToUnit(n) => op!(UNBOUND_TO_UNIT, n),
+            IsBound(n) => op!(IS_BOUND, n),
             GetDef(n) => op!(GET_DEF, n),
+            GetDynamic(n) => op!(GET_DYNAMIC, n),
+            GetModuleDef(n) => op!(GET_MODULE_DEF, n),
+            DefineModule(n) => op!(DEFINE_MODULE, n),
+            WithModule(n) => op!(WITH_MODULE, n),
             Push => op!(PUSH),
+            Dup => op!(DUP),
+            Swap => op!(SWAP),
             Unit => op!(UNIT),
             True => op!(TRUE),
             False => op!(FALSE),
@@ -533,6 +654,14 @@ impl Instruction {
             ConstPush(7) => op!(CONST_PUSH_7),
             ConstPush(n) => op!(CONST_PUSH, n),
             SetDef(n) => op!(SET_DEF, n),
+            SetDefOverload(n) => op!(SET_DEF_OVERLOAD, n),
+            SetDynamic(n) => op!(SET_DYNAMIC, n),
+            BindDynamic(n) => op!(BIND_DYNAMIC, n),
+            UnbindDynamic(n) => op!(UNBIND_DYNAMIC, n),
+            PushHandler(label, n) => jump_op!(PUSH_HANDLER, label, n),
+            PopHandler(n) => op!(POP_HANDLER, n),
+            BeginCapture => op!(BEGIN_CAPTURE),
+            EndCapture => op!(END_CAPTURE),
             List(n) => op!(LIST, n),
             Quote(1) => op!(QUOTE_1),
             Quote(n) => op!(QUOTE, n),
@@ -553,6 +682,7 @@ impl Instruction {
             JumpIfNotEq(label) => jump_op!(JUMP_IF_NOT_EQ, label),
             JumpIfEqConst(label, n) => jump_op!(JUMP_IF_EQ_CONST, label, n),
             JumpIfNotEqConst(label, n) => jump_op!(JUMP_IF_NOT_EQ_CONST, label, n),
+            JumpTable(n) => op!(JUMP_TABLE, n),
             Null => op!(NULL),
             NotNull => op!(NOT_NULL),
             Eq => op!(EQ),
@@ -567,6 +697,9 @@ impl Instruction {
             Tail => op!(TAIL),
             Init => op!(INIT),
             Last => op!(LAST),
+            IterStart => op!(ITER_START),
+            IterFirst => op!(ITER_FIRST),
+            IterRest => op!(ITER_REST),
             FirstPush => op!(FIRST_PUSH),
             TailPush => op!(TAIL_PUSH),
             InitPush => op!(INIT_PUSH),
@@ -584,14 +717,18 @@ impl Instruction {
             CallConst(n, n_args) => op!(CALL_CONST, n, n_args),
             Call(n) => op!(CALL, n),
             Apply(n) => op!(APPLY, n),
+            ApplyWithKeywords(n_kw, n_pos) => op!(APPLY_WITH_KEYWORDS, n_kw, n_pos),
             CallSelf(n) => op!(CALL_SELF, n),
             TailCall(n) => op!(TAIL_CALL, n),
+            TailCallConst(n, n_args) => op!(TAIL_CALL_CONST, n, n_args),
+            TailCallFunction(n) => op!(TAIL_CALL_FUNCTION, n),
             Skip(1) => op!(SKIP, 1),
             Skip(2) => op!(SKIP, 2),
             Skip(3) => op!(SKIP, 3),
             Skip(4) => op!(SKIP, 4),
             Skip(n) => op!(SKIP, n),
             Return => op!(RETURN),
+            ReturnUnit => op!(RETURN_UNIT),
         }
     }
 
@@ -610,7 +747,8 @@ impl Instruction {
             JumpIfEq(label) |
             JumpIfNotEq(label) |
             JumpIfEqConst(label, _) |
-            JumpIfNotEqConst(label, _) => Some(label),
+            JumpIfNotEqConst(label, _) |
+            PushHandler(label, _) => Some(label),
             _ => None
         }
     }
@@ -634,6 +772,7 @@ pub enum JumpInstruction {
     JumpIfNotEq,
     JumpIfEqConst(u32),
     JumpIfNotEqConst(u32),
+    PushHandler(u32),
 }
 
 impl JumpInstruction {
@@ -652,6 +791,7 @@ impl JumpInstruction {
             JumpIfNotEq => Instruction::JumpIfNotEq(label),
             JumpIfEqConst(n) => Instruction::JumpIfEqConst(label, n),
             JumpIfNotEqConst(n) => Instruction::JumpIfNotEqConst(label, n),
+            PushHandler(n) => Instruction::PushHandler(label, n),
         }
     }
 
@@ -670,7 +810,8 @@ impl JumpInstruction {
             JumpIfNotEq => 1 + len,
             JumpIfBound(n) |
             JumpIfEqConst(n) |
-            JumpIfNotEqConst(n) => {
+            JumpIfNotEqConst(n) |
+            PushHandler(n) => {
                 let op_len = if is_short_operand(n) { 1 } else { 2 };
                 1 + len + op_len
             }
@@ -688,6 +829,9 @@ pub struct Code {
     pub consts: Box<[Value]>,
     /// Function body bytecode
     pub code: Box<[u8]>,
+    /// Names of positional parameters, including optional parameters,
+    /// in the order in which they are expected.
+    pub param_names: Box<[Name]>,
     /// Names of keyword parameters accepted in the order in which they are
     /// expected.
     pub kw_params: Box<[Name]>,
@@ -726,6 +870,13 @@ impl Code {
     pub fn has_kw_params(&self) -> bool {
         self.flags & code_flags::PARAM_FLAGS_MASK == code_flags::HAS_KW_PARAMS
     }
+
+    /// Returns whether the function accepts a fixed number of arguments;
+    /// that is, it has no optional, keyword, or rest parameters.
+    pub fn has_fixed_arity(&self) -> bool {
+        self.flags & code_flags::PARAM_FLAGS_MASK == 0 &&
+            self.n_params == self.req_params
+    }
 }
 
 /// Bit flag values for `Code::flags`
@@ -795,6 +946,8 @@ pub struct CodeBlock {
     bytes: Vec<u8>,
     /// Most recently added instruction; may be merged with next instruction
     instr_part: Option<Instruction>,
+    /// Most recently encoded instruction and its offset in `bytes`
+    last_instr: Option<(Instruction, usize)>,
     /// Jump instruction added to the end of the block
     pub jump: Option<(JumpInstruction, u32)>,
     /// Refers to the block that immediately follows this block
@@ -807,6 +960,7 @@ impl CodeBlock {
         CodeBlock{
             bytes: Vec::with_capacity(16),
             instr_part: None,
+            last_instr: None,
             jump: None,
             next: None,
         }
@@ -817,6 +971,7 @@ impl CodeBlock {
         CodeBlock{
             bytes: Vec::new(),
             instr_part: None,
+            last_instr: None,
             jump: None,
             next: None,
         }
@@ -904,6 +1059,7 @@ impl CodeBlock {
     pub fn push_instruction(&mut self, instr: Instruction) -> Result<(), CompileError> {
         if let Some(part) = self.instr_part {
             match merge_instructions(part, instr) {
+                Some(Instruction::Return) => self.merge_return(),
                 Some(new) => self.instr_part = Some(new),
                 None => {
                     try!(self.write_instruction(part));
@@ -916,7 +1072,24 @@ impl CodeBlock {
         Ok(())
     }
 
+    /// Stores a pending `Return` instruction, replacing any pending `Skip`.
+    /// Because `Return` cleans the stack, it may be merged with the instruction
+    /// encoded prior to a `Skip`.
+    fn merge_return(&mut self) {
+        self.instr_part = match self.last_instr
+                .and_then(|(i, off)| merge_instructions(i, Instruction::Return)
+                    .map(|new| (new, off))) {
+            Some((new, off)) => {
+                self.bytes.truncate(off);
+                self.last_instr = None;
+                Some(new)
+            }
+            None => Some(Instruction::Return)
+        };
+    }
+
     fn write_instruction(&mut self, instr: Instruction) -> Result<(), CompileError> {
+        self.last_instr = Some((instr, self.bytes.len()));
         instr.encode(self, false)
     }
 
@@ -961,6 +1134,57 @@ impl CodeBlock {
     }
 }
 
+/// Returns the label in a jump table to which the string `key` leads.
+///
+/// A jump table is a list whose first element is the default label,
+/// followed by pairs of string keys and labels, sorted by key.
+/// If `key` is not in the table, the default label is returned.
+/// Returns `None` if the table is malformed.
+pub fn jump_table_lookup(table: &Value, key: &str) -> Option<u32> {
+    let li = match *table {
+        Value::List(ref li) if li.len() % 2 == 1 => li,
+        _ => return None
+    };
+
+    let n_keys = li.len() / 2;
+    let (mut lo, mut hi) = (0, n_keys);
+
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+
+        let k = match li[1 + mid * 2] {
+            Value::String(ref s) => &s[..],
+            _ => return None
+        };
+
+        match k.cmp(key) {
+            Ordering::Less => lo = mid + 1,
+            Ordering::Greater => hi = mid,
+            Ordering::Equal => return table_label(&li[2 + mid * 2])
+        }
+    }
+
+    table_label(&li[0])
+}
+
+/// Returns all labels in a jump table, including the default label.
+/// Returns `None` if the table is malformed.
+pub fn jump_table_labels(table: &Value) -> Option<Vec<u32>> {
+    match *table {
+        Value::List(ref li) if li.len() % 2 == 1 =>
+            li.iter().enumerate().filter(|&(i, _)| i % 2 == 0)
+                .map(|(_, v)| table_label(v)).collect(),
+        _ => None
+    }
+}
+
+fn table_label(v: &Value) -> Option<u32> {
+    match *v {
+        Value::Integer(ref i) => i.to_u32(),
+        _ => None
+    }
+}
+
 fn merge_jump_instruction(a: Instruction, b: JumpInstruction) -> Option<JumpInstruction> {
     use self::JumpInstruction::*;
 
@@ -1007,7 +1231,10 @@ fn merge_instructions(a: Instruction, b: Instruction) -> Option<Instruction> {
         (EqConst(n), Not) => NotEqConst(n),
         (NotEqConst(n), Not) => EqConst(n),
         (CallSelf(n), Return) => TailCall(n),
+        (CallConst(c, n), Return) => TailCallConst(c, n),
+        (Call(n), Return) => TailCallFunction(n),
         (Skip(_), Return) => Return,
+        (Unit, Return) => ReturnUnit,
         _ => return None
     };
 
@@ -1,25 +1,31 @@
 //! Compiles expressions into bytecode objects.
 
 use std::borrow::Cow::{self, Borrowed, Owned};
+use std::error::Error as StdError;
 use std::f64;
 use std::fmt;
 use std::mem::replace;
 use std::rc::Rc;
 
-use bytecode::{code_flags, Code, CodeBlock,
+use bytecode::{code_flags, jump_table_labels, Code, CodeBlock, CodeReader,
     Instruction, JumpInstruction, MAX_SHORT_OPERAND};
+use condition::is_error_category;
 use error::Error;
-use exec::execute_lambda;
-use function::{Arity, Lambda};
+use exec::{execute, execute_lambda};
+use function::{plural, Arity, Lambda, SystemFn};
 use function::Arity::*;
-use name::{get_system_fn, is_system_operator, standard_names,
-    Name, NameDisplay, NameMap, NameSet, NameStore,
+use module::{check_exports, ImportSet, Module};
+use name::{debug_names, display_names, get_standard_name, get_standard_name_for, get_system_fn,
+    intern_name, is_system_operator, standard_names, Name, NameDisplay, NameMap, NameSet, NameStore,
     NUM_SYSTEM_OPERATORS, SYSTEM_OPERATORS_BEGIN};
-use scope::{GlobalScope, MasterScope, Scope};
+use scope::{GlobalScope, LogLevel, MasterScope, Scope};
 use value::{StructDef, Value};
 
 const MAX_MACRO_RECURSION: u32 = 100;
 
+/// Maximum length of a value rendered within an error message
+const MAX_DISPLAY_VALUE: usize = 40;
+
 /// Represents an error generated while compiling to bytecode.
 #[derive(Debug)]
 pub enum CompileError {
@@ -34,8 +40,14 @@ pub enum CompileError {
     },
     /// Attempt to define name of standard value or operator
     CannotDefine(Name),
+    /// Pattern in a `case` expression which is matched by an earlier pattern
+    /// and so will never be matched; this is reported as a warning,
+    /// except in strict mode
+    DuplicateCasePattern(Value),
     /// Duplicate `exports` declaration
     DuplicateExports,
+    /// Name exported more than once
+    DuplicateExport(Name),
     /// Duplicate name in parameter list
     DuplicateParameter(Name),
     /// Attempt to export nonexistent name from module
@@ -45,6 +57,14 @@ pub enum CompileError {
         /// Imported name
         name: Name,
     },
+    /// Global definition of a name imported from a module;
+    /// this is reported as a warning, except in strict mode
+    DefineImport{
+        /// Module name
+        module: Name,
+        /// Defined name
+        name: Name,
+    },
     /// Recursion in module imports
     ImportCycle(Name),
     /// Attempt to import nonexistent name from module
@@ -61,20 +81,52 @@ pub enum CompileError {
         /// Imported name
         name: Name,
     },
-    /// Invalid expression to function call
-    InvalidCallExpression(&'static str),
+    /// Constant argument to an inlined system function is of a type which
+    /// the function never accepts; this is reported as a warning for string
+    /// and vector constants, except in strict mode
+    ConstantTypeError{
+        /// Function name
+        name: Name,
+        /// Expected type of argument
+        expected: &'static str,
+        /// Type of the constant value
+        found: &'static str,
+    },
+    /// Invalid expression to function call; contains the value
+    /// at the head of the call expression
+    InvalidCallExpression(Value),
     /// `,@expr` form outside of a list
     InvalidCommaAt,
     /// Module name contains invalid characters
     InvalidModuleName(Name),
+    /// Attempt to overload a function with a function accepting
+    /// optional, keyword, or rest parameters
+    InvalidOverload(Name),
+    /// Size limit exceeded by the expansion of the named macro
+    MacroExpansionExceeded(Name),
     /// Recursion limit exceeded while expanding macros
     MacroRecursionExceeded,
     /// Missing `export` declaration in loaded module
     MissingExport,
+    /// Attempt to bind a name which is not a dynamic variable
+    NotDynamic(Name),
+    /// Operator which must appear at top level appears within an expression
+    NotTopLevel(Name),
     /// Failed to load a module
     ModuleError(Name),
     /// Operand value overflow
     OperandOverflow(u32),
+    /// Error in the syntax of a system operator expression
+    OperatorSyntaxError{
+        /// Operator name
+        name: Name,
+        /// Description of the error
+        message: &'static str,
+    },
+    /// Attempt to import a module when imports are disabled
+    ImportsDisabled,
+    /// Attempt to import a module not permitted by the import policy
+    ImportNotAllowed(Name),
     /// Attempt to import value that is not exported
     PrivacyError{
         /// Module name
@@ -82,10 +134,29 @@ pub enum CompileError {
         /// Imported name
         name: Name,
     },
+    /// Compiled code does not maintain a consistent stack depth;
+    /// this indicates a bug in the compiler.
+    /// Code is verified only when debug assertions are enabled.
+    StackError{
+        /// Description of the error
+        message: &'static str,
+        /// Bytecode offset at which the error was detected
+        offset: u32,
+        /// Offsets of the jump instructions on the path to `offset`
+        path: Vec<u32>,
+    },
     /// Error in parsing operator syntax
     SyntaxError(&'static str),
     /// More commas than backquotes
     UnbalancedComma,
+    /// Keyword argument to a function with a rest parameter and no keyword
+    /// parameters; this is reported as a warning, except in strict mode
+    UnexpectedKeyword{
+        /// Function name
+        name: Name,
+        /// Keyword passed
+        keyword: Name,
+    },
 }
 
 impl fmt::Display for CompileError {
@@ -97,53 +168,141 @@ impl fmt::Display for CompileError {
                 write!(f, "expected {}; found {}", expected, found),
             CannotDefine(_) =>
                 f.write_str("cannot define name of standard value or operator"),
+            ConstantTypeError{expected, found, ..} =>
+                write!(f, "constant argument of incorrect type: expected {}; found {}",
+                    expected, found),
+            DuplicateCasePattern(_) =>
+                f.write_str("duplicate `case` pattern will never be matched"),
             DuplicateExports => f.write_str("duplicate `exports` declaration"),
+            DuplicateExport(_) => f.write_str("name exported more than once"),
             DuplicateParameter(_) => f.write_str("duplicate parameter"),
             ExportError{..} => f.write_str("export name not found in module"),
+            DefineImport{..} => f.write_str("definition shadows an imported name"),
             ImportCycle(_) => f.write_str("import cycle detected"),
             ImportError{..} => f.write_str("import name not found in module"),
             ImportShadow{..} => f.write_str("import shadows an existing name"),
-            InvalidCallExpression(ty) =>
-                write!(f, "invalid call expression of type `{}`", ty),
+            ImportsDisabled => f.write_str("module imports are disabled"),
+            ImportNotAllowed(_) => f.write_str("module import is not allowed"),
+            InvalidCallExpression(ref v) =>
+                write!(f, "invalid call expression of type `{}`", v.type_name()),
             InvalidCommaAt =>
                 f.write_str("`,@expr` form is invalid outside of a list"),
             InvalidModuleName(_) => f.write_str("invalid module name"),
+            InvalidOverload(_) => f.write_str(
+                "cannot overload function with optional, keyword, or rest parameters"),
+            MacroExpansionExceeded(_) =>
+                f.write_str("macro expansion exceeded maximum size"),
             MacroRecursionExceeded => f.write_str("macro recursion exceeded"),
             MissingExport => f.write_str("missing `export` declaration"),
+            NotDynamic(_) => f.write_str("name is not a dynamic variable"),
+            NotTopLevel(_) => f.write_str("operator used outside of top level"),
             ModuleError(_) => f.write_str("module not found"),
             OperandOverflow(n) =>
                 write!(f, "operand overflow: {}", n),
+            OperatorSyntaxError{message, ..} => f.write_str(message),
             PrivacyError{..} => f.write_str("name is private"),
+            StackError{message, ..} =>
+                write!(f, "internal compiler error: {}", message),
             SyntaxError(e) => f.write_str(e),
             UnbalancedComma => f.write_str("unbalanced ` and ,"),
+            UnexpectedKeyword{..} =>
+                f.write_str("keyword argument to function without keyword parameters"),
         }
     }
 }
 
+impl StdError for CompileError {}
+
 impl NameDisplay for CompileError {
     fn fmt(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
         use self::CompileError::*;
 
         match *self {
-            ArityError{name, ..} => write!(f, "`{}` {}", names.get(name), self),
+            ArityError{name, ..} => {
+                try!(write!(f, "`{}` {}", names.get(name), self));
+
+                match operator_usage(name) {
+                    Some(usage) => write!(f, "; usage: {}", usage),
+                    None => Ok(())
+                }
+            }
+            OperatorSyntaxError{name, message} =>
+                write!(f, "`{}` {}; usage: {}", names.get(name), message,
+                    get_system_operator(name).usage),
             CannotDefine(name) |
+            DuplicateExport(name) |
             DuplicateParameter(name) |
             InvalidModuleName(name) |
-            ModuleError(name) => write!(f, "{}: {}", self, names.get(name)),
+            InvalidOverload(name) |
+            ModuleError(name) |
+            NotDynamic(name) => write!(f, "{}: {}", self, names.get(name)),
+            ConstantTypeError{name, expected, found} =>
+                write!(f, "`{}` expects a {}; found constant of type {}",
+                    names.get(name), expected, found),
+            DuplicateCasePattern(ref v) =>
+                write!(f, "duplicate `case` pattern `{}` will never be matched",
+                    debug_names(names, v)),
             ExportError{module, name} =>
                 write!(f, "cannot export name `{}`; not found in module `{}`",
                     names.get(name), names.get(module)),
+            DefineImport{module, name} =>
+                write!(f, "definition of `{}` shadows the value imported from `{}`",
+                    names.get(name), names.get(module)),
             ImportCycle(name) =>
                 write!(f, "import cycle in loading module `{}`", names.get(name)),
+            ImportNotAllowed(name) =>
+                write!(f, "import of module `{}` is not allowed", names.get(name)),
+            MacroExpansionExceeded(name) =>
+                write!(f, "expansion of macro `{}` exceeded maximum size",
+                    names.get(name)),
             ImportError{module, name} =>
                 write!(f, "cannot import name `{}`; not found in module `{}`",
                     names.get(name), names.get(module)),
+            NotTopLevel(name) =>
+                write!(f, "`{}` operator used outside of top level", names.get(name)),
             ImportShadow{module, name} =>
                 write!(f, "importing `{}` from `{}` shadows an existing value",
                     names.get(name), names.get(module)),
+            InvalidCallExpression(ref v) => {
+                let s = debug_names(names, v).to_string();
+
+                if s.len() <= MAX_DISPLAY_VALUE && !s.contains('\n') {
+                    try!(write!(f, "invalid call expression `{}` of type `{}`",
+                        s, v.type_name()));
+                } else {
+                    try!(fmt::Display::fmt(self, f));
+                }
+
+                match *v {
+                    Value::Keyword(kw) =>
+                        write!(f, "; to get a struct field, use `(. value :{})`",
+                            names.get(kw)),
+                    Value::Bool(_) | Value::Char(_) | Value::Float(_) |
+                    Value::Integer(_) | Value::Ratio(_) | Value::String(_) =>
+                        f.write_str("; to create a list, use `'(...)` or `(list ...)`"),
+                    _ => Ok(())
+                }
+            }
             PrivacyError{module, name} =>
                 write!(f, "name `{}` in module `{}` is private",
                     names.get(name), names.get(module)),
+            UnexpectedKeyword{name, keyword} =>
+                write!(f, "keyword argument `:{}` passed to `{}`, which accepts \
+                    no keyword parameters; it will be collected into the rest list",
+                    names.get(keyword), names.get(name)),
+            StackError{offset, ref path, ..} => {
+                try!(write!(f, "{} at offset {}", self, offset));
+
+                if !path.is_empty() {
+                    try!(f.write_str("; reached by jumps at offsets"));
+
+                    for (i, off) in path.iter().enumerate() {
+                        try!(write!(f, "{} {}", if i == 0 { "" } else { "," }, off));
+                    }
+                }
+
+                Ok(())
+            }
             _ => fmt::Display::fmt(self, f)
         }
     }
@@ -154,11 +313,15 @@ pub fn compile(scope: &Scope, value: &Value) -> Result<Code, Error> {
     Compiler::new(scope).compile(value)
 }
 
+/// Lambda parameter; contains the parameter name, default value expression,
+/// and the name of a variable bound to whether the parameter was supplied.
+type Param = (Name, Option<Value>, Option<Name>);
+
 fn compile_lambda(compiler: &Compiler,
         name: Option<Name>,
-        params: Vec<(Name, Option<Value>)>,
+        params: Vec<Param>,
         req_params: u32,
-        kw_params: Vec<(Name, Option<Value>)>,
+        kw_params: Vec<Param>,
         rest: Option<Name>, value: &Value)
         -> Result<(Code, Vec<Name>), Error> {
     let outer = compiler.outer.iter().cloned()
@@ -176,6 +339,8 @@ struct Compiler<'a> {
     consts: Vec<Value>,
     /// Blocks of bytecode
     blocks: Vec<CodeBlock>,
+    /// Jump tables referenced by `JumpTable` instructions
+    jump_tables: Vec<CaseTable>,
     /// Current bytecode block
     cur_block: usize,
     /// Named stack values, paired with stack offset
@@ -191,11 +356,19 @@ struct Compiler<'a> {
     self_name: Option<Name>,
     /// Depth of macro expansion
     macro_recursion: u32,
+    /// Whether the next expression compiled is at top level;
+    /// that is, not within any expression other than `do`.
+    top_level: bool,
+    /// Whether a macro body is being compiled; names imported using
+    /// `import-for-syntax` are visible only within macro bodies.
+    for_syntax: bool,
 }
 
 impl<'a> Compiler<'a> {
     fn new(scope: &'a Scope) -> Compiler<'a> {
-        Compiler::with_outer(scope, None, &[])
+        let mut compiler = Compiler::with_outer(scope, None, &[]);
+        compiler.top_level = true;
+        compiler
     }
 
     fn with_outer(scope: &'a Scope, name: Option<Name>,
@@ -204,6 +377,7 @@ impl<'a> Compiler<'a> {
             scope: scope,
             consts: Vec::new(),
             blocks: vec![CodeBlock::new()],
+            jump_tables: Vec::new(),
             cur_block: 0,
             stack: Vec::new(),
             stack_offset: 0,
@@ -211,10 +385,33 @@ impl<'a> Compiler<'a> {
             outer: outer,
             self_name: name,
             macro_recursion: 0,
+            top_level: false,
+            for_syntax: outer.last().map_or(false, |c| c.for_syntax),
+        }
+    }
+
+    /// Compiles an expression and, when debug assertions are enabled,
+    /// verifies that the compiler's account of the stack is left as it was.
+    fn compile_body(&mut self, value: &Value) -> Result<(), Error> {
+        let offset = self.stack_offset;
+
+        try!(self.compile_value(value));
+
+        if cfg!(debug_assertions) && self.stack_offset != offset {
+            return Err(From::from(CompileError::StackError{
+                message: "compiled expression leaves the stack unbalanced",
+                offset: 0,
+                path: Vec::new(),
+            }));
         }
+
+        Ok(())
     }
 
-    fn assemble_code(&mut self) -> Result<Box<[u8]>, CompileError> {
+    /// Assembles code which begins with `base` values on the stack.
+    /// When debug assertions are enabled, the stack effect of the code
+    /// is verified.
+    fn assemble_code(&mut self, base: u32) -> Result<Box<[u8]>, CompileError> {
         let total = try!(self.write_jumps());
         let mut res = Vec::with_capacity(total);
 
@@ -223,25 +420,137 @@ impl<'a> Compiler<'a> {
         }
 
         assert_eq!(res.len(), total);
+
+        if cfg!(debug_assertions) {
+            try!(self.verify_stack(&res, base));
+        }
+
         Ok(res.into_boxed_slice())
     }
 
+    /// Simulates the stack effect of every path through assembled code,
+    /// which begins with `base` values on the stack.
+    ///
+    /// Every path reaching an instruction must do so with the same stack
+    /// depth; no instruction may remove values below `base` or refer to
+    /// a value beyond the top of the stack; and every path must end in
+    /// a return or a tail call. Because `Return` discards values above
+    /// `base`, a `Skip` preceding it may have been elided, so paths may
+    /// return with different stack depths.
+    fn verify_stack(&self, code: &[u8], base: u32) -> Result<(), CompileError> {
+        use bytecode::Instruction::*;
+
+        let mut depths = vec![None; code.len()];
+        // Offset, stack depth, and offsets of jumps leading to it
+        let mut pending = vec![(0, base, Vec::new())];
+
+        while let Some((start, mut depth, path)) = pending.pop() {
+            let mut r = CodeReader::new(code, start as usize);
+
+            loop {
+                let off = r.get_offset();
+                let err = |message| CompileError::StackError{
+                    message: message,
+                    offset: off as u32,
+                    path: path.clone(),
+                };
+
+                if off >= code.len() {
+                    return Err(err("code ends without returning"));
+                }
+
+                match depths[off] {
+                    Some(d) if d == depth => break,
+                    Some(_) => return Err(err(
+                        "paths reach instruction with different stack depths")),
+                    None => depths[off] = Some(depth)
+                }
+
+                let instr = try!(r.read_instruction()
+                    .map_err(|_| err("invalid instruction")));
+
+                match instr {
+                    Load(n) | LoadPush(n) | Store(n) | UnboundToUnit(n) |
+                    IsBound(n) | JumpIfBound(_, n) if n >= depth =>
+                        return Err(err("reference to value beyond top of stack")),
+                    _ => ()
+                }
+
+                let (pop, push) = match instr {
+                    CallSys(n) => match self.sys_fn_arity(n) {
+                        Some(Exact(n_args)) => (n_args, 0),
+                        _ => return Err(err("invalid system function call"))
+                    },
+                    _ => stack_effect(instr)
+                };
+
+                if depth < base + pop {
+                    return Err(err("instruction removes too many values from the stack"));
+                }
+
+                depth = depth - pop + push;
+
+                if let Some(label) = instr.get_jump_label() {
+                    let mut jump_path = path.clone();
+                    jump_path.push(off as u32);
+                    pending.push((label, depth, jump_path));
+                }
+
+                if let JumpTable(n) = instr {
+                    let labels = try!(self.consts.get(n as usize)
+                        .and_then(jump_table_labels)
+                        .ok_or_else(|| err("invalid jump table")));
+
+                    for label in labels {
+                        let mut jump_path = path.clone();
+                        jump_path.push(off as u32);
+                        pending.push((label, depth, jump_path));
+                    }
+                }
+
+                match instr {
+                    Jump(_) | Return | ReturnUnit | TailCall(_) |
+                    TailCallConst(..) | TailCallFunction(_) => break,
+                    _ => ()
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the arity of a system function called by `CallSys`.
+    fn sys_fn_arity(&self, n: u32) -> Option<Arity> {
+        get_standard_name(n).and_then(get_system_fn).map(|f| f.arity)
+            .or_else(|| self.scope.get_registered_fn(n).map(|(_, f)| f.arity))
+    }
+
     /// Writes jump instructions with real offsets to each code blocks.
     /// Returns the total size, in bytes, of code blocks.
     fn write_jumps(&mut self) -> Result<usize, CompileError> {
-        // If all possible offsets can be shortened, shorten them.
-        let short = estimate_size(&self.blocks) <= MAX_SHORT_OPERAND as usize;
-
         let n_blocks = self.blocks.len();
 
         let mut new_blocks = Vec::with_capacity(n_blocks);
-        let mut offsets = vec![!0; n_blocks];
+        // Initial block numbers of blocks in new_blocks
+        let mut live = Vec::with_capacity(n_blocks);
         // Blocks which are a target of a conditional jump must be written out
         // even if they contain only a Return instruction.
         let mut must_live = vec![false; n_blocks];
-        let mut off = 0;
         let mut i = 0;
 
+        // Whether each block returns is determined before any block is
+        // taken from `self.blocks`, as a jump may lead to a preceding block.
+        let returns = self.blocks.iter()
+            .map(|b| block_returns(b, &self.blocks)).collect::<Vec<_>>();
+
+        for table in &self.jump_tables {
+            must_live[table.default as usize] = true;
+
+            for &(_, block) in &table.entries {
+                must_live[block as usize] = true;
+            }
+        }
+
         loop {
             let mut b = replace(&mut self.blocks[i], CodeBlock::empty());
             let next = b.next;
@@ -253,7 +562,7 @@ impl<'a> Compiler<'a> {
                 _ => ()
             }
 
-            if block_returns(&b, &self.blocks) {
+            if returns[i] {
                 // If the block is empty and no other blocks will conditionally
                 // jump to it, then the block may be pruned altogether.
                 // Any blocks which would *unconditionally* jump will
@@ -270,10 +579,7 @@ impl<'a> Compiler<'a> {
 
             if !skip_block {
                 try!(b.flush());
-
-                // Jump block numbers refer to initial ordering
-                offsets[i] = off as u32;
-                off += b.calculate_size(short);
+                live.push(i);
                 new_blocks.push(b);
             }
 
@@ -285,6 +591,20 @@ impl<'a> Compiler<'a> {
 
         replace(&mut self.blocks, new_blocks);
 
+        // Offsets are first computed using short jump offsets.
+        // If any offset cannot be encoded in short format, all offsets are
+        // computed again using long format.
+        let (mut offsets, mut size) = block_offsets(&self.blocks, &live, n_blocks, true);
+        let short = self.blocks.iter().filter_map(|b| b.jump)
+            .all(|(_, dest)| offsets[dest as usize] <= MAX_SHORT_OPERAND);
+
+        if !short {
+            let (long_offsets, long_size) =
+                block_offsets(&self.blocks, &live, n_blocks, false);
+            offsets = long_offsets;
+            size = long_size;
+        }
+
         for block in &mut self.blocks {
             if let Some((_, dest)) = block.jump {
                 let dest_off = offsets[dest as usize];
@@ -293,16 +613,21 @@ impl<'a> Compiler<'a> {
             }
         }
 
-        Ok(off)
+        for table in &self.jump_tables {
+            self.consts[table.konst as usize] = table.build(&offsets);
+        }
+
+        Ok(size)
     }
 
     fn compile(mut self, value: &Value) -> Result<Code, Error> {
-        try!(self.compile_value(value));
+        try!(self.compile_body(value));
 
         Ok(Code{
             name: None,
-            code: try!(self.assemble_code()),
+            code: try!(self.assemble_code(0)),
             consts: self.consts.into_boxed_slice(),
+            param_names: vec![].into_boxed_slice(),
             kw_params: vec![].into_boxed_slice(),
             n_params: 0,
             req_params: 0,
@@ -311,9 +636,9 @@ impl<'a> Compiler<'a> {
     }
 
     fn compile_lambda(mut self, name: Option<Name>,
-            params: Vec<(Name, Option<Value>)>,
+            params: Vec<Param>,
             req_params: u32,
-            kw_params: Vec<(Name, Option<Value>)>,
+            kw_params: Vec<Param>,
             rest: Option<Name>, value: &Value)
             -> Result<(Code, Vec<Name>), Error> {
         let total_params = params.len() + kw_params.len() +
@@ -357,10 +682,15 @@ impl<'a> Compiler<'a> {
             flags |= code_flags::HAS_REST_PARAMS;
         }
 
+        let mut param_names = Vec::with_capacity(n_params);
         let mut kw_names = Vec::with_capacity(kw_params.len());
 
-        for (i, (name, default)) in params.into_iter().enumerate() {
+        for (i, (name, default, supplied)) in params.into_iter().enumerate() {
             if (i as u32) >= req_params {
+                if let Some(supplied) = supplied {
+                    try!(self.bind_supplied(i as u32, supplied));
+                }
+
                 if let Some(default) = default {
                     try!(self.branch_if_unbound(i as u32, &default));
                 } else {
@@ -370,9 +700,14 @@ impl<'a> Compiler<'a> {
             }
 
             self.stack[i].0 = name;
+            param_names.push(name);
         }
 
-        for (i, (name, default)) in kw_params.into_iter().enumerate() {
+        for (i, (name, default, supplied)) in kw_params.into_iter().enumerate() {
+            if let Some(supplied) = supplied {
+                try!(self.bind_supplied((n_params + i) as u32, supplied));
+            }
+
             if let Some(default) = default {
                 try!(self.branch_if_unbound((n_params + i) as u32, &default));
             } else {
@@ -389,12 +724,13 @@ impl<'a> Compiler<'a> {
             self.stack[n - 1].0 = rest;
         }
 
-        try!(self.compile_value(value));
+        try!(self.compile_body(value));
 
         let code = Code{
             name: name,
-            code: try!(self.assemble_code()),
+            code: try!(self.assemble_code(total_params as u32)),
             consts: self.consts.into_boxed_slice(),
+            param_names: param_names.into_boxed_slice(),
             kw_params: kw_names.into_boxed_slice(),
             n_params: n_params as u32,
             req_params: req_params,
@@ -405,13 +741,25 @@ impl<'a> Compiler<'a> {
     }
 
     fn compile_value(&mut self, value: &Value) -> Result<(), Error> {
+        let top_level = replace(&mut self.top_level, false);
+
         match *value {
             Value::Name(name) => {
                 let loaded = try!(self.load_local_name(name));
 
                 if !loaded {
-                    let c = self.add_const(Owned(Value::Name(name)));
-                    try!(self.push_instruction(Instruction::GetDef(c)));
+                    if let Some(var) = self.scope.get_dynamic_var(name) {
+                        let c = self.add_const(Owned(Value::Name(var)));
+                        try!(self.push_instruction(Instruction::GetDynamic(c)));
+                    } else if let Some(imp) = get_ref_import(self.scope,
+                            name, self.for_syntax) {
+                        try!(self.load_module_def(imp));
+                    } else if let Some(imp) = try!(self.get_qualified_name(name)) {
+                        try!(self.load_module_def(imp));
+                    } else {
+                        let c = self.add_const(Owned(Value::Name(name)));
+                        try!(self.push_instruction(Instruction::GetDef(c)));
+                    }
                 }
             }
             Value::List(ref li) => {
@@ -426,14 +774,37 @@ impl<'a> Compiler<'a> {
                             pushed_fn = true;
                         } else if self.self_name == Some(name) {
                             () // This is handled later
+                        } else if let Some(var) = self.scope.get_dynamic_var(name) {
+                            let c = self.add_const(Owned(Value::Name(var)));
+                            try!(self.push_instruction(Instruction::GetDynamic(c)));
+                            try!(self.push_instruction(Instruction::Push));
+                            pushed_fn = true;
+                        } else if let Some(imp) = get_ref_import(self.scope,
+                                name, self.for_syntax) {
+                            try!(self.load_module_def(imp));
+                            try!(self.push_instruction(Instruction::Push));
+                            pushed_fn = true;
+                        } else if let Some(imp) = try!(self.get_qualified_name(name)) {
+                            try!(self.load_module_def(imp));
+                            try!(self.push_instruction(Instruction::Push));
+                            pushed_fn = true;
                         } else if self.is_macro(name) {
                             self.macro_recursion += 1;
                             let v = try!(self.expand_macro(name, &li[1..]));
+                            try!(self.check_expansion(name, &v));
+                            // Macro expansion is at the same level as the macro call
+                            self.top_level = top_level;
                             try!(self.compile_value(&v));
                             self.macro_recursion -= 1;
 
                             return Ok(());
                         } else if is_system_operator(name) {
+                            if !top_level && is_top_level_operator(name) {
+                                try!(self.not_top_level(name));
+                            }
+                            self.top_level = top_level && (name == standard_names::DO ||
+                                name == standard_names::DEFINE_TYPE ||
+                                name == standard_names::DEFINE_RECORD);
                             return self.compile_operator(name, &li[1..]);
                         } else if try!(self.inline_call(name, &li[1..])) {
                             return Ok(());
@@ -445,7 +816,7 @@ impl<'a> Compiler<'a> {
                         pushed_fn = true;
                     }
                     ref v => return Err(From::from(
-                        CompileError::InvalidCallExpression(v.type_name())))
+                        CompileError::InvalidCallExpression(v.clone())))
                 }
 
                 for v in &li[1..] {
@@ -473,12 +844,26 @@ impl<'a> Compiler<'a> {
                                         }));
                                     }
 
-                                    try!(self.write_call_sys(name, sys_fn.arity, n_args));
+                                    try!(self.write_call_sys(name.get(), sys_fn.arity, n_args));
                                 }
-                                None => {
-                                    let c = self.add_const(Owned(Value::Name(name)));
-                                    try!(self.push_instruction(
-                                        Instruction::CallConst(c, n_args)));
+                                None => match self.get_registered_fn(name) {
+                                    Some((n, sys_fn)) => {
+                                        if !sys_fn.arity.accepts(n_args) {
+                                            return Err(From::from(CompileError::ArityError{
+                                                name: name,
+                                                expected: sys_fn.arity,
+                                                found: n_args,
+                                            }));
+                                        }
+
+                                        try!(self.write_call_sys(n, sys_fn.arity, n_args));
+                                    }
+                                    None => {
+                                        try!(self.check_rest_keywords(name, &li[1..]));
+                                        let c = self.add_const(Owned(Value::Name(name)));
+                                        try!(self.push_instruction(
+                                            Instruction::CallConst(c, n_args)));
+                                    }
                                 }
                             }
                         }
@@ -509,7 +894,113 @@ impl<'a> Compiler<'a> {
         let lambda = self.scope.get_macro(name)
             .expect("macro not found in expand_macro");
 
-        execute_lambda(lambda, args.to_vec())
+        let scope = &self.scope;
+        scope.with_expansion_scope(scope, || execute_lambda(lambda, args.to_vec()))
+    }
+
+    /// Reports the use of a top level operator within an expression.
+    /// If the scope is strict, an error is returned;
+    /// otherwise, a warning is reported to the scope's log handler.
+    fn not_top_level(&self, name: Name) -> Result<(), CompileError> {
+        self.warn(CompileError::NotTopLevel(name))
+    }
+
+    /// Checks whether a global definition shadows an imported value.
+    fn check_define_import(&self, name: Name) -> Result<(), CompileError> {
+        match get_import_origin(self.scope, name) {
+            Some(module) => self.warn(CompileError::DefineImport{
+                module: module,
+                name: name,
+            }),
+            None => Ok(())
+        }
+    }
+
+    /// Checks the constant arguments to an inlined system function against
+    /// the types it accepts, as listed in `INLINE_ARG_TYPES`.
+    ///
+    /// String and vector constants produce a warning, rather than an error,
+    /// as the function may be meant to accept them in the future.
+    fn check_const_args(&self, name: Name, args: &[Value]) -> Result<(), CompileError> {
+        // Calls with the wrong number of arguments are reported elsewhere
+        match get_system_fn(name) {
+            Some(sys_fn) if sys_fn.arity.accepts(args.len() as u32) => (),
+            _ => return Ok(())
+        }
+
+        for &(fn_name, idx, ty) in INLINE_ARG_TYPES {
+            if fn_name != name {
+                continue;
+            }
+
+            let arg = match args.get(idx) {
+                Some(arg) if is_constant(arg) => const_value(arg),
+                _ => continue
+            };
+
+            if ty.accepts(arg) {
+                continue;
+            }
+
+            let e = CompileError::ConstantTypeError{
+                name: name,
+                expected: ty.name(),
+                found: arg.type_name(),
+            };
+
+            match *arg {
+                Value::String(_) | Value::Vector(_) => try!(self.warn(e)),
+                _ => return Err(e)
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether a call to a global function passes a keyword argument
+    /// which would be collected into the function's rest parameter.
+    fn check_rest_keywords(&self, name: Name, args: &[Value]) -> Result<(), CompileError> {
+        let n_params = match self.scope.get_value(name) {
+            Some(Value::Lambda(ref l)) if l.code.has_rest_params() =>
+                l.code.n_params as usize,
+            _ => return Ok(())
+        };
+
+        for arg in args.iter().skip(n_params) {
+            if let Value::Keyword(kw) = *arg {
+                return self.warn(CompileError::UnexpectedKeyword{
+                    name: name,
+                    keyword: kw,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reports a warning; in strict mode, the warning is returned as an error.
+    fn warn(&self, e: CompileError) -> Result<(), CompileError> {
+        if self.scope.is_strict() {
+            Err(e)
+        } else {
+            let msg = display_names(&self.scope.borrow_names(), &e).to_string();
+            self.scope.log(LogLevel::Warning, &msg);
+            Ok(())
+        }
+    }
+
+    /// Reports a macro expansion to the scope's trace function and checks
+    /// that its size does not exceed the maximum.
+    fn check_expansion(&self, name: Name, value: &Value) -> Result<(), CompileError> {
+        let size = value.node_count();
+
+        self.scope.trace_macro(name, size);
+
+        if size > self.scope.max_macro_expansion() {
+            Err(CompileError::MacroExpansionExceeded(name))
+        } else {
+            Ok(())
+        }
     }
 
     fn compile_operator(&mut self, name: Name, args: &[Value]) -> Result<(), Error> {
@@ -523,7 +1014,14 @@ impl<'a> Compiler<'a> {
                 found: n_args,
             }))
         } else {
-            (op.callback)(self, args)
+            (op.callback)(self, args).map_err(|e| match e {
+                Error::CompileError(CompileError::SyntaxError(message)) =>
+                    From::from(CompileError::OperatorSyntaxError{
+                        name: name,
+                        message: message,
+                    }),
+                e => e
+            })
         }
     }
 
@@ -544,6 +1042,13 @@ impl<'a> Compiler<'a> {
                 Err(From::from(CompileError::InvalidCommaAt)),
             Value::List(ref li) =>
                 self.compile_quasiquote_list(li, depth),
+            Value::Vector(ref li) if !li.is_empty() => {
+                try!(self.compile_quasiquote_list(li, depth));
+                try!(self.push_instruction(Instruction::Push));
+                try!(self.write_call_sys(standard_names::LIST_TO_VEC.get(),
+                    Arity::Exact(1), 1));
+                Ok(())
+            }
             Value::Quote(ref v, n) => {
                 try!(self.compile_quasiquote(v, depth));
                 try!(self.push_instruction(Instruction::Quote(n)));
@@ -625,6 +1130,8 @@ impl<'a> Compiler<'a> {
     }
 
     fn inline_call(&mut self, name: Name, args: &[Value]) -> Result<bool, Error> {
+        try!(self.check_const_args(name, args));
+
         match name {
             standard_names::NULL if args.len() == 1 => {
                 try!(self.compile_value(&args[0]));
@@ -741,6 +1248,68 @@ impl<'a> Compiler<'a> {
         }
     }
 
+    /// Binds elements following the head of the list at stack position `pos`
+    /// to the given names, after checking that the list contains exactly
+    /// one element for each name. The list is consumed in the process.
+    fn compile_case_head_bindings(&mut self, pos: u32, head: &Value, names: &[Name])
+            -> Result<(), Error> {
+        let bind_block = self.new_block();
+
+        try!(self.push_instruction(Instruction::Load(pos)));
+        try!(self.push_instruction(Instruction::Push));
+        try!(self.write_call_sys(standard_names::LEN.get(), Exact(1), 1));
+        let n = self.add_const(Owned((names.len() + 1).into()));
+        self.current_block().jump_to(JumpInstruction::JumpIfEqConst(n), bind_block);
+
+        let fail_block = self.new_block();
+        self.use_next(fail_block);
+
+        // The message follows that of `ArityError`, as in
+        // "`case-head` pattern `:add` expected exactly 2 values; found 1"
+        let head = debug_names(&self.scope.borrow_names(), head).to_string();
+        let msg = format!(
+            "`case-head` pattern `{}` expected exactly {} value{}; found ~a",
+            head.replace('~', "~~"), names.len(), plural(names.len() as u32));
+        let msg = self.add_const(Owned(msg.into()));
+        let one = self.add_const(Owned(1.into()));
+
+        try!(self.push_instruction(Instruction::Const(msg)));
+        try!(self.push_instruction(Instruction::Push));
+        try!(self.push_instruction(Instruction::Load(pos)));
+        try!(self.push_instruction(Instruction::Push));
+        try!(self.write_call_sys(standard_names::LEN.get(), Exact(1), 1));
+        try!(self.push_instruction(Instruction::Push));
+        try!(self.push_instruction(Instruction::Const(one)));
+        try!(self.push_instruction(Instruction::Push));
+        try!(self.write_call_sys(standard_names::SUB.get(), Min(1), 2));
+        try!(self.push_instruction(Instruction::Push));
+        try!(self.write_call_sys(standard_names::FORMAT.get(), Min(1), 2));
+        try!(self.push_instruction(Instruction::Push));
+        try!(self.write_call_sys(standard_names::ERROR.get(), Min(1), 1));
+
+        self.use_next(bind_block);
+
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        for (i, &name) in names.iter().enumerate() {
+            try!(self.push_instruction(Instruction::Load(pos)));
+            try!(self.push_instruction(Instruction::Tail));
+
+            if i + 1 != names.len() {
+                try!(self.push_instruction(Instruction::Store(pos)));
+                try!(self.push_instruction(Instruction::Load(pos)));
+            }
+
+            try!(self.push_instruction(Instruction::First));
+            self.push_var(name);
+            try!(self.push_instruction(Instruction::Push));
+        }
+
+        Ok(())
+    }
+
     fn branch_if_unbound(&mut self, pos: u32, value: &Value) -> Result<(), Error> {
         let bind_block = self.new_block();
         let final_block = self.new_block();
@@ -754,6 +1323,53 @@ impl<'a> Compiler<'a> {
         Ok(())
     }
 
+    /// Pushes onto the stack a variable indicating whether the parameter
+    /// at `pos` was supplied by the caller.
+    /// Must be called before the parameter is assigned a default value.
+    fn bind_supplied(&mut self, pos: u32, name: Name) -> Result<(), CompileError> {
+        try!(self.push_instruction(Instruction::IsBound(pos)));
+        self.push_var(name);
+        self.push_instruction(Instruction::Push)
+    }
+
+    /// Emits code to load a value imported by reference from a module,
+    /// given the module name and the name exported by the module.
+    /// Resolves a qualified name, `alias:name`, where `alias` names a module
+    /// imported using `(use module :as alias)`. Returns the module name and
+    /// the name exported by the module, which must exist.
+    fn get_qualified_name(&self, name: Name) -> Result<Option<(Name, Name)>, Error> {
+        let parts = self.scope.with_name(name, |s| s.find(':')
+            .map(|pos| (s[..pos].to_owned(), s[pos + 1..].to_owned())));
+
+        let (alias, member) = match parts {
+            Some(parts) => parts,
+            None => return Ok(None)
+        };
+
+        let alias = get_standard_name_for(&alias)
+            .or_else(|| self.scope.borrow_names().get_name(&alias));
+
+        let mod_name = match alias.and_then(|a| self.scope.get_module_alias(a)) {
+            Some(mod_name) => mod_name,
+            None => return Ok(None)
+        };
+
+        let member = get_standard_name_for(&member)
+            .unwrap_or_else(|| self.scope.add_name(&member));
+
+        let m = try!(get_module(self.scope, mod_name));
+        try!(check_import(mod_name, &m.scope, member));
+
+        Ok(Some((mod_name, member)))
+    }
+
+    fn load_module_def(&mut self, (mod_name, name): (Name, Name))
+            -> Result<(), CompileError> {
+        let c = self.add_const(Owned(vec![
+            Value::Name(mod_name), Value::Name(name)].into()));
+        self.push_instruction(Instruction::GetModuleDef(c))
+    }
+
     fn load_lambda(&mut self, n: u32, captures: &[Name]) -> Result<(), CompileError> {
         if captures.is_empty() {
             self.push_instruction(Instruction::Const(n))
@@ -859,15 +1475,25 @@ impl<'a> Compiler<'a> {
         let _ = self.stack.drain(n..);
     }
 
-    fn write_call_sys(&mut self, name: Name, arity: Arity, n_args: u32) -> Result<(), CompileError> {
+    /// Returns a registered system function which may be called directly
+    /// by name; i.e. the name is not shadowed by a global value.
+    fn get_registered_fn(&self, name: Name) -> Option<(u32, SystemFn)> {
+        if self.scope.contains_value(name) {
+            None
+        } else {
+            self.scope.find_registered_fn(name)
+        }
+    }
+
+    fn write_call_sys(&mut self, sys_fn: u32, arity: Arity, n_args: u32) -> Result<(), CompileError> {
         match arity {
             Arity::Exact(n) => {
                 // The only stack_offset adjustment that's done manually.
                 self.stack_offset -= n;
-                self.push_instruction(Instruction::CallSys(name.get()))
+                self.push_instruction(Instruction::CallSys(sys_fn))
             }
             _ => self.push_instruction(
-                Instruction::CallSysArgs(name.get(), n_args))
+                Instruction::CallSysArgs(sys_fn, n_args))
         }
     }
 
@@ -896,42 +1522,57 @@ impl<'a> Compiler<'a> {
 
     fn push_instruction(&mut self, instr: Instruction) -> Result<(), CompileError> {
         match instr {
-            Instruction::Push => {
-                self.stack_offset += 1;
-            }
-            Instruction::BuildClosure(_, n) |
-            Instruction::List(n) |
-            Instruction::Skip(n) => {
-                self.stack_offset -= n;
-            }
             // CallSys is handled at the push site
             // to avoid duplicate get_system_fn call
-            Instruction::CallSysArgs(_, n) |
-            Instruction::CallSelf(n) |
-            Instruction::CallConst(_, n) => {
-                self.stack_offset -= n;
-            }
-            Instruction::Call(n) |
-            Instruction::Apply(n) => {
-                self.stack_offset -= n + 1;
-            }
-            Instruction::Eq |
-            Instruction::NotEq => {
-                self.stack_offset -= 1;
+            Instruction::CallSys(_) => (),
+            _ => {
+                let (pop, push) = stack_effect(instr);
+                self.stack_offset = self.stack_offset - pop + push;
             }
-            _ => ()
         }
 
         self.current_block().push_instruction(instr)
     }
 }
 
+/// Returns the number of values removed from and added to the stack
+/// by an instruction. The values removed by `CallSys` depend on the arity
+/// of the function called and are not included.
+fn stack_effect(instr: Instruction) -> (u32, u32) {
+    use bytecode::Instruction::*;
+
+    match instr {
+        Push | LoadPush(_) | LoadCPush(_) | GetDefPush(_) |
+        UnitPush | TruePush | FalsePush | ConstPush(_) |
+        FirstPush | TailPush | InitPush | LastPush => (0, 1),
+        Dup => (1, 2),
+        Swap => (2, 2),
+        BuildClosure(_, n) |
+        List(n) |
+        Skip(n) |
+        CallSysArgs(_, n) |
+        CallSelf(n) |
+        CallConst(_, n) |
+        TailCall(n) |
+        TailCallConst(_, n) => (n, 0),
+        Call(n) |
+        TailCallFunction(n) |
+        Apply(n) => (n + 1, 0),
+        ApplyWithKeywords(n_kw, n_pos) => (n_kw * 2 + n_pos + 1, 0),
+        Eq | NotEq |
+        JumpIfEq(_) | JumpIfNotEq(_) |
+        Append => (1, 0),
+        _ => (0, 0)
+    }
+}
+
 fn block_returns<'a>(mut b: &'a CodeBlock, blocks: &'a [CodeBlock]) -> bool {
     loop {
         match (b.jump, b.next) {
             (_, None) => return true,
-            // This assumes that jumps cannot be cyclical.
-            // Currently, the compiler does not emit cyclical jumps.
+            // This assumes that no cycle of jumps passes only through
+            // empty blocks. The only cyclical jumps, emitted by `while` and
+            // `for`, return to a block which evaluates the loop condition.
             (Some((JumpInstruction::Jump, n)), _) |
             (_, Some(n)) if blocks[n as usize].is_mostly_empty() => {
                 b = &blocks[n as usize];
@@ -941,9 +1582,94 @@ fn block_returns<'a>(mut b: &'a CodeBlock, blocks: &'a [CodeBlock]) -> bool {
     }
 }
 
-fn estimate_size(blocks: &[CodeBlock]) -> usize {
-    blocks.iter().map(|b| b.calculate_size(false))
-        .fold(0, |a, b| a + b) + 1 // Plus one for final Return
+/// Returns the offset of each block, indexed by initial block number,
+/// and the total size of all blocks. Offsets of pruned blocks are `!0`.
+/// `short` indicates whether jump offsets are encoded in short format.
+fn block_offsets(blocks: &[CodeBlock], live: &[usize], n_blocks: usize, short: bool)
+        -> (Vec<u32>, usize) {
+    let mut offsets = vec![!0; n_blocks];
+    let mut off = 0;
+
+    for (b, &i) in blocks.iter().zip(live) {
+        offsets[i] = off as u32;
+        off += b.calculate_size(short);
+    }
+
+    (offsets, off)
+}
+
+/// Jump table dispatching a `case` expression on string values
+struct CaseTable {
+    /// Const which holds the table; it is built once block offsets are known
+    konst: u32,
+    /// Block to which strings not in the table jump
+    default: u32,
+    /// Pattern strings and the blocks to which they jump, in pattern order
+    entries: Vec<(String, u32)>,
+}
+
+impl CaseTable {
+    /// Builds the table value, in the format read by `jump_table_lookup`,
+    /// given the offset of each block.
+    fn build(&self, offsets: &[u32]) -> Value {
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+
+        // The sort is stable, so the first of any duplicate patterns is kept.
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries.dedup_by(|a, b| a.0 == b.0);
+
+        let mut values: Vec<Value> = Vec::with_capacity(1 + entries.len() * 2);
+        values.push(offsets[self.default as usize].into());
+
+        for &&(ref s, block) in &entries {
+            values.push(s[..].into());
+            values.push(offsets[block as usize].into());
+        }
+
+        values.into()
+    }
+}
+
+/// Type of argument accepted by an inlined system function
+#[derive(Copy, Clone)]
+enum ArgType {
+    /// List or `()`
+    List,
+}
+
+impl ArgType {
+    fn accepts(self, v: &Value) -> bool {
+        match (self, v) {
+            (ArgType::List, &Value::Unit) |
+            (ArgType::List, &Value::List(_)) => true,
+            _ => false
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ArgType::List => "list",
+        }
+    }
+}
+
+/// Types of arguments to system functions which are inlined by the compiler.
+/// Contains function name, argument index, and argument type.
+const INLINE_ARG_TYPES: &'static [(Name, usize, ArgType)] = &[
+    (standard_names::APPEND, 0, ArgType::List),
+    (standard_names::FIRST, 0, ArgType::List),
+    (standard_names::INIT, 0, ArgType::List),
+    (standard_names::LAST, 0, ArgType::List),
+    (standard_names::TAIL, 0, ArgType::List),
+];
+
+/// Returns the value produced by a constant expression.
+/// A value quoted once yields the value itself; other constants are unchanged.
+fn const_value(v: &Value) -> &Value {
+    match *v {
+        Value::Quote(ref v, 1) => v,
+        ref v => v
+    }
 }
 
 fn is_constant(v: &Value) -> bool {
@@ -964,42 +1690,126 @@ fn is_constant(v: &Value) -> bool {
 struct Operator {
     arity: Arity,
     callback: OperatorCallback,
+    /// Usage string describing the syntax of the operator
+    usage: &'static str,
 }
 
 type OperatorCallback = fn(&mut Compiler, args: &[Value]) -> Result<(), Error>;
 
 macro_rules! sys_op {
-    ( $callback:ident, $arity:expr ) => {
+    ( $callback:ident, $arity:expr, $usage:expr ) => {
         Operator{
             arity: $arity,
             callback: $callback,
+            usage: $usage,
         }
     }
 }
 
+/// Returns whether the named operator should be used only at top level.
+fn is_top_level_operator(name: Name) -> bool {
+    use name::standard_names::*;
+
+    match name {
+        DEFINE | MACRO | STRUCT | EXPORT | EXPORT_RENAME | USE | DEFVAR |
+            DEFINE_TYPE | DEFINE_MODULE | IMPORT_FOR_SYNTAX |
+            UNLESS_BOUND | DEFINE_IF_UNBOUND | DEFINE_RECORD |
+            DEFINE_OVERLOAD => true,
+        _ => false
+    }
+}
+
 fn get_system_operator(name: Name) -> &'static Operator {
     &SYSTEM_OPERATORS[(name.get() - SYSTEM_OPERATORS_BEGIN) as usize]
 }
 
+/// Returns a usage string describing the syntax of the named operator,
+/// if it is a system operator.
+pub fn operator_usage(name: Name) -> Option<&'static str> {
+    if is_system_operator(name) {
+        Some(get_system_operator(name).usage)
+    } else {
+        None
+    }
+}
+
 /// System operator implementations.
 ///
 /// These must correspond exactly to names `SYSTEM_OPERATORS_BEGIN` to
 /// `SYSTEM_OPERATORS_END` in `name.rs`.
 static SYSTEM_OPERATORS: [Operator; NUM_SYSTEM_OPERATORS] = [
-    sys_op!(op_apply, Min(2)),
-    sys_op!(op_do, Min(1)),
-    sys_op!(op_let, Exact(2)),
-    sys_op!(op_define, Exact(2)),
-    sys_op!(op_macro, Exact(2)),
-    sys_op!(op_struct, Exact(2)),
-    sys_op!(op_if, Range(2, 3)),
-    sys_op!(op_and, Min(1)),
-    sys_op!(op_or, Min(1)),
-    sys_op!(op_case, Min(2)),
-    sys_op!(op_cond, Min(1)),
-    sys_op!(op_lambda, Exact(2)),
-    sys_op!(op_export, Exact(1)),
-    sys_op!(op_use, Min(2)),
+    sys_op!(op_apply, Min(2),
+        "(apply function [ arguments ... ] argument-list)"),
+    sys_op!(op_do, Min(1),
+        "(do expression ...)"),
+    sys_op!(op_let, Exact(2),
+        "(let ( [ ( name expression ) ... ] ) body)"),
+    sys_op!(op_define, Exact(2),
+        "(define name expression) or (define (name [ arguments ... ]) body)"),
+    sys_op!(op_macro, Exact(2),
+        "(macro (name [ arguments ... ]) body)"),
+    sys_op!(op_struct, Exact(2),
+        "(struct name ( [ ( field-name type-name ) ... ] ))"),
+    sys_op!(op_if, Range(2, 3),
+        "(if condition then-expression [ else-expression ])"),
+    sys_op!(op_and, Min(1),
+        "(and expression ...)"),
+    sys_op!(op_or, Min(1),
+        "(or expression ...)"),
+    sys_op!(op_case, Min(2),
+        "(case expression ( ( [ value ... ] ) branch ) ... [ ( else branch ) ])"),
+    sys_op!(op_cond, Min(1),
+        "(cond ( condition branch ) ... [ ( else branch ) ])"),
+    sys_op!(op_lambda, Exact(2),
+        "(lambda ( [ arguments ... ] ) body)"),
+    sys_op!(op_export, Exact(1),
+        "(export ( [ name ... ] ))"),
+    sys_op!(op_use, Min(2),
+        "(use module { :all | :as alias | ( [ name ... ] ) } \
+            [ :macro { :all | ( [ name ... ] ) } ])"),
+    sys_op!(op_defvar, Exact(2),
+        "(defvar name expression)"),
+    sys_op!(op_with_bindings, Min(2),
+        "(with-bindings ( [ ( name expression ) ... ] ) expression ...)"),
+    sys_op!(op_export_rename, Min(1),
+        "(export-rename [ ( name :as new-name ) ... ])"),
+    sys_op!(op_guard, Min(2),
+        "(guard ( name category handler ... ) expression ...)"),
+    sys_op!(op_try, Min(2),
+        "(try expression ( catch category name handler ... ) ...)"),
+    sys_op!(op_apply_kw, Min(2),
+        "(apply/keyword function [ arguments ... ] [ keyword value ... ] argument-list)"),
+    sys_op!(op_case_head, Min(2),
+        "(case-head expression ( ( constant [ name ... ] ) branch ) ... [ ( else branch ) ])"),
+    sys_op!(op_define_type, Min(2),
+        "(define-type name ( variant-name [ field-name ... ] ) ...)"),
+    sys_op!(op_type_case, Min(3),
+        "(type-case ( ( struct-def [ field-name ... ] ) ... ) expression \
+            ( ( struct-name [ name ... ] ) branch ) ... [ ( else branch ) ])"),
+    sys_op!(op_define_module, Min(1),
+        "(define-module name expression ...)"),
+    sys_op!(op_import_for_syntax, Exact(2),
+        "(import-for-syntax module ( [ name ... ] ))"),
+    sys_op!(op_with_output_to_string, Min(0),
+        "(with-output-to-string [ expression ... ])"),
+    sys_op!(op_unless_bound, Exact(2),
+        "(unless-bound name expression)"),
+    sys_op!(op_define_if_unbound, Exact(2),
+        "(define-if-unbound name expression)"),
+    sys_op!(op_with_module, Min(2),
+        "(with-module module expression ...)"),
+    sys_op!(op_let_fn, Exact(2),
+        "(let-fn ( [ ( name ( param ... ) body ) ... ] ) body)"),
+    sys_op!(op_define_record, Exact(2),
+        "(define-record name ( [ field-name | ( field-name type-name ) ... ] ))"),
+    sys_op!(op_while, Min(1),
+        "(while condition [ expression ... ])"),
+    sys_op!(op_for, Min(2),
+        "(for name sequence [ expression ... ])"),
+    sys_op!(op_define_overload, Exact(2),
+        "(define-overload (name [ arguments ... ] ) expression)"),
+    sys_op!(op_let, Exact(2),
+        "(let* ( [ ( name expression ) ... ] ) body)"),
 ];
 
 /// `apply` calls a function or lambda with a series of arguments.
@@ -1025,16 +1835,54 @@ fn op_apply(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     Ok(())
 }
 
+/// `apply/keyword` calls a function or lambda with a series of positional
+/// arguments, keyword-value pairs, and a final list of positional arguments.
+/// Positional arguments end at the first keyword literal; keywords
+/// of following pairs may be any expression yielding a keyword.
+///
+/// ```lisp
+/// (apply/keyword foo 1 :bar 2 key value '(3 4))
+/// ```
+fn op_apply_kw(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let last = args.len() - 1;
+    let n_pos = args[1..last].iter()
+        .take_while(|v| match **v { Value::Keyword(_) => false, _ => true })
+        .count();
+    let n_kw = last - 1 - n_pos;
+
+    if n_kw % 2 != 0 {
+        return Err(From::from(CompileError::SyntaxError(
+            "expected keyword-value pairs")));
+    }
+
+    for arg in &args[..last] {
+        try!(compiler.compile_value(arg));
+        try!(compiler.push_instruction(Instruction::Push));
+    }
+
+    try!(compiler.compile_value(&args[last]));
+    try!(compiler.push_instruction(Instruction::ApplyWithKeywords(
+        n_kw as u32 / 2, n_pos as u32)));
+
+    Ok(())
+}
+
 /// `do` evaluates a series of expressions, yielding the value of the last
 /// expression.
 fn op_do(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let top_level = compiler.top_level;
+
     for arg in args {
+        compiler.top_level = top_level;
         try!(compiler.compile_value(arg));
     }
     Ok(())
 }
 
 /// `let` defines a series of named value bindings.
+/// Each binding is visible to the expressions which follow it,
+/// but not to its own expression; `let-fn` defines recursive functions.
+/// `let*` is an alias for `let`.
 ///
 /// ```lisp
 /// (let ((a (foo))
@@ -1065,6 +1913,12 @@ fn op_let(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
         _ => return Err(From::from(CompileError::SyntaxError("expected list")))
     }
 
+    // With no bindings, there is nothing to remove from the stack
+    // and the body may be compiled in place.
+    if n_vars == 0 {
+        return compiler.compile_value(&args[1]);
+    }
+
     try!(compiler.compile_value(&args[1]));
 
     // Create a new block containing the Skip.
@@ -1078,25 +1932,165 @@ fn op_let(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     Ok(())
 }
 
-/// `define` declares a value binding or function binding in global scope.
+/// `let-fn` defines a series of named local functions.
+/// Each function may call itself and any function defined before it.
 ///
 /// ```lisp
-/// (define foo 123)
-///
-/// (define (bar a) (+ a foo))
+/// (let-fn ((fact (n) (if (= n 0) 1 (* n (fact (- n 1))))))
+///   (fact 10))
 /// ```
-fn op_define(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+fn op_let_fn(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let mut n_vars = 0;
+
     match args[0] {
-        Value::Name(name) => {
-            try!(test_define_name(name));
-            try!(compiler.compile_value(&args[1]));
-            let c = compiler.add_const(Owned(Value::Name(name)));
-            try!(compiler.push_instruction(Instruction::SetDef(c)));
-            Ok(())
+        Value::Unit => (),
+        Value::List(ref li) => {
+            n_vars = li.len() as u32;
+            for v in li.iter() {
+                match *v {
+                    Value::List(ref li) if li.len() == 3 => {
+                        let name = try!(get_name(&li[0]));
+
+                        let params = match li[1] {
+                            Value::Unit => &[][..],
+                            Value::List(ref li) => &li[..],
+                            _ => return Err(From::from(CompileError::SyntaxError(
+                                "expected list of parameters")))
+                        };
+
+                        let (lambda, captures) = try!(make_lambda(
+                            &compiler, Some(name), params, &li[2]));
+
+                        let c = compiler.add_const(Owned(Value::Lambda(lambda)));
+                        try!(compiler.load_lambda(c, &captures));
+                        compiler.push_var(name);
+                        try!(compiler.push_instruction(Instruction::Push));
+                    }
+                    _ => return Err(From::from(CompileError::SyntaxError(
+                        "expected list of 3 elements")))
+                }
+            }
+        }
+        _ => return Err(From::from(CompileError::SyntaxError("expected list")))
+    }
+
+    if n_vars == 0 {
+        return compiler.compile_value(&args[1]);
+    }
+
+    try!(compiler.compile_value(&args[1]));
+
+    let next_block = compiler.new_block();
+    compiler.use_next(next_block);
+
+    try!(compiler.push_instruction(Instruction::Skip(n_vars)));
+    compiler.pop_vars(n_vars);
+
+    Ok(())
+}
+
+/// `while` evaluates a series of expressions for as long as the condition
+/// expression yields a true value, then yields `()`.
+///
+/// ```lisp
+/// (while (has-input)
+///   (process-input))
+/// ```
+fn op_while(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let cond_block = compiler.new_block();
+    let body_block = compiler.new_block();
+    let final_block = compiler.new_block();
+
+    compiler.use_next(cond_block);
+    try!(compiler.compile_value(&args[0]));
+    compiler.current_block().jump_to(JumpInstruction::JumpIfNot, final_block);
+
+    compiler.use_next(body_block);
+    for arg in &args[1..] {
+        try!(compiler.compile_value(arg));
+    }
+    compiler.current_block().jump_to(JumpInstruction::Jump, cond_block);
+
+    compiler.use_next(final_block);
+    try!(compiler.push_instruction(Instruction::Unit));
+    Ok(())
+}
+
+/// `for` evaluates a series of expressions once for each element of a list,
+/// vector, or range, with the element bound to the given name,
+/// then yields `()`.
+///
+/// ```lisp
+/// (for item (get-items)
+///   (process-item item))
+/// ```
+fn op_for(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let name = try!(get_name(&args[0]));
+
+    // The remainder of the sequence is held on the stack between iterations
+    try!(compiler.compile_value(&args[1]));
+    try!(compiler.push_instruction(Instruction::IterStart));
+    let seq_pos = compiler.stack_offset;
+    try!(compiler.push_instruction(Instruction::Push));
+
+    let cond_block = compiler.new_block();
+    let body_block = compiler.new_block();
+    let final_block = compiler.new_block();
+
+    compiler.use_next(cond_block);
+    try!(compiler.push_instruction(Instruction::Load(seq_pos)));
+    compiler.current_block().jump_to(JumpInstruction::JumpIfNull, final_block);
+
+    compiler.use_next(body_block);
+    try!(compiler.push_instruction(Instruction::Load(seq_pos)));
+    try!(compiler.push_instruction(Instruction::IterFirst));
+    compiler.push_var(name);
+    try!(compiler.push_instruction(Instruction::Push));
+
+    for arg in &args[2..] {
+        try!(compiler.compile_value(arg));
+    }
+
+    try!(compiler.push_instruction(Instruction::Skip(1)));
+    compiler.pop_vars(1);
+
+    try!(compiler.push_instruction(Instruction::Load(seq_pos)));
+    try!(compiler.push_instruction(Instruction::IterRest));
+    try!(compiler.push_instruction(Instruction::Store(seq_pos)));
+    compiler.current_block().jump_to(JumpInstruction::Jump, cond_block);
+
+    compiler.use_next(final_block);
+    try!(compiler.push_instruction(Instruction::Skip(1)));
+    try!(compiler.push_instruction(Instruction::Unit));
+    Ok(())
+}
+
+/// `define` declares a value binding or function binding in global scope.
+///
+/// ```lisp
+/// (define foo 123)
+///
+/// (define (bar a) (+ a foo))
+/// ```
+fn op_define(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    match args[0] {
+        Value::Name(name) => {
+            try!(test_define_name(name));
+            try!(compiler.check_define_import(name));
+            try!(compiler.compile_value(&args[1]));
+            let c = compiler.add_const(Owned(Value::Name(name)));
+
+            if compiler.scope.get_dynamic_var(name).is_some() {
+                try!(compiler.push_instruction(Instruction::SetDynamic(c)));
+            } else {
+                try!(compiler.push_instruction(Instruction::SetDef(c)));
+            }
+            Ok(())
         }
         Value::List(ref li) => {
             let name = try!(get_name(&li[0]));
             try!(test_define_name(name));
+            try!(compiler.check_define_import(name));
             let c = compiler.add_const(Owned(Value::Name(name)));
 
             let (lambda, captures) = try!(make_lambda(
@@ -1111,6 +2105,100 @@ fn op_define(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     }
 }
 
+/// `define-overload` declares a function binding in global scope which is
+/// combined with any existing function of the same name accepting
+/// a different number of arguments.
+///
+/// ```lisp
+/// (define (area r) (* r r))
+/// (define-overload (area w h) (* w h))
+/// ```
+fn op_define_overload(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let li = match args[0] {
+        Value::List(ref li) => li,
+        _ => return Err(From::from(CompileError::SyntaxError("expected list")))
+    };
+
+    let name = try!(get_name(&li[0]));
+    try!(test_define_name(name));
+    try!(compiler.check_define_import(name));
+    let c = compiler.add_const(Owned(Value::Name(name)));
+
+    let (lambda, captures) = try!(make_lambda(
+        &compiler, Some(name), &li[1..], &args[1]));
+
+    if !lambda.code.has_fixed_arity() {
+        return Err(From::from(CompileError::InvalidOverload(name)));
+    }
+
+    let code_c = compiler.add_const(Owned(Value::Lambda(lambda)));
+    try!(compiler.load_lambda(code_c, &captures));
+    try!(compiler.push_instruction(Instruction::SetDefOverload(c)));
+    Ok(())
+}
+
+/// `unless-bound` defines a value in global scope, unless a value of
+/// the same name is already defined when the expression is compiled.
+/// Otherwise, the value expression is not evaluated.
+///
+/// ```lisp
+/// (unless-bound max-retries 3)
+/// ```
+fn op_unless_bound(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let name = try!(get_name(&args[0]));
+    try!(test_define_name(name));
+    let c = compiler.add_const(Owned(Value::Name(name)));
+
+    if compiler.scope.contains_value(name) ||
+            compiler.scope.get_dynamic_var(name).is_some() {
+        try!(compiler.push_instruction(Instruction::Const(c)));
+    } else {
+        try!(compiler.compile_value(&args[1]));
+        try!(compiler.push_instruction(Instruction::SetDef(c)));
+    }
+
+    Ok(())
+}
+
+/// `define-if-unbound` defines a value in global scope, unless a value of
+/// the same name is already defined when the expression is evaluated.
+/// Otherwise, the value expression is not evaluated.
+///
+/// ```lisp
+/// (define-if-unbound max-retries 3)
+/// ```
+fn op_define_if_unbound(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let name = try!(get_name(&args[0]));
+    try!(test_define_name(name));
+    let c = compiler.add_const(Owned(Value::Name(name)));
+
+    // A dynamic variable is always bound
+    if compiler.scope.get_dynamic_var(name).is_some() {
+        try!(compiler.push_instruction(Instruction::Const(c)));
+        return Ok(());
+    }
+
+    let define_block = compiler.new_block();
+    let final_block = compiler.new_block();
+
+    try!(compiler.push_instruction(Instruction::Const(c)));
+    try!(compiler.push_instruction(Instruction::Push));
+    try!(compiler.write_call_sys(standard_names::IS_DEFINED.get(), Exact(1), 1));
+    compiler.current_block().jump_to(JumpInstruction::JumpIfNot, define_block);
+
+    let bound_block = compiler.new_block();
+    compiler.use_next(bound_block);
+    try!(compiler.push_instruction(Instruction::Const(c)));
+    compiler.current_block().jump_to(JumpInstruction::Jump, final_block);
+
+    compiler.use_next(define_block);
+    try!(compiler.compile_value(&args[1]));
+    try!(compiler.push_instruction(Instruction::SetDef(c)));
+
+    compiler.use_next(final_block);
+    Ok(())
+}
+
 /// `macro` defines a compile-time macro function in global scope.
 fn op_macro(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     let (name, params) = match args[0] {
@@ -1123,8 +2211,11 @@ fn op_macro(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
 
     try!(test_define_name(name));
 
-    let (lambda, captures) = try!(make_lambda(compiler,
-        Some(name), params, &args[1]));
+    let for_syntax = replace(&mut compiler.for_syntax, true);
+    let r = make_lambda(compiler, Some(name), params, &args[1]);
+    compiler.for_syntax = for_syntax;
+
+    let (lambda, captures) = try!(r);
 
     if !captures.is_empty() {
         return Err(From::from(CompileError::SyntaxError(
@@ -1168,7 +2259,15 @@ fn op_struct(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
         _ => return Err(From::from(CompileError::SyntaxError("expected list")))
     }
 
-    let def = Value::StructDef(Rc::new(StructDef::new(name, fields.into_slice())));
+    let fields = fields.into_slice();
+
+    let def = match compiler.scope.get_value(name) {
+        Some(Value::StructDef(ref old)) if compiler.scope.reuse_struct_defs() &&
+                old.variant_of.is_none() &&
+                old.fields.iter().eq(fields.iter()) =>
+            Value::StructDef(old.clone()),
+        _ => Value::StructDef(Rc::new(StructDef::new(name, fields)))
+    };
 
     let name_c = compiler.add_const(Owned(Value::Name(name)));
     let c = compiler.add_const(Owned(def));
@@ -1177,6 +2276,252 @@ fn op_struct(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     Ok(())
 }
 
+/// `define-type` defines a type consisting of a set of struct variants.
+/// Each variant is given a constructor function and a predicate function.
+/// A predicate for the type itself and a macro, which dispatches on
+/// the variant of a value, are also defined.
+///
+/// ```lisp
+/// (define-type Shape
+///   (Circle radius)
+///   (Rectangle width height))
+///
+/// (shape-case (Circle 1.0)
+///   ((Circle r) (* r r 3.14))
+///   ((Rectangle w h) (* w h)))
+/// ```
+fn op_define_type(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    use name::standard_names::{ANY, CONCAT, DEFINE, DO, IS, MACRO, NEW, REST, TYPE_CASE};
+
+    let type_name = try!(get_name(&args[0]));
+    try!(test_define_name(type_name));
+
+    let value_name = compiler.scope.add_name("value");
+    let mut spec = Vec::with_capacity(args.len() - 1);
+    let mut body = vec![Value::Name(DO)];
+
+    for v in &args[1..] {
+        let li = match *v {
+            Value::List(ref li) => li,
+            _ => return Err(From::from(CompileError::SyntaxError("expected list")))
+        };
+
+        let name = try!(get_name(&li[0]));
+        try!(test_define_name(name));
+
+        let mut field_names = Vec::with_capacity(li.len() - 1);
+        let mut fields = NameMap::new();
+
+        for f in &li[1..] {
+            let (fname, fty) = match *f {
+                Value::Name(fname) => (fname, ANY),
+                Value::List(ref fli) if fli.len() == 2 =>
+                    (try!(get_name(&fli[0])), try!(get_name(&fli[1]))),
+                _ => return Err(From::from(CompileError::SyntaxError(
+                    "expected name or list of 2 elements")))
+            };
+
+            field_names.push(Value::Name(fname));
+            fields.insert(fname, fty);
+        }
+
+        let def = Value::StructDef(Rc::new(
+            StructDef::new_variant(name, fields.into_slice(), type_name)));
+
+        // (define (Name field ...) (new def :field field ...))
+        let mut new = vec![Value::Name(NEW), def.clone()];
+
+        for f in &field_names {
+            if let Value::Name(fname) = *f {
+                new.push(Value::Keyword(fname));
+                new.push(f.clone());
+            }
+        }
+
+        let mut params = vec![Value::Name(name)];
+        params.extend(field_names.iter().cloned());
+
+        body.push(vec![Value::Name(DEFINE), params.into(), new.into()].into());
+
+        // (define (Name? value) (and (is 'struct value) (is-instance def value)))
+        let pred = compiler.scope.with_name(name, |s| format!("{}?", s));
+        let pred = compiler.scope.add_name(&pred);
+        body.push(vec![
+            Value::Name(DEFINE),
+            vec![Value::Name(pred), Value::Name(value_name)].into(),
+            instance_test(def.clone(), value_name),
+        ].into());
+
+        let mut variant = vec![def];
+        variant.extend(field_names);
+        spec.push(Value::from(variant));
+    }
+
+    // (define (Type? value) (is 'Type value))
+    let type_pred = compiler.scope.with_name(type_name, |s| format!("{}?", s));
+    let type_pred = compiler.scope.add_name(&type_pred);
+    body.push(vec![
+        Value::Name(DEFINE),
+        vec![Value::Name(type_pred), Value::Name(value_name)].into(),
+        vec![
+            Value::Name(IS),
+            Value::Quote(Box::new(Value::Name(type_name)), 1),
+            Value::Name(value_name),
+        ].into(),
+    ].into());
+
+    // (macro (name-case :rest args) (concat '(type-case spec) args))
+    let macro_name = compiler.scope.with_name(type_name, case_macro_name);
+    let macro_name = compiler.scope.add_name(&macro_name);
+    let args_name = compiler.scope.add_name("args");
+
+    body.push(vec![
+        Value::Name(MACRO),
+        vec![Value::Name(macro_name), Value::Keyword(REST), Value::Name(args_name)].into(),
+        vec![
+            Value::Name(CONCAT),
+            Value::Quote(Box::new(vec![Value::Name(TYPE_CASE), spec.into()].into()), 1),
+            Value::Name(args_name),
+        ].into(),
+    ].into());
+
+    body.push(Value::Quote(Box::new(Value::Name(type_name)), 1));
+
+    compiler.compile_value(&body.into())
+}
+
+/// `define-record` defines a struct along with a positional constructor,
+/// a predicate, and an accessor function for each field.
+///
+/// ```lisp
+/// (define-record point (x y))
+///
+/// (define p (make-point 1 2))
+/// (point? p)  ; true
+/// (point-x p) ; 1
+/// ```
+fn op_define_record(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    use name::standard_names::{ANY, DEFINE, DO, DOT, NEW, STRUCT};
+
+    let name = try!(get_name(&args[0]));
+    try!(test_define_name(name));
+
+    let mut field_names = Vec::new();
+    let mut fields: Vec<Value> = Vec::new();
+
+    match args[1] {
+        Value::Unit => (),
+        Value::List(ref li) => {
+            for f in li.iter() {
+                let (fname, fty) = match *f {
+                    Value::Name(fname) => (fname, ANY),
+                    Value::List(ref fli) if fli.len() == 2 =>
+                        (try!(get_name(&fli[0])), try!(get_name(&fli[1]))),
+                    _ => return Err(From::from(CompileError::SyntaxError(
+                        "expected name or list of 2 elements")))
+                };
+
+                field_names.push(fname);
+                fields.push(vec![Value::Name(fname), Value::Name(fty)].into());
+            }
+        }
+        _ => return Err(From::from(CompileError::SyntaxError("expected list")))
+    }
+
+    let ctor = compiler.scope.with_name(name, |s| format!("make-{}", s));
+    let ctor = intern_name(compiler.scope, &ctor);
+    try!(test_define_name(ctor));
+
+    let pred = compiler.scope.with_name(name, |s| format!("{}?", s));
+    let pred = intern_name(compiler.scope, &pred);
+    try!(test_define_name(pred));
+
+    let mut accessors = Vec::with_capacity(field_names.len());
+
+    for &fname in &field_names {
+        let acc = compiler.scope.with_name(name,
+            |s| compiler.scope.with_name(fname, |f| format!("{}-{}", s, f)));
+        let acc = intern_name(compiler.scope, &acc);
+        try!(test_define_name(acc));
+        accessors.push(acc);
+    }
+
+    let value_name = compiler.scope.add_name("value");
+
+    // (struct name ((field type) ...))
+    let mut body = vec![
+        Value::Name(DO),
+        vec![Value::Name(STRUCT), Value::Name(name), fields.into()].into(),
+    ];
+
+    // (define (make-name field ...) (new name :field field ...))
+    let mut params = vec![Value::Name(ctor)];
+    let mut new = vec![Value::Name(NEW), Value::Name(name)];
+
+    for &fname in &field_names {
+        params.push(Value::Name(fname));
+        new.push(Value::Keyword(fname));
+        new.push(Value::Name(fname));
+    }
+
+    body.push(vec![Value::Name(DEFINE), params.into(), new.into()].into());
+
+    // (define (name? value) (and (is 'struct value) (is-instance name value)))
+    body.push(vec![
+        Value::Name(DEFINE),
+        vec![Value::Name(pred), Value::Name(value_name)].into(),
+        instance_test(Value::Name(name), value_name),
+    ].into());
+
+    // (define (name-field value) (. value :field))
+    for (&fname, &acc) in field_names.iter().zip(&accessors) {
+        body.push(vec![
+            Value::Name(DEFINE),
+            vec![Value::Name(acc), Value::Name(value_name)].into(),
+            vec![Value::Name(DOT), Value::Name(value_name), Value::Keyword(fname)].into(),
+        ].into());
+    }
+
+    body.push(Value::Quote(Box::new(Value::Name(name)), 1));
+
+    compiler.compile_value(&body.into())
+}
+
+/// Returns the expression `(and (is 'struct value) (is-instance def value))`.
+fn instance_test(def: Value, value: Name) -> Value {
+    use name::standard_names::{AND, IS, IS_INSTANCE, STRUCT};
+
+    vec![
+        Value::Name(AND),
+        vec![
+            Value::Name(IS),
+            Value::Quote(Box::new(Value::Name(STRUCT)), 1),
+            Value::Name(value),
+        ].into(),
+        vec![Value::Name(IS_INSTANCE), def, Value::Name(value)].into(),
+    ].into()
+}
+
+/// Returns the name of the dispatch macro for a type defined by `define-type`;
+/// e.g. `Shape` yields `shape-case` and `BinaryTree` yields `binary-tree-case`.
+fn case_macro_name(type_name: &str) -> String {
+    let mut s = String::with_capacity(type_name.len() + 8);
+
+    for (i, ch) in type_name.chars().enumerate() {
+        if ch.is_uppercase() {
+            if i != 0 {
+                s.push('-');
+            }
+            s.extend(ch.to_lowercase());
+        } else {
+            s.push(ch);
+        }
+    }
+
+    s.push_str("-case");
+    s
+}
+
 /// `if` evaluates a boolean condition expression and chooses a branch based
 /// on the result.
 ///
@@ -1207,9 +2552,10 @@ fn op_if(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     Ok(())
 }
 
-/// `and` evaluates a series of boolean expressions, yielding the logical AND
+/// `and` evaluates a series of expressions, yielding the logical AND
 /// of all expressions. If a `false` value is evaluated, no further expressions
-/// will be evaluated.
+/// will be evaluated and `false` is yielded; otherwise, the value of
+/// the last expression is yielded.
 fn op_and(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     let (last, init) = args.split_last().unwrap();
     let last_block = compiler.new_block();
@@ -1217,7 +2563,7 @@ fn op_and(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     for arg in init {
         try!(compiler.compile_value(arg));
 
-        // The `and` operator expects a boolean value in the value register
+        // The `and` operator expects the evaluated value in the value register
         // after this jump instruction is run. Therefore, we must prevent
         // the compiler from merging it with a previous instruction,
         // which might result in a different value, e.g. () for JumpIfNotNull.
@@ -1233,9 +2579,10 @@ fn op_and(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     Ok(())
 }
 
-/// `and` evaluates a series of boolean expressions, yielding the logical OR
-/// of all expressions. If a `true` value is evaluated, no further expressions
-/// will be evaluated.
+/// `or` evaluates a series of expressions, yielding the logical OR
+/// of all expressions. If a value other than `false` is evaluated, no further
+/// expressions will be evaluated and that value is yielded; otherwise,
+/// the value of the last expression is yielded.
 fn op_or(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     let (last, init) = args.split_last().unwrap();
     let last_block = compiler.new_block();
@@ -1243,7 +2590,7 @@ fn op_or(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     for arg in init {
         try!(compiler.compile_value(arg));
 
-        // The `or` operator expects a boolean value in the value register
+        // The `or` operator expects the evaluated value in the value register
         // after this jump instruction is run. Therefore, we must prevent
         // the compiler from merging it with a previous instruction,
         // which might result in a different value, e.g. () for JumpIfNull.
@@ -1265,6 +2612,9 @@ fn op_or(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
 /// The last branch may use `else` as its pattern to match all values.
 /// If there is not a successful match, the value `()` is yielded.
 ///
+/// When every pattern is a string, string values are matched using a jump
+/// table rather than by comparing the value to each pattern in turn.
+///
 /// ```lisp
 /// (case foo
 ///   ((0 2 4 6 8) 'even)
@@ -1279,9 +2629,27 @@ fn op_case(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     let final_block = compiler.new_block();
     let mut code_blocks = Vec::with_capacity(args.len());
     let mut else_case = false;
+    let mut patterns: Vec<&Value> = Vec::new();
 
     try!(compiler.compile_value(&args[0]));
 
+    // Values other than strings fall through the jump table
+    // and are compared to each pattern, as though it were absent.
+    let mut table = if use_case_table(&args[1..]) {
+        let n = compiler.consts.len() as u32;
+        // Placeholder; the table is built by write_jumps
+        compiler.consts.push(Value::Unbound);
+        try!(compiler.push_instruction(Instruction::JumpTable(n)));
+
+        Some(CaseTable{
+            konst: n,
+            default: 0,
+            entries: Vec::new(),
+        })
+    } else {
+        None
+    };
+
     for case in &args[1..] {
         if else_case {
             return Err(From::from(CompileError::SyntaxError("unreachable case")));
@@ -1301,6 +2669,16 @@ fn op_case(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
         match *pat {
             Value::List(ref li) => {
                 for v in li.iter() {
+                    if patterns.iter().any(|p| p.is_identical(v)) {
+                        try!(compiler.warn(
+                            CompileError::DuplicateCasePattern(v.clone())));
+                    }
+                    patterns.push(v);
+
+                    if let (Some(ref mut table), &Value::String(ref s)) = (table.as_mut(), v) {
+                        table.entries.push((s.clone(), code_begin));
+                    }
+
                     match *v {
                         Value::Unit => compiler.current_block().jump_to(
                             JumpInstruction::JumpIfNull, code_begin),
@@ -1321,6 +2699,10 @@ fn op_case(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
             Value::Name(standard_names::ELSE) => {
                 else_case = true;
                 compiler.current_block().jump_to(JumpInstruction::Jump, code_begin);
+
+                if let Some(ref mut table) = table {
+                    table.default = code_begin;
+                }
             }
             _ => return Err(From::from(CompileError::SyntaxError(
                 "expected list or `else`")))
@@ -1339,17 +2721,263 @@ fn op_case(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     }
 
     if !else_case {
+        if let Some(ref mut table) = table {
+            table.default = compiler.cur_block as u32;
+        }
+
         try!(compiler.push_instruction(Instruction::Unit));
         compiler.current_block().jump_to(JumpInstruction::Jump, final_block);
     }
 
+    if let Some(table) = table {
+        compiler.jump_tables.push(table);
+    }
+
     for (begin, end) in code_blocks {
         compiler.current_block().set_next(begin);
         compiler.use_block(end);
     }
 
-    compiler.use_next(final_block);
-    Ok(())
+    compiler.use_next(final_block);
+    Ok(())
+}
+
+/// Minimum number of string patterns for which a `case` expression
+/// is compiled using a jump table
+const MIN_CASE_TABLE: usize = 4;
+
+/// Returns whether a `case` expression with the given branches should be
+/// compiled using a jump table; that is, whether every pattern is a string
+/// and there are at least `MIN_CASE_TABLE` of them.
+fn use_case_table(cases: &[Value]) -> bool {
+    let mut n = 0;
+
+    for case in cases {
+        match *case {
+            Value::List(ref li) if li.len() == 2 => match li[0] {
+                Value::List(ref pats) => {
+                    if !pats.iter().all(|v| match *v {
+                            Value::String(_) => true,
+                            _ => false
+                        }) {
+                        return false;
+                    }
+                    n += pats.len();
+                }
+                Value::Name(standard_names::ELSE) => (),
+                _ => return false
+            },
+            _ => return false
+        }
+    }
+
+    n >= MIN_CASE_TABLE
+}
+
+/// `case-head` evaluates an expression yielding a list and selects a branch
+/// by comparing the first element of the list to the constant at the head
+/// of each pattern. Remaining elements of the list are bound to the names
+/// following the constant. If the number of remaining elements does not
+/// match the number of names, an error is raised.
+///
+/// The last branch may use `else` as its pattern to match all values.
+/// If there is not a successful match, the value `()` is yielded.
+///
+/// ```lisp
+/// (case-head msg
+///   ((:add a b) (+ a b))
+///   ((:neg a)   (- a))
+///   (else       'unknown))
+/// ```
+fn op_case_head(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let final_block = compiler.new_block();
+    let mut code_blocks = Vec::with_capacity(args.len());
+    let mut else_case = false;
+
+    // The list is held on the stack while its head is compared to each pattern
+    try!(compiler.compile_value(&args[0]));
+    let list_pos = compiler.stack_offset;
+    try!(compiler.push_instruction(Instruction::Push));
+    try!(compiler.push_instruction(Instruction::Load(list_pos)));
+    try!(compiler.push_instruction(Instruction::First));
+
+    for case in &args[1..] {
+        if else_case {
+            return Err(From::from(CompileError::SyntaxError("unreachable case")));
+        }
+
+        let li = match *case {
+            Value::List(ref li) if li.len() == 2 => li,
+            _ => return Err(From::from(CompileError::SyntaxError(
+                "expected list of 2 elements")))
+        };
+
+        let pat = &li[0];
+        let code = &li[1];
+
+        let code_begin = compiler.new_block();
+
+        let bindings = match *pat {
+            Value::List(ref li) => {
+                let c = compiler.add_const(Borrowed(&li[0]));
+                compiler.current_block().jump_to(
+                    JumpInstruction::JumpIfEqConst(c), code_begin);
+                let b = compiler.new_block();
+                compiler.use_next(b);
+
+                let mut names = Vec::with_capacity(li.len() - 1);
+
+                for v in &li[1..] {
+                    names.push(try!(get_name(v)));
+                }
+
+                Some((&li[0], names))
+            }
+            Value::Name(standard_names::ELSE) => {
+                else_case = true;
+                compiler.current_block().jump_to(JumpInstruction::Jump, code_begin);
+                None
+            }
+            _ => return Err(From::from(CompileError::SyntaxError(
+                "expected list or `else`")))
+        };
+
+        let prev_block = compiler.cur_block as u32;
+        compiler.use_block(code_begin);
+
+        match bindings {
+            Some((head, names)) => {
+                try!(compiler.compile_case_head_bindings(list_pos, head, &names));
+                try!(compiler.compile_value(code));
+
+                if !names.is_empty() {
+                    let skip_block = compiler.new_block();
+                    compiler.use_next(skip_block);
+                    try!(compiler.push_instruction(Instruction::Skip(names.len() as u32)));
+                    compiler.pop_vars(names.len() as u32);
+                }
+            }
+            None => try!(compiler.compile_value(code))
+        }
+
+        compiler.current_block().jump_to(JumpInstruction::Jump, final_block);
+        let code_end = compiler.cur_block as u32;
+        code_blocks.push((code_begin, code_end));
+
+        let b = compiler.new_block();
+        compiler.use_block(prev_block);
+        compiler.use_next(b);
+    }
+
+    if !else_case {
+        try!(compiler.push_instruction(Instruction::Unit));
+        compiler.current_block().jump_to(JumpInstruction::Jump, final_block);
+    }
+
+    for (begin, end) in code_blocks {
+        compiler.current_block().set_next(begin);
+        compiler.use_block(end);
+    }
+
+    compiler.use_next(final_block);
+    try!(compiler.push_instruction(Instruction::Skip(1)));
+    Ok(())
+}
+
+/// `type-case` dispatches on the struct definition of a value.
+/// It is not usually written directly; instead, `define-type` defines
+/// a macro for each type which supplies the list of variants and their fields.
+///
+/// ```lisp
+/// (struct Circle ((radius number)))
+/// (struct Rectangle ((width number) (height number)))
+///
+/// (type-case ((Circle radius) (Rectangle width height)) shape
+///   ((Circle r) (* r r 3.14))
+///   (else 0))
+/// ```
+fn op_type_case(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    use name::standard_names::{COND, DOT, ELSE, LET};
+
+    let variants = match args[0] {
+        Value::List(ref li) => li,
+        _ => return Err(From::from(CompileError::SyntaxError("expected list")))
+    };
+
+    // The value is bound to a name which cannot be written in source code
+    let value_name = compiler.scope.add_name("#type-case-value");
+    let mut cases = vec![Value::Name(COND)];
+
+    for case in &args[2..] {
+        let li = match *case {
+            Value::List(ref li) if li.len() == 2 => li,
+            _ => return Err(From::from(CompileError::SyntaxError(
+                "expected list of 2 elements")))
+        };
+
+        let pat = match li[0] {
+            Value::List(ref pat) => pat,
+            Value::Name(ELSE) => {
+                cases.push(case.clone());
+                continue;
+            }
+            _ => return Err(From::from(CompileError::SyntaxError(
+                "expected list or `else`")))
+        };
+
+        let name = try!(get_name(&pat[0]));
+
+        let variant = variants.iter().filter_map(|v| match *v {
+            Value::List(ref v) => match v[0] {
+                Value::Name(n) if n == name => Some(v),
+                Value::StructDef(ref def) if def.name == name => Some(v),
+                _ => None
+            },
+            _ => None
+        }).next();
+
+        let (def, fields) = match variant {
+            Some(v) => (&v[0], &v[1..]),
+            None => return Err(From::from(CompileError::SyntaxError(
+                "pattern does not name a variant of the type")))
+        };
+
+        if pat.len() - 1 != fields.len() {
+            return Err(From::from(CompileError::SyntaxError(
+                "pattern must name each field of the variant")));
+        }
+
+        // (test (let ((name (. value :field)) ...) code))
+        let test = instance_test(def.clone(), value_name);
+
+        let mut bindings = Vec::with_capacity(fields.len());
+
+        for (bind, field) in pat[1..].iter().zip(fields) {
+            let field = try!(get_name(field));
+
+            bindings.push(Value::from(vec![
+                Value::Name(try!(get_name(bind))),
+                vec![Value::Name(DOT), Value::Name(value_name), Value::Keyword(field)].into(),
+            ]));
+        }
+
+        let code = if bindings.is_empty() {
+            li[1].clone()
+        } else {
+            vec![Value::Name(LET), bindings.into(), li[1].clone()].into()
+        };
+
+        cases.push(vec![test, code].into());
+    }
+
+    // (let ((value expr)) (cond ...))
+    let expr = vec![
+        Value::Name(LET),
+        vec![Value::from(vec![Value::Name(value_name), args[1].clone()])].into(),
+        cases.into(),
+    ];
+
+    compiler.compile_value(&expr.into())
 }
 
 /// `cond` evaluates a series of boolean expressions and chooses the branch
@@ -1464,7 +3092,13 @@ fn op_export(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     let mut names = NameSet::new();
 
     for v in li {
-        names.insert(try!(get_name(v)));
+        let name = try!(get_name(v));
+
+        if compiler.scope.is_exported(name) {
+            return Err(From::from(CompileError::DuplicateExport(name)));
+        }
+
+        names.insert(name);
     }
 
     compiler.scope.set_exports(names.into_slice());
@@ -1473,6 +3107,44 @@ fn op_export(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     Ok(())
 }
 
+/// `export-rename` exports a series of names from a module's global scope,
+/// each under a different name. It may be used in addition to `export`.
+///
+/// ```lisp
+/// (export-rename (internal-helper :as helper))
+/// ```
+fn op_export_rename(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    if compiler.scope.with_export_renames(|r| r.is_some()) {
+        return Err(From::from(CompileError::DuplicateExports));
+    }
+
+    let mut renames = NameMap::new();
+
+    for arg in args {
+        let (src, dest) = match *arg {
+            Value::List(ref li) if li.len() == 3 => {
+                match (&li[0], &li[1], &li[2]) {
+                    (&Value::Name(src), &Value::Keyword(standard_names::AS),
+                            &Value::Name(dest)) => (src, dest),
+                    _ => return Err(From::from(CompileError::SyntaxError(
+                        "expected `(name :as new-name)`")))
+                }
+            }
+            _ => return Err(From::from(CompileError::SyntaxError(
+                "expected `(name :as new-name)`")))
+        };
+
+        if compiler.scope.is_exported(dest) || renames.insert(dest, src).is_some() {
+            return Err(From::from(CompileError::DuplicateExport(dest)));
+        }
+    }
+
+    compiler.scope.set_export_renames(renames.into_slice());
+
+    try!(compiler.push_instruction(Instruction::Unit));
+    Ok(())
+}
+
 /// `use` imports a series of names from a module.
 ///
 /// ```lisp
@@ -1480,25 +3152,71 @@ fn op_export(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
 ///
 /// (use foo (alpha beta)
 ///          :macro (gamma))
+///
+/// ; Loads the module for its side effects, importing nothing
+/// (use foo ())
+///
+/// ; Values are found within the module each time they are used
+/// (use foo :ref (alpha beta))
+///
+/// ; Values are referred to by qualified names, e.g. `f:alpha`
+/// (use foo :as f)
 /// ```
 fn op_use(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     let mod_name = try!(get_name(&args[0]));
-    let mods = compiler.scope.get_modules();
-    let m = try!(mods.get_module(mod_name, compiler.scope));
+    let m = try!(get_module(compiler.scope, mod_name));
 
-    match args[1] {
-        Value::Keyword(standard_names::ALL) => {
-            m.scope.import_all_values(compiler.scope);
-        }
-        Value::Unit => (),
-        Value::List(ref li) => {
-            try!(import_values(mod_name, compiler.scope, &m.scope, li));
+    let mut imports = ImportSet::new(mod_name);
+    let mut args = &args[1..];
+
+    if let Value::Keyword(standard_names::REF) = args[0] {
+        let li = match args.get(1) {
+            Some(&Value::List(ref li)) => li,
+            _ => return Err(From::from(CompileError::SyntaxError(
+                "expected list of names after `:ref`")))
+        };
+
+        try!(each_import(li, |src, dest| {
+            try!(check_import(mod_name, &m.scope, src));
+            imports.names.push((src, dest));
+            Ok(())
+        }));
+
+        imports.by_ref = true;
+        args = &args[1..];
+    } else if let Value::Keyword(standard_names::AS) = args[0] {
+        imports.alias = match args.get(1) {
+            Some(&Value::Name(alias)) => Some(alias),
+            _ => return Err(From::from(CompileError::SyntaxError(
+                "expected name after `:as`")))
+        };
+
+        args = &args[1..];
+    } else {
+        match args[0] {
+            Value::Keyword(standard_names::ALL) => {
+                m.scope.import_all_values(compiler.scope);
+                imports.all = true;
+            }
+            // The module is loaded and initialized, but no values are imported
+            Value::Unit => (),
+            Value::List(ref li) => {
+                try!(import_values(mod_name, compiler.scope, &m.scope, li));
+                try!(each_import(li, |src, dest| {
+                    imports.names.push((src, dest));
+                    Ok(())
+                }));
+            }
+            _ => return Err(From::from(CompileError::SyntaxError(
+                "expected list of names, `:all`, or `:ref`")))
         }
-        _ => return Err(From::from(CompileError::SyntaxError(
-            "expected list of names or `:all`")))
     }
 
-    let mut iter = args[2..].iter();
+    // Imports are recorded so that they may be repeated
+    // when compiled code is loaded.
+    compiler.scope.add_imports(imports);
+
+    let mut iter = args[1..].iter();
 
     while let Some(arg) = iter.next() {
         match *arg {
@@ -1522,10 +3240,356 @@ fn op_use(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
     Ok(())
 }
 
+/// `define-module` defines a module from a series of expressions,
+/// as though they were the contents of a module file. The module is
+/// compiled and executed in its own scope and may then be imported
+/// with `use`. Definitions within the module are not visible
+/// in the enclosing scope.
+///
+/// As with `use`, the module is defined when the expression is compiled.
+/// Because the module exists only in the scope which compiled it,
+/// code containing `define-module` cannot be encoded.
+///
+/// ```lisp
+/// (define-module shapes
+///   (export (area))
+///   (define pi 3.14159)
+///   (define (area r) (* pi r r)))
+///
+/// (use shapes (area))
+/// ```
+fn op_define_module(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let mod_name = try!(get_name(&args[0]));
+
+    let scope = GlobalScope::new_using(compiler.scope);
+    scope.set_module_name(mod_name);
+
+    for arg in &args[1..] {
+        let code = try!(compile(&scope, arg));
+
+        if !scope.is_compile_only() {
+            try!(execute(&scope, Rc::new(code)));
+        }
+    }
+
+    try!(check_exports(&scope, mod_name));
+
+    compiler.scope.get_modules().add_module(mod_name, Module{
+        name: mod_name,
+        scope: scope,
+    });
+
+    let c = compiler.add_const(Owned(Value::Name(mod_name)));
+    try!(compiler.push_instruction(Instruction::DefineModule(c)));
+    Ok(())
+}
+
+/// `with-module` evaluates a series of expressions within the global scope
+/// of a module, yielding the value of the last expression. The module is
+/// loaded, if necessary, when the expression is compiled. Definitions made
+/// within the body are made in the module scope.
+///
+/// Expressions are compiled in the module scope. When the code is executed,
+/// each is bound to the module scope, loading the module if necessary,
+/// and called as a lambda.
+///
+/// ```lisp
+/// (with-module config
+///   (define threshold 20))
+/// ```
+fn op_with_module(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let mod_name = try!(get_name(&args[0]));
+    let m = try!(get_module(compiler.scope, mod_name));
+    let name_c = compiler.add_const(Owned(Value::Name(mod_name)));
+
+    for arg in &args[1..] {
+        let code = try!(compile(&m.scope, arg));
+        let lambda = Lambda::new(Rc::new(code), &m.scope);
+
+        let c = compiler.add_const(Owned(Value::Lambda(lambda)));
+        try!(compiler.push_instruction(Instruction::Const(c)));
+        try!(compiler.push_instruction(Instruction::WithModule(name_c)));
+        try!(compiler.push_instruction(Instruction::Push));
+        try!(compiler.push_instruction(Instruction::Call(0)));
+    }
+
+    Ok(())
+}
+
+/// `import-for-syntax` loads a module and makes a series of its values
+/// available to macros defined in the current scope. The imported names
+/// are visible only within macro bodies; other code cannot refer to them.
+///
+/// ```lisp
+/// (import-for-syntax helpers (make-getter))
+///
+/// (macro (getter name) (make-getter name))
+/// ```
+fn op_import_for_syntax(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let mod_name = try!(get_name(&args[0]));
+    let m = try!(get_module(compiler.scope, mod_name));
+
+    let li = match args[1] {
+        Value::Unit => &[][..],
+        Value::List(ref li) => &li[..],
+        _ => return Err(From::from(CompileError::SyntaxError(
+            "expected list of names in `import-for-syntax`")))
+    };
+
+    let mut imports = ImportSet::new(mod_name);
+
+    try!(each_import(li, |src, dest| {
+        try!(check_import(mod_name, &m.scope, src));
+        imports.names.push((src, dest));
+        Ok(())
+    }));
+
+    imports.for_syntax = true;
+    compiler.scope.add_imports(imports);
+
+    try!(compiler.push_instruction(Instruction::Unit));
+    Ok(())
+}
+
+/// `defvar` declares a dynamic variable and assigns its global value.
+/// Dynamic variables may be given new values for the duration of an
+/// expression using `with-bindings`.
+///
+/// ```lisp
+/// (defvar *indent* 0)
+/// ```
+fn op_defvar(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let name = try!(get_name(&args[0]));
+    try!(test_define_name(name));
+
+    // Subsequent references to the name within the same compilation
+    // must be compiled as dynamic lookups.
+    if compiler.scope.get_dynamic_var(name).is_none() {
+        compiler.scope.add_dynamic(name, name);
+    }
+
+    try!(compiler.compile_value(&args[1]));
+    let c = compiler.add_const(Owned(Value::Name(name)));
+    try!(compiler.push_instruction(Instruction::SetDynamic(c)));
+    Ok(())
+}
+
+/// `with-bindings` binds new values to a series of dynamic variables
+/// while evaluating a series of expressions, yielding the value of the
+/// last expression. Previous values are restored when evaluation ends,
+/// whether or not an error was raised.
+///
+/// ```lisp
+/// (with-bindings ((*indent* (+ *indent* 2)))
+///   (print-tree tree))
+/// ```
+fn op_with_bindings(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let mut n_vars = 0;
+
+    match args[0] {
+        Value::Unit => (),
+        Value::List(ref li) => {
+            n_vars = li.len() as u32;
+            for v in li.iter() {
+                match *v {
+                    Value::List(ref li) if li.len() == 2 => {
+                        let name = try!(get_name(&li[0]));
+                        let var = try!(compiler.scope.get_dynamic_var(name)
+                            .ok_or(CompileError::NotDynamic(name)));
+
+                        try!(compiler.compile_value(&li[1]));
+                        let c = compiler.add_const(Owned(Value::Name(var)));
+                        try!(compiler.push_instruction(Instruction::BindDynamic(c)));
+                    }
+                    _ => return Err(From::from(CompileError::SyntaxError(
+                        "expected list of 2 elements")))
+                }
+            }
+        }
+        _ => return Err(From::from(CompileError::SyntaxError("expected list")))
+    }
+
+    for arg in &args[1..] {
+        try!(compiler.compile_value(arg));
+    }
+
+    if n_vars != 0 {
+        try!(compiler.push_instruction(Instruction::UnbindDynamic(n_vars)));
+    }
+
+    Ok(())
+}
+
+/// `with-output-to-string` evaluates a series of expressions, capturing
+/// any output written to `stdout`, and yields the captured output as a string.
+/// Output is no longer captured when evaluation ends, whether or not
+/// an error was raised.
+///
+/// ```lisp
+/// (with-output-to-string
+///   (println "a")
+///   (println "b"))
+/// ```
+fn op_with_output_to_string(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    try!(compiler.push_instruction(Instruction::BeginCapture));
+
+    for arg in args {
+        try!(compiler.compile_value(arg));
+    }
+
+    try!(compiler.push_instruction(Instruction::EndCapture));
+    Ok(())
+}
+
+/// `guard` evaluates a series of expressions, yielding the value of the
+/// last expression. If an error of the given category is raised, the error
+/// condition is bound to the given name and the handler expressions are
+/// evaluated instead, yielding the value of the last handler expression.
+///
+/// The category `:error` catches any error.
+///
+/// ```lisp
+/// (guard (e :error (error-message e))
+///   (error "bad value" 42))
+/// ```
+fn op_guard(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let (name, kind, handler) = match args[0] {
+        Value::List(ref li) if li.len() >= 3 => {
+            let name = try!(get_name(&li[0]));
+            let kind = try!(get_error_category(compiler, &li[1]));
+            (name, kind, &li[2..])
+        }
+        _ => return Err(From::from(CompileError::SyntaxError(
+            "expected list of name, error category, and handler expressions")))
+    };
+
+    let body_block = compiler.new_block();
+    let handler_block = compiler.new_block();
+    let final_block = compiler.new_block();
+
+    let c = compiler.add_const(Owned(Value::Keyword(kind)));
+    compiler.current_block().jump_to(JumpInstruction::PushHandler(c), handler_block);
+
+    compiler.use_next(body_block);
+
+    for arg in &args[1..] {
+        try!(compiler.compile_value(arg));
+    }
+
+    try!(compiler.push_instruction(Instruction::PopHandler(1)));
+    compiler.current_block().jump_to(JumpInstruction::Jump, final_block);
+
+    compiler.use_next(handler_block);
+    try!(compile_handler(compiler, name, handler));
+
+    compiler.use_next(final_block);
+    Ok(())
+}
+
+/// `try` evaluates an expression, yielding its value. If an error is raised,
+/// the first `catch` clause whose category matches the error binds the
+/// error condition to the given name and its handler expressions are
+/// evaluated instead, yielding the value of the last handler expression.
+///
+/// The category `:error` or `else` catches any error.
+///
+/// ```lisp
+/// (try (/ 1 0)
+///   (catch :arith e 0)
+///   (catch else e (error-message e)))
+/// ```
+fn op_try(compiler: &mut Compiler, args: &[Value]) -> Result<(), Error> {
+    let expr = &args[0];
+    let mut clauses = Vec::with_capacity(args.len() - 1);
+
+    for arg in &args[1..] {
+        match *arg {
+            Value::List(ref li) if li.len() >= 4 &&
+                    li[0].is_identical(&Value::Name(standard_names::CATCH)) => {
+                let kind = match li[1] {
+                    Value::Name(standard_names::ELSE) => standard_names::ERROR,
+                    ref v => try!(get_error_category(compiler, v))
+                };
+                let name = try!(get_name(&li[2]));
+                clauses.push((kind, name, &li[3..]));
+            }
+            _ => return Err(From::from(CompileError::SyntaxError(
+                "expected `(catch category name handler ...)`")))
+        }
+    }
+
+    let n_clauses = clauses.len() as u32;
+    let handler_blocks = (0..clauses.len())
+        .map(|_| compiler.new_block()).collect::<Vec<_>>();
+    let final_block = compiler.new_block();
+
+    // Handlers are installed in reverse order,
+    // so that the first clause is the first to receive an error.
+    for (&(kind, _, _), &block) in clauses.iter().zip(&handler_blocks).rev() {
+        let c = compiler.add_const(Owned(Value::Keyword(kind)));
+        compiler.current_block().jump_to(JumpInstruction::PushHandler(c), block);
+
+        let next = compiler.new_block();
+        compiler.use_next(next);
+    }
+
+    try!(compiler.compile_value(expr));
+    try!(compiler.push_instruction(Instruction::PopHandler(n_clauses)));
+    compiler.current_block().jump_to(JumpInstruction::Jump, final_block);
+
+    for (i, (&(_, name, handler), &block)) in clauses.iter()
+            .zip(&handler_blocks).enumerate() {
+        compiler.use_next(block);
+
+        // Handlers installed for later clauses have not been removed
+        let n_remaining = n_clauses - 1 - i as u32;
+
+        if n_remaining != 0 {
+            try!(compiler.push_instruction(Instruction::PopHandler(n_remaining)));
+        }
+
+        try!(compile_handler(compiler, name, handler));
+
+        if n_remaining != 0 {
+            compiler.current_block().jump_to(JumpInstruction::Jump, final_block);
+        }
+    }
+
+    compiler.use_next(final_block);
+    Ok(())
+}
+
+/// Compiles handler expressions for `guard` or `try`.
+/// The handler begins with the error condition in the value register.
+fn compile_handler(compiler: &mut Compiler, name: Name, handler: &[Value])
+        -> Result<(), Error> {
+    compiler.push_var(name);
+    try!(compiler.push_instruction(Instruction::Push));
+
+    for v in handler {
+        try!(compiler.compile_value(v));
+    }
+
+    try!(compiler.push_instruction(Instruction::Skip(1)));
+    compiler.pop_vars(1);
+    Ok(())
+}
+
+fn get_error_category(compiler: &Compiler, v: &Value) -> Result<Name, Error> {
+    match *v {
+        Value::Keyword(name) if is_error_category(
+                &compiler.scope.borrow_names(), name) => Ok(name),
+        _ => Err(From::from(CompileError::SyntaxError(
+            "expected error category keyword")))
+    }
+}
+
 fn import_macros(mod_name: Name, a: &GlobalScope, b: &GlobalScope,
         names: &[Value]) -> Result<(), CompileError> {
     each_import(names, |src, dest| {
-        match b.get_macro(src) {
+        let name = b.get_export(src).unwrap_or(src);
+
+        match b.get_macro(name) {
             Some(v) => {
                 if !b.is_exported(src) {
                     return Err(CompileError::PrivacyError{
@@ -1549,27 +3613,69 @@ fn import_macros(mod_name: Name, a: &GlobalScope, b: &GlobalScope,
 fn import_values(mod_name: Name, a: &GlobalScope, b: &GlobalScope,
         names: &[Value]) -> Result<(), CompileError> {
     each_import(names, |src, dest| {
-        match b.get_value(src) {
-            Some(v) => {
-                if !b.is_exported(src) {
-                    return Err(CompileError::PrivacyError{
-                        module: mod_name,
-                        name: src,
-                    });
-                }
+        let name = try!(check_import(mod_name, b, src));
 
-                a.add_value(dest, v);
-            }
-            None => return Err(CompileError::ImportError{
-                module: mod_name,
-                name: src,
-            })
+        if let Some(v) = b.get_value(name) {
+            a.add_value(dest, v);
+        }
+
+        if let Some(var) = b.get_dynamic_var(name) {
+            a.add_dynamic(dest, var);
         }
 
         Ok(())
     })
 }
 
+/// Checks that a module defines and exports a value of the given name.
+/// Returns the name under which the value is defined within the module.
+///
+/// The code of a compile-only module is not executed, so only the export
+/// is checked.
+fn check_import(mod_name: Name, b: &GlobalScope, src: Name) -> Result<Name, CompileError> {
+    let name = b.get_export(src).unwrap_or(src);
+
+    if b.get_value(name).is_none() && !b.is_compile_only() {
+        Err(CompileError::ImportError{
+            module: mod_name,
+            name: src,
+        })
+    } else if !b.is_exported(src) {
+        Err(CompileError::PrivacyError{
+            module: mod_name,
+            name: src,
+        })
+    } else {
+        Ok(name)
+    }
+}
+
+/// Returns the module and exported name of a value imported by reference.
+/// If `for_syntax` is `true`, values imported by `import-for-syntax`
+/// are also considered.
+fn get_ref_import(scope: &GlobalScope, name: Name, for_syntax: bool)
+        -> Option<(Name, Name)> {
+    scope.with_imports(|imports| imports.iter().rev()
+        .filter(|imp| imp.by_ref || (for_syntax && imp.for_syntax))
+        .filter_map(|imp| imp.names.iter()
+            .find(|&&(_, dest)| dest == name)
+            .map(|&(src, _)| (imp.module_name, src)))
+        .next())
+}
+
+/// Returns the name of the module from which a value of the given name
+/// was imported, if any.
+fn get_import_origin(scope: &Scope, name: Name) -> Option<Name> {
+    let mods = scope.get_modules();
+
+    scope.with_imports(|imports| imports.iter().rev()
+        .filter(|imp| !imp.for_syntax)
+        .find(|imp| imp.names.iter().any(|&(_, dest)| dest == name) ||
+            (imp.all && mods.get_module(imp.module_name, scope)
+                .map(|m| m.scope.is_exported(name)).unwrap_or(false)))
+        .map(|imp| imp.module_name))
+}
+
 fn each_import<F>(items: &[Value], mut f: F) -> Result<(), CompileError>
         where F: FnMut(Name, Name) -> Result<(), CompileError> {
     let mut iter = items.iter();
@@ -1592,6 +3698,13 @@ fn each_import<F>(items: &[Value], mut f: F) -> Result<(), CompileError>
     Ok(())
 }
 
+/// Returns a module, loading it if necessary, if the import policy
+/// of the scope permits it.
+fn get_module(scope: &Scope, name: Name) -> Result<Module, Error> {
+    try!(scope.check_import_policy(name));
+    scope.get_modules().get_module(name, scope)
+}
+
 fn get_name(v: &Value) -> Result<Name, CompileError> {
     match *v {
         Value::Name(name) => Ok(name),
@@ -1624,8 +3737,8 @@ fn make_lambda(compiler: &Compiler, name: Option<Name>,
     let mut iter = args.iter();
 
     while let Some(v) = iter.next() {
-        let (name, default) = match *v {
-            Value::Name(name) => (name, None),
+        let (name, default, supplied) = match *v {
+            Value::Name(name) => (name, None, None),
             Value::Keyword(kw) => {
                 match kw {
                     standard_names::KEY => {
@@ -1677,23 +3790,40 @@ fn make_lambda(compiler: &Compiler, name: Option<Name>,
             }
             Value::List(ref li) if li.len() == 2 => {
                 let name = try!(get_name(&li[0]));
-                (name, Some(li[1].clone()))
+                (name, Some(li[1].clone()), None)
+            }
+            Value::List(ref li) if li.len() == 3 => {
+                if !(key || optional) {
+                    return Err(From::from(CompileError::SyntaxError(
+                        "supplied parameter is only valid for \
+                         `:key` and `:optional` parameters")));
+                }
+
+                let name = try!(get_name(&li[0]));
+                let supplied = try!(get_name(&li[2]));
+                (name, Some(li[1].clone()), Some(supplied))
             }
             _ => return Err(From::from(CompileError::SyntaxError(
-                "expected name, keyword, or list of 2 elements")))
+                "expected name, keyword, or list of 2 or 3 elements")))
         };
 
-        let exists = params.iter().any(|&(n, _)| n == name) ||
-            kw_params.iter().any(|&(n, _)| n == name);
-
-        if exists {
+        if supplied == Some(name) {
             return Err(From::from(CompileError::DuplicateParameter(name)));
         }
 
+        for name in Some(name).into_iter().chain(supplied) {
+            let exists = params.iter().chain(&kw_params)
+                .any(|&(n, _, s)| n == name || s == Some(name));
+
+            if exists {
+                return Err(From::from(CompileError::DuplicateParameter(name)));
+            }
+        }
+
         if key {
-            kw_params.push((name, default));
+            kw_params.push((name, default, supplied));
         } else {
-            params.push((name, default));
+            params.push((name, default, supplied));
         }
     }
 
@@ -0,0 +1,287 @@
+//! Implements error values which may be raised and caught by ketos code.
+//!
+//! Each error caught by a `guard` or `try` expression is represented as a
+//! `Condition`, which describes the error with a category, a specific kind,
+//! a message, and a list of values, called irritants. Handlers may catch
+//! errors of a single category:
+//!
+//! | Category    | Errors                                                      |
+//! | ----------- | ----------------------------------------------------------- |
+//! | `:arith`    | Division by zero, overflow, `NaN` comparison or result      |
+//! | `:type`     | Values of an unexpected type                                |
+//! | `:index`    | Out of bounds list and string indices                       |
+//! | `:io`       | File I/O errors                                             |
+//! | `:custom`   | Errors raised by `error` and `panic`                        |
+//! | `:name`     | Undefined or invalid names                                  |
+//! | `:value`    | Invalid arguments, such as incorrect argument counts        |
+//! | `:compile`  | Errors in parsing or compiling code                         |
+//! | `:encode`   | Errors in reading or writing compiled bytecode              |
+//! | `:internal` | Invalid bytecode and exceeded interpreter limits            |
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::io;
+
+use error::Error;
+use exec::ExecError;
+use name::{debug_names, display_names, Name, NameStore};
+use name::standard_names::ERROR;
+use value::{ForeignValue, Value};
+
+/// Names of all error categories
+pub const ERROR_CATEGORIES: &'static [&'static str] = &[
+    "arith", "type", "index", "io", "custom",
+    "name", "value", "compile", "encode", "internal",
+];
+
+/// Describes an error raised by the `error` function or received
+/// by the handler of a `guard` or `try` expression.
+#[derive(Clone, Debug)]
+pub struct Condition {
+    /// Error category; see `ERROR_CATEGORIES`
+    pub category: &'static str,
+    /// Specific kind of error within its category
+    pub kind: &'static str,
+    /// Error message
+    pub message: String,
+    /// Values further describing the error
+    pub irritants: Vec<Value>,
+}
+
+impl Condition {
+    /// Creates a new `Condition`, as raised by the `error` function,
+    /// with the given message and irritants.
+    pub fn new(message: String, irritants: Vec<Value>) -> Condition {
+        Condition{
+            category: "custom",
+            kind: "error",
+            message: message,
+            irritants: irritants,
+        }
+    }
+
+    /// Creates a `Condition` from a caught error.
+    ///
+    /// An error raised by the `error` function yields its original condition.
+    /// Any other error yields a condition whose message describes the error
+    /// and whose irritants contain values associated with the error;
+    /// e.g. the path of a file which could not be opened.
+    pub fn from_error(names: &mut NameStore, e: Error) -> Condition {
+        let (category, kind) = error_kind(&e);
+        let irritants = error_irritants(names, &e);
+
+        match e {
+            Error::ExecError(ExecError::UserError(cond)) => cond,
+            e => Condition{
+                category: category,
+                kind: kind,
+                message: display_names(names, &e).to_string(),
+                irritants: irritants,
+            }
+        }
+    }
+
+    /// Writes the message and irritants, as displayed by `ExecError::UserError`.
+    pub fn fmt_message(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(f.write_str(&self.message));
+
+        for v in &self.irritants {
+            try!(write!(f, " {}", debug_names(names, v)));
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns whether the given name is an error category or `error`,
+/// which matches errors of any category.
+pub fn is_error_category(names: &NameStore, name: Name) -> bool {
+    name == ERROR || ERROR_CATEGORIES.contains(&names.get(name))
+}
+
+/// Returns whether an error is caught by a handler for the given category.
+/// The category `error` catches any error.
+pub fn condition_matches(names: &NameStore, category: Name, e: &Error) -> bool {
+    category == ERROR || names.get(category) == error_kind(e).0
+}
+
+/// Returns the category and specific kind of an error.
+pub fn error_kind(e: &Error) -> (&'static str, &'static str) {
+    match *e {
+        Error::CompileError(_) => ("compile", "compile-error"),
+        Error::ContextError(ref e) => error_kind(&e.error),
+        Error::DecodeError(_) => ("encode", "decode-error"),
+        Error::EncodeError(_) => ("encode", "encode-error"),
+        Error::ExecError(ref e) => exec_error_kind(e),
+        Error::IoError(ref e) => ("io", io_error_kind(e.err.kind())),
+        Error::ModuleLoadError(ref e) => error_kind(&e.cause),
+        Error::ParseError(_) => ("compile", "parse-error"),
+    }
+}
+
+fn exec_error_kind(e: &ExecError) -> (&'static str, &'static str) {
+    use exec::ExecError::*;
+
+    match *e {
+        CompareNaN => ("arith", "compare-nan"),
+        DivideByZero => ("arith", "divide-by-zero"),
+        NonFiniteFloat{..} => ("arith", "non-finite-float"),
+        IntegerOutOfRange{..} => ("arith", "integer-out-of-range"),
+        Overflow => ("arith", "overflow"),
+
+        CannotCompare(_) => ("type", "cannot-compare"),
+        FieldTypeError{..} => ("type", "field-type-error"),
+        NotCallable{..} => ("type", "not-callable"),
+        StructDefMismatch{..} |
+        StructMismatch{..} => ("type", "struct-mismatch"),
+        TypeError{..} => ("type", "type-error"),
+        TypeMismatch{..} => ("type", "type-mismatch"),
+
+        InvalidSlice(..) => ("index", "invalid-slice"),
+        NotCharBoundary(_) => ("index", "not-char-boundary"),
+        OutOfBounds(_) => ("index", "out-of-bounds"),
+
+        Panic(_) => ("custom", "panic"),
+        UserError(ref cond) => (cond.category, cond.kind),
+
+        CannotDefine(_) => ("name", "cannot-define"),
+        NameError(_) => ("name", "name-error"),
+        StructDefError(_) => ("name", "struct-def-error"),
+
+        ArityError{..} |
+        OverloadArityError{..} => ("value", "arity-error"),
+        ByteCount{..} => ("value", "byte-count"),
+        DuplicateField(_) => ("value", "duplicate-field"),
+        DuplicateKeyword(_) => ("value", "duplicate-keyword"),
+        DuplicateStructDef(_) => ("value", "duplicate-struct-def"),
+        FieldError{..} => ("value", "field-error"),
+        FormatError{..} => ("value", "format-error"),
+        MissingField{..} => ("value", "missing-field"),
+        OddKeywordParams => ("value", "odd-keyword-params"),
+        UnexpectedKeyword{..} => ("value", "unexpected-keyword"),
+        UnrecognizedKeyword(_) => ("value", "unrecognized-keyword"),
+
+        DefaultError{ref error, ..} => error_kind(error),
+
+        InvalidClosureValue(_) => ("internal", "invalid-closure-value"),
+        InvalidConst(_) => ("internal", "invalid-const"),
+        InvalidDepth => ("internal", "invalid-depth"),
+        InvalidDynamicBinding => ("internal", "invalid-dynamic-binding"),
+        InvalidHandler => ("internal", "invalid-handler"),
+        InvalidCapture => ("internal", "invalid-capture"),
+        InvalidJump(_) => ("internal", "invalid-jump"),
+        InvalidStack(_) => ("internal", "invalid-stack"),
+        InvalidSystemFn(_) => ("internal", "invalid-system-fn"),
+        MissingArgCount(_) => ("internal", "missing-arg-count"),
+        StackOverflow => ("internal", "stack-overflow"),
+        Interrupted => ("internal", "interrupted"),
+        UnexpectedEnd => ("internal", "unexpected-end"),
+        UnrecognizedOpCode(_) => ("internal", "unrecognized-opcode"),
+    }
+}
+
+fn io_error_kind(kind: io::ErrorKind) -> &'static str {
+    match kind {
+        io::ErrorKind::NotFound => "not-found",
+        io::ErrorKind::PermissionDenied => "permission-denied",
+        io::ErrorKind::AlreadyExists => "already-exists",
+        io::ErrorKind::InvalidData => "invalid-data",
+        io::ErrorKind::UnexpectedEof => "unexpected-eof",
+        _ => "other"
+    }
+}
+
+/// Returns values associated with an error.
+fn error_irritants(names: &mut NameStore, e: &Error) -> Vec<Value> {
+    match *e {
+        Error::ContextError(ref e) => error_irritants(names, &e.error),
+        Error::ModuleLoadError(ref e) => error_irritants(names, &e.cause),
+        Error::ExecError(ref e) => exec_error_irritants(names, e),
+        Error::IoError(ref e) => vec![
+            e.path.to_string_lossy().into_owned().into(),
+            Value::Name(names.add(&e.mode.to_string())),
+        ],
+        _ => Vec::new()
+    }
+}
+
+fn exec_error_irritants(names: &mut NameStore, e: &ExecError) -> Vec<Value> {
+    use exec::ExecError::*;
+
+    match *e {
+        ArityError{found, ..} |
+        OverloadArityError{found, ..} => vec![found.into()],
+        ByteCount{found, ..} => vec![found.into()],
+        CannotCompare(ty) => vec![Value::Name(names.add(ty))],
+        CannotDefine(name) |
+        DuplicateField(name) |
+        DuplicateKeyword(name) |
+        DuplicateStructDef(name) |
+        NameError(name) |
+        StructDefError(name) |
+        UnrecognizedKeyword(name) => vec![Value::Name(name)],
+        UnexpectedKeyword{keyword, ..} => vec![Value::Keyword(keyword)],
+        DefaultError{ref error, ..} => error_irritants(names, error),
+        FieldError{struct_name, field} |
+        MissingField{struct_name, field} =>
+            vec![Value::Name(struct_name), Value::Keyword(field)],
+        InvalidSlice(begin, end) => vec![begin.into(), end.into()],
+        NotCallable{ref value, ..} => vec![value.clone()],
+        NonFiniteFloat{operation, lhs, rhs, ..} =>
+            vec![Value::Name(names.add(operation)), lhs.into(), rhs.into()],
+        NotCharBoundary(n) |
+        OutOfBounds(n) => vec![n.into()],
+        Panic(Some(ref v)) => vec![v.clone()],
+        StructDefMismatch{ref lhs, ref rhs} =>
+            vec![Value::Name(lhs.name), Value::Name(rhs.name)],
+        StructMismatch{lhs, rhs} => vec![Value::Name(lhs), Value::Name(rhs)],
+        TypeError{expected, found} =>
+            vec![Value::Name(names.add(expected)), Value::Name(names.add(found))],
+        TypeMismatch{lhs, rhs} =>
+            vec![Value::Name(names.add(lhs)), Value::Name(names.add(rhs))],
+        UserError(ref cond) => cond.irritants.clone(),
+        _ => Vec::new()
+    }
+}
+
+impl ForeignValue for Condition {
+    fn compare_to(&self, _rhs: &ForeignValue) -> Result<Ordering, ExecError> {
+        Err(ExecError::CannotCompare("condition"))
+    }
+
+    fn is_equal_to(&self, rhs: &ForeignValue) -> Result<bool, ExecError> {
+        match rhs.downcast_ref::<Condition>() {
+            Some(rhs) => {
+                if self.category != rhs.category || self.kind != rhs.kind ||
+                        self.message != rhs.message ||
+                        self.irritants.len() != rhs.irritants.len() {
+                    return Ok(false);
+                }
+
+                for (a, b) in self.irritants.iter().zip(&rhs.irritants) {
+                    if !try!(a.is_equal(b)) {
+                        return Ok(false);
+                    }
+                }
+
+                Ok(true)
+            }
+            None => Err(ExecError::TypeMismatch{
+                lhs: self.type_name(),
+                rhs: rhs.type_name(),
+            })
+        }
+    }
+
+    fn fmt_debug(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "<condition :{} {:?}", self.kind, self.message));
+
+        for v in &self.irritants {
+            try!(write!(f, " {}", debug_names(names, v)));
+        }
+
+        f.write_str(">")
+    }
+
+    fn type_name(&self) -> &'static str { "condition" }
+}
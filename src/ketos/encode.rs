@@ -1,6 +1,7 @@
 //! Implements encoding and decoding of compiled bytecode file format.
 
 use std::char::from_u32;
+use std::error::Error as StdError;
 use std::fmt;
 use std::fs::File;
 use std::io::{Cursor, Read, Write};
@@ -10,19 +11,28 @@ use std::str::from_utf8;
 
 use byteorder::{self, BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
 
-use bytecode::{BYTECODE_VERSION, Code};
+use bytecode::{BYTECODE_VERSION, Code, CodeReader, Instruction};
+use bytecode::opcodes::{CONST_0, RETURN, SET_DEF};
 use error::Error;
-use function::Lambda;
+use function::{Lambda, Overload};
 use integer::{Integer, Ratio, Sign};
 use io::{IoError, IoMode};
-use name::{Name, NameMap, NameSet, NameSetSlice, NameStore,
-    NameInputConversion, NameOutputConversion};
+use module::{ImportSet, Module};
+use name::{Name, NameMap, NameMapSlice, NameSet, NameSetSlice, NameSnapshot, NameStore,
+    NameInputConversion, NameOutputConversion, REGISTERED_FNS_BEGIN};
 use scope::Scope;
 use value::{StructDef, Value};
 
 /// First four bytes written to a compiled bytecode file.
 pub const MAGIC_NUMBER: &'static [u8; 4] = b"\0MUR";
 
+/// First four bytes written to a compiled bytecode file which refers to
+/// names of a `NameSnapshot`, rather than containing all of its names.
+pub const SHARED_NAMES_MAGIC_NUMBER: &'static [u8; 4] = b"\0MUS";
+
+/// First four bytes written to a name snapshot file.
+pub const NAME_SNAPSHOT_MAGIC_NUMBER: &'static [u8; 4] = b"\0MUN";
+
 /// Error in decoding bytecode file format
 #[derive(Debug)]
 pub enum DecodeError {
@@ -40,12 +50,25 @@ pub enum DecodeError {
     InvalidCodeFlags(u32),
     /// Invalid name value
     InvalidName(u32),
+    /// Overloaded functions with variable or duplicate arity
+    InvalidOverload,
     /// Invalid parameter count in code object
     InvalidParamCount,
+    /// Registered system function is not registered in the current scope
+    /// or is registered under another operand value
+    InvalidSystemFn(u32),
     /// Invalid type value
     InvalidType(u8),
     /// Invalid UTF-8 in string value
     InvalidUtf8,
+    /// Bytecode refers to the names of a snapshot which does not match
+    /// the names of the interpreter
+    MissingNameSnapshot{
+        /// Label of the required snapshot
+        label: String,
+        /// Number of names used from the snapshot
+        len: u32,
+    },
     /// Unbalanced `Quasiquote` and `Comma` values
     UnbalancedComma,
     /// Unexpected end-of-file
@@ -69,15 +92,22 @@ impl fmt::Display for DecodeError {
             InvalidCodeFlags(flags) =>
                 write!(f, "invalid code object flags: {:#x}", flags),
             InvalidName(n) => write!(f, "invalid name: {}", n),
+            InvalidOverload => f.write_str("invalid overloaded function"),
             InvalidParamCount => f.write_str("invalid parameter count"),
+            InvalidSystemFn(n) => write!(f, "invalid system function: {}", n),
             InvalidType(ty) => write!(f, "invalid type {:#x}", ty),
             InvalidUtf8 => f.write_str("invalid UTF-8 in string"),
+            MissingNameSnapshot{ref label, len} => write!(f,
+                "bytecode requires an interpreter created from name snapshot `{}` \
+                 ({} names)", label, len),
             UnbalancedComma => f.write_str("unbalanced quasiquote and comma values"),
             UnexpectedEof => f.write_str("unexpected end-of-file"),
         }
     }
 }
 
+impl StdError for DecodeError {}
+
 /// Error in encoding bytecode file format
 #[derive(Debug)]
 pub enum EncodeError {
@@ -85,6 +115,9 @@ pub enum EncodeError {
     Overflow,
     /// Attempt to encode a type that cannot be encoded
     UnencodableType(&'static str),
+    /// Attempt to encode code containing `define-module`,
+    /// whose module is defined only when the code is compiled
+    DefineModule,
 }
 
 impl fmt::Display for EncodeError {
@@ -94,18 +127,141 @@ impl fmt::Display for EncodeError {
         match *self {
             Overflow => f.write_str("integer overflow"),
             UnencodableType(ty) => write!(f, "cannot encode value of type `{}`", ty),
+            DefineModule => f.write_str(
+                "cannot encode `define-module`; the module is defined only when compiled"),
         }
     }
 }
 
+impl StdError for EncodeError {}
+
 /// Contains code from a compiled module
+#[derive(Clone)]
 pub struct ModuleCode {
     /// Decoded `Code` objects
     pub code: Vec<Rc<Code>>,
     /// Exported names
     pub exports: NameSetSlice,
+    /// Names exported under another name;
+    /// maps exported name to internal name
+    pub export_renames: NameMapSlice<Name>,
     /// Decoded macro objects
     pub macros: Vec<(Name, Rc<Code>)>,
+    /// Registered system functions called by the code;
+    /// maps `CallSys` operand to function name
+    pub system_fns: Vec<(u32, Name)>,
+    /// Values imported from other modules, which are imported again
+    /// before the code is executed
+    pub imports: Vec<ImportSet>,
+}
+
+impl ModuleCode {
+    /// Creates a `ModuleCode` from code compiled and executed within a
+    /// module scope.
+    ///
+    /// Macros, exports, and imports are taken from the scope. Registered system
+    /// functions called by the code are recorded so that the code may be
+    /// checked against the functions registered when it is later decoded.
+    pub fn new(code: Vec<Rc<Code>>, scope: &Scope) -> ModuleCode {
+        let macros = scope.with_macros(
+            |macros| macros.iter()
+                .map(|&(name, ref l)| (name, l.code.clone())).collect::<Vec<_>>());
+
+        let mut calls = Vec::new();
+
+        for code in code.iter().chain(macros.iter().map(|&(_, ref c)| c)) {
+            registered_fn_calls(code, &mut calls);
+        }
+
+        calls.sort();
+        calls.dedup();
+
+        ModuleCode{
+            system_fns: calls.into_iter()
+                .filter_map(|n| scope.get_registered_fn(n).map(|(name, _)| (n, name)))
+                .collect(),
+            code: code,
+            macros: macros,
+            exports: scope.with_exports(|e| e.cloned().unwrap_or_default()),
+            export_renames: scope.with_export_renames(
+                |r| r.cloned().unwrap_or_default()),
+            imports: scope.with_imports(|i| i.to_vec()),
+        }
+    }
+
+    /// Creates a `ModuleCode` from a loaded module.
+    ///
+    /// The resulting code defines each of the module's values, adds its
+    /// macros, and exports the module's exported names.
+    /// Values are copied as they are; functions will continue to refer to
+    /// the scope of the original module.
+    pub fn from_module(module: &Module) -> ModuleCode {
+        let scope = &module.scope;
+
+        let code = scope.with_values(|values| values.iter()
+            .map(|&(name, ref value)| Rc::new(Code{
+                name: None,
+                consts: vec![value.clone(), Value::Name(name)].into_boxed_slice(),
+                code: vec![CONST_0, SET_DEF, 1, RETURN].into_boxed_slice(),
+                param_names: Box::new([]),
+                kw_params: Box::new([]),
+                n_params: 0,
+                req_params: 0,
+                flags: 0,
+            })).collect());
+
+        ModuleCode{
+            code: code,
+            macros: scope.with_macros(
+                |macros| macros.iter()
+                    .map(|&(name, ref l)| (name, l.code.clone())).collect()),
+            exports: scope.with_exports(|e| e.cloned().unwrap_or_default()),
+            export_renames: scope.with_export_renames(
+                |r| r.cloned().unwrap_or_default()),
+            system_fns: Vec::new(),
+            imports: Vec::new(),
+        }
+    }
+}
+
+/// Collects operands of `CallSys` and `CallSysArgs` instructions which refer
+/// to registered system functions, including those within nested lambdas.
+fn registered_fn_calls(code: &Code, calls: &mut Vec<u32>) {
+    let mut r = CodeReader::new(&code.code, 0);
+
+    loop {
+        match r.read_instruction() {
+            Ok(Instruction::CallSys(n)) |
+            Ok(Instruction::CallSysArgs(n, _)) if n >= REGISTERED_FNS_BEGIN =>
+                calls.push(n),
+            Ok(_) => (),
+            Err(_) => break
+        }
+    }
+
+    for v in code.consts.iter() {
+        match *v {
+            Value::Lambda(ref l) => registered_fn_calls(&l.code, calls),
+            Value::Overload(ref o) => for l in &o.lambdas {
+                registered_fn_calls(&l.code, calls);
+            },
+            _ => ()
+        }
+    }
+}
+
+/// Returns whether the code contains a `DefineModule` instruction.
+/// Nested lambdas are checked as they are encoded.
+fn defines_module(code: &Code) -> bool {
+    let mut r = CodeReader::new(&code.code, 0);
+
+    loop {
+        match r.read_instruction() {
+            Ok(Instruction::DefineModule(_)) => return true,
+            Ok(_) => (),
+            Err(_) => return false
+        }
+    }
 }
 
 /// Read compiled bytecode from a file
@@ -122,7 +278,7 @@ pub fn read_bytecode<R: Read>(r: &mut R, path: &Path,
 
     try!(r.read_exact(&mut buf)
         .map_err(|e| IoError::new(IoMode::Read, path, e)));
-    try!(check_magic_number(&buf));
+    let shared = try!(check_magic_number(&buf));
 
     try!(r.read_exact(&mut buf)
         .map_err(|e| IoError::new(IoMode::Read, path, e)));
@@ -134,8 +290,24 @@ pub fn read_bytecode<R: Read>(r: &mut R, path: &Path,
 
     let mut dec = ValueDecoder::new(scope, &buf);
 
+    let mut names = if shared {
+        let label = try!(dec.read_string()).to_owned();
+        let len = try!(dec.read_uint());
+        let checksum = try!(dec.read_u32());
+
+        if scope.borrow_names().checksum(len) != Some(checksum) {
+            return Err(From::from(DecodeError::MissingNameSnapshot{
+                label: label,
+                len: len,
+            }));
+        }
+
+        NameInputConversion::with_shared(len)
+    } else {
+        NameInputConversion::new()
+    };
+
     let n_names = try!(dec.read_uint());
-    let mut names = NameInputConversion::new();
 
     {
         let mut name_store = scope.get_names().borrow_mut();
@@ -154,6 +326,54 @@ pub fn read_bytecode<R: Read>(r: &mut R, path: &Path,
         exports.insert(name);
     }
 
+    let n_sys_fns = try!(dec.read_uint());
+    let mut system_fns = Vec::with_capacity(n_sys_fns as usize);
+
+    for _ in 0..n_sys_fns {
+        let n = try!(dec.read_uint());
+        let name = try!(dec.read_name(&names));
+
+        match scope.get_registered_fn(n) {
+            Some((reg_name, _)) if reg_name == name => (),
+            _ => return Err(From::from(DecodeError::InvalidSystemFn(n)))
+        }
+
+        system_fns.push((n, name));
+    }
+
+    let n_renames = try!(dec.read_uint());
+    let mut export_renames = NameMap::new();
+
+    for _ in 0..n_renames {
+        let dest = try!(dec.read_name(&names));
+        let src = try!(dec.read_name(&names));
+        export_renames.insert(dest, src);
+    }
+
+    let n_imports = try!(dec.read_uint());
+    let mut imports = Vec::with_capacity(n_imports as usize);
+
+    for _ in 0..n_imports {
+        let mut imp = ImportSet::new(try!(dec.read_name(&names)));
+        imp.all = try!(dec.read_u8()) != 0;
+        imp.by_ref = try!(dec.read_u8()) != 0;
+        imp.for_syntax = try!(dec.read_u8()) != 0;
+
+        if try!(dec.read_u8()) != 0 {
+            imp.alias = Some(try!(dec.read_name(&names)));
+        }
+
+        let n_names = try!(dec.read_uint());
+
+        for _ in 0..n_names {
+            let src = try!(dec.read_name(&names));
+            let dest = try!(dec.read_name(&names));
+            imp.names.push((src, dest));
+        }
+
+        imports.push(imp);
+    }
+
     let n_macros = try!(dec.read_uint());
     let mut macros = Vec::with_capacity(n_macros as usize);
 
@@ -173,23 +393,81 @@ pub fn read_bytecode<R: Read>(r: &mut R, path: &Path,
         code: exprs,
         macros: macros,
         exports: exports.into_slice(),
+        export_renames: export_renames.into_slice(),
+        system_fns: system_fns,
+        imports: imports,
     })
 }
 
 /// Write compiled bytecode to a file
+///
+/// The file is not created if the code cannot be encoded.
 pub fn write_bytecode_file(path: &Path, module: &ModuleCode,
         name_store: &NameStore) -> Result<(), Error> {
+    let mut buf = Vec::new();
+    try!(write_bytecode(&mut buf, path, module, name_store));
+
     let mut f = try!(File::create(path)
         .map_err(|e| IoError::new(IoMode::Create, path, e)));
-    write_bytecode(&mut f, path, module, name_store)
+    try!(f.write_all(&buf)
+        .map_err(|e| IoError::new(IoMode::Write, path, e)));
+    Ok(())
 }
 
 /// Write compiled bytecode
+///
+/// If the `NameStore` was created from a `NameSnapshot`, names contained in
+/// the snapshot are written by value and the file may only be read by
+/// an interpreter whose names begin with those of the snapshot.
 pub fn write_bytecode<W: Write>(w: &mut W, path: &Path, module: &ModuleCode,
         name_store: &NameStore) -> Result<(), Error> {
-    let mut names = NameOutputConversion::new(name_store);
+    let snapshot = name_store.snapshot_info();
+    let mut names = match snapshot {
+        Some((_, len)) => NameOutputConversion::with_shared(name_store, len),
+        None => NameOutputConversion::new(name_store)
+    };
     let mut body_enc = ValueEncoder::new();
 
+    // Registered functions, renamed exports, and imports are written into the body
+    // so that names which appear nowhere else are collected before
+    // the name table is written.
+    try!(body_enc.write_len(module.system_fns.len()));
+
+    for &(n, name) in &module.system_fns {
+        try!(body_enc.write_uint(n));
+        try!(body_enc.write_name(name, &mut names));
+    }
+
+    try!(body_enc.write_len(module.export_renames.len()));
+
+    for &(dest, src) in &module.export_renames {
+        try!(body_enc.write_name(dest, &mut names));
+        try!(body_enc.write_name(src, &mut names));
+    }
+
+    try!(body_enc.write_len(module.imports.len()));
+
+    for imp in &module.imports {
+        try!(body_enc.write_name(imp.module_name, &mut names));
+        body_enc.write_u8(imp.all as u8);
+        body_enc.write_u8(imp.by_ref as u8);
+        body_enc.write_u8(imp.for_syntax as u8);
+
+        match imp.alias {
+            Some(alias) => {
+                body_enc.write_u8(1);
+                try!(body_enc.write_name(alias, &mut names));
+            }
+            None => body_enc.write_u8(0)
+        }
+        try!(body_enc.write_len(imp.names.len()));
+
+        for &(src, dest) in &imp.names {
+            try!(body_enc.write_name(src, &mut names));
+            try!(body_enc.write_name(dest, &mut names));
+        }
+    }
+
     try!(body_enc.write_len(module.macros.len()));
 
     for &(name, ref mac) in &module.macros {
@@ -203,6 +481,14 @@ pub fn write_bytecode<W: Write>(w: &mut W, path: &Path, module: &ModuleCode,
 
     let mut head_enc = ValueEncoder::new();
 
+    if let Some((label, len)) = snapshot {
+        let checksum = name_store.checksum(len)
+            .expect("snapshot names missing from store");
+        try!(head_enc.write_string(label));
+        try!(head_enc.write_uint(len));
+        head_enc.write_u32(checksum);
+    }
+
     try!(head_enc.write_len(names.len()));
 
     for name in names.get_names() {
@@ -215,7 +501,9 @@ pub fn write_bytecode<W: Write>(w: &mut W, path: &Path, module: &ModuleCode,
         try!(head_enc.write_name(name, &mut names));
     }
 
-    try!(w.write_all(MAGIC_NUMBER)
+    let magic = if snapshot.is_some() { SHARED_NAMES_MAGIC_NUMBER } else { MAGIC_NUMBER };
+
+    try!(w.write_all(magic)
         .map_err(|e| IoError::new(IoMode::Write, path, e)));
 
     match w.write_u32::<BigEndian>(BYTECODE_VERSION) {
@@ -233,14 +521,75 @@ pub fn write_bytecode<W: Write>(w: &mut W, path: &Path, module: &ModuleCode,
     Ok(())
 }
 
-fn check_magic_number(num: &[u8; 4]) -> Result<(), DecodeError> {
+/// Checks the magic number of a bytecode file.
+/// Returns whether the file refers to names of a `NameSnapshot`.
+fn check_magic_number(num: &[u8; 4]) -> Result<bool, DecodeError> {
     if num == MAGIC_NUMBER {
-        Ok(())
+        Ok(false)
+    } else if num == SHARED_NAMES_MAGIC_NUMBER {
+        Ok(true)
     } else {
         Err(DecodeError::IncorrectMagicNumber(*num))
     }
 }
 
+/// Reads a `NameSnapshot` from a file. The file name is used as the label
+/// of the snapshot.
+pub fn read_name_snapshot_file(path: &Path) -> Result<NameSnapshot, Error> {
+    let mut buf = Vec::new();
+
+    try!(File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut buf))
+        .map_err(|e| IoError::new(IoMode::Read, path, e)));
+
+    if buf.len() < 4 || &buf[..4] != NAME_SNAPSHOT_MAGIC_NUMBER {
+        let mut num = [0; 4];
+        for (a, b) in num.iter_mut().zip(&buf) {
+            *a = *b;
+        }
+        return Err(From::from(DecodeError::IncorrectMagicNumber(num)));
+    }
+
+    let mut data = Cursor::new(&buf[4..]);
+    let n = try!(data.read_u32::<BigEndian>()
+        .map_err(|_| DecodeError::UnexpectedEof));
+    let mut names = Vec::with_capacity(n as usize);
+
+    for _ in 0..n {
+        let len = try!(data.read_u32::<BigEndian>()
+            .map_err(|_| DecodeError::UnexpectedEof));
+        let b = try!(read_cursor(&mut data, len as usize)
+            .ok_or(DecodeError::UnexpectedEof));
+        let s = try!(from_utf8(b).map_err(|_| DecodeError::InvalidUtf8));
+        names.push(s.to_owned());
+    }
+
+    let label = path.file_name()
+        .map_or_else(|| path.display().to_string(),
+            |name| name.to_string_lossy().into_owned());
+
+    Ok(NameSnapshot::new(label, names))
+}
+
+/// Writes a `NameSnapshot` to a file.
+pub fn write_name_snapshot_file(path: &Path, snapshot: &NameSnapshot) -> Result<(), Error> {
+    let mut buf = Vec::new();
+
+    buf.extend(NAME_SNAPSHOT_MAGIC_NUMBER);
+    let _ = buf.write_u32::<BigEndian>(snapshot.names().len() as u32);
+
+    for name in snapshot.names() {
+        let _ = buf.write_u32::<BigEndian>(name.len() as u32);
+        buf.extend(name.as_bytes());
+    }
+
+    try!(File::create(path)
+        .and_then(|mut f| f.write_all(&buf))
+        .map_err(|e| IoError::new(IoMode::Write, path, e)));
+
+    Ok(())
+}
+
 fn check_version(num: &[u8; 4]) -> Result<(), DecodeError> {
     let version = BigEndian::read_u32(num);
 
@@ -276,15 +625,55 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
 
     /// Reads a `Value` from the byte stream.
     fn read_value(&mut self, names: &NameInputConversion) -> Result<Value, DecodeError> {
+        // Nested values are read using an explicit stack so that
+        // deeply nested lists do not overflow the call stack.
+        let mut stack = Vec::new();
+
+        loop {
+            let mut v = match try!(self.read_value_node(names)) {
+                DecodeNode::Value(v) => v,
+                DecodeNode::Nested(frame) => {
+                    stack.push(frame);
+                    continue;
+                }
+            };
+
+            // Complete each enclosing value whose contents have been read
+            loop {
+                match stack.pop() {
+                    None => return Ok(v),
+                    Some(DecodeFrame::List(mut values, n, is_vector)) => {
+                        values.push(v);
+
+                        if values.len() < n {
+                            stack.push(DecodeFrame::List(values, n, is_vector));
+                            break;
+                        }
+
+                        v = if is_vector {
+                            Value::new_vector(values)
+                        } else {
+                            values.into()
+                        };
+                    }
+                    Some(DecodeFrame::Quote(f, n)) => v = f(v, n),
+                }
+            }
+        }
+    }
+
+    /// Reads a single value from the byte stream. Lists, vectors, and quoted
+    /// values yield a `DecodeFrame`, whose contents are to be read next.
+    fn read_value_node(&mut self, names: &NameInputConversion) -> Result<DecodeNode, DecodeError> {
         use self::types::*;
 
         let ty = try!(self.read_u8());
 
-        match ty {
-            UNIT => Ok(Value::Unit),
-            BOOL_TRUE => Ok(Value::Bool(true)),
-            BOOL_FALSE => Ok(Value::Bool(false)),
-            FLOAT => Ok(Value::Float(try!(self.read_f64()))),
+        let v = match ty {
+            UNIT => Value::Unit,
+            BOOL_TRUE => Value::Bool(true),
+            BOOL_FALSE => Value::Bool(false),
+            FLOAT => Value::Float(try!(self.read_f64())),
             INTEGER | INTEGER_NEG => {
                 let sign = if ty == INTEGER {
                     Sign::Plus
@@ -292,9 +681,9 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
                     Sign::Minus
                 };
 
-                self.read_integer(sign).map(Value::Integer)
+                Value::Integer(try!(self.read_integer(sign)))
             }
-            INTEGER_ZERO => Ok(Value::Integer(Integer::zero())),
+            INTEGER_ZERO => Value::Integer(Integer::zero()),
             RATIO | RATIO_NEG => {
                 let sign = if ty == RATIO {
                     Sign::Plus
@@ -307,21 +696,19 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
                 let denom = try!(self.read_integer(Sign::Plus));
 
                 if denom.is_zero() {
-                    Err(DecodeError::DivisionByZero)
-                } else {
-                    Ok(Ratio::new(numer, denom).into())
+                    return Err(DecodeError::DivisionByZero);
                 }
+
+                Ratio::new(numer, denom).into()
             }
-            RATIO_ZERO => Ok(Value::Ratio(Ratio::zero())),
-            NAME => Ok(Value::Name(try!(self.read_name(names)))),
-            KEYWORD => Ok(Value::Keyword(try!(self.read_name(names)))),
+            RATIO_ZERO => Value::Ratio(Ratio::zero()),
+            NAME => Value::Name(try!(self.read_name(names))),
+            KEYWORD => Value::Keyword(try!(self.read_name(names))),
             CHAR => {
                 let c = try!(self.read_u32());
-                from_u32(c)
-                    .map(Value::Char)
-                    .ok_or(DecodeError::InvalidChar(c))
+                Value::Char(try!(from_u32(c).ok_or(DecodeError::InvalidChar(c))))
             }
-            STRING => self.read_string().map(|s| s.into()),
+            STRING => try!(self.read_string()).into(),
             STRUCT => panic!("struct value decoding not implemented"),
             STRUCT_DEF => {
                 let name = try!(self.read_name(names));
@@ -335,52 +722,75 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
                     fields.insert(field, ty);
                 }
 
-                Ok(Value::StructDef(Rc::new(StructDef{
-                    name: name,
-                    fields: fields.into_slice(),
-                })))
-            }
-            QUASI_QUOTE => {
-                let n = try!(self.read_u8()) as u32;
-                self.read_value(names).map(|v| v.quasiquote(n))
+                let def = match try!(self.read_u8()) {
+                    0 => StructDef::new(name, fields.into_slice()),
+                    _ => StructDef::new_variant(name, fields.into_slice(),
+                        try!(self.read_name(names)))
+                };
+
+                Value::StructDef(self.scope.get_modules().intern_struct_def(def))
             }
-            QUASI_QUOTE_ONE => self.read_value(names).map(|v| v.quasiquote(1)),
-            COMMA => {
-                let n = try!(self.read_u8()) as u32;
-                self.read_value(names).map(|v| v.comma(n))
+            QUASI_QUOTE | COMMA | COMMA_AT | QUOTE |
+            QUASI_QUOTE_ONE | COMMA_ONE | COMMA_AT_ONE | QUOTE_ONE => {
+                let n = match ty {
+                    QUASI_QUOTE | COMMA | COMMA_AT | QUOTE =>
+                        try!(self.read_u8()) as u32,
+                    _ => 1
+                };
+
+                let f: fn(Value, u32) -> Value = match ty {
+                    QUASI_QUOTE | QUASI_QUOTE_ONE => Value::quasiquote,
+                    COMMA | COMMA_ONE => Value::comma,
+                    COMMA_AT | COMMA_AT_ONE => Value::comma_at,
+                    _ => Value::quote
+                };
+
+                return Ok(DecodeNode::Nested(DecodeFrame::Quote(f, n)));
             }
-            COMMA_ONE => self.read_value(names).map(|v| v.comma(1)),
-            COMMA_AT => {
-                let n = try!(self.read_u8()) as u32;
-                self.read_value(names).map(|v| v.comma_at(n))
+            LIST | VECTOR => {
+                let n = try!(self.read_len());
+
+                if n == 0 {
+                    if ty == LIST {
+                        return Err(DecodeError::EmptyList);
+                    }
+                    Value::new_vector(Vec::<Value>::new())
+                } else {
+                    return Ok(DecodeNode::Nested(
+                        DecodeFrame::List(Vec::with_capacity(n), n, ty == VECTOR)));
+                }
             }
-            COMMA_AT_ONE => self.read_value(names).map(|v| v.comma_at(1)),
-            QUOTE => {
-                let n = try!(self.read_u8()) as u32;
-                self.read_value(names).map(|v| v.quote(n))
+            LAMBDA => {
+                let code = try!(self.read_code(names));
+                Value::Lambda(Lambda::new(Rc::new(code), &self.scope))
             }
-            QUOTE_ONE => self.read_value(names).map(|v| v.quote(1)),
-            LIST => {
+            OVERLOAD => {
+                let name = try!(self.read_name(names));
                 let n = try!(self.read_len());
 
-                if n == 0 {
-                    return Err(DecodeError::EmptyList);
+                if n < 2 {
+                    return Err(DecodeError::InvalidOverload);
                 }
 
-                let mut v = Vec::with_capacity(n);
+                let mut lambdas = Vec::with_capacity(n);
 
                 for _ in 0..n {
-                    v.push(try!(self.read_value(names)));
+                    let code = try!(self.read_code(names));
+
+                    if !code.has_fixed_arity() ||
+                            lambdas.iter().any(|l: &Lambda| l.code.n_params == code.n_params) {
+                        return Err(DecodeError::InvalidOverload);
+                    }
+
+                    lambdas.push(Lambda::new(Rc::new(code), &self.scope));
                 }
 
-                Ok(v.into())
-            }
-            LAMBDA => {
-                let code = try!(self.read_code(names));
-                Ok(Value::Lambda(Lambda::new(Rc::new(code), &self.scope)))
+                Value::Overload(Rc::new(Overload::new(name, lambdas)))
             }
-            _ => Err(DecodeError::InvalidType(ty))
-        }
+            _ => return Err(DecodeError::InvalidType(ty))
+        };
+
+        Ok(DecodeNode::Value(v))
     }
 
     fn read_bytes(&mut self, n: usize) -> Result<&'data [u8], DecodeError> {
@@ -421,6 +831,12 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
             return Err(DecodeError::InvalidParamCount);
         }
 
+        let mut param_names = Vec::with_capacity(n_params as usize);
+
+        for _ in 0..n_params {
+            param_names.push(try!(self.read_name(names)));
+        }
+
         let mut kw_params = Vec::new();
 
         match flags & PARAM_FLAGS_MASK {
@@ -445,6 +861,7 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
             name: name,
             consts: consts.into_boxed_slice(),
             code: code.into_boxed_slice(),
+            param_names: param_names.into_boxed_slice(),
             kw_params: kw_params.into_boxed_slice(),
             n_params: n_params,
             req_params: req_params,
@@ -502,28 +919,46 @@ impl<'a, 'data> ValueDecoder<'a, 'data> {
     }
 }
 
-fn validate_value(v: &Value) -> Result<(), DecodeError> {
-    validate_value_inner(v, 0)
-}
+fn validate_value(mut v: &Value) -> Result<(), DecodeError> {
+    let mut quasi = 0;
 
-fn validate_value_inner(v: &Value, quasi: u32) -> Result<(), DecodeError> {
-    match *v {
-        Value::Quasiquote(ref v, n) => validate_value_inner(v, quasi + n),
-        Value::Comma(ref v, n) => if n >= quasi {
-            Err(DecodeError::UnbalancedComma)
-        } else {
-            validate_value_inner(v, quasi - n)
-        },
-        Value::CommaAt(ref v, n) => if n >= quasi {
-            Err(DecodeError::UnbalancedComma)
-        } else {
-            validate_value_inner(v, quasi - n)
-        },
-        Value::Quote(ref v, _) => validate_value_inner(v, quasi),
-        _ => Ok(())
+    loop {
+        match *v {
+            Value::Quasiquote(ref inner, n) => {
+                quasi += n;
+                v = inner;
+            }
+            Value::Comma(ref inner, n) |
+            Value::CommaAt(ref inner, n) => {
+                if n >= quasi {
+                    return Err(DecodeError::UnbalancedComma);
+                }
+                quasi -= n;
+                v = inner;
+            }
+            Value::Quote(ref inner, _) => v = inner,
+            _ => return Ok(())
+        }
     }
 }
 
+/// Result of reading a single value in `ValueDecoder::read_value_node`
+enum DecodeNode {
+    /// Complete value
+    Value(Value),
+    /// Value whose contents are yet to be read
+    Nested(DecodeFrame),
+}
+
+/// Partially decoded value, awaiting the values it contains
+enum DecodeFrame {
+    /// List or vector; contains values read so far, total length,
+    /// and whether the value is a vector
+    List(Vec<Value>, usize, bool),
+    /// Quoted value; contains the function applying the quote and its depth
+    Quote(fn(Value, u32) -> Value, u32),
+}
+
 /// Encodes values to a byte stream
 struct ValueEncoder {
     data: Vec<u8>,
@@ -544,6 +979,22 @@ impl ValueEncoder {
 
     /// Writes a `Value` to the byte stream.
     fn write_value(&mut self, value: &Value, names: &mut NameOutputConversion) -> Result<(), EncodeError> {
+        // Nested values are written using an explicit stack so that
+        // deeply nested lists do not overflow the call stack.
+        let mut stack = vec![value];
+
+        while let Some(v) = stack.pop() {
+            try!(self.write_value_node(v, names, &mut stack));
+        }
+
+        Ok(())
+    }
+
+    /// Writes a single `Value` to the byte stream. Values nested within
+    /// the value are pushed onto `stack` in reverse order, to be written next.
+    fn write_value_node<'v>(&mut self, value: &'v Value,
+            names: &mut NameOutputConversion, stack: &mut Vec<&'v Value>)
+            -> Result<(), EncodeError> {
         use self::types::*;
 
         match *value {
@@ -617,54 +1068,64 @@ impl ValueEncoder {
                     try!(self.write_name(name, names));
                     try!(self.write_name(ty, names));
                 }
+
+                match def.variant_of {
+                    Some(type_name) => {
+                        self.write_u8(1);
+                        try!(self.write_name(type_name, names));
+                    }
+                    None => self.write_u8(0)
+                }
             }
             Value::Quasiquote(ref v, 1) => {
                 self.write_u8(QUASI_QUOTE_ONE);
-                try!(self.write_value(v, names));
+                stack.push(v);
             }
             Value::Quasiquote(ref v, n) if n <= 0xff => {
                 self.write_u8(QUASI_QUOTE);
                 self.write_u8(n as u8);
-                try!(self.write_value(v, names));
+                stack.push(v);
             }
             Value::Quasiquote(_, _) => return Err(EncodeError::Overflow),
             Value::Comma(ref v, 1) => {
                 self.write_u8(COMMA_ONE);
-                try!(self.write_value(v, names));
+                stack.push(v);
             }
             Value::Comma(ref v, n) if n <= 0xff => {
                 self.write_u8(COMMA);
                 self.write_u8(n as u8);
-                try!(self.write_value(v, names));
+                stack.push(v);
             }
             Value::Comma(_, _) => return Err(EncodeError::Overflow),
             Value::CommaAt(ref v, 1) => {
                 self.write_u8(COMMA_AT_ONE);
-                try!(self.write_value(v, names));
+                stack.push(v);
             }
             Value::CommaAt(ref v, n) if n <= 0xff => {
                 self.write_u8(COMMA_AT);
                 self.write_u8(n as u8);
-                try!(self.write_value(v, names));
+                stack.push(v);
             }
             Value::CommaAt(_, _) => return Err(EncodeError::Overflow),
             Value::Quote(ref v, 1) => {
                 self.write_u8(QUOTE_ONE);
-                try!(self.write_value(v, names));
+                stack.push(v);
             }
             Value::Quote(ref v, n) if n <= 0xff => {
                 self.write_u8(QUOTE);
                 self.write_u8(n as u8);
-                try!(self.write_value(v, names));
+                stack.push(v);
             }
             Value::Quote(_, _) => return Err(EncodeError::Overflow),
             Value::List(ref li) => {
                 self.write_u8(LIST);
                 try!(self.write_len(li.len()));
-
-                for v in li.iter() {
-                    try!(self.write_value(v, names));
-                }
+                stack.extend(li.iter().rev());
+            }
+            Value::Vector(ref v) => {
+                self.write_u8(VECTOR);
+                try!(self.write_len(v.len()));
+                stack.extend(v.iter().rev());
             }
             Value::Lambda(ref l) => {
                 if l.values.is_some() {
@@ -673,6 +1134,18 @@ impl ValueEncoder {
                 self.write_u8(LAMBDA);
                 try!(self.write_code(&l.code, names));
             }
+            Value::Overload(ref o) => {
+                if o.lambdas.iter().any(|l| l.values.is_some()) {
+                    panic!("cannot encode Lambda with enclosed values");
+                }
+                self.write_u8(OVERLOAD);
+                try!(self.write_name(o.name, names));
+                try!(self.write_len(o.lambdas.len()));
+
+                for l in &o.lambdas {
+                    try!(self.write_code(&l.code, names));
+                }
+            }
             Value::Foreign(_) =>
                 return Err(EncodeError::UnencodableType("foreign value")),
             ref v => return Err(EncodeError::UnencodableType(v.type_name()))
@@ -688,6 +1161,10 @@ impl ValueEncoder {
     fn write_code(&mut self, code: &Code, names: &mut NameOutputConversion) -> Result<(), EncodeError> {
         use bytecode::code_flags::*;
 
+        if defines_module(code) {
+            return Err(EncodeError::DefineModule);
+        }
+
         self.write_u8(code.flags as u8);
 
         assert_eq!(code.flags & HAS_NAME != 0, code.name.is_some());
@@ -708,6 +1185,12 @@ impl ValueEncoder {
         try!(self.write_uint(code.n_params));
         try!(self.write_uint(code.req_params));
 
+        assert_eq!(code.param_names.len(), code.n_params as usize);
+
+        for &name in code.param_names.iter() {
+            try!(self.write_name(name, names));
+        }
+
         assert_eq!(code.flags & PARAM_FLAGS_MASK == HAS_KW_PARAMS,
             !code.kw_params.is_empty());
 
@@ -829,4 +1312,6 @@ types!{
     QUOTE_ONE = 23,
     LIST = 24,
     LAMBDA = 25,
+    VECTOR = 26,
+    OVERLOAD = 27,
 }
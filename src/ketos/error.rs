@@ -1,11 +1,14 @@
 //! Contains consolidated `Error` type.
 
+use std::error::Error as StdError;
 use std::fmt;
 
 use compile::CompileError;
 use encode::{DecodeError, EncodeError};
 use exec::ExecError;
 use io::IoError;
+use module::ModuleLoadError;
+use name::{display_names, NameDisplay, NameDisplayer, NameStore};
 use parser::ParseError;
 
 macro_rules! error_type {
@@ -24,6 +27,14 @@ macro_rules! error_type {
             }
         }
 
+        impl StdError for $name {
+            fn source(&self) -> Option<&(StdError + 'static)> {
+                match *self {
+                    $( $name::$var(ref e) => e.source() ),+
+                }
+            }
+        }
+
         $(
             impl From<$ty> for $name {
                 fn from(e: $ty) -> $name {
@@ -40,6 +51,8 @@ error_type!{
     pub enum Error {
         /// Error in compiling code to bytecode
         CompileError(CompileError),
+        /// Error with additional context
+        ContextError(ContextError),
         /// Error in decoding bytecode file format
         DecodeError(DecodeError),
         /// Error in encoding bytecode file format
@@ -48,7 +61,74 @@ error_type!{
         ExecError(ExecError),
         /// Error in file I/O operation
         IoError(IoError),
+        /// Error in reading the file of a module
+        ModuleLoadError(ModuleLoadError),
         /// Error in scanning text or parsing syntax
         ParseError(ParseError),
     }
 }
+
+impl NameDisplay for Error {
+    fn fmt(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::CompileError(ref e) => NameDisplay::fmt(e, names, f),
+            Error::ExecError(ref e) => NameDisplay::fmt(e, names, f),
+            Error::ModuleLoadError(ref e) => NameDisplay::fmt(e, names, f),
+            Error::ParseError(ref e) => NameDisplay::fmt(e, names, f),
+            ref e => fmt::Display::fmt(e, f)
+        }
+    }
+}
+
+impl Error {
+    /// Wraps the error with a description of the operation during which
+    /// the error occurred.
+    pub fn context<S: Into<String>>(self, context: S) -> Error {
+        Error::ContextError(ContextError{
+            context: context.into(),
+            error: Box::new(self),
+        })
+    }
+
+    /// Returns a wrapper which displays the error with names resolved
+    /// using the given `NameStore`, as with `NameDisplay`.
+    ///
+    /// The `Display` implementation of `Error` does not have access to names
+    /// and omits them from messages.
+    pub fn display_with<'a>(&'a self, names: &'a NameStore) -> NameDisplayer<'a, Error> {
+        display_names(names, self)
+    }
+
+    /// Returns the error which caused this error, if any.
+    ///
+    /// Unlike `source`, this method returns only wrapped ketos errors.
+    pub fn cause(&self) -> Option<&Error> {
+        match *self {
+            Error::ContextError(ref e) => Some(&e.error),
+            Error::ModuleLoadError(ref e) => Some(&e.cause),
+            Error::ExecError(ExecError::DefaultError{ref error, ..}) => Some(error),
+            _ => None
+        }
+    }
+}
+
+/// Error with a description of the operation during which it occurred
+#[derive(Debug)]
+pub struct ContextError {
+    /// Description of the operation during which the error occurred
+    pub context: String,
+    /// Error which occurred
+    pub error: Box<Error>,
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.context)
+    }
+}
+
+impl StdError for ContextError {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        Some(&*self.error)
+    }
+}
@@ -23,21 +23,24 @@
 //! returns a value, which is available to the calling function through the
 //! value register.
 
+use std::error::Error as StdError;
 use std::fmt;
 use std::mem::replace;
 use std::rc::Rc;
 use std::vec::Drain;
 
-use bytecode::{Code, CodeReader};
+use bytecode::{jump_table_lookup, Code, CodeReader, Instruction};
+use condition::{condition_matches, error_kind, Condition};
 use error::Error;
-use function::{Arity, Function, Lambda, SystemFn};
+use function::{Arity, Function, Lambda, Overload, SystemFn};
 use integer::Integer;
 use lexer::{highlight_span, Span};
-use scope::{MasterScope, Scope};
+use range::Range as IntRange;
+use scope::{MasterScope, Scope, YieldAction, YieldHook};
 use string_fmt::FormatError;
 use name::{display_names, get_standard_name, get_system_fn,
     Name, NameDisplay, NameStore};
-use value::{FromValueRef, Value};
+use value::{StructDef, Value};
 
 /// Represents an error generated while executing bytecode.
 #[derive(Debug)]
@@ -51,12 +54,28 @@ pub enum ExecError {
         /// Number of arguments present
         found: u32,
     },
+    /// Incorrect number of bytes in a byte list
+    ByteCount{
+        /// Number of bytes expected
+        expected: usize,
+        /// Number of bytes found
+        found: usize,
+    },
     /// Attempt to compare with a `NaN` `Float` value.
     CompareNaN,
     /// Type does not support ordered comparison
     CannotCompare(&'static str),
     /// Attempt to redefine a name in master scope
     CannotDefine(Name),
+    /// Error raised while evaluating the default value of a parameter
+    DefaultError{
+        /// Name of function, if available
+        function: Option<Name>,
+        /// Name of parameter
+        param: Name,
+        /// Error raised by the default value expression
+        error: Box<Error>,
+    },
     /// Attempt to divide by a number equal to zero.
     DivideByZero,
     /// Duplicate field name in struct definition
@@ -92,12 +111,26 @@ pub enum ExecError {
         /// Formatting error produced
         err: FormatError,
     },
+    /// Integer value out of range of the Rust integer type
+    /// into which it was converted
+    IntegerOutOfRange{
+        /// Name of the integer type
+        expected: &'static str,
+        /// Value which was converted
+        value: Integer,
+    },
     /// Invalid index into closure values
     InvalidClosureValue(u32),
     /// Invalid const index
     InvalidConst(u32),
     /// Invalid (zero) depth value to `Quote`, `Quasiquote`, or `Comma` instruction
     InvalidDepth,
+    /// `UnbindDynamic` instruction without corresponding dynamic bindings
+    InvalidDynamicBinding,
+    /// `PopHandler` instruction without corresponding error handler
+    InvalidHandler,
+    /// `EndCapture` instruction without corresponding output capture
+    InvalidCapture,
     /// Invalid jump label
     InvalidJump(u32),
     /// Slice indices out of order
@@ -117,6 +150,25 @@ pub enum ExecError {
     },
     /// Attempt to lookup a name that did not exist in scope.
     NameError(Name),
+    /// Arithmetic on finite `Float` values produced `NaN` or infinity
+    /// in strict float mode
+    NonFiniteFloat{
+        /// Name of the arithmetic function
+        operation: &'static str,
+        /// Left-hand side operand
+        lhs: f64,
+        /// Right-hand side operand
+        rhs: f64,
+        /// Result of the operation
+        result: f64,
+    },
+    /// Attempt to call a value which is not a function
+    NotCallable{
+        /// Name under which the value was found, if called by name
+        name: Option<Name>,
+        /// Value which was called
+        value: Value,
+    },
     /// Attempt to slice a string not along UTF-8 code point boundaries.
     NotCharBoundary(usize),
     /// Odd number of parameters when keyword-value pairs expected
@@ -125,10 +177,22 @@ pub enum ExecError {
     OutOfBounds(usize),
     /// Integer overflow during certain arithmetic operations.
     Overflow,
+    /// Error in arity to call of an overloaded function
+    OverloadArityError{
+        /// Name of function
+        name: Name,
+        /// Number of arguments accepted by each function, in ascending order
+        expected: Vec<u32>,
+        /// Number of arguments present
+        found: u32,
+    },
     /// Code called `panic`
     Panic(Option<Value>),
     /// Exceeded maximum stack size
     StackOverflow,
+    /// Execution was aborted by a yield hook;
+    /// see `GlobalScope::set_yield_hook`
+    Interrupted,
     /// Struct definition not found
     StructDefError(Name),
     /// Operation performed on unexpected type
@@ -138,6 +202,14 @@ pub enum ExecError {
         /// Name of the type received
         found: &'static str,
     },
+    /// Attempt to compare struct values of the same name created from
+    /// different definitions; e.g. after a struct is defined again
+    StructDefMismatch{
+        /// Definition of left-hand side value
+        lhs: Rc<StructDef>,
+        /// Definition of right-hand side value
+        rhs: Rc<StructDef>,
+    },
     /// Function received a value of incorrect type
     StructMismatch{
         /// Type of left-hand side value
@@ -154,10 +226,20 @@ pub enum ExecError {
     },
     /// Unexpected end in bytecode
     UnexpectedEnd,
+    /// Keyword passed as a rest argument to a function without keyword
+    /// parameters; reported only in strict keyword mode
+    UnexpectedKeyword{
+        /// Name of function, if available
+        name: Option<Name>,
+        /// Keyword passed
+        keyword: Name,
+    },
     /// Unrecognized keyword passed to function
     UnrecognizedKeyword(Name),
     /// Unrecognized opcode
     UnrecognizedOpCode(u8),
+    /// Code called `error`
+    UserError(Condition),
 }
 
 impl ExecError {
@@ -178,10 +260,14 @@ impl fmt::Display for ExecError {
         match *self {
             ArityError{expected, found, ..} =>
                 write!(f, "expected {}; found {}", expected, found),
+            ByteCount{expected, found} =>
+                write!(f, "expected {} bytes; found {}", expected, found),
             CannotCompare(ty) => write!(f, "cannot compare values of type {}", ty),
             CannotDefine(_) =>
                 f.write_str("cannot define name of standard value or operator"),
             CompareNaN => f.write_str("attempt to compare NaN value"),
+            DefaultError{ref error, ..} =>
+                write!(f, "error in parameter default value: {}", error),
             DivideByZero => f.write_str("attempt to divide by zero"),
             DuplicateField(_) => f.write_str("duplicate field"),
             DuplicateKeyword(_) => f.write_str("duplicate keyword"),
@@ -190,9 +276,14 @@ impl fmt::Display for ExecError {
             FieldTypeError{..} => f.write_str("incorrect field type"),
             FormatError{ref err, ..} =>
                 write!(f, "error in string formatting: {}", err),
+            IntegerOutOfRange{expected, ref value} =>
+                write!(f, "integer {} out of range for `{}`", value, expected),
             InvalidClosureValue(n) => write!(f, "invalid closure value: {}", n),
             InvalidConst(n) => write!(f, "invalid const: {}", n),
             InvalidDepth => f.write_str("invalid depth operand"),
+            InvalidDynamicBinding => f.write_str("invalid dynamic binding"),
+            InvalidHandler => f.write_str("invalid error handler"),
+            InvalidCapture => f.write_str("invalid output capture"),
             InvalidJump(label) => write!(f, "invalid jump label: {}", label),
             InvalidSlice(begin, end) => write!(f, "invalid slice {}..{}", begin, end),
             InvalidStack(n) => write!(f, "invalid stack index: {}", n),
@@ -201,21 +292,55 @@ impl fmt::Display for ExecError {
                 write!(f, "system function requires argument count"),
             MissingField{..} => f.write_str("missing field in struct"),
             NameError(_) => f.write_str("name not found in global scope"),
+            NonFiniteFloat{operation, lhs, rhs, result} =>
+                write!(f, "`{}` of {:?} and {:?} produced {}",
+                    operation, lhs, rhs, result),
+            NotCallable{ref value, ..} =>
+                write!(f, "cannot call value of type `{}`", value.type_name()),
             StructDefError(_) => f.write_str("struct definition not found"),
             NotCharBoundary(n) => write!(f, "index not on char boundary: {}", n),
             OddKeywordParams => f.write_str("expected keyword-value pairs"),
             OutOfBounds(n) => write!(f, "index out of bounds: {}", n),
             Overflow => f.write_str("integer overflow"),
+            OverloadArityError{ref expected, found, ..} => {
+                try!(f.write_str("expected "));
+
+                for (i, n) in expected.iter().enumerate() {
+                    if i + 1 == expected.len() && i != 0 {
+                        try!(f.write_str(" or "));
+                    } else if i != 0 {
+                        try!(f.write_str(", "));
+                    }
+                    try!(write!(f, "{}", n));
+                }
+
+                write!(f, " arguments; found {}", found)
+            }
             Panic(_) => f.write_str("panic"),
             StackOverflow => f.write_str("stack overflow"),
+            Interrupted => f.write_str("execution interrupted"),
             TypeError{expected, found} =>
                 write!(f, "type error: expected {}; found {}", expected, found),
+            StructDefMismatch{..} => f.write_str("struct values have different definitions"),
             StructMismatch{..} => f.write_str("incorrect struct type"),
             TypeMismatch{lhs, rhs} =>
                 write!(f, "type mismatch; {} and {}", lhs, rhs),
             UnexpectedEnd => f.write_str("unexpected end of bytecode"),
+            UnexpectedKeyword{..} =>
+                f.write_str("keyword argument to function without keyword parameters"),
             UnrecognizedKeyword(_) => f.write_str("unrecognized keyword argument"),
             UnrecognizedOpCode(n) => write!(f, "unrecognized opcode {} ({:x})", n, n),
+            UserError(ref cond) => f.write_str(&cond.message),
+        }
+    }
+}
+
+impl StdError for ExecError {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match *self {
+            ExecError::DefaultError{ref error, ..} => Some(&**error),
+            ExecError::FormatError{ref err, ..} => Some(err),
+            _ => None
         }
     }
 }
@@ -225,7 +350,8 @@ impl NameDisplay for ExecError {
         use self::ExecError::*;
 
         match *self {
-            ArityError{name: Some(name), ..} =>
+            ArityError{name: Some(name), ..} |
+            OverloadArityError{name, ..} =>
                 write!(f, "`{}` {}", names.get(name), self),
             CannotDefine(name) |
             DuplicateField(name) |
@@ -235,6 +361,22 @@ impl NameDisplay for ExecError {
             StructDefError(name) |
             UnrecognizedKeyword(name) =>
                 write!(f, "{}: {}", self, names.get(name)),
+            UnexpectedKeyword{name, keyword} => {
+                try!(write!(f, "keyword argument `:{}` passed to ", names.get(keyword)));
+                match name {
+                    Some(name) => try!(write!(f, "`{}`", names.get(name))),
+                    None => try!(f.write_str("lambda"))
+                }
+                f.write_str(", which accepts no keyword parameters")
+            }
+            DefaultError{function, param, ref error} => {
+                try!(write!(f, "error in default value of parameter `{}`",
+                    names.get(param)));
+                if let Some(function) = function {
+                    try!(write!(f, " of `{}`", names.get(function)));
+                }
+                write!(f, ": {}", display_names(names, &**error))
+            }
             FieldError{struct_name, field} =>
                 write!(f, "no such field `{}` in struct `{}`",
                     names.get(field),
@@ -261,10 +403,35 @@ impl NameDisplay for ExecError {
                 write!(f, "missing field `{}` in struct `{}`",
                     names.get(field),
                     names.get(struct_name)),
+            NotCallable{name, ref value} => {
+                let ty = value.type_name();
+
+                match name {
+                    Some(name) => try!(write!(f, "`{}` is {} {}, not a function",
+                        names.get(name), indefinite_article(ty), ty)),
+                    None => try!(fmt::Display::fmt(self, f))
+                }
+
+                match *value {
+                    Value::Keyword(kw) =>
+                        write!(f, "; to get a struct field, use `(. value :{})`",
+                            names.get(kw)),
+                    Value::StructDef(ref def) =>
+                        write!(f, "; to create a struct, use `(new {} ...)`",
+                            names.get(def.name)),
+                    _ => Ok(())
+                }
+            }
             Panic(ref value) => match *value {
                 Some(ref v) => write!(f, "panic: {}", display_names(names, v)),
                 None => f.write_str("explicit panic"),
             },
+            UserError(ref cond) => cond.fmt_message(names, f),
+            StructDefMismatch{ref lhs, ref rhs} => {
+                try!(write!(f, "struct type mismatch: values of `{}` have different definitions",
+                    names.get(lhs.name)));
+                fmt_struct_def_diff(names, lhs, rhs, f)
+            }
             StructMismatch{lhs, rhs} =>
                 write!(f, "struct type mismatch: `{}` and `{}`",
                     names.get(lhs),
@@ -274,6 +441,45 @@ impl NameDisplay for ExecError {
     }
 }
 
+/// Describes how the fields of two definitions of a struct differ.
+fn fmt_struct_def_diff(names: &NameStore, lhs: &StructDef, rhs: &StructDef,
+        f: &mut fmt::Formatter) -> fmt::Result {
+    if lhs.fields.iter().eq(rhs.fields.iter()) {
+        return f.write_str("; the definitions have identical fields, \
+            but were defined at different times");
+    }
+
+    for &(name, ty) in lhs.fields.iter() {
+        match rhs.fields.get(name) {
+            None => try!(write!(f, "; field `{}` is only in the left-hand definition",
+                names.get(name))),
+            Some(&rhs_ty) if rhs_ty != ty =>
+                try!(write!(f, "; field `{}` has type `{}` and `{}`",
+                    names.get(name), names.get(ty), names.get(rhs_ty))),
+            Some(_) => ()
+        }
+    }
+
+    for &(name, _) in rhs.fields.iter() {
+        if lhs.fields.get(name).is_none() {
+            try!(write!(f, "; field `{}` is only in the right-hand definition",
+                names.get(name)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the indefinite article, "a" or "an", which precedes the given word.
+fn indefinite_article(word: &str) -> &'static str {
+    match word.chars().next() {
+        Some('a') | Some('e') | Some('i') | Some('o') => "an",
+        // "unit" is pronounced with a consonant sound
+        Some('u') if !word.starts_with("uni") => "an",
+        _ => "a"
+    }
+}
+
 /// Executes a code object and returns the value.
 pub fn execute(scope: &Scope, code: Rc<Code>) -> Result<Value, Error> {
     Machine::new().execute(scope, code)
@@ -285,7 +491,52 @@ pub fn call_function(scope: &Scope, fun: Value, args: Vec<Value>)
     match fun {
         Value::Function(fun) => execute_function(scope, fun, args),
         Value::Lambda(l) => execute_lambda(l, args),
-        ref v => Err(From::from(ExecError::expected("function", v)))
+        Value::Overload(o) => {
+            let n_args = args.len() as u32;
+            let l = try!(get_overload(&o, n_args)).clone();
+            execute_lambda(l, args)
+        }
+        Value::Foreign(fv) => {
+            let mut args = args;
+            fv.call_value(scope, &mut args)
+        }
+        v => Err(From::from(ExecError::NotCallable{
+            name: None,
+            value: v,
+        }))
+    }
+}
+
+/// Returns the function of an `Overload` accepting `n_args` arguments.
+fn get_overload(o: &Overload, n_args: u32) -> Result<&Lambda, ExecError> {
+    o.get(n_args).ok_or_else(|| ExecError::OverloadArityError{
+        name: o.name,
+        expected: o.arities(),
+        found: n_args,
+    })
+}
+
+/// Returns the value to be defined when a named function is defined
+/// by `define-overload`.
+///
+/// If the name refers to an existing function with fixed arity and the new
+/// function also has fixed arity, the functions are combined into an
+/// `Overload`. A function with the same arity as an existing function
+/// replaces that function. Otherwise, the new function replaces the old value.
+fn overload_def(scope: &Scope, name: Name, lambda: Lambda) -> Value {
+    if lambda.code.name != Some(name) || !lambda.code.has_fixed_arity() {
+        return Value::Lambda(lambda);
+    }
+
+    match scope.get_value(name) {
+        Some(Value::Lambda(ref old)) if old.code.name == Some(name) &&
+                old.code.has_fixed_arity() &&
+                old.code.n_params != lambda.code.n_params =>
+            Value::Overload(Rc::new(
+                Overload::new(name, vec![old.clone(), lambda]))),
+        Some(Value::Overload(ref o)) =>
+            Value::Overload(Rc::new(o.with_lambda(lambda))),
+        _ => Value::Lambda(lambda)
     }
 }
 
@@ -310,6 +561,19 @@ pub fn execute_lambda(lambda: Lambda, args: Vec<Value>) -> Result<Value, Error>
     Machine::new().execute_lambda(lambda, args)
 }
 
+/// Returns the overloaded function to be called when a function calls itself
+/// with a number of arguments which it does not accept.
+fn get_self_overload(frame: &StackFrame, n_args: u32) -> Option<Lambda> {
+    if frame.code.arity().accepts(n_args) {
+        return None;
+    }
+
+    match frame.code.name.and_then(|name| frame.scope.get_value(name)) {
+        Some(Value::Overload(ref o)) => o.get(n_args).cloned(),
+        _ => None
+    }
+}
+
 struct StackFrame {
     /// Code object
     code: Rc<Code>,
@@ -325,10 +589,28 @@ struct StackFrame {
     fn_on_stack: bool,
 }
 
+/// Error handler installed by a `guard` expression
+struct Handler {
+    /// Type of errors caught by the handler
+    kind: Name,
+    /// Length of the call stack when the handler was installed
+    frame_depth: usize,
+    /// Length of the value stack when the handler was installed
+    stack_depth: usize,
+    /// Number of dynamic bindings when the handler was installed
+    dynamic_depth: usize,
+    /// Number of output captures when the handler was installed
+    capture_depth: usize,
+    /// Instruction pointer of handler code
+    iptr: u32,
+}
+
 struct Machine {
     stack: Vec<Value>,
     call_stack: Vec<StackFrame>,
+    handlers: Vec<Handler>,
     value: Value,
+    yield_hook: Option<YieldHook>,
 }
 
 impl Machine {
@@ -337,7 +619,9 @@ impl Machine {
             // TODO: Configurable stack limits
             stack: Vec::with_capacity(10240),
             call_stack: Vec::with_capacity(1024),
+            handlers: Vec::new(),
             value: Value::Unit,
+            yield_hook: None,
         }
     }
 
@@ -382,6 +666,9 @@ impl Machine {
             if n_params >= n_args {
                 try!(self.push(Value::Unit));
             } else {
+                if scope.is_strict_keywords() {
+                    try!(self.check_rest_keywords(&lambda.code, n_args - n_params));
+                }
                 try!(self.build_list(n_args - n_params));
                 try!(self.push_value());
             }
@@ -398,9 +685,76 @@ impl Machine {
     }
 
     fn run(&mut self, mut frame: StackFrame) -> Result<Value, Error> {
+        let scope = frame.scope.clone();
+        let depth = scope.dynamic_depth();
+        let capture_depth = scope.get_io().capture_depth();
+
+        self.yield_hook = scope.yield_hook();
+
+        loop {
+            match self.run_frames(&mut frame) {
+                Ok(v) => return Ok(v),
+                Err(e) => {
+                    let e = match self.catch_error(&mut frame, e) {
+                        Ok(()) => continue,
+                        Err(e) => e
+                    };
+
+                    // Remove any dynamic bindings and output captures
+                    // left by unfinished expressions
+                    scope.unbind_dynamic(depth);
+                    scope.get_io().end_captures(capture_depth);
+                    return Err(self.annotate_error(&frame, e));
+                }
+            }
+        }
+    }
+
+    /// Passes an error to the innermost handler which catches it.
+    /// Execution is resumed at the handler code, with the frame in which
+    /// the handler was installed.
+    ///
+    /// If no handler catches the error, it is returned.
+    fn catch_error(&mut self, frame: &mut StackFrame, e: Error) -> Result<(), Error> {
+        // Interruption by the yield hook cannot be caught
+        if error_kind(&e) == ("internal", "interrupted") {
+            return Err(e);
+        }
+
+        while let Some(h) = self.handlers.pop() {
+            if !condition_matches(&frame.scope.borrow_names(), h.kind, &e) {
+                continue;
+            }
+
+            if self.call_stack.len() > h.frame_depth {
+                *frame = self.call_stack.drain(h.frame_depth..).next()
+                    .expect("missing handler frame");
+            }
+
+            self.stack.truncate(h.stack_depth);
+            frame.scope.unbind_dynamic(h.dynamic_depth);
+            frame.scope.get_io().end_captures(h.capture_depth);
+            frame.iptr = h.iptr;
+
+            let cond = Condition::from_error(&mut frame.scope.borrow_names_mut(), e);
+            self.value = Value::new_foreign(cond);
+
+            return Ok(());
+        }
+
+        Err(e)
+    }
+
+    fn run_frames(&mut self, frame: &mut StackFrame) -> Result<Value, Error> {
         use bytecode::Instruction::*;
 
         loop {
+            if let Some(ref hook) = self.yield_hook {
+                if hook.tick() == YieldAction::Abort {
+                    return Err(From::from(ExecError::Interrupted));
+                }
+            }
+
             let instr = {
                 let mut r = CodeReader::new(&frame.code.code, frame.iptr as usize);
                 let instr = try!(r.read_instruction());
@@ -410,23 +764,38 @@ impl Machine {
 
             match instr {
                 Load(n) => try!(self.load(frame.sptr + n)),
-                LoadC(n) => try!(self.load_c(&frame, n)),
+                LoadC(n) => try!(self.load_c(frame, n)),
                 UnboundToUnit(n) => try!(self.unbound_to_unit(frame.sptr + n)),
-                GetDef(n) => try!(self.get_def(&frame, n)),
+                IsBound(n) => try!(self.is_bound(frame.sptr + n)),
+                GetDef(n) => try!(self.get_def(frame, n)),
+                GetDynamic(n) => try!(self.get_dynamic(frame, n)),
+                GetModuleDef(n) => try!(self.get_module_def(frame, n)),
+                DefineModule(n) => try!(self.load_const(&frame.code, n)),
+                WithModule(n) => try!(self.with_module(frame, n)),
                 Push => try!(self.push_value()),
+                Dup => try!(self.dup()),
+                Swap => try!(self.swap()),
                 Unit => self.value = Value::Unit,
                 True => self.value = Value::Bool(true),
                 False => self.value = Value::Bool(false),
                 Const(n) => try!(self.load_const(&frame.code, n)),
                 Store(n) => try!(self.store(frame.sptr + n)),
                 LoadPush(n) => try!(self.load_push(frame.sptr + n)),
-                LoadCPush(n) => try!(self.load_c_push(&frame, n)),
-                GetDefPush(n) => try!(self.get_def_push(&frame, n)),
+                LoadCPush(n) => try!(self.load_c_push(frame, n)),
+                GetDefPush(n) => try!(self.get_def_push(frame, n)),
                 UnitPush => try!(self.push(Value::Unit)),
                 TruePush => try!(self.push(Value::Bool(true))),
                 FalsePush => try!(self.push(Value::Bool(false))),
                 ConstPush(n) => try!(self.push_const(&frame.code, n)),
-                SetDef(n) => try!(self.set_def(&frame, n)),
+                SetDef(n) => try!(self.set_def(frame, n, false)),
+                SetDefOverload(n) => try!(self.set_def(frame, n, true)),
+                SetDynamic(n) => try!(self.set_dynamic(frame, n)),
+                BindDynamic(n) => try!(self.bind_dynamic(frame, n)),
+                UnbindDynamic(n) => try!(self.unbind_dynamic(frame, n)),
+                PushHandler(label, n) => try!(self.push_handler(frame, label, n)),
+                PopHandler(n) => try!(self.pop_handler(n)),
+                BeginCapture => frame.scope.get_io().begin_capture(),
+                EndCapture => try!(self.end_capture(frame)),
                 List(n) => try!(self.build_list(n)),
                 Quote(n) => try!(self.quote_value(n)),
                 Quasiquote(n) => try!(self.quasiquote_value(n)),
@@ -434,28 +803,29 @@ impl Machine {
                 CommaAt(n) => try!(self.comma_at_value(n)),
                 BuildClosure(n_const, n_values) =>
                     try!(self.build_closure(&frame.code, n_const, n_values)),
-                Jump(label) => try!(self.jump(&mut frame, label)),
-                JumpIf(label) => try!(self.jump_if(&mut frame, label)),
+                Jump(label) => try!(self.jump(frame, label)),
+                JumpIf(label) => try!(self.jump_if(frame, label)),
                 JumpIfBound(label, n) => {
                     let n = frame.sptr + n;
-                    try!(self.jump_if_bound(&mut frame, label, n))
+                    try!(self.jump_if_bound(frame, label, n))
                 }
-                JumpIfNot(label) => try!(self.jump_if_not(&mut frame, label)),
-                JumpIfEq(label) => try!(self.jump_if_eq(&mut frame, label)),
-                JumpIfNotEq(label) => try!(self.jump_if_not_eq(&mut frame, label)),
-                JumpIfNull(label) => try!(self.jump_if_null(&mut frame, label)),
-                JumpIfNotNull(label) => try!(self.jump_if_not_null(&mut frame, label)),
+                JumpIfNot(label) => try!(self.jump_if_not(frame, label)),
+                JumpIfEq(label) => try!(self.jump_if_eq(frame, label)),
+                JumpIfNotEq(label) => try!(self.jump_if_not_eq(frame, label)),
+                JumpIfNull(label) => try!(self.jump_if_null(frame, label)),
+                JumpIfNotNull(label) => try!(self.jump_if_not_null(frame, label)),
                 JumpIfEqConst(label, n) =>
-                    try!(self.jump_if_eq_const(&mut frame, label, n)),
+                    try!(self.jump_if_eq_const(frame, label, n)),
                 JumpIfNotEqConst(label, n) =>
-                    try!(self.jump_if_not_eq_const(&mut frame, label, n)),
+                    try!(self.jump_if_not_eq_const(frame, label, n)),
+                JumpTable(n) => try!(self.jump_table(frame, n)),
                 Null => self.is_null(),
                 NotNull => self.is_not_null(),
                 Eq => try!(self.equal()),
                 NotEq => try!(self.not_equal()),
                 EqConst(n) => try!(self.equal_const(&frame.code, n)),
                 NotEqConst(n) => try!(self.not_equal_const(&frame.code, n)),
-                Not => try!(self.negate()),
+                Not => self.negate(),
                 Inc => try!(self.increment()),
                 Dec => try!(self.decrement()),
                 Append => try!(self.append_value()),
@@ -463,31 +833,40 @@ impl Machine {
                 Tail => try!(self.tail()),
                 Init => try!(self.init()),
                 Last => try!(self.last()),
+                IterStart => try!(self.iter_start()),
+                IterFirst => try!(self.iter_first()),
+                IterRest => try!(self.iter_rest()),
                 FirstPush => try!(self.first_push()),
                 TailPush => try!(self.tail_push()),
                 InitPush => try!(self.init_push()),
                 LastPush => try!(self.last_push()),
-                CallSys(n) => try!(self.call_sys(&mut frame, n)),
+                CallSys(n) => try!(self.call_sys(frame, n)),
                 CallSysArgs(n, n_args) =>
-                    try!(self.call_sys_args(&mut frame, n, n_args)),
+                    try!(self.call_sys_args(frame, n, n_args)),
                 CallConst(n, n_args) =>
-                    try!(self.call_const(&mut frame, n, n_args)),
-                Call(n) => try!(self.call_function(&mut frame, n)),
-                Apply(n) => try!(self.apply(&mut frame, n)),
-                CallSelf(n) => try!(self.call_self(&mut frame, n)),
-                TailCall(n) => try!(self.tail_call(&mut frame, n)),
+                    try!(self.call_const(frame, n, n_args)),
+                Call(n) => try!(self.call_function(frame, n)),
+                Apply(n) => try!(self.apply(frame, n)),
+                ApplyWithKeywords(n_kw, n_pos) =>
+                    try!(self.apply_with_keywords(frame, n_kw, n_pos)),
+                CallSelf(n) => try!(self.call_self(frame, n)),
+                TailCall(n) => try!(self.tail_call(frame, n)),
+                TailCallConst(n, n_args) =>
+                    if try!(self.tail_call_const(frame, n, n_args)) {
+                        break;
+                    },
+                TailCallFunction(n) =>
+                    if try!(self.tail_call_function(frame, n)) {
+                        break;
+                    },
                 Skip(n) => try!(self.skip_stack(n as usize)),
-                Return => {
-                    match self.call_stack.pop() {
-                        None => break,
-                        Some(call) => {
-                            self.clean_stack(frame.sptr as usize);
-                            if frame.fn_on_stack {
-                                // Pop one more value for the function
-                                try!(self.pop());
-                            }
-                            frame = call;
-                        }
+                Return | ReturnUnit => {
+                    if instr == ReturnUnit {
+                        self.value = Value::Unit;
+                    }
+
+                    if try!(self.return_frame(frame)) {
+                        break;
                     }
                 }
             }
@@ -496,6 +875,34 @@ impl Machine {
         Ok(self.value.take())
     }
 
+    /// If an error was raised while evaluating the default value of a
+    /// parameter, wraps the error with the names of function and parameter.
+    ///
+    /// `frame` is the innermost frame at the time the error was raised.
+    fn annotate_error(&self, frame: &StackFrame, e: Error) -> Error {
+        let frames = Some(frame).into_iter()
+            .chain(self.call_stack.iter().rev());
+
+        for frame in frames {
+            if let Some(n) = default_param_at(&frame.code, frame.iptr) {
+                let code = &frame.code;
+                let param = if n < code.n_params {
+                    code.param_names[n as usize]
+                } else {
+                    code.kw_params[(n - code.n_params) as usize]
+                };
+
+                return From::from(ExecError::DefaultError{
+                    function: code.name,
+                    param: param,
+                    error: Box::new(e),
+                });
+            }
+        }
+
+        e
+    }
+
     fn build_closure(&mut self, code: &Code, n_const: u32, n_values: u32)
             -> Result<(), ExecError> {
         let (code, scope) = match *try!(get_const(code, n_const)) {
@@ -516,13 +923,14 @@ impl Machine {
         Ok(())
     }
 
-    fn get_sys_fn(&self, n: u32) -> Result<(Name, &'static SystemFn), ExecError> {
-        get_standard_name(n).and_then(|n| get_system_fn(n).map(|f| (n, f)))
+    fn get_sys_fn(&self, frame: &StackFrame, n: u32) -> Result<(Name, SystemFn), ExecError> {
+        get_standard_name(n).and_then(|n| get_system_fn(n).map(|f| (n, *f)))
+            .or_else(|| frame.scope.get_registered_fn(n))
             .ok_or(ExecError::InvalidSystemFn(n))
     }
 
     fn call_sys(&mut self, frame: &mut StackFrame, n: u32) -> Result<(), Error> {
-        let (name, sys_fn) = try!(self.get_sys_fn(n));
+        let (name, sys_fn) = try!(self.get_sys_fn(frame, n));
 
         let n_args = match sys_fn.arity {
             Arity::Exact(n) => n,
@@ -534,8 +942,8 @@ impl Machine {
 
     fn call_sys_args(&mut self, frame: &mut StackFrame, sys_fn: u32, n_args: u32)
             -> Result<(), Error> {
-        let (name, sys_fn) = try!(self.get_sys_fn(sys_fn));
-        self.call_sys_fn(frame, name, sys_fn, n_args, false)
+        let (name, sys_fn) = try!(self.get_sys_fn(frame, sys_fn));
+        self.call_sys_fn(frame, name, &sys_fn, n_args, false)
     }
 
     fn call_sys_fn(&mut self, frame: &mut StackFrame, name: Name,
@@ -568,7 +976,16 @@ impl Machine {
         let v = try!(self.get_value(frame, name));
 
         self.value = Value::Unit;
-        self.call_value(frame, v, n_args, false)
+        self.call_value(frame, Some(name), v, n_args, false)
+    }
+
+    fn tail_call_const(&mut self, frame: &mut StackFrame,
+            n: u32, n_args: u32) -> Result<bool, Error> {
+        let name = try!(get_const_name(&frame.code, n));
+        let v = try!(self.get_value(frame, name));
+
+        self.value = Value::Unit;
+        self.tail_call_value(frame, Some(name), v, n_args, false)
     }
 
     /// Calls a function on the stack with `n_args` arguments.
@@ -576,16 +993,70 @@ impl Machine {
     fn call_function(&mut self, frame: &mut StackFrame, n_args: u32)
             -> Result<(), Error> {
         let v = try!(self.get_stack_top(n_args)).clone();
-        self.call_value(frame, v, n_args, true)
+        self.call_value(frame, None, v, n_args, true)
     }
 
-    fn call_value(&mut self, frame: &mut StackFrame, value: Value,
-            n_args: u32, fn_on_stack: bool) -> Result<(), Error> {
+    fn tail_call_function(&mut self, frame: &mut StackFrame, n_args: u32)
+            -> Result<bool, Error> {
+        let v = try!(self.get_stack_top(n_args)).clone();
+        self.tail_call_value(frame, None, v, n_args, true)
+    }
+
+    /// Calls a function value in tail position. If the value is a lambda,
+    /// it replaces the current stack frame; otherwise, it is called normally
+    /// and the current function returns its result.
+    ///
+    /// Returns `Ok(true)` if the outermost frame has returned.
+    fn tail_call_value(&mut self, frame: &mut StackFrame, name: Option<Name>,
+            value: Value, n_args: u32, fn_on_stack: bool) -> Result<bool, Error> {
+        let lambda = match value {
+            Value::Lambda(fun) => fun,
+            Value::Overload(ref o) => try!(get_overload(o, n_args)).clone(),
+            value => {
+                try!(self.call_value(frame, name, value, n_args, fn_on_stack));
+                return self.return_frame(frame).map_err(From::from);
+            }
+        };
+
+        let scope = lambda.scope.upgrade()
+            .expect("Lambda scope has been destroyed");
+
+        let len = self.stack.len();
+
+        if len < frame.sptr as usize + n_args as usize {
+            return Err(From::from(ExecError::InvalidStack(len as u32)));
+        }
+
+        // Remove values belonging to the current frame, including the called
+        // function if it is on the stack. The arguments take their place.
+        let start = frame.sptr as usize;
+        let end = len - n_args as usize;
+
+        let _ = self.stack.drain(start..end);
+
+        try!(self.setup_call(&scope, &lambda.code, n_args));
+
+        frame.code = lambda.code;
+        frame.scope = scope;
+        frame.values = lambda.values;
+        frame.iptr = 0;
+
+        Ok(false)
+    }
+
+    /// Calls a function value with `n_args` arguments on the stack.
+    /// `name` is the name under which the value was found, if any.
+    fn call_value(&mut self, frame: &mut StackFrame, name: Option<Name>,
+            value: Value, n_args: u32, fn_on_stack: bool) -> Result<(), Error> {
         match value {
             Value::Function(fun) =>
                 self.call_sys_fn(frame, fun.name, &fun.sys_fn, n_args, fn_on_stack),
             Value::Lambda(fun) =>
                 self.call_lambda(frame, fun, n_args, fn_on_stack),
+            Value::Overload(ref o) => {
+                let fun = try!(get_overload(o, n_args)).clone();
+                self.call_lambda(frame, fun, n_args, fn_on_stack)
+            }
             Value::Foreign(ref fv) => {
                 let mut args = try!(self.drain_stack_top(n_args))
                     .collect::<Vec<_>>();
@@ -599,7 +1070,10 @@ impl Machine {
 
                 Ok(())
             }
-            ref v => Err(From::from(ExecError::expected("function", v)))
+            v => Err(From::from(ExecError::NotCallable{
+                name: name,
+                value: v,
+            }))
         }
     }
 
@@ -612,7 +1086,7 @@ impl Machine {
             return Err(From::from(ExecError::StackOverflow));
         }
 
-        let n_args = try!(self.setup_call(&lambda.code, n_args));
+        let n_args = try!(self.setup_call(&scope, &lambda.code, n_args));
 
         let old_frame = replace(frame, StackFrame{
             code: lambda.code,
@@ -633,7 +1107,8 @@ impl Machine {
     /// are pushed to the stack, if necessary.
     ///
     /// Returns the final count of stack argument values.
-    fn setup_call(&mut self, code: &Code, mut n_args: u32) -> Result<u32, Error> {
+    fn setup_call(&mut self, scope: &Scope, code: &Code, mut n_args: u32)
+            -> Result<u32, Error> {
         if n_args < code.req_params {
             return Err(From::from(ExecError::ArityError{
                 name: code.name,
@@ -649,6 +1124,9 @@ impl Machine {
 
         if code.has_rest_params() {
             if n_args > code.n_params {
+                if scope.is_strict_keywords() {
+                    try!(self.check_rest_keywords(code, n_args - code.n_params));
+                }
                 try!(self.build_list(n_args - code.n_params));
                 try!(self.push_value());
                 n_args = code.n_params + 1;
@@ -698,6 +1176,26 @@ impl Machine {
         Ok(n_args)
     }
 
+    /// Returns an error if any of the `n` values on the top of the stack,
+    /// which are to be collected into the rest parameter of `code`,
+    /// is a keyword.
+    fn check_rest_keywords(&self, code: &Code, n: u32) -> Result<(), ExecError> {
+        let len = self.stack.len();
+        let start = try!(len.checked_sub(n as usize)
+            .ok_or(ExecError::InvalidStack(len as u32)));
+
+        match self.stack[start..].iter().filter_map(|v| match *v {
+            Value::Keyword(kw) => Some(kw),
+            _ => None
+        }).next() {
+            Some(kw) => Err(ExecError::UnexpectedKeyword{
+                name: code.name,
+                keyword: kw,
+            }),
+            None => Ok(())
+        }
+    }
+
     fn apply(&mut self, frame: &mut StackFrame, mut n_args: u32) -> Result<(), Error> {
         let v = self.value.take();
 
@@ -713,7 +1211,34 @@ impl Machine {
         self.call_function(frame, n_args)
     }
 
+    fn apply_with_keywords(&mut self, frame: &mut StackFrame,
+            n_kw_pairs: u32, n_positional: u32) -> Result<(), Error> {
+        let kw_args = try!(self.drain_stack_top(n_kw_pairs * 2)).collect::<Vec<_>>();
+
+        for pair in kw_args.chunks(2) {
+            try!(get_keyword(&pair[0]));
+        }
+
+        let mut n_args = n_positional + kw_args.len() as u32;
+
+        match self.value.take() {
+            Value::Unit => (),
+            Value::List(li) => {
+                n_args += li.len() as u32;
+                try!(self.push_iter(li.to_vec()));
+            }
+            ref v => return Err(From::from(ExecError::expected("list", v)))
+        }
+
+        try!(self.push_iter(kw_args));
+        self.call_function(frame, n_args)
+    }
+
     fn call_self(&mut self, frame: &mut StackFrame, n: u32) -> Result<(), Error> {
+        if let Some(lambda) = get_self_overload(frame, n) {
+            return self.call_lambda(frame, lambda, n, false);
+        }
+
         let lambda = Lambda{
             code: frame.code.clone(),
             scope: Rc::downgrade(&frame.scope),
@@ -736,11 +1261,36 @@ impl Machine {
         let _ = self.stack.drain(start..end);
         frame.iptr = 0;
 
-        try!(self.setup_call(&frame.code, n_args));
+        if let Some(lambda) = get_self_overload(frame, n_args) {
+            frame.scope = lambda.scope.upgrade()
+                .expect("Lambda scope has been destroyed");
+            frame.code = lambda.code;
+            frame.values = lambda.values;
+        }
+
+        try!(self.setup_call(&frame.scope, &frame.code, n_args));
 
         Ok(())
     }
 
+    /// Returns from the current function to its caller.
+    ///
+    /// Returns `Ok(true)` if the outermost frame has returned.
+    fn return_frame(&mut self, frame: &mut StackFrame) -> Result<bool, ExecError> {
+        match self.call_stack.pop() {
+            None => Ok(true),
+            Some(call) => {
+                self.clean_stack(frame.sptr as usize);
+                if frame.fn_on_stack {
+                    // Pop one more value for the function
+                    try!(self.pop());
+                }
+                *frame = call;
+                Ok(false)
+            }
+        }
+    }
+
     /// Cleans the stack when returning from a function.
     /// All values `stack[pos..]` are removed.
     fn clean_stack(&mut self, pos: usize) {
@@ -830,6 +1380,15 @@ impl Machine {
         Ok(())
     }
 
+    fn is_bound(&mut self, n: u32) -> Result<(), ExecError> {
+        let bound = match *try!(self.get_stack(n)) {
+            Value::Unbound => false,
+            _ => true
+        };
+        self.value = Value::Bool(bound);
+        Ok(())
+    }
+
     /// Store value on the stack.
     fn store(&mut self, n: u32) -> Result<(), ExecError> {
         let v = self.value.take();
@@ -850,6 +1409,12 @@ impl Machine {
     fn get_value(&self, frame: &StackFrame, name: Name) -> Result<Value, ExecError> {
         MasterScope::get(name)
             .or_else(|| frame.scope.get_value(name))
+            .or_else(|| frame.scope.find_registered_fn(name)
+                .map(|(_, sys_fn)| Value::Function(Function{
+                    name: name,
+                    sys_fn: sys_fn,
+                })))
+            .or_else(|| frame.scope.resolve_unbound_name(name))
             .ok_or(ExecError::NameError(name))
     }
 
@@ -859,7 +1424,7 @@ impl Machine {
         self.push(v)
     }
 
-    fn set_def(&mut self, frame: &StackFrame, n: u32) -> Result<(), ExecError> {
+    fn set_def(&mut self, frame: &StackFrame, n: u32, overload: bool) -> Result<(), ExecError> {
         let name = try!(get_const_name(&frame.code, n));
 
         if !MasterScope::can_define(name) {
@@ -867,12 +1432,96 @@ impl Machine {
         }
 
         // Resulting value is the definition name
-        let v = replace(&mut self.value, Value::Name(name));
+        let v = match replace(&mut self.value, Value::Name(name)) {
+            Value::Lambda(l) if overload => overload_def(&frame.scope, name, l),
+            v => v
+        };
 
         frame.scope.add_value(name, v);
         Ok(())
     }
 
+    /// Load the active value of a dynamic variable named by a const value.
+    fn get_dynamic(&mut self, frame: &StackFrame, n: u32) -> Result<(), ExecError> {
+        let name = try!(get_const_name(&frame.code, n));
+        self.value = try!(frame.scope.get_dynamic(name)
+            .ok_or(ExecError::NameError(name)));
+
+        Ok(())
+    }
+
+    /// Loads a value imported by reference from another module.
+    /// The value is found in the module currently registered under
+    /// the module name, so that a module which is loaded again
+    /// provides its new values.
+    fn get_module_def(&mut self, frame: &StackFrame, n: u32) -> Result<(), Error> {
+        let (mod_name, name) = match *try!(get_const(&frame.code, n)) {
+            Value::List(ref li) if li.len() == 2 => {
+                match (&li[0], &li[1]) {
+                    (&Value::Name(m), &Value::Name(name)) => (m, name),
+                    _ => return Err(From::from(ExecError::InvalidConst(n)))
+                }
+            }
+            _ => return Err(From::from(ExecError::InvalidConst(n)))
+        };
+
+        try!(frame.scope.check_import_policy(mod_name));
+        let m = try!(frame.scope.get_modules().get_module(mod_name, &frame.scope));
+        let src = m.scope.get_export(name).unwrap_or(name);
+
+        self.value = try!(m.scope.get_value(src)
+            .ok_or(ExecError::NameError(name)));
+
+        Ok(())
+    }
+
+    fn with_module(&mut self, frame: &StackFrame, n: u32) -> Result<(), Error> {
+        let mod_name = try!(get_const_name(&frame.code, n));
+        try!(frame.scope.check_import_policy(mod_name));
+        let m = try!(frame.scope.get_modules().get_module(mod_name, &frame.scope));
+
+        let code = match self.value {
+            Value::Lambda(ref l) => l.code.clone(),
+            ref v => return Err(From::from(ExecError::expected("lambda", v)))
+        };
+
+        self.value = Value::Lambda(Lambda::new(code, &m.scope));
+        Ok(())
+    }
+
+    fn set_dynamic(&mut self, frame: &StackFrame, n: u32) -> Result<(), ExecError> {
+        let name = try!(get_const_name(&frame.code, n));
+
+        if !MasterScope::can_define(name) {
+            return Err(ExecError::CannotDefine(name));
+        }
+
+        // Resulting value is the definition name
+        let v = replace(&mut self.value, Value::Name(name));
+
+        frame.scope.set_dynamic(name, v);
+        Ok(())
+    }
+
+    fn bind_dynamic(&mut self, frame: &StackFrame, n: u32) -> Result<(), ExecError> {
+        let name = try!(get_const_name(&frame.code, n));
+        let v = self.value.take();
+
+        frame.scope.bind_dynamic(name, v);
+        Ok(())
+    }
+
+    fn unbind_dynamic(&mut self, frame: &StackFrame, n: u32) -> Result<(), ExecError> {
+        let depth = frame.scope.dynamic_depth();
+
+        if n as usize > depth {
+            return Err(ExecError::InvalidDynamicBinding);
+        }
+
+        frame.scope.unbind_dynamic(depth - n as usize);
+        Ok(())
+    }
+
     /// Pop from the top of the stack and return the value.
     fn pop(&mut self) -> Result<Value, ExecError> {
         self.stack.pop().ok_or(ExecError::InvalidStack(0))
@@ -960,6 +1609,22 @@ impl Machine {
             .ok_or(ExecError::InvalidStack(len as u32))
     }
 
+    fn dup(&mut self) -> Result<(), ExecError> {
+        let v = try!(self.stack.last().cloned().ok_or(ExecError::InvalidStack(0)));
+        self.push(v)
+    }
+
+    fn swap(&mut self) -> Result<(), ExecError> {
+        let len = self.stack.len();
+
+        if len < 2 {
+            return Err(ExecError::InvalidStack(len as u32));
+        }
+
+        self.stack.swap(len - 1, len - 2);
+        Ok(())
+    }
+
     fn load_push(&mut self, n: u32) -> Result<(), ExecError> {
         let v = try!(self.get_stack(n)).clone();
         self.push(v)
@@ -970,6 +1635,48 @@ impl Machine {
         self.push(v)
     }
 
+    fn push_handler(&mut self, frame: &StackFrame, label: u32, n: u32)
+            -> Result<(), ExecError> {
+        let kind = match *try!(get_const(&frame.code, n)) {
+            Value::Keyword(kind) => kind,
+            ref v => return Err(ExecError::expected("keyword", v))
+        };
+
+        if label as usize >= frame.code.code.len() {
+            return Err(ExecError::InvalidJump(label));
+        }
+
+        self.handlers.push(Handler{
+            kind: kind,
+            frame_depth: self.call_stack.len(),
+            stack_depth: self.stack.len(),
+            dynamic_depth: frame.scope.dynamic_depth(),
+            capture_depth: frame.scope.get_io().capture_depth(),
+            iptr: label,
+        });
+
+        Ok(())
+    }
+
+    fn pop_handler(&mut self, n: u32) -> Result<(), ExecError> {
+        let len = self.handlers.len();
+
+        if n as usize > len {
+            return Err(ExecError::InvalidHandler);
+        }
+
+        self.handlers.truncate(len - n as usize);
+        Ok(())
+    }
+
+    fn end_capture(&mut self, frame: &StackFrame) -> Result<(), ExecError> {
+        let s = try!(frame.scope.get_io().end_capture()
+            .ok_or(ExecError::InvalidCapture));
+
+        self.value = s.into();
+        Ok(())
+    }
+
     fn jump(&mut self, frame: &mut StackFrame, label: u32) -> Result<(), ExecError> {
         if label as usize >= frame.code.code.len() {
             Err(ExecError::InvalidJump(label))
@@ -980,7 +1687,7 @@ impl Machine {
     }
 
     fn jump_if(&mut self, frame: &mut StackFrame, label: u32) -> Result<(), ExecError> {
-        if try!(get_bool(&self.value)) {
+        if self.value.is_truthy() {
             self.jump(frame, label)
         } else {
             Ok(())
@@ -1009,7 +1716,7 @@ impl Machine {
     }
 
     fn jump_if_not(&mut self, frame: &mut StackFrame, label: u32) -> Result<(), ExecError> {
-        if try!(get_bool(&self.value)) {
+        if self.value.is_truthy() {
             Ok(())
         } else {
             self.jump(frame, label)
@@ -1047,13 +1754,25 @@ impl Machine {
     fn jump_if_not_eq_const(&mut self, frame: &mut StackFrame, label: u32, n: u32) -> Result<(), ExecError> {
         let eq = try!(get_const(&frame.code, n).and_then(|v| self.value.is_equal(v)));
 
-        if !eq {
+        if eq {
             Ok(())
         } else {
             self.jump(frame, label)
         }
     }
 
+    fn jump_table(&mut self, frame: &mut StackFrame, n: u32) -> Result<(), ExecError> {
+        let label = match self.value {
+            Value::String(ref s) => {
+                let table = try!(get_const(&frame.code, n));
+                try!(jump_table_lookup(table, s).ok_or(ExecError::InvalidConst(n)))
+            }
+            _ => return Ok(())
+        };
+
+        self.jump(frame, label)
+    }
+
     fn is_null(&mut self) {
         let null = match self.value {
             Value::Unit => true,
@@ -1100,12 +1819,8 @@ impl Machine {
         Ok(())
     }
 
-    fn negate(&mut self) -> Result<(), ExecError> {
-        match self.value {
-            Value::Bool(ref mut v) => *v = !*v,
-            ref v => return Err(ExecError::expected("bool", v))
-        }
-        Ok(())
+    fn negate(&mut self) {
+        self.value = Value::Bool(!self.value.is_truthy());
     }
 
     fn increment(&mut self) -> Result<(), ExecError> {
@@ -1158,6 +1873,52 @@ impl Machine {
         Ok(())
     }
 
+    fn iter_start(&mut self) -> Result<(), ExecError> {
+        let v = match self.value {
+            Value::Unit | Value::List(_) => return Ok(()),
+            // Lists and vectors share the same storage
+            Value::Vector(ref v) => v.clone().into(),
+            ref v => match IntRange::from_value(v) {
+                Some(r) if r.is_empty() => Value::Unit,
+                Some(_) => return Ok(()),
+                None => return Err(ExecError::expected("list, vector, or range", v))
+            }
+        };
+
+        self.value = v;
+        Ok(())
+    }
+
+    fn iter_first(&mut self) -> Result<(), ExecError> {
+        let v = match self.value {
+            Value::List(ref li) => li[0].clone(),
+            ref v => match IntRange::from_value(v) {
+                Some(r) => r.start.into(),
+                None => return Err(ExecError::expected("list or range", v))
+            }
+        };
+
+        self.value = v;
+        Ok(())
+    }
+
+    fn iter_rest(&mut self) -> Result<(), ExecError> {
+        let v = match self.value {
+            Value::List(ref li) => li.slice(1..).into(),
+            ref v => match IntRange::from_value(v) {
+                Some(r) => match r.get(1) {
+                    Some(start) => Value::new_foreign(
+                        IntRange{start: start, end: r.end, step: r.step}),
+                    None => Value::Unit
+                },
+                None => return Err(ExecError::expected("list or range", v))
+            }
+        };
+
+        self.value = v;
+        Ok(())
+    }
+
     fn init(&mut self) -> Result<(), ExecError> {
         let v = match self.value {
             Value::List(ref li) => li.slice(..li.len() - 1),
@@ -1215,8 +1976,33 @@ impl Machine {
     }
 }
 
-fn get_bool(v: &Value) -> Result<bool, ExecError> {
-    FromValueRef::from_value_ref(v)
+/// Returns the index of the parameter whose default value is computed by
+/// the instruction ending at `iptr`, if any.
+///
+/// Default values are computed at the very beginning of a function body.
+/// For each parameter with a default value, the compiler emits a
+/// `JumpIfBound` instruction which skips over the default value code.
+fn default_param_at(code: &Code, iptr: u32) -> Option<u32> {
+    if code.req_params == code.n_params && code.kw_params.is_empty() {
+        return None;
+    }
+
+    let mut r = CodeReader::new(&code.code, 0);
+
+    loop {
+        match r.read_instruction() {
+            Ok(Instruction::UnboundToUnit(_)) |
+            Ok(Instruction::IsBound(_)) |
+            Ok(Instruction::Push) => (),
+            Ok(Instruction::JumpIfBound(label, n)) => {
+                if iptr > r.get_offset() as u32 && iptr <= label {
+                    return Some(n);
+                }
+                r = CodeReader::new(&code.code, label as usize);
+            }
+            _ => return None
+        }
+    }
 }
 
 fn get_const(code: &Code, n: u32) -> Result<&Value, ExecError> {
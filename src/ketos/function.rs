@@ -9,11 +9,17 @@ use std::rc::Rc;
 use num::{Float, Zero};
 
 use bytecode::Code;
+use compile::operator_usage;
+use condition::Condition;
 use error::Error;
-use exec::ExecError;
+use exec::{call_function, ExecError};
 use integer::{Integer, Ratio};
-use name::{Name, NameMap, NUM_SYSTEM_FNS};
+use io::SharedWrite;
+use name::{debug_names, display_names, is_system_fn, Name, NameMap, NUM_SYSTEM_FNS};
+use range::Range as IntRange;
+use rc_vec::RcVec;
 use scope::{Scope, WeakScope};
+use string::{eq_folded, escape_string, starts_with_folded, unescape_string};
 use string_fmt::format_string;
 use value::{FromValueRef, Struct, StructDef, Value};
 
@@ -83,6 +89,21 @@ pub static SYSTEM_FNS: [SystemFn; NUM_SYSTEM_FNS] = [
     sys_fn!(fn_tail,        Exact(1)),
     sys_fn!(fn_list,        Min(0)),
     sys_fn!(fn_reverse,     Exact(1)),
+    sys_fn!(fn_apply_n,     Exact(3)),
+    sys_fn!(fn_apply_first, Exact(2)),
+    sys_fn!(fn_apply_rest,  Exact(2)),
+    sys_fn!(fn_scan,        Exact(3)),
+    sys_fn!(fn_iterate,     Exact(3)),
+    sys_fn!(fn_range,       Range(1, 3)),
+    sys_fn!(fn_range_list,  Exact(1)),
+    sys_fn!(fn_contains,    Exact(2)),
+    sys_fn!(fn_vector,      Min(0)),
+    sys_fn!(fn_vec_ref,     Exact(2)),
+    sys_fn!(fn_vec_set,     Exact(3)),
+    sys_fn!(fn_vec_len,     Exact(1)),
+    sys_fn!(fn_vec_slice,   Exact(3)),
+    sys_fn!(fn_vec_to_list, Exact(1)),
+    sys_fn!(fn_list_to_vec, Exact(1)),
     sys_fn!(fn_abs,         Exact(1)),
     sys_fn!(fn_ceil,        Exact(1)),
     sys_fn!(fn_floor,       Exact(1)),
@@ -90,6 +111,12 @@ pub static SYSTEM_FNS: [SystemFn; NUM_SYSTEM_FNS] = [
     sys_fn!(fn_trunc,       Exact(1)),
     sys_fn!(fn_int,         Exact(1)),
     sys_fn!(fn_float,       Exact(1)),
+    sys_fn!(fn_float_bits,  Exact(1)),
+    sys_fn!(fn_float_to_bytes, Exact(2)),
+    sys_fn!(fn_bytes_to_float, Exact(2)),
+    sys_fn!(fn_integer_bits, Exact(2)),
+    sys_fn!(fn_integer_to_bytes, Exact(2)),
+    sys_fn!(fn_bytes_to_integer, Exact(2)),
     sys_fn!(fn_inf,         Min(0)),
     sys_fn!(fn_nan,         Min(0)),
     sys_fn!(fn_denom,       Exact(1)),
@@ -97,19 +124,47 @@ pub static SYSTEM_FNS: [SystemFn; NUM_SYSTEM_FNS] = [
     sys_fn!(fn_numer,       Exact(1)),
     sys_fn!(fn_rat,         Range(1, 2)),
     sys_fn!(fn_recip,       Exact(1)),
+    sys_fn!(fn_set_float_mode, Exact(1)),
     sys_fn!(fn_chars,       Exact(1)),
     sys_fn!(fn_string,      Exact(1)),
+    sys_fn!(fn_string_from_chars, Exact(1)),
+    sys_fn!(fn_map_string,  Exact(2)),
+    sys_fn!(fn_string_len,  Exact(1)),
+    sys_fn!(fn_string_byte_len, Exact(1)),
+    sys_fn!(fn_string_escape, Exact(1)),
+    sys_fn!(fn_string_unescape, Exact(1)),
+    sys_fn!(fn_string_escape_html, Exact(1)),
+    sys_fn!(fn_string_ci_eq, Exact(2)),
+    sys_fn!(fn_starts_with, Exact(2)),
+    sys_fn!(fn_starts_with_ci, Exact(2)),
     sys_fn!(fn_id,          Exact(1)),
     sys_fn!(fn_is,          Exact(2)),
     sys_fn!(fn_is_instance, Exact(2)),
     sys_fn!(fn_null,        Exact(1)),
+    sys_fn!(fn_is_list,     Exact(1)),
+    sys_fn!(fn_is_defined,  Exact(1)),
+    sys_fn!(fn_is_macro_defined, Exact(1)),
+    sys_fn!(fn_current_module, Exact(0)),
+    sys_fn!(fn_exports_of,  Range(1, 2)),
+    sys_fn!(fn_doc,         Exact(1)),
+    sys_fn!(fn_keyword_namespace, Exact(1)),
+    sys_fn!(fn_keyword_name, Exact(1)),
+    sys_fn!(fn_error,       Min(1)),
+    sys_fn!(fn_error_message, Exact(1)),
+    sys_fn!(fn_error_irritants, Exact(1)),
+    sys_fn!(fn_error_category, Exact(1)),
+    sys_fn!(fn_error_kind, Exact(1)),
     sys_fn!(fn_type_of,     Exact(1)),
     sys_fn!(fn_dot,         Exact(2)),
     sys_fn!(fn_dot_eq,      Min(1)),
     sys_fn!(fn_new,         Min(1)),
+    sys_fn!(fn_is_struct,   Exact(2)),
+    sys_fn!(fn_struct_type, Exact(1)),
     sys_fn!(fn_format,      Min(1)),
     sys_fn!(fn_print,       Min(1)),
     sys_fn!(fn_println,     Min(1)),
+    sys_fn!(fn_display,     Exact(1)),
+    sys_fn!(fn_write,       Exact(1)),
     sys_fn!(fn_panic,       Range(0, 1)),
     sys_fn!(fn_xor,         Exact(2)),
     sys_fn!(fn_not,         Exact(1)),
@@ -129,24 +184,40 @@ pub enum Arity {
 impl Arity {
     /// Returns whether this arity may accept `n` arguments.
     pub fn accepts(&self, n: u32) -> bool {
+        self.contains(n)
+    }
+
+    /// Returns whether `n` is within the range of accepted argument counts.
+    pub fn contains(&self, n: u32) -> bool {
+        n >= self.min() && self.max().map_or(true, |max| n <= max)
+    }
+
+    /// Returns the minimum number of arguments accepted.
+    pub fn min(&self) -> u32 {
+        match *self {
+            Arity::Exact(n) | Arity::Min(n) | Arity::Range(n, _) => n
+        }
+    }
+
+    /// Returns the maximum number of arguments accepted,
+    /// or `None` if there is no limit.
+    pub fn max(&self) -> Option<u32> {
         match *self {
-            Arity::Exact(num) => n == num,
-            Arity::Min(min) => n >= min,
-            Arity::Range(min, max) => n >= min && n <= max,
+            Arity::Exact(n) | Arity::Range(_, n) => Some(n),
+            Arity::Min(_) => None,
         }
     }
 }
 
+/// Describes the accepted number of arguments; e.g. `exactly 2 arguments`,
+/// `at least 1 argument`, or `between 2 and 3 arguments`.
 impl fmt::Display for Arity {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Arity::Exact(n) => write!(f, "{} argument{}", n, plural(n)),
+            Arity::Exact(n) => write!(f, "exactly {} argument{}", n, plural(n)),
             Arity::Min(n) => write!(f, "at least {} argument{}", n, plural(n)),
-            Arity::Range(min, max) => if min + 1 == max {
-                write!(f, "{} or {} arguments", min, max)
-            } else {
-                write!(f, "between {} and {} arguments", min, max)
-            }
+            Arity::Range(min, max) =>
+                write!(f, "between {} and {} arguments", min, max),
         }
     }
 }
@@ -193,6 +264,8 @@ pub struct Lambda {
 impl Lambda {
     /// Creates a new `Lambda`.
     pub fn new(code: Rc<Code>, scope: &Scope) -> Lambda {
+        scope.track_code(&code);
+
         Lambda{
             code: code,
             scope: Rc::downgrade(scope),
@@ -228,6 +301,63 @@ impl PartialEq for Lambda {
     }
 }
 
+/// Represents a set of functions defined under a single name,
+/// each accepting a distinct, fixed number of arguments.
+///
+/// Calls to an `Overload` are dispatched to the function accepting
+/// the number of arguments given.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overload {
+    /// Function name
+    pub name: Name,
+    /// Functions, in ascending order of parameter count
+    pub lambdas: Vec<Lambda>,
+}
+
+impl Overload {
+    /// Creates a new `Overload` from a set of functions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any function does not accept a fixed number of arguments
+    /// or if more than one function accepts the same number of arguments.
+    pub fn new(name: Name, mut lambdas: Vec<Lambda>) -> Overload {
+        lambdas.sort_by_key(|l| l.code.n_params);
+
+        assert!(lambdas.iter().all(|l| l.code.has_fixed_arity()),
+            "overloaded function must have fixed arity");
+        assert!(lambdas.windows(2).all(|w| w[0].code.n_params != w[1].code.n_params),
+            "overloaded functions must have distinct arity");
+
+        Overload{
+            name: name,
+            lambdas: lambdas,
+        }
+    }
+
+    /// Returns the function accepting `n_args` arguments, if any.
+    pub fn get(&self, n_args: u32) -> Option<&Lambda> {
+        self.lambdas.iter().find(|l| l.code.n_params == n_args)
+    }
+
+    /// Returns the number of arguments accepted by each function,
+    /// in ascending order.
+    pub fn arities(&self) -> Vec<u32> {
+        self.lambdas.iter().map(|l| l.code.n_params).collect()
+    }
+
+    /// Returns a new `Overload` with the given function added,
+    /// replacing any existing function with the same arity.
+    pub fn with_lambda(&self, lambda: Lambda) -> Overload {
+        let n = lambda.code.n_params;
+        let mut lambdas = self.lambdas.iter()
+            .filter(|l| l.code.n_params != n).cloned().collect::<Vec<_>>();
+
+        lambdas.push(lambda);
+        Overload::new(self.name, lambdas)
+    }
+}
+
 fn get_float(v: &Value) -> Result<f64, ExecError> {
     FromValueRef::from_value_ref(v)
 }
@@ -239,6 +369,17 @@ fn get_keyword(v: &Value) -> Result<Name, ExecError> {
     }
 }
 
+fn get_condition(v: &Value) -> Result<&Condition, ExecError> {
+    match *v {
+        Value::Foreign(ref f) => f.downcast_ref::<Condition>()
+            .ok_or_else(|| ExecError::TypeError{
+                expected: "condition",
+                found: f.type_name(),
+            }),
+        ref v => Err(ExecError::expected("condition", v))
+    }
+}
+
 fn get_name(v: &Value) -> Result<Name, ExecError> {
     match *v {
         Value::Name(name) => Ok(name),
@@ -290,8 +431,10 @@ fn value_is(scope: &Scope, a: &Value, ty: Name) -> bool {
     use name::standard_names::*;
 
     match *a {
+        _ if ty == ANY => true,
         Value::Float(_) | Value::Integer(_) | Value::Ratio(_)
             if ty == NUMBER => true,
+        Value::Struct(ref s) if s.def.variant_of == Some(ty) => true,
         Value::Unit | Value::List(_) if ty == LIST => true,
         Value::Foreign(ref a) =>
             scope.with_name(ty, |name| a.is_type(name)),
@@ -329,10 +472,43 @@ fn coerce_numbers(lhs: Value, rhs: &Value) -> Result<(Value, Cow<Value>), ExecEr
     Ok((lhs, rhs))
 }
 
+/// Performs an arithmetic operation on two numbers. In strict float mode,
+/// returns an error if the operation produces `NaN` or infinity
+/// from finite operands.
+fn float_op<F>(scope: &Scope, operation: &'static str, lhs: Value, rhs: &Value, f: F)
+        -> Result<Value, Error> where F: FnOnce(Value, &Value) -> Result<Value, Error> {
+    if !scope.is_strict_float() {
+        return f(lhs, rhs);
+    }
+
+    let a = float_operand(&lhs);
+    let b = float_operand(rhs);
+
+    match try!(f(lhs, rhs)) {
+        Value::Float(r) if !r.is_finite() && a.is_finite() && b.is_finite() =>
+            Err(From::from(ExecError::NonFiniteFloat{
+                operation: operation,
+                lhs: a,
+                rhs: b,
+                result: r,
+            })),
+        r => Ok(r)
+    }
+}
+
+fn float_operand(v: &Value) -> f64 {
+    match *v {
+        Value::Float(f) => f,
+        Value::Integer(ref i) => i.to_f64().unwrap_or(f64::INFINITY),
+        Value::Ratio(ref r) => r.to_f64().unwrap_or(f64::INFINITY),
+        _ => f64::NAN
+    }
+}
+
 /// `+` returns the sum of all arguments.
 ///
 /// Given no arguments, returns the additive identity, `0`.
-fn fn_add(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+fn fn_add(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     if args.is_empty() {
         return Ok(Integer::zero().into());
     }
@@ -343,7 +519,7 @@ fn fn_add(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
 
     for arg in &args[1..] {
         try!(expect_number(arg));
-        v = try!(add_number(v, arg));
+        v = try!(float_op(scope, "+", v, arg, add_number));
     }
 
     Ok(v)
@@ -364,7 +540,7 @@ fn add_number(lhs: Value, rhs: &Value) -> Result<Value, Error> {
 }
 
 /// `-` returns the cumulative difference between successive arguments.
-fn fn_sub(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+fn fn_sub(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let mut v = args[0].take();
 
     if args.len() == 1 {
@@ -374,7 +550,7 @@ fn fn_sub(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
 
         for arg in &args[1..] {
             try!(expect_number(arg));
-            v = try!(sub_number(v, arg));
+            v = try!(float_op(scope, "-", v, arg, sub_number));
         }
 
         Ok(v)
@@ -407,7 +583,7 @@ fn sub_number(lhs: Value, rhs: &Value) -> Result<Value, Error> {
 /// `*` returns the product of all arguments.
 ///
 /// Given no arguments, returns the multiplicative identity, `1`.
-fn fn_mul(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+fn fn_mul(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     if args.is_empty() {
         return Ok(Integer::one().into());
     }
@@ -418,7 +594,7 @@ fn fn_mul(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
 
     for arg in &args[1..] {
         try!(expect_number(arg));
-        v = try!(mul_number(v, arg));
+        v = try!(float_op(scope, "*", v, arg, mul_number));
     }
 
     Ok(v)
@@ -439,14 +615,14 @@ fn mul_number(lhs: Value, rhs: &Value) -> Result<Value, Error> {
 }
 
 /// `^` returns a base value raised to an exponent.
-fn fn_pow(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+fn fn_pow(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let a = args[0].take();
     let b = args[1].take();
 
     try!(expect_number(&a));
     try!(expect_number(&b));
 
-    pow_number(a, b)
+    float_op(scope, "^", a, &b, |a, b| pow_number(a, b.clone()))
 }
 
 fn pow_number(lhs: Value, rhs: Value) -> Result<Value, Error> {
@@ -503,28 +679,28 @@ fn pow_ratio_integer(lhs: &Ratio, rhs: &Integer) -> Result<Value, Error> {
 }
 
 /// `/` returns the cumulative quotient of successive arguments.
-fn fn_div(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+fn fn_div(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let mut v = args[0].take();
 
     try!(expect_number(&v));
 
     for arg in &args[1..] {
         try!(expect_number(arg));
-        v = try!(div_number(v, arg));
+        v = try!(float_op(scope, "/", v, arg, div_number));
     }
 
     Ok(v)
 }
 
 /// `//` returns the cumulative floor quotient of successive arguments.
-fn fn_floor_div(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+fn fn_floor_div(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let mut v = args[0].take();
 
     try!(expect_number(&v));
 
     for arg in &args[1..] {
         try!(expect_number(arg));
-        v = try!(floor_div_number(v, arg));
+        v = try!(float_op(scope, "//", v, arg, floor_div_number));
     }
 
     Ok(v)
@@ -569,14 +745,14 @@ fn floor_div_number(lhs: Value, rhs: &Value) -> Result<Value, Error> {
 }
 
 /// `rem` returns the remainder of two arguments.
-fn fn_rem(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+fn fn_rem(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let a = args[0].take();
     try!(expect_number(&a));
 
     let b = &args[1];
     try!(expect_number(b));
 
-    rem_number(a, b)
+    float_op(scope, "rem", a, b, rem_number)
 }
 
 fn rem_number(lhs: Value, rhs: &Value) -> Result<Value, Error> {
@@ -810,12 +986,9 @@ fn fn_xor(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     }
 }
 
-/// `not` returns the inverse of the given boolean value.
+/// `not` returns `true` if the given value is `false`; otherwise, `false`.
 fn fn_not(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
-    match args[0] {
-        Value::Bool(a) => Ok((!a).into()),
-        ref v => Err(From::from(ExecError::expected("bool", v)))
-    }
+    Ok((!args[0].is_truthy()).into())
 }
 
 /// `id` returns the unmodified value of the argument received.
@@ -855,6 +1028,183 @@ fn fn_null(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     Ok(is_null.into())
 }
 
+/// `list?` returns whether the given value is a proper list.
+/// Unit, `()`, is the empty list and is a proper list.
+///
+/// All lists are currently proper lists; there is no representation for
+/// a dotted pair whose tail is not a list.
+fn fn_is_list(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let is_list = match args[0] {
+        Value::Unit | Value::List(_) => true,
+        _ => false
+    };
+
+    Ok(is_list.into())
+}
+
+/// Returns the scope of the innermost macro expansion, if a macro is being
+/// expanded; otherwise, the given scope.
+fn context_scope(scope: &Scope) -> Scope {
+    scope.expansion_scope().unwrap_or_else(|| scope.clone())
+}
+
+/// `defined?` returns whether a name refers to a global value or
+/// a system function. During macro expansion, the scope of the code
+/// being compiled is searched.
+fn fn_is_defined(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let name = try!(get_name(&args[0]));
+    let scope = context_scope(scope);
+
+    Ok((is_system_fn(name) || scope.contains_value(name)).into())
+}
+
+/// `macro-defined?` returns whether a name refers to a macro.
+/// During macro expansion, the scope of the code being compiled is searched.
+fn fn_is_macro_defined(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let name = try!(get_name(&args[0]));
+    let scope = context_scope(scope);
+
+    Ok(scope.contains_macro(name).into())
+}
+
+/// `current-module` returns the name of the current module, or `()`
+/// outside of a module. During macro expansion, the module of the code
+/// being compiled is returned.
+fn fn_current_module(scope: &Scope, _args: &mut [Value]) -> Result<Value, Error> {
+    let scope = context_scope(scope);
+
+    Ok(match scope.module_name() {
+        Some(name) => Value::Name(name),
+        None => Value::Unit
+    })
+}
+
+/// `exports-of` returns a list of the names exported by the named module,
+/// loading the module if necessary. An optional keyword, `:values`,
+/// `:macros`, or `:all`, selects which exported names are returned;
+/// the default is `:all`.
+///
+/// ```lisp
+/// (exports-of 'math :macros)
+/// ```
+fn fn_exports_of(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let mod_name = try!(get_name(&args[0]));
+
+    let (values, macros) = match args.get(1) {
+        None => (true, true),
+        Some(v) => {
+            let kind = try!(get_keyword(v));
+
+            match scope.with_name(kind, |s| match s {
+                "values" => Some((true, false)),
+                "macros" => Some((false, true)),
+                "all" => Some((true, true)),
+                _ => None
+            }) {
+                Some(kind) => kind,
+                None => return Err(From::from(ExecError::UnrecognizedKeyword(kind)))
+            }
+        }
+    };
+
+    let scope = context_scope(scope);
+    try!(scope.check_import_policy(mod_name));
+    let m = try!(scope.get_modules().get_module(mod_name, &scope));
+
+    Ok(m.exports().into_iter()
+        .filter(|&name| (values && macros) ||
+            (values && m.get_export(name).is_some()) ||
+            (macros && m.get_export_macro(name).is_some()))
+        .map(Value::Name)
+        .collect::<Vec<_>>().into())
+}
+
+/// `doc` returns a string describing the usage of the named operator.
+/// If no documentation is available, `()` is returned.
+fn fn_doc(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let name = try!(get_name(&args[0]));
+
+    Ok(match operator_usage(name) {
+        Some(usage) => usage.into(),
+        None => Value::Unit
+    })
+}
+
+/// Splits a keyword name into its namespace, if any, and its name.
+/// A namespaced keyword has the form `:namespace/name`; the namespace ends
+/// at the first `/` and neither part may be empty.
+fn split_keyword(name: &str) -> (Option<&str>, &str) {
+    match name.find('/') {
+        Some(pos) if pos != 0 && pos + 1 != name.len() =>
+            (Some(&name[..pos]), &name[pos + 1..]),
+        _ => (None, name)
+    }
+}
+
+/// `keyword-namespace` returns the namespace of a keyword as a string;
+/// e.g. `(keyword-namespace :foo/bar)` returns `"foo"`.
+/// If the keyword has no namespace, `()` is returned.
+fn fn_keyword_namespace(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let name = try!(get_keyword(&args[0]));
+
+    Ok(scope.with_name(name, |s| match split_keyword(s) {
+        (Some(ns), _) => ns.into(),
+        (None, _) => Value::Unit
+    }))
+}
+
+/// `keyword-name` returns the name of a keyword, excluding any namespace,
+/// as a string; e.g. `(keyword-name :foo/bar)` returns `"bar"`.
+fn fn_keyword_name(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let name = try!(get_keyword(&args[0]));
+    Ok(scope.with_name(name, |s| split_keyword(s).1.into()))
+}
+
+/// `error` raises an error with a message and any number of additional
+/// values, called irritants, further describing the error.
+///
+/// ```lisp
+/// (error "bad value" x)
+/// ```
+fn fn_error(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let message = try!(get_string(&args[0])).to_owned();
+    let irritants = args[1..].iter_mut().map(|v| v.take()).collect();
+
+    Err(From::from(ExecError::UserError(Condition::new(message, irritants))))
+}
+
+/// `error-message` returns the message of an error condition.
+fn fn_error_message(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let cond = try!(get_condition(&args[0]));
+    Ok(cond.message.clone().into())
+}
+
+/// `error-irritants` returns the list of irritants of an error condition.
+fn fn_error_irritants(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let cond = try!(get_condition(&args[0]));
+    Ok(cond.irritants.clone().into())
+}
+
+/// `error-category` returns a keyword naming the category of an error condition.
+///
+/// ```lisp
+/// (guard (e :error (error-category e)) (/ 1 0)) ; => :arith
+/// ```
+fn fn_error_category(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let cond = try!(get_condition(&args[0]));
+    Ok(Value::Keyword(scope.add_name(cond.category)))
+}
+
+/// `error-kind` returns a keyword naming the specific kind of an error condition.
+///
+/// ```lisp
+/// (guard (e :error (error-kind e)) (/ 1 0)) ; => :divide-by-zero
+/// ```
+fn fn_error_kind(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let cond = try!(get_condition(&args[0]));
+    Ok(Value::Keyword(scope.add_name(cond.kind)))
+}
+
 fn type_of(scope: &Scope, v: &Value) -> Name {
     use name::standard_names::*;
 
@@ -874,8 +1224,9 @@ fn type_of(scope: &Scope, v: &Value) -> Name {
         Value::Char(_) => CHAR,
         Value::String(_) => STRING,
         Value::List(_) => LIST,
+        Value::Vector(_) => VECTOR,
         Value::Function(_) => FUNCTION,
-        Value::Lambda(_) => LAMBDA,
+        Value::Lambda(_) | Value::Overload(_) => LAMBDA,
         Value::Quasiquote(_, _) |
         Value::Comma(_, _) |
         Value::CommaAt(_, _) |
@@ -965,6 +1316,36 @@ fn fn_dot_eq(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     Ok(Value::Struct(s))
 }
 
+/// `struct?` returns whether a value is an instance of the struct
+/// definition with the given name in the current scope.
+///
+/// ```lisp
+/// (struct? foo 'Point)
+/// ```
+fn fn_is_struct(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let name = try!(get_name(&args[1]));
+
+    let def = match scope.get_value(name) {
+        Some(Value::StructDef(def)) => def,
+        Some(ref v) => return Err(From::from(ExecError::expected("struct-def", v))),
+        None => return Err(From::from(ExecError::NameError(name)))
+    };
+
+    Ok(match args[0] {
+        Value::Struct(ref s) => s.def == def,
+        _ => false
+    }.into())
+}
+
+/// `struct-type` returns the name of the struct definition of a struct value
+/// or `()` if the value is not a struct.
+fn fn_struct_type(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    Ok(match args[0] {
+        Value::Struct(ref s) => Value::Name(s.def.name),
+        _ => Value::Unit
+    })
+}
+
 /// `new` creates a struct value.
 ///
 /// ```lisp
@@ -1034,8 +1415,8 @@ fn fn_print(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
 
     let s = try!(format_string(&scope.borrow_names(), fmt, &args[1..]));
 
-    try!(scope.get_io().stdout.write_all(s.as_bytes()));
-    try!(scope.get_io().stdout.flush());
+    try!(scope.get_io().write_all(s.as_bytes()));
+    try!(scope.get_io().flush());
 
     Ok(Value::Unit)
 }
@@ -1049,8 +1430,28 @@ fn fn_println(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
         s.push('\n');
     }
 
-    try!(scope.get_io().stdout.write_all(s.as_bytes()));
-    try!(scope.get_io().stdout.flush());
+    try!(scope.get_io().write_all(s.as_bytes()));
+    try!(scope.get_io().flush());
+
+    Ok(Value::Unit)
+}
+
+/// `display` prints a value to `stdout` as it is formatted by `~a`.
+fn fn_display(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let s = display_names(&scope.borrow_names(), &args[0]).to_string();
+
+    try!(scope.get_io().write_all(s.as_bytes()));
+    try!(scope.get_io().flush());
+
+    Ok(Value::Unit)
+}
+
+/// `write` prints a value to `stdout` as it is formatted by `~s`.
+fn fn_write(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let s = debug_names(&scope.borrow_names(), &args[0]).to_string();
+
+    try!(scope.get_io().write_all(s.as_bytes()));
+    try!(scope.get_io().flush());
 
     Ok(Value::Unit)
 }
@@ -1083,8 +1484,13 @@ fn fn_elt(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
 
     let idx = try!(usize::from_value_ref(idx));
 
+    if let Some(r) = IntRange::from_value(li) {
+        return r.get(idx as u64).map(Value::from)
+            .ok_or(From::from(ExecError::OutOfBounds(idx)));
+    }
+
     match *li {
-        Value::List(ref li) => li.get(idx).cloned()
+        Value::List(ref li) | Value::Vector(ref li) => li.get(idx).cloned()
             .ok_or(From::from(ExecError::OutOfBounds(idx))),
         ref v => Err(From::from(ExecError::expected("list", v)))
     }
@@ -1203,11 +1609,15 @@ fn join_string(sep: &str, args: &[Value]) -> Result<Value, Error> {
     Ok(res.into())
 }
 
-/// `len` returns the length of the given list or string.
+/// `len` returns the length of the given list, vector, or string.
 fn fn_len(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    if let Some(r) = IntRange::from_value(&args[0]) {
+        return Ok(r.len().into());
+    }
+
     let n = match args[0] {
         Value::Unit => 0,
-        Value::List(ref li) => li.len(),
+        Value::List(ref li) | Value::Vector(ref li) => li.len(),
         Value::String(ref s) => s.len(),
         ref v => return Err(From::from(ExecError::expected("list", v)))
     };
@@ -1215,7 +1625,7 @@ fn fn_len(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     Ok(n.into())
 }
 
-/// `slice` returns a subsequence of a list or string.
+/// `slice` returns a subsequence of a list, vector, or string.
 fn fn_slice(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let begin = try!(usize::from_value_ref(&args[1]));
     let end = try!(usize::from_value_ref(&args[2]));
@@ -1244,6 +1654,16 @@ fn fn_slice(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
                 Ok(li.slice(begin..end).into())
             }
         }
+        Value::Vector(ref v) => {
+            let n = v.len();
+            if begin > n {
+                Err(From::from(ExecError::OutOfBounds(begin)))
+            } else if end > n {
+                Err(From::from(ExecError::OutOfBounds(end)))
+            } else {
+                Ok(Value::Vector(v.slice(begin..end)))
+            }
+        }
         Value::String(ref s) => {
             let n = s.len();
             if begin > n {
@@ -1346,6 +1766,273 @@ fn fn_reverse(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     }
 }
 
+/// Calls a function with the elements of a list following the first `n`.
+fn apply_from(scope: &Scope, f: Value, n: usize, list: Value) -> Result<Value, Error> {
+    let args = match list {
+        Value::Unit if n == 0 => Vec::new(),
+        Value::Unit => return Err(From::from(ExecError::OutOfBounds(n))),
+        Value::List(ref li) if n > li.len() =>
+            return Err(From::from(ExecError::OutOfBounds(n))),
+        Value::List(li) => li.into_vec().split_off(n),
+        ref v => return Err(From::from(ExecError::expected("list", v)))
+    };
+
+    call_function(scope, f, args)
+}
+
+/// `apply-n` calls a function with the elements of a list,
+/// skipping the first `n` elements.
+///
+/// ```lisp
+/// (apply-n + 1 '(1 2 3))
+/// ```
+fn fn_apply_n(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let n = try!(usize::from_value_ref(&args[1]));
+    apply_from(scope, args[0].take(), n, args[2].take())
+}
+
+/// `apply-first` calls a function with two arguments: the first element
+/// of a list and a list of the remaining elements.
+///
+/// ```lisp
+/// (apply-first list '(1 2 3))
+/// ```
+fn fn_apply_first(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    match args[1].take() {
+        Value::List(li) => {
+            let mut li = li.into_vec();
+            let rest = li.split_off(1);
+            let first = li.pop().unwrap();
+
+            call_function(scope, args[0].take(), vec![first, rest.into()])
+        }
+        ref v => Err(From::from(ExecError::expected("list", v)))
+    }
+}
+
+/// `apply-rest` calls a function with all elements of a list
+/// following the first element.
+fn fn_apply_rest(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    apply_from(scope, args[0].take(), 1, args[1].take())
+}
+
+/// `scan` folds a function over a list, from left to right, returning
+/// the initial value followed by each intermediate result.
+///
+/// ```lisp
+/// (scan + 0 '(1 2 3))
+/// ```
+fn fn_scan(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let f = args[0].take();
+    let init = args[1].take();
+
+    if let Some(r) = IntRange::from_value(&args[2]) {
+        return scan_values(scope, f, init, r.iter().map(Value::from));
+    }
+
+    match args[2].take() {
+        Value::Unit => scan_values(scope, f, init, None.into_iter()),
+        Value::List(li) => scan_values(scope, f, init, li.into_vec().into_iter()),
+        ref v => Err(From::from(ExecError::expected("list", v)))
+    }
+}
+
+fn scan_values<I>(scope: &Scope, f: Value, mut acc: Value, iter: I)
+        -> Result<Value, Error> where I: Iterator<Item=Value> {
+    let mut res = Vec::with_capacity(iter.size_hint().0 + 1);
+
+    for (i, v) in iter.enumerate() {
+        let next = try!(call_function(scope, f.clone(), vec![acc.clone(), v])
+            .map_err(|e| e.context(format!("`scan` failed at index {}", i))));
+        res.push(acc);
+        acc = next;
+    }
+
+    res.push(acc);
+    Ok(res.into())
+}
+
+/// `range` returns a range of integers from `start`, inclusive, to `end`,
+/// exclusive, separated by `step`. If only one argument is given, it is
+/// the end of a range beginning at zero. Values in the range are computed
+/// as needed.
+///
+/// ```lisp
+/// (range 10)
+/// (range 0 10 2)
+/// ```
+fn fn_range(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let (start, end) = match args.len() {
+        1 => (0, try!(i64::from_value_ref(&args[0]))),
+        _ => (try!(i64::from_value_ref(&args[0])),
+              try!(i64::from_value_ref(&args[1])))
+    };
+
+    let step = match args.get(2) {
+        Some(v) => try!(i64::from_value_ref(v)),
+        None => 1
+    };
+
+    match IntRange::new(start, end, step) {
+        Some(r) => Ok(Value::new_foreign(r)),
+        None => Err(From::from(ExecError::expected("nonzero integer", &args[2])))
+    }
+}
+
+/// `range-list` returns a list of all values in a range.
+fn fn_range_list(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    match IntRange::from_value(&args[0]) {
+        Some(r) => Ok(r.iter().map(Value::from).collect::<Vec<_>>().into()),
+        None => Err(From::from(ExecError::expected("range", &args[0])))
+    }
+}
+
+/// `contains?` returns whether a range, list, or vector contains a value.
+/// Ranges are tested without examining each value; elements of lists
+/// and vectors are compared using `=`. Values of differing types are
+/// considered unequal.
+///
+/// ```lisp
+/// (contains? (range 0 100 3) 42)
+/// ```
+fn fn_contains(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    if let Some(r) = IntRange::from_value(&args[0]) {
+        return Ok(match args[1] {
+            Value::Integer(ref i) => i.to_i64().map_or(false, |i| r.contains(i)),
+            _ => false
+        }.into());
+    }
+
+    match args[0] {
+        Value::Unit => Ok(false.into()),
+        Value::List(ref li) | Value::Vector(ref li) => {
+            Ok(li.iter().any(|v| v.is_equal(&args[1]).unwrap_or(false)).into())
+        }
+        ref v => Err(From::from(ExecError::expected("range or list", v)))
+    }
+}
+
+/// `iterate` returns a list of `n` values, beginning with `x`,
+/// in which each value is the result of calling `f` on the previous value.
+///
+/// ```lisp
+/// (iterate (lambda (x) (* x 2)) 1 4)
+/// ```
+fn fn_iterate(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let f = args[0].take();
+    let n = try!(usize::from_value_ref(&args[2]));
+
+    if n == 0 {
+        return Ok(Value::Unit);
+    }
+
+    let mut res = Vec::with_capacity(n);
+    res.push(args[1].take());
+
+    for i in 1..n {
+        let prev = res[i - 1].clone();
+        let next = try!(call_function(scope, f.clone(), vec![prev])
+            .map_err(|e| e.context(format!("`iterate` failed at index {}", i))));
+        res.push(next);
+    }
+
+    Ok(res.into())
+}
+
+/// `vector` returns a vector of values.
+///
+/// ```lisp
+/// (vector 1 2 3)
+/// ```
+fn fn_vector(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    Ok(Value::Vector(RcVec::new(args.iter_mut().map(|v| v.take()).collect())))
+}
+
+/// `vec-ref` returns an element from a vector, starting at zero index.
+fn fn_vec_ref(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let idx = try!(usize::from_value_ref(&args[1]));
+
+    match args[0] {
+        Value::Vector(ref v) => v.get(idx).cloned()
+            .ok_or(From::from(ExecError::OutOfBounds(idx))),
+        ref v => Err(From::from(ExecError::expected("vector", v)))
+    }
+}
+
+/// `vec-set` returns a vector with the element at the given index replaced.
+///
+/// ```lisp
+/// (vec-set (vector 1 2 3) 0 4)
+/// ```
+///
+/// If the given vector is not referenced by any other value, it is modified
+/// in place and returned. Otherwise, the vector is copied before modification.
+fn fn_vec_set(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let idx = try!(usize::from_value_ref(&args[1]));
+
+    match args[0].take() {
+        Value::Vector(mut v) => {
+            if idx >= v.len() {
+                return Err(From::from(ExecError::OutOfBounds(idx)));
+            }
+
+            v[idx] = args[2].take();
+            Ok(Value::Vector(v))
+        }
+        ref v => Err(From::from(ExecError::expected("vector", v)))
+    }
+}
+
+/// `vec-len` returns the length of a vector.
+fn fn_vec_len(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    match args[0] {
+        Value::Vector(ref v) => Ok(v.len().into()),
+        ref v => Err(From::from(ExecError::expected("vector", v)))
+    }
+}
+
+/// `vec-slice` returns a subsequence of a vector.
+/// The new vector shares storage with the original.
+fn fn_vec_slice(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let begin = try!(usize::from_value_ref(&args[1]));
+    let end = try!(usize::from_value_ref(&args[2]));
+
+    if end < begin {
+        return Err(From::from(ExecError::InvalidSlice(begin, end)));
+    }
+
+    match args[0] {
+        Value::Vector(ref v) => {
+            let n = v.len();
+            if begin > n {
+                Err(From::from(ExecError::OutOfBounds(begin)))
+            } else if end > n {
+                Err(From::from(ExecError::OutOfBounds(end)))
+            } else {
+                Ok(Value::Vector(v.slice(begin..end)))
+            }
+        }
+        ref v => Err(From::from(ExecError::expected("vector", v)))
+    }
+}
+
+/// `vec->list` returns a list containing the elements of a vector.
+fn fn_vec_to_list(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    match args[0].take() {
+        Value::Vector(v) => Ok(v.into()),
+        ref v => Err(From::from(ExecError::expected("vector", v)))
+    }
+}
+
+/// `list->vec` returns a vector containing the elements of a list.
+fn fn_list_to_vec(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    match args[0].take() {
+        Value::Unit => Ok(Value::Vector(RcVec::new(Vec::new()))),
+        Value::List(li) => Ok(Value::Vector(li)),
+        ref v => Err(From::from(ExecError::expected("list", v)))
+    }
+}
+
 /// `abs` returns the absolute value of the given numerical value.
 fn fn_abs(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     match args[0] {
@@ -1427,6 +2114,124 @@ fn fn_float(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     }
 }
 
+/// Byte or bit order, given as the keyword `:big` or `:little`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Endian {
+    Big,
+    Little,
+}
+
+fn get_endian(scope: &Scope, v: &Value) -> Result<Endian, ExecError> {
+    let kw = try!(get_keyword(v));
+
+    match scope.borrow_names().get(kw) {
+        "big" => Ok(Endian::Big),
+        "little" => Ok(Endian::Little),
+        _ => Err(ExecError::UnrecognizedKeyword(kw))
+    }
+}
+
+/// Returns a list of the 8 bytes of a 64-bit value in the given order.
+fn u64_to_bytes(n: u64, endian: Endian) -> Value {
+    let mut bytes = (0..8).map(|i| ((n >> (i * 8)) as u8).into())
+        .collect::<Vec<Value>>();
+
+    if endian == Endian::Big {
+        bytes.reverse();
+    }
+
+    bytes.into()
+}
+
+/// Reads a 64-bit value from a list of 8 bytes in the given order.
+fn bytes_to_u64(v: &Value, endian: Endian) -> Result<u64, ExecError> {
+    let bytes = try!(<&[Value]>::from_value_ref(v));
+
+    if bytes.len() != 8 {
+        return Err(ExecError::ByteCount{expected: 8, found: bytes.len()});
+    }
+
+    let mut n = 0;
+
+    for i in 0..8 {
+        let b = match endian {
+            Endian::Big => &bytes[i],
+            Endian::Little => &bytes[7 - i],
+        };
+
+        let b = match *b {
+            Value::Integer(ref b) => try!(b.to_u8().ok_or(ExecError::Overflow)),
+            ref v => return Err(ExecError::expected("integer", v))
+        };
+
+        n = (n << 8) | b as u64;
+    }
+
+    Ok(n)
+}
+
+fn get_i64(v: &Value) -> Result<i64, ExecError> {
+    match *v {
+        Value::Integer(ref i) => i.to_i64().ok_or(ExecError::Overflow),
+        ref v => Err(ExecError::expected("integer", v))
+    }
+}
+
+/// `float-bits` returns the IEEE 754 bit pattern of a `float` as an `integer`.
+fn fn_float_bits(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let f = try!(get_float(&args[0]));
+    Ok(Integer::from_u64(f.to_bits()).into())
+}
+
+/// `float->bytes` returns a list of the 8 bytes of a `float`
+/// in `:big` or `:little` endian order.
+fn fn_float_to_bytes(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let f = try!(get_float(&args[0]));
+    let endian = try!(get_endian(scope, &args[1]));
+    Ok(u64_to_bytes(f.to_bits(), endian))
+}
+
+/// `bytes->float` returns a `float` from a list of 8 bytes
+/// in `:big` or `:little` endian order.
+fn fn_bytes_to_float(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let endian = try!(get_endian(scope, &args[1]));
+    let n = try!(bytes_to_u64(&args[0], endian));
+    Ok(f64::from_bits(n).into())
+}
+
+/// `integer-bits` returns a list of the 64 bits of an `integer`, in two's
+/// complement representation, in `:big` (most significant bit first)
+/// or `:little` (least significant bit first) order.
+fn fn_integer_bits(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let n = try!(get_i64(&args[0])) as u64;
+    let endian = try!(get_endian(scope, &args[1]));
+
+    let mut bits = (0..64).map(|i| (((n >> i) & 1) as u8).into())
+        .collect::<Vec<Value>>();
+
+    if endian == Endian::Big {
+        bits.reverse();
+    }
+
+    Ok(bits.into())
+}
+
+/// `integer->bytes` returns a list of the 8 bytes of an `integer`, in two's
+/// complement representation, in `:big` or `:little` endian order.
+fn fn_integer_to_bytes(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let n = try!(get_i64(&args[0]));
+    let endian = try!(get_endian(scope, &args[1]));
+    Ok(u64_to_bytes(n as u64, endian))
+}
+
+/// `bytes->integer` returns an `integer` from a list of 8 bytes, in two's
+/// complement representation, in `:big` or `:little` endian order.
+fn fn_bytes_to_integer(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let endian = try!(get_endian(scope, &args[1]));
+    let n = try!(bytes_to_u64(&args[0], endian));
+    Ok(Integer::from_i64(n as i64).into())
+}
+
 /// `inf` returns whether all given arguments are equal to positive or negative infinity.
 /// Given no arguments, returns the value of positive infinity.
 fn fn_inf(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
@@ -1446,6 +2251,25 @@ fn fn_inf(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     }
 }
 
+/// `set-float-mode` sets the float mode of the current scope; either
+/// `:strict`, in which arithmetic producing `NaN` or infinity from finite
+/// values returns an error, or `:default`.
+fn fn_set_float_mode(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let mode = try!(get_keyword(&args[0]));
+
+    let strict = match scope.with_name(mode, |s| match s {
+        "strict" => Some(true),
+        "default" => Some(false),
+        _ => None
+    }) {
+        Some(strict) => strict,
+        None => return Err(From::from(ExecError::UnrecognizedKeyword(mode)))
+    };
+
+    scope.set_strict_float(strict);
+    Ok(Value::Unit)
+}
+
 /// `nan` returns whether all given arguments are equal to `NaN`.
 /// Given no arguments, returns the value of `NaN`.
 fn fn_nan(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
@@ -1554,6 +2378,116 @@ fn fn_string(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     }
 }
 
+/// `string-from-chars` returns a string composed of a list of characters.
+///
+/// ```lisp
+/// (string-from-chars '(#'a' #'b' #'c'))
+/// ```
+fn fn_string_from_chars(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let li = try!(<&[Value]>::from_value_ref(&args[0]));
+    let mut s = String::with_capacity(li.len());
+
+    for v in li {
+        s.push(try!(char::from_value_ref(v)));
+    }
+
+    Ok(s.into())
+}
+
+/// `map-string` calls a function with each character in a string and returns
+/// a string composed of the results. The function must return either
+/// a character or a string.
+///
+/// ```lisp
+/// (map-string (lambda (c) (concat c c)) "abc")
+/// ```
+fn fn_map_string(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let f = args[0].take();
+    let s = try!(get_string(&args[1]));
+    let mut res = String::with_capacity(s.len());
+
+    for ch in s.chars() {
+        match try!(call_function(scope, f.clone(), vec![Value::Char(ch)])) {
+            Value::Char(ch) => res.push(ch),
+            Value::String(ref s) => res.push_str(s),
+            ref v => return Err(From::from(ExecError::expected("char or string", v)))
+        }
+    }
+
+    Ok(res.into())
+}
+
+/// `string-len` returns the number of characters in a string.
+fn fn_string_len(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let s = try!(get_string(&args[0]));
+    Ok(s.chars().count().into())
+}
+
+/// `string-byte-len` returns the length, in bytes, of a string.
+fn fn_string_byte_len(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let s = try!(get_string(&args[0]));
+    Ok(s.len().into())
+}
+
+/// `string-escape` returns a string with special characters replaced
+/// by escape sequences, as in a string literal.
+fn fn_string_escape(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let s = try!(get_string(&args[0]));
+    Ok(escape_string(s).into())
+}
+
+/// `string-unescape` returns a string with escape sequences replaced
+/// by the characters they represent.
+fn fn_string_unescape(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let s = try!(get_string(&args[0]));
+    Ok(try!(unescape_string(s)).into())
+}
+
+/// `string-escape-html` returns a string with characters `&`, `<`, `>`,
+/// `"`, and `'` replaced by HTML entities.
+fn fn_string_escape_html(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let s = try!(get_string(&args[0]));
+    let mut res = String::with_capacity(s.len());
+
+    for ch in s.chars() {
+        match ch {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&#39;"),
+            _ => res.push(ch)
+        }
+    }
+
+    Ok(res.into())
+}
+
+/// `string-ci=?` returns whether two strings are equal, ignoring case.
+///
+/// Case is compared using Unicode simple case folding, without
+/// locale-specific mappings.
+fn fn_string_ci_eq(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let a = try!(get_string(&args[0]));
+    let b = try!(get_string(&args[1]));
+    Ok(eq_folded(a, b).into())
+}
+
+/// `starts-with?` returns whether a string begins with the given prefix.
+fn fn_starts_with(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let s = try!(get_string(&args[0]));
+    let prefix = try!(get_string(&args[1]));
+    Ok(s.starts_with(prefix).into())
+}
+
+/// `starts-with-ci?` returns whether a string begins with the given prefix,
+/// ignoring case.
+fn fn_starts_with_ci(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let s = try!(get_string(&args[0]));
+    let prefix = try!(get_string(&args[1]));
+    Ok(starts_with_folded(s, prefix).into())
+}
+
 /// `max` returns the greatest value of given arguments.
 fn fn_max(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
     let mut v = args[0].take();
@@ -10,12 +10,57 @@ pub use num::bigint::Sign;
 use num::{self, BigInt, BigRational};
 use num::{FromPrimitive, ToPrimitive, Integer as NumInteger, Signed, Num, Zero, One};
 
+/// Smallest value held in the table of preallocated integers
+const SMALL_INTEGER_MIN: i64 = -128;
+/// Largest value held in the table of preallocated integers
+const SMALL_INTEGER_MAX: i64 = 1024;
+
+thread_local!(static SMALL_INTEGERS: Vec<Integer> =
+    (SMALL_INTEGER_MIN..SMALL_INTEGER_MAX + 1)
+        .map(|i| Integer(BigInt::from_i64(i).unwrap())).collect());
+
+/// Returns a copy of a preallocated `Integer`, if `i` is in the table.
+///
+/// Copying a small `BigInt` is considerably cheaper than converting one
+/// from a primitive integer.
+#[inline]
+fn small_integer(i: i64) -> Option<Integer> {
+    if i >= SMALL_INTEGER_MIN && i <= SMALL_INTEGER_MAX {
+        Some(SMALL_INTEGERS.with(|t| t[(i - SMALL_INTEGER_MIN) as usize].clone()))
+    } else {
+        None
+    }
+}
+
+/// Returns the sign and magnitude of a `BigInt`,
+/// if the magnitude can be represented by a `u128`.
+fn magnitude_u128(i: &BigInt) -> Option<(Sign, u128)> {
+    let (sign, bytes) = i.to_bytes_le();
+
+    if bytes.len() > 16 {
+        None
+    } else {
+        Some((sign, bytes.iter().rev().fold(0, |n, &b| n << 8 | b as u128)))
+    }
+}
+
+fn u128_to_bytes_le(mut u: u128) -> [u8; 16] {
+    let mut bytes = [0; 16];
+
+    for b in &mut bytes {
+        *b = u as u8;
+        u >>= 8;
+    }
+
+    bytes
+}
+
 /// Arbitrary precision signed integer
-#[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Ord, PartialOrd)]
 pub struct Integer(BigInt);
 
 /// Arbitrary precision signed integer ratio
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Ord, PartialOrd)]
 pub struct Ratio(BigRational);
 
 /// Error produced when failing to parse an `Integer` from `&str`.
@@ -74,67 +119,107 @@ impl Integer {
     /// Creates an `Integer` with the value of the given `i8`.
     #[inline]
     pub fn from_i8(i: i8) -> Integer {
-        Integer(BigInt::from_i8(i).unwrap())
+        Integer::from_i64(i as i64)
     }
 
     /// Creates an `Integer` with the value of the given `i16`.
     #[inline]
     pub fn from_i16(i: i16) -> Integer {
-        Integer(BigInt::from_i16(i).unwrap())
+        Integer::from_i64(i as i64)
     }
 
     /// Creates an `Integer` with the value of the given `i32`.
     #[inline]
     pub fn from_i32(i: i32) -> Integer {
-        Integer(BigInt::from_i32(i).unwrap())
+        Integer::from_i64(i as i64)
     }
 
     /// Creates an `Integer` with the value of the given `i64`.
     #[inline]
     pub fn from_i64(i: i64) -> Integer {
-        Integer(BigInt::from_i64(i).unwrap())
+        small_integer(i)
+            .unwrap_or_else(|| Integer(BigInt::from_i64(i).unwrap()))
     }
 
     /// Creates an `Integer` with the value of the given `isize`.
     #[inline]
     pub fn from_isize(i: isize) -> Integer {
-        Integer(BigInt::from_isize(i).unwrap())
+        Integer::from_i64(i as i64)
     }
 
     /// Creates an `Integer` with the value of the given `u8`.
     #[inline]
     pub fn from_u8(i: u8) -> Integer {
-        Integer(BigInt::from_u8(i).unwrap())
+        Integer::from_i64(i as i64)
     }
 
     /// Creates an `Integer` with the value of the given `u16`.
     #[inline]
     pub fn from_u16(i: u16) -> Integer {
-        Integer(BigInt::from_u16(i).unwrap())
+        Integer::from_i64(i as i64)
     }
 
     /// Creates an `Integer` with the value of the given `u32`.
     #[inline]
     pub fn from_u32(i: u32) -> Integer {
-        Integer(BigInt::from_u32(i).unwrap())
+        Integer::from_i64(i as i64)
     }
 
     /// Creates an `Integer` with the value of the given `u64`.
     #[inline]
     pub fn from_u64(i: u64) -> Integer {
-        Integer(BigInt::from_u64(i).unwrap())
+        if i <= SMALL_INTEGER_MAX as u64 {
+            Integer::from_i64(i as i64)
+        } else {
+            Integer(BigInt::from_u64(i).unwrap())
+        }
     }
 
     /// Creates an `Integer` with the value of the given `usize`.
     #[inline]
     pub fn from_usize(u: usize) -> Integer {
-        Integer(BigInt::from_usize(u).unwrap())
+        Integer::from_u64(u as u64)
+    }
+
+    /// Creates an `Integer` with the value of the given `i128`.
+    pub fn from_i128(i: i128) -> Integer {
+        if i >= i64::min_value() as i128 && i <= i64::max_value() as i128 {
+            Integer::from_i64(i as i64)
+        } else {
+            let sign = if i < 0 { Sign::Minus } else { Sign::Plus };
+            // `wrapping_abs` of the minimum value is its magnitude as `u128`
+            Integer::from_bytes_le(sign, &u128_to_bytes_le(i.wrapping_abs() as u128))
+        }
+    }
+
+    /// Creates an `Integer` with the value of the given `u128`.
+    pub fn from_u128(u: u128) -> Integer {
+        if u <= u64::max_value() as u128 {
+            Integer::from_u64(u as u64)
+        } else {
+            Integer::from_bytes_le(Sign::Plus, &u128_to_bytes_le(u))
+        }
+    }
+
+    /// Creates an `Integer` with the exact value of the given `f64`.
+    /// Returns `None` if the value is infinite, `NaN`, or has a fractional part,
+    /// rather than truncating it as `from_f64` does.
+    pub fn from_f64_exact(f: f64) -> Option<Integer> {
+        if f.is_finite() && f.fract() == 0.0 {
+            Integer::from_f64(f)
+        } else {
+            None
+        }
     }
 
     /// Returns an `Integer` represented by a string in the given radix.
     /// `radix` must be in the range `[2, 36]`.
     #[inline]
     pub fn from_str_radix(s: &str, radix: u32) -> Result<Integer, FromStrRadixError> {
+        if let Some(i) = parse_large(s, radix) {
+            return Ok(Integer(i));
+        }
+
         BigInt::from_str_radix(s, radix)
             .map(Integer).map_err(FromStrRadixError)
     }
@@ -154,7 +239,14 @@ impl Integer {
     /// Returns a string representation of the `Integer` in the given radix.
     /// `radix` must be in the range `[2, 36]`.
     pub fn to_str_radix(&self, radix: u32) -> String {
-        self.0.to_str_radix(radix)
+        let mut s = String::new();
+
+        if self.is_negative() {
+            s.push('-');
+        }
+
+        write_digits(&mut s, &self.0.abs(), radix);
+        s
     }
 
     /// Returns the `Integer` as an `i8` value.
@@ -217,6 +309,25 @@ impl Integer {
         self.0.to_usize()
     }
 
+    /// Returns the `Integer` as an `i128` value.
+    pub fn to_i128(&self) -> Option<i128> {
+        match magnitude_u128(&self.0) {
+            Some((Sign::Minus, n)) if n <= 1 << 127 =>
+                Some((n as i128).wrapping_neg()),
+            Some((Sign::Minus, _)) | None => None,
+            Some((_, n)) if n <= i128::max_value() as u128 => Some(n as i128),
+            Some(_) => None
+        }
+    }
+
+    /// Returns the `Integer` as an `u128` value.
+    pub fn to_u128(&self) -> Option<u128> {
+        match magnitude_u128(&self.0) {
+            Some((Sign::Minus, _)) | None => None,
+            Some((_, n)) => Some(n)
+        }
+    }
+
     /// Returns the `Integer` as an `f32` value.
     #[inline]
     pub fn to_f32(&self) -> Option<f32> {
@@ -443,7 +554,9 @@ impl PartialEq<Ratio> for Integer {
 
 impl fmt::Display for Integer {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(&self.0, f)
+        let mut s = String::new();
+        write_digits(&mut s, &self.0.abs(), 10);
+        f.pad_integral(!self.is_negative(), "", &s)
     }
 }
 
@@ -452,10 +565,134 @@ impl FromStr for Integer {
 
     #[inline]
     fn from_str(s: &str) -> Result<Integer, FromStrIntError> {
+        if let Some(i) = parse_large(s, 10) {
+            return Ok(Integer(i));
+        }
+
         s.parse().map(Integer).map_err(FromStrIntError)
     }
 }
 
+/// Number of digits at or below which conversion between integers and
+/// strings is left to the simple, quadratic algorithm.
+///
+/// Longer values are split in half around a power of the radix and each half
+/// is converted separately, which lets the subquadratic multiplication
+/// in `num` do most of the work.
+const SPLIT_DIGITS: usize = 1000;
+
+/// Lazily computed powers `radix ^ (SPLIT_DIGITS * 2 ^ n)`.
+struct RadixPowers {
+    radix: u32,
+    powers: Vec<BigInt>,
+}
+
+impl RadixPowers {
+    fn new(radix: u32) -> RadixPowers {
+        RadixPowers{
+            radix: radix,
+            powers: Vec::new(),
+        }
+    }
+
+    /// Returns the number of digits represented by power `n`.
+    fn digits(n: usize) -> usize {
+        SPLIT_DIGITS << n
+    }
+
+    fn get(&mut self, n: usize) -> &BigInt {
+        while self.powers.len() <= n {
+            let next = match self.powers.last() {
+                Some(p) => p * p,
+                None => num::pow(BigInt::from_u32(self.radix).unwrap(), SPLIT_DIGITS)
+            };
+            self.powers.push(next);
+        }
+
+        &self.powers[n]
+    }
+}
+
+/// Parses a long string of digits, with an optional sign, using a
+/// divide-and-conquer algorithm.
+///
+/// Returns `None` if the string is short enough for the simple algorithm or
+/// contains anything other than valid digits; in either case, the caller
+/// falls back to the `num` implementation, which also produces any errors.
+fn parse_large(s: &str, radix: u32) -> Option<BigInt> {
+    let (negative, digits) = match s.as_bytes().first() {
+        Some(&b'-') => (true, &s[1..]),
+        Some(&b'+') => (false, &s[1..]),
+        _ => (false, s)
+    };
+
+    if digits.len() <= SPLIT_DIGITS ||
+            !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+
+    let n = parse_digits(digits, &mut RadixPowers::new(radix));
+
+    Some(if negative { -n } else { n })
+}
+
+fn parse_digits(s: &str, powers: &mut RadixPowers) -> BigInt {
+    if s.len() <= SPLIT_DIGITS {
+        return BigInt::from_str_radix(s, powers.radix)
+            .expect("digits were validated");
+    }
+
+    let mut n = 0;
+
+    while RadixPowers::digits(n + 1) < s.len() {
+        n += 1;
+    }
+
+    let (hi, lo) = s.split_at(s.len() - RadixPowers::digits(n));
+
+    let hi = parse_digits(hi, powers);
+    let lo = parse_digits(lo, powers);
+
+    hi * powers.get(n) + lo
+}
+
+/// Writes the digits of non-negative integer `i` to the string.
+fn write_digits(s: &mut String, i: &BigInt, radix: u32) {
+    write_digits_padded(s, i, &mut RadixPowers::new(radix), 0);
+}
+
+/// Writes the digits of non-negative integer `i`,
+/// left-padded with zeroes to `width` digits.
+fn write_digits_padded(s: &mut String, i: &BigInt,
+        powers: &mut RadixPowers, width: usize) {
+    let radix = powers.radix;
+
+    // A quick estimate: the value has fewer bits than
+    // a radix-2 string of `SPLIT_DIGITS` characters.
+    if i.bits() <= SPLIT_DIGITS || i < powers.get(0) {
+        let digits = i.to_str_radix(radix);
+
+        for _ in digits.len()..width {
+            s.push('0');
+        }
+        s.push_str(&digits);
+        return;
+    }
+
+    // Find the largest power not greater than `i`
+    let mut n = 0;
+
+    while powers.get(n).bits() * 2 - 1 <= i.bits() && powers.get(n + 1) <= i {
+        n += 1;
+    }
+
+    let (hi, lo) = i.div_rem(powers.get(n));
+    let lo_width = RadixPowers::digits(n);
+
+    write_digits_padded(s, &hi, powers, width.saturating_sub(lo_width));
+    write_digits_padded(s, &lo, powers, lo_width);
+}
+
 impl ops::Shl<usize> for Integer {
     type Output = Integer;
 
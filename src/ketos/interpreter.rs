@@ -10,12 +10,15 @@ use bytecode::Code;
 use compile::compile;
 use error::Error;
 use exec::{call_function, execute, ExecError};
+use function::{Arity, Function, FunctionImpl, SystemFn};
 use io::{IoError, IoMode};
 use lexer::{CodeMap, Lexer};
-use module::{FileModuleLoader, ModuleLoader, ModuleRegistry};
-use name::{debug_names, display_names, Name, NameStore};
+use mod_test::run_tests;
+use module::{check_exports, run_main_file, FileModuleLoader, Module, ModuleLoader,
+    ModuleRegistry, FILE_EXTENSION};
+use name::{debug_names, display_names, Name, NameSnapshot, NameStore};
 use parser::{ParseError, Parser};
-use scope::{GlobalIo, GlobalScope, MasterScope, Scope};
+use scope::{CodeStats, GlobalIo, GlobalScope, MasterScope, Scope};
 use value::Value;
 
 /// Provides a context in which to compile and execute code.
@@ -39,7 +42,23 @@ impl Interpreter {
 
     /// Creates a new `Interpreter` using the given `ModuleLoader` instance.
     pub fn with_loader(loader: Box<ModuleLoader>) -> Interpreter {
-        let names = Rc::new(RefCell::new(NameStore::new()));
+        Interpreter::with_names(NameStore::new(), loader)
+    }
+
+    /// Creates a new `Interpreter` whose names are seeded from a snapshot,
+    /// using the given `ModuleLoader` instance.
+    ///
+    /// Each name in the snapshot has the same `Name` value as in the
+    /// interpreter from which the snapshot was taken. Bytecode written by
+    /// this interpreter refers to those names by value and can be read only
+    /// by interpreters created from the same snapshot.
+    pub fn with_name_snapshot(snapshot: &NameSnapshot, loader: Box<ModuleLoader>)
+            -> Interpreter {
+        Interpreter::with_names(NameStore::from_snapshot(snapshot), loader)
+    }
+
+    fn with_names(names: NameStore, loader: Box<ModuleLoader>) -> Interpreter {
+        let names = Rc::new(RefCell::new(names));
         let codemap = Rc::new(RefCell::new(CodeMap::new()));
         let modules = Rc::new(ModuleRegistry::new(loader));
         let io = Rc::new(GlobalIo::default());
@@ -53,56 +72,185 @@ impl Interpreter {
         }
     }
 
+    /// Creates a new `Interpreter` using the given `Scope`.
+    pub fn with_scope(scope: Scope) -> Interpreter {
+        Interpreter{
+            scope: scope,
+        }
+    }
+
+    /// Executes prelude source code within the scope of this interpreter and
+    /// returns a new `Interpreter` whose scope inherits its definitions.
+    ///
+    /// Values and macros defined by the prelude are available in the new
+    /// scope, and in the scopes of modules it loads, without `use`.
+    /// Definitions made in those scopes shadow prelude values without warning.
+    pub fn with_prelude(&self, source: &str, path: Option<String>)
+            -> Result<Interpreter, Error> {
+        try!(self.run_code(source, path));
+        Ok(Interpreter::with_scope(GlobalScope::new_with_prelude(&self.scope)))
+    }
+
+    /// Imports all values and macros exported by a module into the scope of
+    /// this interpreter and returns a new `Interpreter` whose scope inherits
+    /// them, as with `with_prelude`.
+    pub fn with_prelude_module(&self, module: &Module) -> Interpreter {
+        module.scope.import_all_values(&self.scope);
+        module.scope.import_all_macros(&self.scope);
+        Interpreter::with_scope(GlobalScope::new_with_prelude(&self.scope))
+    }
+
+    /// Returns statistics on the live code objects of lambdas created within
+    /// this interpreter. Returns `None` unless code tracking has been enabled
+    /// using `GlobalScope::set_track_code`.
+    pub fn live_code_stats(&self) -> Option<CodeStats> {
+        self.scope.live_code_stats()
+    }
+
     /// Clears cached source from the contained `CodeMap`.
     ///
     /// # Note
     ///
-    /// This will invalidate any previously created `ParseError` values.
+    /// Spans of previously created `ParseError` values can no longer be
+    /// displayed, but source added afterward does not reuse their offsets.
+    /// See `CodeMap::clear`.
     pub fn clear_codemap(&self) {
         self.scope.borrow_codemap_mut().clear();
     }
 
-    /// Prints an error to `stderr`.
-    /// `input` is the source code which produced the error and `name`
-    /// is the optional filename of the program. These are used if the error
-    /// message refers to a span within the source code.
+    /// Prints an error to `stderr`, as formatted by `format_error`.
     pub fn display_error(&self, e: &Error) {
-        match *e {
-            Error::CompileError(ref e) => {
-                let _ = writeln!(stderr(), "compile error: {}",
-                    display_names(&self.scope.borrow_names(), e));
-            }
-            Error::DecodeError(ref e) => {
-                let _ = writeln!(stderr(), "decode error: {}", e);
-            }
-            Error::EncodeError(ref e) => {
-                let _ = writeln!(stderr(), "encode error: {}", e);
+        let _ = stderr().write_all(self.format_error(e).as_bytes());
+    }
+
+    /// Formats an error, as printed by `display_error`.
+    ///
+    /// A parse error is followed by an excerpt of the source code
+    /// with the location of the error highlighted.
+    pub fn format_error(&self, e: &Error) -> String {
+        self.format_error_style(e, false)
+    }
+
+    /// Formats an error as `format_error` does, using ANSI escape sequences
+    /// to highlight the kind of error and the location of a parse error.
+    pub fn format_error_ansi(&self, e: &Error) -> String {
+        self.format_error_style(e, true)
+    }
+
+    fn format_error_style(&self, e: &Error, color: bool) -> String {
+        let names = self.scope.borrow_names();
+
+        let (kind, msg) = match *e {
+            Error::CompileError(ref e) =>
+                ("compile error", display_names(&names, e).to_string()),
+            Error::ContextError(ref e) => ("error", e.to_string()),
+            Error::DecodeError(ref e) => ("decode error", e.to_string()),
+            Error::EncodeError(ref e) => ("encode error", e.to_string()),
+            Error::ExecError(ref e) =>
+                ("execution error", self.format_exec_error(&names, e)),
+            Error::IoError(ref e) => ("io error", e.to_string()),
+            Error::ModuleLoadError(ref e) => ("error", format!("{}: {}",
+                display_names(&names, e), display_names(&names, &*e.cause))),
+            Error::ParseError(ref e) => return self.format_parse_error(e, color),
+        };
+
+        format!("{}: {}\n", paint(kind, color), msg)
+    }
+
+    fn format_parse_error(&self, e: &ParseError, color: bool) -> String {
+        let codemap = self.scope.borrow_codemap();
+        let hi = codemap.highlight_span(e.span);
+
+        format!("{}:{}:{}:{}: {}\n    {}\n    {}\n",
+            hi.filename.unwrap_or("<input>"), hi.line, hi.col,
+            paint("parse error", color), e.kind,
+            hi.source, paint(&hi.highlight, color))
+    }
+
+    /// Prints an error to `stderr`, followed by each error in its chain
+    /// of causes. Each cause is printed on a new line, indented beneath
+    /// the error it caused.
+    pub fn display_error_chain(&self, e: &Error) {
+        let _ = stderr().write_all(self.format_error_chain(e).as_bytes());
+    }
+
+    /// Formats an error and each error in its chain of causes,
+    /// as printed by `display_error_chain`.
+    pub fn format_error_chain(&self, e: &Error) -> String {
+        let mut res = String::new();
+        let mut err = Some(e);
+        let mut depth = 0;
+
+        while let Some(e) = err {
+            for _ in 0..depth {
+                res.push_str("  ");
             }
-            Error::ExecError(ref e) => {
-                let _ = writeln!(stderr(), "execution error: {}",
-                    display_names(&self.scope.borrow_names(), e));
+            res.push_str(&self.format_error_line(e));
+            res.push('\n');
+
+            err = e.cause();
+            depth += 1;
+        }
+
+        res
+    }
+
+    /// Formats a single error in a chain, excluding its causes.
+    fn format_error_line(&self, e: &Error) -> String {
+        let names = self.scope.borrow_names();
+
+        match *e {
+            Error::CompileError(ref e) =>
+                format!("compile error: {}", display_names(&names, e)),
+            Error::ContextError(ref e) => e.context.clone(),
+            Error::DecodeError(ref e) => format!("decode error: {}", e),
+            Error::EncodeError(ref e) => format!("encode error: {}", e),
+            Error::ExecError(ExecError::DefaultError{function, param, ..}) => {
+                let mut s = format!(
+                    "execution error: error in default value of parameter `{}`",
+                    names.get(param));
+                if let Some(function) = function {
+                    s.push_str(&format!(" of `{}`", names.get(function)));
+                }
+                s
             }
-            Error::IoError(ref e) => {
-                let _ = writeln!(stderr(), "io error: {}", e);
+            Error::ExecError(ref e) =>
+                format!("execution error: {}", self.format_exec_error(&names, e)),
+            Error::IoError(ref e) => format!("io error: {}", e),
+            Error::ModuleLoadError(ref e) => display_names(&names, e).to_string(),
+            Error::ParseError(ref e) => {
+                let codemap = self.scope.borrow_codemap();
+                let hi = codemap.highlight_span(e.span);
+
+                format!("{}:{}:{}:parse error: {}",
+                    hi.filename.unwrap_or("<input>"), hi.line, hi.col, e.kind)
             }
-            Error::ParseError(ref e) => self.display_parse_error(e),
         }
     }
 
-    fn display_parse_error(&self, e: &ParseError) {
-        let codemap = self.scope.borrow_codemap();
-        let hi = codemap.highlight_span(e.span);
+    /// Formats an execution error. A name error is followed by a suggestion
+    /// of a similarly spelled name defined in the interpreter scope, if any.
+    fn format_exec_error(&self, names: &NameStore, e: &ExecError) -> String {
+        let mut s = display_names(names, e).to_string();
+
+        if let ExecError::NameError(name) = *e {
+            if let Some(similar) = self.scope.suggest_name(name) {
+                s.push_str(&format!("; did you mean `{}`?", names.get(similar)));
+            }
+        }
 
-        let mut stderr = stderr();
-        let _ = writeln!(stderr, "{}:{}:{}:parse error: {}",
-            hi.filename.unwrap_or("<input>"), hi.line, hi.col, e.kind);
-        let _ = writeln!(stderr, "    {}", hi.source);
-        let _ = writeln!(stderr, "    {}", hi.highlight);
+        s
     }
 
     /// Prints a string representation of a value to `stdout`.
+    ///
+    /// Very large integers are truncated, followed by a note
+    /// indicating the number of digits omitted.
     pub fn display_value(&self, value: &Value) {
-        println!("{}", debug_names(&self.scope.borrow_names(), value));
+        match *value {
+            Value::Integer(ref i) => println!("{}", truncate_digits(i.to_string())),
+            _ => println!("{}", debug_names(&self.scope.borrow_names(), value))
+        }
     }
 
     /// Formats a value into a string.
@@ -110,6 +258,14 @@ impl Interpreter {
         debug_names(&self.scope.borrow_names(), value).to_string()
     }
 
+    /// Runs each test defined in the interpreter scope using `define-test`
+    /// from the `test` module, printing a report to `stdout`.
+    /// Returns whether all tests passed.
+    pub fn run_tests(&self) -> Result<bool, Error> {
+        let summary = try!(run_tests(&self.scope, &self.scope));
+        Ok(summary.is_success())
+    }
+
     /// Executes a code object taking no parameters.
     pub fn execute(&self, code: Code) -> Result<Value, Error> {
         let v = try!(execute(&self.scope, Rc::new(code)));
@@ -158,6 +314,26 @@ impl Interpreter {
 
     fn get_value_name(&self, name: Name) -> Option<Value> {
         MasterScope::get(name).or_else(|| self.scope.get_value(name))
+            .or_else(|| self.scope.find_registered_fn(name)
+                .map(|(_, sys_fn)| Value::Function(Function{
+                    name: name,
+                    sys_fn: sys_fn,
+                })))
+    }
+
+    /// Registers a function implemented in Rust, which may be called by name
+    /// from code compiled within this interpreter.
+    ///
+    /// Unlike a function value added to the scope, calls to a registered
+    /// function are compiled directly into `CallSys` instructions, with the
+    /// argument count checked at compile time. A global value of the same
+    /// name takes precedence over the registered function.
+    pub fn register_system_fn(&self, name: &str, arity: Arity, callback: FunctionImpl) {
+        let name = self.scope.borrow_names_mut().add(name);
+        self.scope.register_system_fn(name, SystemFn{
+            arity: arity,
+            callback: callback,
+        });
     }
 
     /// Calls a closure with a borrowed reference to the global scope.
@@ -175,7 +351,22 @@ impl Interpreter {
     }
 
     /// Compiles and executes the contents of a file.
+    ///
+    /// If caching is enabled using `GlobalScope::set_cache_run_file` and
+    /// the file has extension `.kts`, compiled bytecode is cached in the
+    /// same manner as modules loaded by `FileModuleLoader`: bytecode is loaded
+    /// from the corresponding `.ktsc` file, if it is newer than the source file;
+    /// otherwise, the compiled source is written to the `.ktsc` file.
+    /// Code is executed within the interpreter scope, rather than a module scope.
+    ///
+    /// If, after execution, a function named `main` is defined, it is called.
     pub fn run_file(&self, path: &Path) -> Result<(), Error> {
+        if self.scope.cache_run_file() &&
+                path.extension().map_or(false, |ext| ext == FILE_EXTENSION) {
+            try!(run_main_file(&self.scope, path));
+            return self.call_main();
+        }
+
         let mut f = try!(File::open(path)
             .map_err(|e| IoError::new(IoMode::Open, path, e)));
 
@@ -187,6 +378,58 @@ impl Interpreter {
         self.run_main(&buf, path.to_string_lossy().into_owned())
     }
 
+    /// Parses and compiles the contents of a file, without executing it.
+    ///
+    /// Macros are expanded, so any macro bodies are executed.
+    /// To also avoid executing the code of modules imported by the file,
+    /// enable compile-only mode using `GlobalScope::set_compile_only`.
+    pub fn compile_file(&self, path: &Path) -> Result<Vec<Code>, Error> {
+        let mut f = try!(File::open(path)
+            .map_err(|e| IoError::new(IoMode::Open, path, e)));
+
+        let mut buf = String::new();
+
+        try!(f.read_to_string(&mut buf)
+            .map_err(|e| IoError::new(IoMode::Read, path, e)));
+
+        let exprs = try!(self.parse_file(&buf, Some(path.to_string_lossy().into_owned())));
+        exprs.iter().map(|v| compile(&self.scope, v)).collect()
+    }
+
+    /// Compiles and executes the source of a module in a new scope.
+    /// The module is then added to the module registry so that subsequent
+    /// code may import from it with `use`.
+    ///
+    /// As with modules loaded from files, `source` must contain an `export`
+    /// declaration.
+    pub fn run_module_source(&self, name: &str, source: &str) -> Result<Module, Error> {
+        let scope = GlobalScope::new_using(&self.scope);
+        let name = scope.add_name(name);
+        scope.set_module_name(name);
+
+        let exprs = {
+            let offset = scope.borrow_codemap_mut().add_source(source, None);
+            let mut names = scope.borrow_names_mut();
+            try!(Parser::new(&mut names, Lexer::new(source, offset)).parse_exprs())
+        };
+
+        for e in &exprs {
+            let code = try!(compile(&scope, e));
+            try!(execute(&scope, Rc::new(code)));
+        }
+
+        try!(check_exports(&scope, name));
+
+        let m = Module{
+            name: name,
+            scope: scope,
+        };
+
+        self.scope.get_modules().add_module(name, m.clone());
+
+        Ok(m)
+    }
+
     /// Compiles and executes an input expression.
     pub fn run_single_expr(&self, input: &str, path: Option<String>) -> Result<Value, Error> {
         let c = try!(self.compile_single_expr(input, path));
@@ -264,3 +507,56 @@ impl Interpreter {
         self.call_main()
     }
 }
+
+/// Number of digits beyond which `display_value` truncates an integer
+const MAX_DISPLAY_DIGITS: usize = 1000;
+
+/// Number of leading digits displayed for a truncated integer
+const TRUNCATED_DIGITS: usize = 100;
+
+fn truncate_digits(mut s: String) -> String {
+    let n_digits = s.trim_left_matches('-').len();
+
+    if n_digits <= MAX_DISPLAY_DIGITS {
+        return s;
+    }
+
+    let omitted = n_digits - TRUNCATED_DIGITS;
+    let keep = s.len() - omitted;
+
+    s.truncate(keep);
+    s.push_str(&format!("\u{2026} {} more digits", group_thousands(omitted)));
+    s
+}
+
+fn group_thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut s = String::with_capacity(digits.len() + digits.len() / 3);
+
+    for (i, ch) in digits.chars().enumerate() {
+        if i != 0 && (digits.len() - i) % 3 == 0 {
+            s.push(',');
+        }
+        s.push(ch);
+    }
+
+    s
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        // Only break cycles if no other references to the scope remain
+        if Rc::strong_count(&self.scope) == 1 {
+            self.scope.break_cycles();
+        }
+    }
+}
+
+/// Wraps text in ANSI escape sequences for bold red, if `color` is `true`.
+fn paint(s: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[1;31m{}\x1b[0m", s)
+    } else {
+        s.to_owned()
+    }
+}
@@ -1,5 +1,6 @@
 //! Creates an abstraction layer to I/O operations
 
+use std::error::Error as StdError;
 use std::fmt::{self, Arguments};
 use std::fs;
 use std::io::{self, Stdout, Stderr, Write};
@@ -34,6 +35,12 @@ impl fmt::Display for IoError {
     }
 }
 
+impl StdError for IoError {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        Some(&self.err)
+    }
+}
+
 /// Indicates the type of I/O operation that generated an error.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum IoMode {
@@ -13,9 +13,13 @@ pub enum Token<'lex> {
     LeftParen,
     /// Right parenthesis `)`
     RightParen,
+    /// Beginning of a vector literal `#(`
+    HashParen,
     /// A series of line comments beginning with `;;`,
     /// used to document declared values.
     DocComment(&'lex str),
+    /// Boolean literal `#t`, `#f`, `#true`, or `#false`
+    Bool(bool),
     /// Floating point literal
     Float(&'lex str),
     /// Integer literal in a given radix
@@ -48,7 +52,9 @@ impl<'lex> Token<'lex> {
         match *self {
             Token::LeftParen => "(",
             Token::RightParen => ")",
+            Token::HashParen => "#(",
             Token::DocComment(_) => "doc-comment",
+            Token::Bool(_) => "bool",
             Token::Float(_) => "float",
             Token::Integer(_, _) => "integer",
             Token::Ratio(_) => "ratio",
@@ -124,10 +130,16 @@ pub fn highlight_span(text: &str, span: Span) -> SpanDisplay {
 }
 
 /// Contains source code of parsed programs
+///
+/// Each source added to the codemap is assigned a range of global offsets,
+/// following those of the previously added source. Offsets are not reused
+/// after the codemap is cleared.
 #[derive(Clone, Debug)]
 pub struct CodeMap {
     text: String,
     files: Vec<File>,
+    /// Global offset of the beginning of `text`
+    base: BytePos,
 }
 
 #[derive(Clone, Debug)]
@@ -136,18 +148,43 @@ struct File {
     begin: BytePos,
 }
 
+/// Describes a source file contained in a `CodeMap`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SourceFile<'a> {
+    /// Index of the file in the codemap, in order of addition
+    pub index: usize,
+    /// Path of the file, if any
+    pub path: Option<&'a str>,
+    /// Global offset of the first byte of the file
+    pub begin: BytePos,
+    /// Global offset one byte past the end of the file
+    pub end: BytePos,
+}
+
+/// Location of a byte offset within a source file
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Position {
+    /// Index of the file in the codemap
+    pub file: usize,
+    /// Line number, beginning at `1`
+    pub line: usize,
+    /// Number of characters preceding the offset within the line
+    pub col: usize,
+}
+
 impl CodeMap {
     /// Creates a new `CodeMap`.
     pub fn new() -> CodeMap {
         CodeMap{
             text: String::new(),
             files: Vec::new(),
+            base: 0,
         }
     }
 
-    /// Adds a source to the codemap, returning its offset in the internal buffer.
+    /// Adds a source to the codemap, returning its global offset.
     pub fn add_source(&mut self, text: &str, path: Option<String>) -> BytePos {
-        let begin = self.text.len() as BytePos;
+        let begin = self.base + self.text.len() as BytePos;
 
         self.text.push_str(text);
         self.files.push(File{
@@ -159,40 +196,179 @@ impl CodeMap {
     }
 
     /// Clears all source from the codemap.
+    ///
+    /// Sources added afterward receive new offsets, so spans referring to
+    /// cleared source are not mistaken for spans within new source.
+    /// Such spans can no longer be displayed: `position` returns `None`
+    /// for their offsets and `highlight_span` will panic.
     pub fn clear(&mut self) {
+        self.base += self.text.len() as BytePos;
         self.text.clear();
         self.files.clear();
     }
 
+    /// Returns an iterator over the source files contained in the codemap.
+    pub fn files(&self) -> Files {
+        Files{
+            codemap: self,
+            index: 0,
+        }
+    }
+
+    /// Returns the file at the given index.
+    pub fn file(&self, index: usize) -> Option<SourceFile> {
+        self.files.get(index).map(|f| SourceFile{
+            index: index,
+            path: f.path.as_ref().map(|s| &s[..]),
+            begin: f.begin,
+            end: self.file_end(index),
+        })
+    }
+
+    /// Returns the global offset one byte past the end of the file at `index`.
+    fn file_end(&self, index: usize) -> BytePos {
+        match self.files.get(index + 1) {
+            Some(f) => f.begin,
+            None => self.base + self.text.len() as BytePos
+        }
+    }
+
+    /// Returns the text of the file at the given index.
+    fn file_text(&self, index: usize) -> &str {
+        let begin = (self.files[index].begin - self.base) as usize;
+        let end = (self.file_end(index) - self.base) as usize;
+        &self.text[begin..end]
+    }
+
+    /// Returns the index of the file containing the given global offset.
+    ///
+    /// An offset at the boundary between two files belongs to the latter;
+    /// the offset one byte past the end of the last file belongs to it.
+    fn find_file(&self, offset: BytePos) -> Option<usize> {
+        if offset < self.base || offset > self.base + self.text.len() as BytePos {
+            return None;
+        }
+
+        self.files.iter().rposition(|f| f.begin <= offset)
+    }
+
+    /// Returns the file, line, and column of the given global offset.
+    ///
+    /// Returns `None` if the offset is not within any contained file
+    /// or does not fall on a character boundary.
+    pub fn position(&self, offset: BytePos) -> Option<Position> {
+        let n = match self.find_file(offset) {
+            Some(n) => n,
+            None => return None
+        };
+
+        let text = self.file_text(n);
+        let pos = (offset - self.files[n].begin) as usize;
+
+        if !text.is_char_boundary(pos) {
+            return None;
+        }
+
+        let line_start = text[..pos].rfind('\n').map_or(0, |p| p + 1);
+
+        Some(Position{
+            file: n,
+            line: text[..line_start].matches('\n').count() + 1,
+            col: text[line_start..pos].chars().count(),
+        })
+    }
+
+    /// Returns the text of a line, beginning at `1`, within the file at the
+    /// given index. The line terminator, `\n` or `\r\n`, is not included.
+    pub fn line_text(&self, file: usize, line: usize) -> Option<&str> {
+        if file >= self.files.len() || line == 0 {
+            return None;
+        }
+
+        self.file_text(file).split('\n').nth(line - 1)
+            .map(|s| s.trim_right_matches('\r'))
+    }
+
+    /// Returns the global offset of the given line and column within
+    /// the file at the given index. Lines begin at `1`; a column is
+    /// a number of characters from the start of the line and may refer
+    /// to the end of the line.
+    pub fn offset(&self, file: usize, line: usize, col: usize) -> Option<BytePos> {
+        if file >= self.files.len() || line == 0 {
+            return None;
+        }
+
+        let text = self.file_text(file);
+        let mut line_start = 0;
+
+        for _ in 1..line {
+            match text[line_start..].find('\n') {
+                Some(p) => line_start += p + 1,
+                None => return None
+            }
+        }
+
+        let line_text = match text[line_start..].find('\n') {
+            Some(p) => &text[line_start..line_start + p],
+            None => &text[line_start..]
+        };
+
+        let pos = if col == line_text.chars().count() {
+            line_text.len()
+        } else {
+            match line_text.char_indices().nth(col) {
+                Some((p, _)) => p,
+                None => return None
+            }
+        };
+
+        Some(self.files[file].begin + (line_start + pos) as BytePos)
+    }
+
     /// Highlights a span within the codemap.
     ///
     /// # Panics
     ///
     /// Panics if `span` is not valid.
     pub fn highlight_span(&self, span: Span) -> SpanDisplay {
-        let n = match self.files.binary_search_by(|f| f.begin.cmp(&span.lo)) {
-            Ok(n) => n,
-            Err(n) => n - 1
+        let n = match self.find_file(span.lo) {
+            Some(n) => n,
+            None => panic!("span {:?} is not within the codemap", span)
         };
 
-        let end = match self.files.get(n as usize + 1) {
-            Some(f) => f.begin,
-            None => self.text.len() as u32
-        };
+        let f = &self.files[n];
 
-        if span.hi > end {
+        if span.hi > self.file_end(n) {
             panic!("span {:?} spans multiple files", span);
         }
 
-        let f = &self.files[n as usize];
-
         let Span{lo, hi} = span;
         let adj_span = Span{lo: lo - f.begin, hi: hi - f.begin};
 
         SpanDisplay{
             filename: f.path.as_ref().map(|s| &s[..]),
-            ..highlight_span(&self.text[f.begin as usize..end as usize], adj_span)
+            ..highlight_span(self.file_text(n), adj_span)
+        }
+    }
+}
+
+/// Iterates over the source files contained in a `CodeMap`.
+pub struct Files<'a> {
+    codemap: &'a CodeMap,
+    index: usize,
+}
+
+impl<'a> Iterator for Files<'a> {
+    type Item = SourceFile<'a>;
+
+    fn next(&mut self) -> Option<SourceFile<'a>> {
+        let f = self.codemap.file(self.index);
+
+        if f.is_some() {
+            self.index += 1;
         }
+
+        f
     }
 }
 
@@ -201,6 +377,8 @@ pub struct Lexer<'lex> {
     input: &'lex str,
     cur_pos: BytePos,
     code_offset: BytePos,
+    /// Whether `,` is skipped as whitespace
+    commas_whitespace: bool,
 }
 
 /// Represents a beginning and end point within a body of text.
@@ -236,9 +414,16 @@ impl<'lex> Lexer<'lex> {
             input: input,
             cur_pos: 0,
             code_offset: offset,
+            commas_whitespace: false,
         }
     }
 
+    /// Causes the `Lexer` to skip commas as whitespace rather than producing
+    /// `Comma` and `CommaAt` tokens.
+    pub fn skip_commas(&mut self) {
+        self.commas_whitespace = true;
+    }
+
     /// Scans the input stream for the next token, returning the token and
     /// the span of input text from which it was scanned.
     pub fn next_token(&mut self) -> Result<(Span, Token<'lex>), ParseError> {
@@ -252,6 +437,10 @@ impl<'lex> Lexer<'lex> {
                 ')' => Ok((Token::RightParen, 1)),
                 '\'' => Ok((Token::Quote, 1)),
                 '`' => Ok((Token::BackQuote, 1)),
+                ',' if self.commas_whitespace => {
+                    self.cur_pos += 1;
+                    continue;
+                }
                 ',' => match chars.next() {
                     Some((_, '@')) => Ok((Token::CommaAt, 2)),
                     _ => Ok((Token::Comma, 1)),
@@ -259,7 +448,9 @@ impl<'lex> Lexer<'lex> {
                 '-' | '0' ... '9' => parse_number(&self.input[ind..]),
                 '"' => Ok(try!(parse_string(&self.input[ind..], lo))),
                 '#' => match chars.next() {
-                    Some((_, '\'')) => Ok(try!(parse_char(&self.input[ind..], lo))),
+                    Some((_, '\'')) | Some((_, '\\')) =>
+                        Ok(try!(parse_char(&self.input[ind..], lo))),
+                    Some((_, '(')) => Ok((Token::HashParen, 2)),
                     Some((_, '|')) => match consume_block_comment(ind, &mut chars) {
                         Ok(n) => {
                             self.cur_pos += n as u32;
@@ -267,7 +458,9 @@ impl<'lex> Lexer<'lex> {
                         }
                         Err(k) => Err(k)
                     },
-                    Some(_) => Err(ParseErrorKind::InvalidToken),
+                    Some((_, ch)) if is_identifier(ch) =>
+                        parse_bool(&self.input[ind..]),
+                    Some(_) => Err(ParseErrorKind::UnknownHashSyntax),
                     None => Err(ParseErrorKind::UnexpectedEof)
                 },
                 'r' => match chars.next() {
@@ -318,6 +511,12 @@ impl<'lex> Lexer<'lex> {
         Ok((self.span(Span::empty(self.cur_pos)), Token::End))
     }
 
+    /// Returns the offset added to the position of each `Span`
+    /// produced by this `Lexer`.
+    pub fn code_offset(&self) -> BytePos {
+        self.code_offset
+    }
+
     /// Skips over a shebang line at the start of input. This is used when
     /// parsing files which, on Unix systems, may use a line consisting of `#!`
     /// followed by a path to the interpreter.
@@ -453,6 +652,21 @@ fn parse_identifier(input: &str) -> Result<(&str, usize), ParseErrorKind> {
     Ok((input, input.len()))
 }
 
+fn parse_bool(input: &str) -> Result<(Token, usize), ParseErrorKind> {
+    let (ident, size) = try!(parse_identifier(&input[1..])); // Skip leading '#'
+
+    let b = match ident {
+        "t" | "T" | "true" | "True" => true,
+        "f" | "F" | "false" | "False" => false,
+        _ if ident.eq_ignore_ascii_case("true") ||
+            ident.eq_ignore_ascii_case("false") =>
+                return Err(ParseErrorKind::InvalidBool),
+        _ => return Err(ParseErrorKind::UnknownHashSyntax)
+    };
+
+    Ok((Token::Bool(b), size + 1))
+}
+
 fn parse_keyword(input: &str) -> Result<(Token, usize), ParseErrorKind> {
     parse_identifier(&input[1..]) // Skip leading ':'
         .and_then(|(ident, size)| {
@@ -465,7 +679,19 @@ fn parse_keyword(input: &str) -> Result<(Token, usize), ParseErrorKind> {
 }
 
 fn parse_name(input: &str) -> Result<(Token, usize), ParseErrorKind> {
-    parse_identifier(input).map(|(ident, size)| (Token::Name(ident), size))
+    let (_, mut size) = try!(parse_identifier(input));
+
+    // A qualified name, `alias:name`, refers to a value in an aliased module.
+    // A colon is part of a name only when it is followed by an identifier.
+    if input[size..].starts_with(':') {
+        let (member, member_size) = try!(parse_identifier(&input[size + 1..]));
+
+        if !member.is_empty() {
+            size += 1 + member_size;
+        }
+    }
+
+    Ok((Token::Name(&input[..size]), size))
 }
 
 fn parse_number(input: &str) -> Result<(Token, usize), ParseErrorKind> {
@@ -570,7 +796,7 @@ fn parse_raw_string(input: &str, pos: BytePos) -> Result<(Token, usize), ParseEr
 
 #[cfg(test)]
 mod test {
-    use super::{BytePos, Lexer, Span, Token};
+    use super::{BytePos, CodeMap, Lexer, Position, Span, Token};
     use parser::ParseErrorKind;
 
     fn sp(lo: BytePos, hi: BytePos) -> Span {
@@ -632,6 +858,13 @@ mod test {
              (sp(7, 12), Token::Integer("0o777", 8)),
              (sp(13, 23), Token::Integer("0xdeadBEEF", 16))]);
 
+        assert_eq!(tokens("#(1) #()"),
+            [(sp(0, 2), Token::HashParen),
+             (sp(2, 3), Token::Integer("1", 10)),
+             (sp(3, 4), Token::RightParen),
+             (sp(5, 7), Token::HashParen),
+             (sp(7, 8), Token::RightParen)]);
+
         assert_eq!(tokens("1/2 -10/3"),
             [(sp(0, 3), Token::Ratio("1/2")),
              (sp(4, 9), Token::Ratio("-10/3"))]);
@@ -771,11 +1004,64 @@ mod test {
              (sp(8, 15), Token::DocComment(";; bar\n"))]);
     }
 
+    #[test]
+    fn test_bool() {
+        assert_eq!(tokens("#t #f #true #false"),
+            [(sp(0, 2), Token::Bool(true)),
+             (sp(3, 5), Token::Bool(false)),
+             (sp(6, 11), Token::Bool(true)),
+             (sp(12, 18), Token::Bool(false))]);
+
+        assert_eq!(tokens("#T #F #True #False"),
+            [(sp(0, 2), Token::Bool(true)),
+             (sp(3, 5), Token::Bool(false)),
+             (sp(6, 11), Token::Bool(true)),
+             (sp(12, 18), Token::Bool(false))]);
+
+        assert_eq!(tokens("(#t)"),
+            [(sp(0, 1), Token::LeftParen),
+             (sp(1, 3), Token::Bool(true)),
+             (sp(3, 4), Token::RightParen)]);
+    }
+
+    #[test]
+    fn test_qualified_name() {
+        assert_eq!(tokens("m:parse (m:a-b) a:b:c :d"),
+            [(sp(0, 7), Token::Name("m:parse")),
+             (sp(8, 9), Token::LeftParen),
+             (sp(9, 14), Token::Name("m:a-b")),
+             (sp(14, 15), Token::RightParen),
+             (sp(16, 19), Token::Name("a:b")),
+             (sp(19, 21), Token::Keyword("c")),
+             (sp(22, 24), Token::Keyword("d"))]);
+    }
+
+    #[test]
+    fn test_named_char() {
+        assert_eq!(tokens(r"(#\space #\x7f #\a #\))"),
+            [(sp(0, 1), Token::LeftParen),
+             (sp(1, 8), Token::Char(r"#\space")),
+             (sp(9, 14), Token::Char(r"#\x7f")),
+             (sp(15, 18), Token::Char(r"#\a")),
+             (sp(19, 22), Token::Char(r"#\)")),
+             (sp(22, 23), Token::RightParen)]);
+
+        assert_eq!(error(r"#\foo"), Err(ParseErrorKind::UnknownCharName));
+        assert_eq!(error(r"#\xd800"), Err(ParseErrorKind::InvalidNumericEscape('x')));
+        assert_eq!(error(r"#\"), Err(ParseErrorKind::UnterminatedChar));
+    }
+
     #[test]
     fn test_errors() {
         assert_eq!(error("\rfoo"), Err(ParseErrorKind::InvalidChar('\r')));
         assert_eq!(error(":"), Err(ParseErrorKind::InvalidToken));
 
+        assert_eq!(error("#TRUE"), Err(ParseErrorKind::InvalidBool));
+        assert_eq!(error("#fAlse"), Err(ParseErrorKind::InvalidBool));
+        assert_eq!(error("#yes"), Err(ParseErrorKind::UnknownHashSyntax));
+        assert_eq!(error("#truth"), Err(ParseErrorKind::UnknownHashSyntax));
+        assert_eq!(error("#["), Err(ParseErrorKind::UnknownHashSyntax));
+
         assert_eq!(error("-0x1"), Err(ParseErrorKind::InvalidLiteral));
         assert_eq!(error("0o78"), Err(ParseErrorKind::InvalidLiteral));
         assert_eq!(error("0b012"), Err(ParseErrorKind::InvalidLiteral));
@@ -786,4 +1072,57 @@ mod test {
         assert_eq!(error("10e1/2"), Err(ParseErrorKind::InvalidLiteral));
         assert_eq!(error("10/2e3"), Err(ParseErrorKind::InvalidLiteral));
     }
+
+    #[test]
+    fn test_codemap() {
+        let mut cm = CodeMap::new();
+
+        let a = cm.add_source("(foo\n  bar)\n", Some("a.kts".to_owned()));
+        let b = cm.add_source("x\r\ny λz\r\n", None);
+        let c = cm.add_source("", Some("c.kts".to_owned()));
+
+        assert_eq!((a, b, c), (0, 12, 22));
+        assert_eq!(cm.files().map(|f| (f.index, f.path, f.begin, f.end))
+            .collect::<Vec<_>>(),
+            [(0, Some("a.kts"), 0, 12), (1, None, 12, 22), (2, Some("c.kts"), 22, 22)]);
+
+        assert_eq!(cm.position(0), Some(Position{file: 0, line: 1, col: 0}));
+        assert_eq!(cm.position(7), Some(Position{file: 0, line: 2, col: 2}));
+        assert_eq!(cm.position(11), Some(Position{file: 0, line: 2, col: 6}));
+        // Offsets at a file boundary belong to the following file
+        assert_eq!(cm.position(12), Some(Position{file: 1, line: 1, col: 0}));
+        assert_eq!(cm.position(15), Some(Position{file: 1, line: 2, col: 0}));
+        assert_eq!(cm.position(19), Some(Position{file: 1, line: 2, col: 3}));
+        assert_eq!(cm.position(18), None);
+        assert_eq!(cm.position(22), Some(Position{file: 2, line: 1, col: 0}));
+        assert_eq!(cm.position(23), None);
+
+        assert_eq!(cm.line_text(0, 1), Some("(foo"));
+        assert_eq!(cm.line_text(0, 2), Some("  bar)"));
+        assert_eq!(cm.line_text(1, 1), Some("x"));
+        assert_eq!(cm.line_text(1, 2), Some("y λz"));
+        assert_eq!(cm.line_text(1, 4), None);
+        assert_eq!(cm.line_text(0, 0), None);
+        assert_eq!(cm.line_text(3, 1), None);
+
+        assert_eq!(cm.offset(0, 2, 2), Some(7));
+        assert_eq!(cm.offset(1, 2, 3), Some(19));
+        assert_eq!(cm.offset(1, 1, 1), Some(13));
+        assert_eq!(cm.offset(1, 1, 3), None);
+        assert_eq!(cm.offset(0, 4, 0), None);
+
+        for off in (0..23).filter(|&off| off != 18) {
+            let pos = cm.position(off).unwrap();
+            assert_eq!(cm.offset(pos.file, pos.line, pos.col), Some(off));
+        }
+
+        let hi = cm.highlight_span(Span{lo: 7, hi: 10});
+        assert_eq!((hi.filename, hi.line, hi.col, hi.source), (Some("a.kts"), 2, 2, "  bar)"));
+
+        // Offsets are not reused after clearing
+        cm.clear();
+        assert_eq!(cm.position(0), None);
+        assert_eq!(cm.add_source("foo", None), 22);
+        assert_eq!(cm.position(23), Some(Position{file: 0, line: 1, col: 1}));
+    }
 }
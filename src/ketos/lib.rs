@@ -38,14 +38,16 @@ pub use function::Arity;
 pub use interpreter::Interpreter;
 pub use integer::{Integer, Ratio};
 pub use io::IoError;
-pub use module::{BuiltinModuleLoader, FileModuleLoader, Module, ModuleBuilder, ModuleLoader};
-pub use name::{Name, NameStore};
+pub use module::{BuiltinModuleLoader, FileModuleLoader, ImportPolicy, MemoryModuleLoader,
+    Module, ModuleBuilder, ModuleLoadError, ModuleLoader};
+pub use name::{Name, NameMap, NameSet, NameSnapshot, NameStore};
 pub use parser::{ParseError, ParseErrorKind};
-pub use scope::{GlobalScope, Scope};
+pub use scope::{GlobalScope, LogLevel, Scope, YieldAction};
 pub use value::{ForeignValue, FromValue, FromValueRef, Value};
 
 pub mod bytecode;
 pub mod compile;
+pub mod condition;
 pub mod encode;
 pub mod error;
 pub mod exec;
@@ -57,6 +59,7 @@ pub mod lexer;
 pub mod module;
 pub mod name;
 pub mod parser;
+pub mod range;
 pub mod rc_vec;
 pub mod scope;
 mod string;
@@ -65,4 +68,7 @@ pub mod value;
 
 mod mod_code;
 mod mod_math;
+mod mod_os;
 mod mod_random;
+mod mod_string;
+mod mod_test;
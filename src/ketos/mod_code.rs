@@ -1,17 +1,27 @@
 //! Implements builtin `code` module.
 
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{self, Write};
+use std::hash::Hasher;
+use std::ptr;
 use std::rc::Rc;
 
-use bytecode::{CodeReader, Instruction};
+use bytecode::{jump_table_labels, CodeReader, Instruction};
 use compile::compile;
 use error::Error;
-use exec::ExecError;
+use exec::{call_function, ExecError};
 use function::{plural, Lambda};
-use function::Arity::Exact;
+use function::Arity::{Exact, Min, Range};
+use io::SharedWrite;
 use module::{Module, ModuleBuilder};
-use name::{debug_names, get_standard_name};
-use scope::Scope;
-use value::{FromValueRef, Value};
+use name::{debug_names, get_standard_name, Name, NameStore};
+use parser::read_data;
+use rc_vec::RcVec;
+use scope::{MasterScope, Scope};
+use value::{ForeignValue, FromValueRef, Value};
 
 /// Loads the `code` module into the given scope.
 pub fn load(scope: Scope) -> Module {
@@ -20,6 +30,10 @@ pub fn load(scope: Scope) -> Module {
         .add_function("disassemble", fn_disassemble, Exact(1))
         .add_function("get-const",   fn_get_const,   Exact(2))
         .add_function("get-value",   fn_get_value,   Exact(2))
+        .add_function("memoize",     fn_memoize,     Range(1, 2))
+        .add_function("read-data",   fn_read_data,   Exact(1))
+        .add_function("trace",       fn_trace,       Min(0))
+        .add_function("untrace",     fn_untrace,     Min(0))
         .finish()
 }
 
@@ -90,7 +104,13 @@ fn fn_disassemble(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
 
     // Collect all jump labels
     for &(_, ref instr) in &instrs {
-        if let Some(off) = instr.get_jump_label() {
+        let labels = match *instr {
+            Instruction::JumpTable(n) => code.consts.get(n as usize)
+                .and_then(jump_table_labels).unwrap_or_default(),
+            ref instr => instr.get_jump_label().into_iter().collect()
+        };
+
+        for off in labels {
             match jumps.binary_search(&off) {
                 Ok(_) => (),
                 Err(pos) => jumps.insert(pos, off)
@@ -143,12 +163,21 @@ fn print_instruction(scope: &Scope, lambda: &Lambda,
                 lambda.values.as_ref().and_then(|v| v.get(n as usize))
                     .map(|v| debug_names(&names, v).to_string()),
             GetDef(n) |
+            GetDynamic(n) |
+            GetModuleDef(n) |
+            DefineModule(n) |
+            WithModule(n) |
             Const(n) |
             GetDefPush(n) |
             ConstPush(n) |
             SetDef(n) |
+            SetDefOverload(n) |
+            SetDynamic(n) |
+            BindDynamic(n) |
             BuildClosure(n, _) |
-            CallConst(n, _)
+            JumpTable(n) |
+            CallConst(n, _) |
+            TailCallConst(n, _)
                 => code.consts.get(n as usize).map(
                     |c| debug_names(&names, c).to_string()),
             Jump(l) |
@@ -161,14 +190,17 @@ fn print_instruction(scope: &Scope, lambda: &Lambda,
             JumpIfBound(l, _)
                 => Some(format!("L{}", l)),
             JumpIfEqConst(l, n) |
-            JumpIfNotEqConst(l, n)
+            JumpIfNotEqConst(l, n) |
+            PushHandler(l, n)
                 => Some(match code.consts.get(n as usize) {
                     None => format!("L{}", l),
                     Some(c) => format!("L{} {}", l, debug_names(&names, c))
                 }),
             CallSys(n) |
             CallSysArgs(n, _) =>
-                get_standard_name(n).map(|n| names.get(n).to_owned()),
+                get_standard_name(n)
+                    .or_else(|| scope.get_registered_fn(n).map(|(n, _)| n))
+                    .map(|n| names.get(n).to_owned()),
             _ => None
         }
     };
@@ -213,3 +245,349 @@ fn fn_get_value(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
         ref v => Err(From::from(ExecError::expected("lambda", v)))
     }
 }
+
+/// Wraps a global function to print the arguments and return value
+/// of each call.
+#[derive(Debug)]
+struct Traced {
+    name: Name,
+    value: Value,
+}
+
+impl Traced {
+    fn write_trace(&self, scope: &Scope, depth: u32, s: &str) -> Result<(), Error> {
+        let io = scope.get_io();
+        try!(write!(io, "{:1$}{2}\n", "", depth as usize * 2, s));
+        try!(io.flush());
+        Ok(())
+    }
+}
+
+impl ForeignValue for Traced {
+    fn compare_to(&self, _rhs: &ForeignValue) -> Result<Ordering, ExecError> {
+        Err(ExecError::CannotCompare("traced-fn"))
+    }
+
+    fn is_equal_to(&self, rhs: &ForeignValue) -> Result<bool, ExecError> {
+        match rhs.downcast_ref::<Traced>() {
+            Some(rhs) => Ok(self.name == rhs.name &&
+                self.value.is_identical(&rhs.value)),
+            None => Err(ExecError::TypeMismatch{
+                lhs: self.type_name(),
+                rhs: rhs.type_name(),
+            })
+        }
+    }
+
+    fn fmt_debug(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<traced-fn {}>", debug_names(names, &self.value))
+    }
+
+    fn type_name(&self) -> &'static str { "traced-fn" }
+
+    fn call_value(&self, scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+        let depth = scope.trace_depth();
+
+        let call = {
+            let names = scope.borrow_names();
+            let mut s = format!("({}", names.get(self.name));
+
+            for v in args.iter() {
+                let _ = write!(s, " {}", debug_names(&names, v));
+            }
+
+            s.push(')');
+            s
+        };
+
+        try!(self.write_trace(scope, depth, &call));
+
+        let args = args.iter_mut().map(|v| v.take()).collect();
+
+        scope.set_trace_depth(depth + 1);
+        let r = call_function(scope, self.value.clone(), args);
+        scope.set_trace_depth(depth);
+
+        let v = try!(r);
+
+        let ret = {
+            let names = scope.borrow_names();
+            format!("{} => {}", names.get(self.name), debug_names(&names, &v))
+        };
+
+        try!(self.write_trace(scope, depth, &ret));
+        Ok(v)
+    }
+}
+
+/// Returns the names of traced global functions.
+fn traced_names(scope: &Scope) -> Vec<Name> {
+    scope.with_values(|values| values.iter()
+        .filter(|&&(_, ref v)| get_traced(v).is_some())
+        .map(|&(name, _)| name).collect())
+}
+
+fn get_traced(v: &Value) -> Option<&Traced> {
+    match *v {
+        Value::Foreign(ref fv) => fv.downcast_ref::<Traced>(),
+        _ => None
+    }
+}
+
+fn get_name(v: &Value) -> Result<Name, ExecError> {
+    match *v {
+        Value::Name(name) => Ok(name),
+        ref v => Err(ExecError::expected("name", v))
+    }
+}
+
+/// `trace` replaces each named global function with a function which prints
+/// its arguments and return value when called. Returns a list of the names
+/// of all traced functions.
+fn fn_trace(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    for arg in args.iter() {
+        let name = try!(get_name(arg));
+
+        if !MasterScope::can_define(name) {
+            return Err(From::from(ExecError::CannotDefine(name)));
+        }
+
+        let value = try!(scope.get_value(name)
+            .ok_or(ExecError::NameError(name)));
+
+        match value {
+            Value::Foreign(ref fv) if fv.is::<Traced>() => continue,
+            Value::Function(_) | Value::Lambda(_) |
+            Value::Overload(_) | Value::Foreign(_) => (),
+            ref v => return Err(From::from(ExecError::expected("function", v)))
+        }
+
+        scope.add_value(name, Value::new_foreign(Traced{
+            name: name,
+            value: value,
+        }));
+    }
+
+    Ok(traced_names(scope).into_iter().map(Value::Name).collect::<Vec<_>>().into())
+}
+
+/// `untrace` restores each named traced function to its original value.
+/// If no names are given, all traced functions are restored.
+fn fn_untrace(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let names = if args.is_empty() {
+        traced_names(scope)
+    } else {
+        try!(args.iter().map(get_name).collect::<Result<Vec<_>, _>>())
+    };
+
+    for name in names {
+        let orig = scope.get_value(name)
+            .and_then(|v| get_traced(&v).map(|t| t.value.clone()));
+
+        if let Some(v) = orig {
+            scope.add_value(name, v);
+        }
+    }
+
+    Ok(Value::Unit)
+}
+
+/// `read-data` parses a string as a series of data values and returns a list.
+/// Names are returned as strings; keywords are returned as strings
+/// beginning with `:`.
+///
+/// ```lisp
+/// (read-data "(1, 2) foo :bar")
+/// ```
+fn fn_read_data(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let input = match args[0] {
+        Value::String(ref s) => s,
+        ref v => return Err(From::from(ExecError::expected("string", v)))
+    };
+
+    let offset = scope.borrow_codemap_mut().add_source(input, None);
+    let (values, names) = try!(read_data(input, offset));
+
+    Ok(names_to_strings(values, &names))
+}
+
+/// Replaces names and keywords parsed into a separate `NameStore`,
+/// so that they are not added to the interpreter's `NameStore`.
+fn names_to_strings(v: Value, names: &NameStore) -> Value {
+    match v {
+        Value::Name(name) => names.get(name).into(),
+        Value::Keyword(name) => format!(":{}", names.get(name)).into(),
+        Value::Quote(v, n) => Value::Quote(Box::new(names_to_strings(*v, names)), n),
+        Value::List(li) => Value::List(RcVec::new(li.into_vec().into_iter()
+            .map(|v| names_to_strings(v, names)).collect())),
+        Value::Vector(li) => Value::Vector(RcVec::new(li.into_vec().into_iter()
+            .map(|v| names_to_strings(v, names)).collect())),
+        v => v
+    }
+}
+
+/// Wraps a function, caching its results by argument list.
+#[derive(Debug)]
+struct Memoized {
+    value: Value,
+    max_entries: Option<usize>,
+    cache: RefCell<MemoCache>,
+}
+
+/// Cached results of a `Memoized` function, grouped by argument hash.
+#[derive(Debug, Default)]
+struct MemoCache {
+    entries: HashMap<u64, Vec<MemoEntry>>,
+    len: usize,
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct MemoEntry {
+    args: Vec<Value>,
+    result: Value,
+    last_used: u64,
+}
+
+impl MemoCache {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn get(&mut self, hash: u64, args: &[Value]) -> Option<Value> {
+        let now = self.tick();
+
+        self.entries.get_mut(&hash)
+            .and_then(|ents| ents.iter_mut().find(|e| args_identical(&e.args, args)))
+            .map(|e| {
+                e.last_used = now;
+                e.result.clone()
+            })
+    }
+
+    fn insert(&mut self, hash: u64, args: Vec<Value>, result: Value,
+            max_entries: Option<usize>) {
+        if let Some(max) = max_entries {
+            while self.len >= max {
+                self.evict_oldest();
+            }
+        }
+
+        let now = self.tick();
+
+        self.entries.entry(hash).or_insert_with(Vec::new).push(MemoEntry{
+            args: args,
+            result: result,
+            last_used: now,
+        });
+        self.len += 1;
+    }
+
+    /// Removes the least recently used entry.
+    fn evict_oldest(&mut self) {
+        let oldest = self.entries.iter()
+            .flat_map(|(&hash, ents)| ents.iter().enumerate()
+                .map(move |(i, e)| (e.last_used, hash, i)))
+            .min();
+
+        if let Some((_, hash, i)) = oldest {
+            let empty = {
+                let ents = self.entries.get_mut(&hash).unwrap();
+                ents.remove(i);
+                ents.is_empty()
+            };
+
+            if empty {
+                self.entries.remove(&hash);
+            }
+
+            self.len -= 1;
+        }
+    }
+}
+
+fn args_identical(a: &[Value], b: &[Value]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.is_identical(b))
+}
+
+/// Returns a hash of the argument list, if all arguments can be hashed.
+fn hash_args(args: &[Value]) -> Option<u64> {
+    let mut h = DefaultHasher::new();
+
+    if args.iter().all(|v| v.hash_identical(&mut h)) {
+        Some(h.finish())
+    } else {
+        None
+    }
+}
+
+impl ForeignValue for Memoized {
+    fn compare_to(&self, _rhs: &ForeignValue) -> Result<Ordering, ExecError> {
+        Err(ExecError::CannotCompare("memoized-fn"))
+    }
+
+    fn is_equal_to(&self, rhs: &ForeignValue) -> Result<bool, ExecError> {
+        match rhs.downcast_ref::<Memoized>() {
+            Some(rhs) => Ok(ptr::eq(self, rhs)),
+            None => Err(ExecError::TypeMismatch{
+                lhs: self.type_name(),
+                rhs: rhs.type_name(),
+            })
+        }
+    }
+
+    fn fmt_debug(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<memoized-fn {}>", debug_names(names, &self.value))
+    }
+
+    fn type_name(&self) -> &'static str { "memoized-fn" }
+
+    fn call_value(&self, scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+        let hash = match hash_args(args) {
+            Some(hash) => hash,
+            None => {
+                let args = args.iter_mut().map(|v| v.take()).collect();
+                return call_function(scope, self.value.clone(), args);
+            }
+        };
+
+        // The cache is not borrowed during the call,
+        // which may recursively call this function.
+        if let Some(v) = self.cache.borrow_mut().get(hash, args) {
+            return Ok(v);
+        }
+
+        let key = args.to_vec();
+        let args = args.iter_mut().map(|v| v.take()).collect();
+        let v = try!(call_function(scope, self.value.clone(), args));
+
+        self.cache.borrow_mut().insert(hash, key, v.clone(), self.max_entries);
+        Ok(v)
+    }
+}
+
+/// `memoize` returns a function which calls the given function,
+/// caching results by argument list. If a maximum number of entries is given,
+/// the least recently used result is discarded when the cache is full.
+fn fn_memoize(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    match args[0] {
+        Value::Function(_) | Value::Lambda(_) |
+        Value::Overload(_) | Value::Foreign(_) => (),
+        ref v => return Err(From::from(ExecError::expected("function", v)))
+    }
+
+    let max_entries = match args.get(1) {
+        Some(v) => match try!(usize::from_value_ref(v)) {
+            0 => return Err(From::from(ExecError::expected("positive integer", v))),
+            n => Some(n)
+        },
+        None => None
+    };
+
+    Ok(Value::new_foreign(Memoized{
+        value: args[0].take(),
+        max_entries: max_entries,
+        cache: RefCell::new(MemoCache::default()),
+    }))
+}
@@ -0,0 +1,104 @@
+//! Implements builtin `os` module.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use error::Error;
+use exec::ExecError;
+use function::Arity::{Exact, Min};
+use io::{IoError, IoMode};
+use module::{Module, ModuleBuilder};
+use scope::Scope;
+use value::Value;
+
+/// Loads the `os` module into the given scope.
+pub fn load(scope: Scope) -> Module {
+    ModuleBuilder::new("os", scope)
+        .add_function("path-join",         fn_path_join,         Min(1))
+        .add_function("path-parent",       fn_path_parent,       Exact(1))
+        .add_function("path-file-name",    fn_path_file_name,    Exact(1))
+        .add_function("path-extension",    fn_path_extension,    Exact(1))
+        .add_function("path-absolute?",    fn_path_is_absolute,  Exact(1))
+        .add_function("path-canonicalize", fn_path_canonicalize, Exact(1))
+        .finish()
+}
+
+fn get_path(v: &Value) -> Result<&Path, ExecError> {
+    match *v {
+        Value::String(ref s) => Ok(Path::new(&s[..])),
+        ref v => Err(ExecError::expected("string", v))
+    }
+}
+
+/// Returns a path component as a string value or `()` if it is missing.
+///
+/// Components of a path created from a string are always valid UTF-8.
+fn opt_path_value<P: AsRef<Path>>(p: Option<P>) -> Value {
+    match p {
+        Some(p) => p.as_ref().to_string_lossy().into_owned().into(),
+        None => Value::Unit
+    }
+}
+
+/// `path-join` joins one or more paths using the native path separator.
+/// If any path is absolute, it replaces all preceding paths.
+///
+/// ```lisp
+/// (path-join "foo" "bar" "baz.txt")
+/// ```
+fn fn_path_join(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let mut path = PathBuf::new();
+
+    for arg in args.iter() {
+        path.push(try!(get_path(arg)));
+    }
+
+    Ok(opt_path_value(Some(path)))
+}
+
+/// `path-parent` returns the path without its final component,
+/// or `()` if the path has no parent.
+fn fn_path_parent(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let path = try!(get_path(&args[0]));
+    Ok(opt_path_value(path.parent()))
+}
+
+/// `path-file-name` returns the final component of a path,
+/// or `()` if the path has no final component or it is `..`.
+fn fn_path_file_name(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let path = try!(get_path(&args[0]));
+    Ok(opt_path_value(path.file_name()))
+}
+
+/// `path-extension` returns the extension of the final component of a path,
+/// excluding the leading `.`, or `()` if there is no extension.
+fn fn_path_extension(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let path = try!(get_path(&args[0]));
+    Ok(opt_path_value(path.extension()))
+}
+
+/// `path-absolute?` returns whether a path is absolute.
+///
+/// On Windows, a path is absolute if it has both a prefix, such as a drive
+/// letter or UNC server and share, and a root.
+fn fn_path_is_absolute(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let path = try!(get_path(&args[0]));
+    Ok(path.is_absolute().into())
+}
+
+/// `path-canonicalize` returns the absolute form of a path, with all
+/// intermediate components normalized and symbolic links resolved.
+/// The path must exist.
+fn fn_path_canonicalize(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let path = try!(get_path(&args[0]));
+
+    let canon = try!(path.canonicalize()
+        .map_err(|e| IoError::new(IoMode::Stat, path, e)));
+
+    match canon.into_os_string().into_string() {
+        Ok(s) => Ok(s.into()),
+        Err(_) => Err(From::from(IoError::new(IoMode::Stat, path,
+            io::Error::new(io::ErrorKind::InvalidData,
+                "canonical path is not valid UTF-8"))))
+    }
+}
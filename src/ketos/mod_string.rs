@@ -0,0 +1,157 @@
+//! Implements builtin `string` module.
+
+use error::Error;
+use exec::ExecError;
+use function::Arity::{Exact, Range};
+use module::{Module, ModuleBuilder};
+use name::Name;
+use scope::Scope;
+use string::find_folded;
+use value::{FromValueRef, Value};
+
+/// Loads the `string` module into the given scope.
+pub fn load(scope: Scope) -> Module {
+    ModuleBuilder::new("string", scope)
+        .add_function("find",    fn_find,    Exact(2))
+        .add_function("find-ci", fn_find_ci, Exact(2))
+        .add_function("split",   fn_split,   Range(2, 4))
+        .add_function("replace", fn_replace, Range(3, 5))
+        .finish()
+}
+
+fn get_keyword(v: &Value) -> Result<Name, ExecError> {
+    match *v {
+        Value::Keyword(name) => Ok(name),
+        ref v => Err(ExecError::expected("keyword", v))
+    }
+}
+
+fn get_string(v: &Value) -> Result<&str, ExecError> {
+    FromValueRef::from_value_ref(v)
+}
+
+/// `find` returns the char index of the first occurrence of a substring
+/// within a string, or `()` if it does not occur.
+///
+/// ```lisp
+/// (find "hello" "l")
+/// ```
+fn fn_find(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    find_impl(args, false)
+}
+
+/// `find-ci` returns the char index of the first occurrence of a substring
+/// within a string, ignoring case, or `()` if it does not occur.
+fn fn_find_ci(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    find_impl(args, true)
+}
+
+fn find_impl(args: &mut [Value], ci: bool) -> Result<Value, Error> {
+    let s = try!(get_string(&args[0]));
+    let pat = try!(get_string(&args[1]));
+
+    Ok(match find_str(s, pat, 0, ci) {
+        Some((pos, _)) => s[..pos].chars().count().into(),
+        None => Value::Unit
+    })
+}
+
+/// `split` returns a list of the substrings separated by a pattern string.
+/// Given the option `:ci true`, case is ignored in matching the pattern.
+///
+/// ```lisp
+/// (split "a,b,c" ",")
+/// (split "aXbxc" "x" :ci true)
+/// ```
+fn fn_split(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let ci = try!(get_ci_option(scope, &args[2..]));
+    let s = try!(get_string(&args[0]));
+    let pat = try!(get_pattern(&args[1]));
+
+    let mut res: Vec<Value> = Vec::new();
+    let mut start = 0;
+
+    while let Some((lo, hi)) = find_str(s, pat, start, ci) {
+        res.push(s[start..lo].into());
+        start = hi;
+    }
+
+    res.push(s[start..].into());
+    Ok(res.into())
+}
+
+/// `replace` returns a string with each occurrence of a pattern string
+/// replaced with another string.
+/// Given the option `:ci true`, case is ignored in matching the pattern.
+///
+/// ```lisp
+/// (replace "a-b-c" "-" "+")
+/// (replace "Hello, hello" "hello" "bye" :ci true)
+/// ```
+fn fn_replace(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let ci = try!(get_ci_option(scope, &args[3..]));
+    let s = try!(get_string(&args[0]));
+    let pat = try!(get_pattern(&args[1]));
+    let rep = try!(get_string(&args[2]));
+
+    let mut res = String::with_capacity(s.len());
+    let mut start = 0;
+
+    while let Some((lo, hi)) = find_str(s, pat, start, ci) {
+        res.push_str(&s[start..lo]);
+        res.push_str(rep);
+        start = hi;
+    }
+
+    res.push_str(&s[start..]);
+    Ok(res.into())
+}
+
+/// Returns the byte range of the first occurrence of `pat` in `s`,
+/// beginning at byte offset `start`.
+fn find_str(s: &str, pat: &str, start: usize, ci: bool) -> Option<(usize, usize)> {
+    if ci {
+        find_folded(s, pat, start)
+    } else {
+        s[start..].find(pat).map(|pos| (start + pos, start + pos + pat.len()))
+    }
+}
+
+/// Returns a pattern string, which must not be empty.
+fn get_pattern(v: &Value) -> Result<&str, ExecError> {
+    match *v {
+        Value::String(ref s) if !s.is_empty() => Ok(s),
+        ref v => Err(ExecError::expected("non-empty string", v))
+    }
+}
+
+/// Parses the keyword options accepted by string matching functions.
+/// Currently, the only option is `:ci`, which enables case-insensitive
+/// matching when its value is `true`.
+fn get_ci_option(scope: &Scope, args: &[Value]) -> Result<bool, ExecError> {
+    let mut ci = None;
+    let mut iter = args.iter();
+
+    while let Some(kw) = iter.next() {
+        let kw = try!(get_keyword(kw));
+
+        let value = match iter.next() {
+            Some(value) => value,
+            None => return Err(ExecError::OddKeywordParams)
+        };
+
+        if !scope.with_name(kw, |s| s == "ci") {
+            return Err(ExecError::UnrecognizedKeyword(kw));
+        }
+        if ci.is_some() {
+            return Err(ExecError::DuplicateKeyword(kw));
+        }
+
+        ci = Some(match *value {
+            Value::Bool(b) => b,
+            ref v => return Err(ExecError::expected("bool", v))
+        });
+    }
+
+    Ok(ci.unwrap_or(false))
+}
@@ -0,0 +1,338 @@
+//! Implements builtin `test` module.
+
+use std::rc::Rc;
+
+use compile::compile;
+use condition::{condition_matches, is_error_category, Condition};
+use error::Error;
+use exec::{call_function, execute, ExecError};
+use function::Arity::{Exact, Range};
+use io::SharedWrite;
+use lexer::Lexer;
+use module::{Module, ModuleBuilder};
+use name::{display_names, intern_name, Name, NameMap};
+use parser::Parser;
+use scope::Scope;
+use value::{Struct, StructDef, Value};
+
+/// Macros defined by the module, in terms of its functions
+const MACROS: &'static str = "
+(macro (define-test name :rest body)
+  `(register-test ',name (lambda () (do () ,@body))))
+(macro (assert-raises category :rest body)
+  `(assert-raises/call ,category (lambda () (do () ,@body))))
+(macro (assert-error-matches pattern :rest body)
+  `(assert-error-matches/call ,pattern (lambda () (do () ,@body))))
+";
+
+/// Name of the global value in which a scope's registered tests are stored.
+/// The name cannot be written in source code.
+const TESTS: &'static str = "#tests";
+
+/// Loads the `test` module into the given scope.
+pub fn load(scope: Scope) -> Module {
+    let summary = scope.add_name("test-summary");
+    let failure = scope.add_name("test-failure");
+
+    let module = ModuleBuilder::new("test", scope.clone())
+        .add_function("assert",        fn_assert,        Range(1, 2))
+        .add_function("assert-eq",     fn_assert_eq,     Exact(2))
+        .add_function("assert-raises/call", fn_assert_raises_call, Exact(2))
+        .add_function("assert-error-matches/call",
+            fn_assert_error_matches_call, Exact(2))
+        .add_function("register-test", fn_register_test, Exact(2))
+        .add_function("run-tests",     fn_run_tests,     Range(0, 1))
+        .add_value("test-summary", struct_def(&scope, summary,
+            &[("passed", "integer"), ("failed", "integer"), ("failures", "list")]))
+        .add_value("test-failure", struct_def(&scope, failure,
+            &[("name", "name"), ("message", "string"), ("irritants", "list")]));
+
+    let exprs = {
+        let mut names = scope.borrow_names_mut();
+        let offset = scope.borrow_codemap_mut().add_source(MACROS, None);
+
+        Parser::new(&mut names, Lexer::new(MACROS, offset)).parse_exprs()
+            .expect("failed to parse test module macros")
+    };
+
+    for e in &exprs {
+        let code = compile(&scope, e).expect("failed to compile test module macros");
+        execute(&scope, Rc::new(code)).expect("failed to define test module macros");
+    }
+
+    module.finish()
+}
+
+fn struct_def(scope: &Scope, name: Name, fields: &[(&str, &str)]) -> Value {
+    let mut map = NameMap::new();
+
+    for &(field, ty) in fields {
+        map.insert(intern_name(scope, field), intern_name(scope, ty));
+    }
+
+    Value::StructDef(Rc::new(StructDef::new(name, map.into_slice())))
+}
+
+/// Result of running a set of tests
+pub struct TestSummary {
+    /// Number of tests which passed
+    pub passed: usize,
+    /// Name of each test which failed and the error it raised
+    pub failures: Vec<(Name, Condition)>,
+}
+
+impl TestSummary {
+    /// Returns whether all tests passed.
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    fn into_value(self, scope: &Scope) -> Result<Value, Error> {
+        let m = try!(scope.get_modules().get_module(scope.add_name("test"), scope));
+
+        let summary_def = try!(get_def(&m.scope, "test-summary"));
+        let failure_def = try!(get_def(&m.scope, "test-failure"));
+
+        let n_failed = self.failures.len();
+
+        let failures = self.failures.into_iter().map(|(name, cond)| {
+            let mut fields = NameMap::new();
+
+            fields.insert(intern_name(scope, "name"), Value::Name(name));
+            fields.insert(intern_name(scope, "message"), cond.message.into());
+            fields.insert(intern_name(scope, "irritants"), cond.irritants.into());
+
+            Value::Struct(Rc::new(Struct::new(failure_def.clone(), fields.into_slice())))
+        }).collect::<Vec<_>>();
+
+        let mut fields = NameMap::new();
+
+        fields.insert(intern_name(scope, "passed"), self.passed.into());
+        fields.insert(intern_name(scope, "failed"), n_failed.into());
+        fields.insert(intern_name(scope, "failures"), failures.into());
+
+        Ok(Value::Struct(Rc::new(Struct::new(summary_def, fields.into_slice()))))
+    }
+}
+
+fn get_def(scope: &Scope, name: &str) -> Result<Rc<StructDef>, ExecError> {
+    let name = scope.add_name(name);
+
+    match scope.get_value(name) {
+        Some(Value::StructDef(def)) => Ok(def),
+        _ => Err(ExecError::StructDefError(name))
+    }
+}
+
+/// Runs all tests registered in the scope `tests`, printing a report
+/// to the standard output of `scope`.
+///
+/// An error is returned only if the report cannot be written.
+pub fn run_tests(scope: &Scope, tests: &Scope) -> Result<TestSummary, Error> {
+    let tests = get_tests(tests);
+    let out = scope.get_io();
+
+    let mut summary = TestSummary{
+        passed: 0,
+        failures: Vec::new(),
+    };
+
+    try!(writeln!(out, "running {} test{}", tests.len(),
+        if tests.len() == 1 { "" } else { "s" }));
+
+    for (name, thunk) in tests {
+        let r = call_function(scope, thunk, Vec::new());
+        let status = if r.is_ok() { "ok" } else { "FAILED" };
+
+        try!(scope.with_name(name,
+            |name| writeln!(out, "test {} ... {}", name, status)));
+
+        match r {
+            Ok(_) => summary.passed += 1,
+            Err(e) => summary.failures.push((name, caught_condition(scope, e)))
+        }
+    }
+
+    if !summary.is_success() {
+        try!(writeln!(out, "\nfailures:"));
+
+        for &(name, ref cond) in &summary.failures {
+            try!(scope.with_name(name,
+                |name| writeln!(out, "    {}: {}", name, cond.message)));
+        }
+    }
+
+    try!(writeln!(out, "\ntest result: {}. {} passed; {} failed",
+        if summary.is_success() { "ok" } else { "FAILED" },
+        summary.passed, summary.failures.len()));
+    try!(out.flush());
+
+    Ok(summary)
+}
+
+/// Returns registered tests, in the order in which they were defined.
+fn get_tests(scope: &Scope) -> Vec<(Name, Value)> {
+    let key = scope.add_name(TESTS);
+
+    match scope.with_values(|v| v.get(key).cloned()) {
+        Some(Value::List(li)) => li.iter().filter_map(|v| match *v {
+            Value::List(ref t) => match (&t[0], &t[1]) {
+                (&Value::Name(name), thunk) => Some((name, thunk.clone())),
+                _ => None
+            },
+            _ => None
+        }).collect(),
+        _ => Vec::new()
+    }
+}
+
+fn assertion_failed(message: String, irritants: Vec<Value>) -> Error {
+    From::from(ExecError::UserError(Condition{
+        category: "custom",
+        kind: "assertion-failed",
+        message: message,
+        irritants: irritants,
+    }))
+}
+
+/// `assert` raises an error if the given value is `false`.
+/// An optional message describes the assertion.
+fn fn_assert(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let ok = match args[0] {
+        Value::Bool(b) => b,
+        ref v => return Err(From::from(ExecError::expected("bool", v)))
+    };
+
+    if ok {
+        return Ok(Value::Unit);
+    }
+
+    let msg = match args.get(1) {
+        Some(&Value::String(ref s)) => format!("assertion failed: {}", s),
+        Some(v) => return Err(From::from(ExecError::expected("string", v))),
+        None => "assertion failed".to_owned()
+    };
+
+    Err(assertion_failed(msg, Vec::new()))
+}
+
+/// `assert-eq` raises an error, including both values, if the given values
+/// are not equal.
+fn fn_assert_eq(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    if try!(args[0].is_equal(&args[1])) {
+        Ok(Value::Unit)
+    } else {
+        Err(assertion_failed("assertion failed: values are not equal".to_owned(),
+            vec![args[0].take(), args[1].take()]))
+    }
+}
+
+/// Calls a function with no arguments, returning the error it raises.
+/// If no error is raised, an assertion error is returned, including
+/// the given value.
+fn call_expecting_error(scope: &Scope, thunk: Value, expected: Value)
+        -> Result<Error, Error> {
+    match call_function(scope, thunk, Vec::new()) {
+        Ok(_) => Err(assertion_failed("assertion failed: no error raised".to_owned(),
+            vec![expected])),
+        Err(e) => Ok(e)
+    }
+}
+
+/// Converts a caught error into a `Condition` whose message is
+/// the rendered error message.
+fn caught_condition(scope: &Scope, e: Error) -> Condition {
+    let msg = display_names(&scope.borrow_names(), &e).to_string();
+    let cond = Condition::from_error(&mut scope.borrow_names_mut(), e);
+    Condition{message: msg, ..cond}
+}
+
+/// `assert-raises/call` calls a function with no arguments and raises
+/// an error unless it raises an error of the given category.
+/// The category `:error` accepts any error. The caught error is returned.
+/// It is called by `assert-raises`.
+fn fn_assert_raises_call(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let category = match args[0] {
+        Value::Keyword(name) if is_error_category(&scope.borrow_names(), name) => name,
+        Value::Keyword(name) => return Err(From::from(ExecError::UnrecognizedKeyword(name))),
+        ref v => return Err(From::from(ExecError::expected("keyword", v)))
+    };
+
+    let e = try!(call_expecting_error(scope, args[1].take(), Value::Keyword(category)));
+
+    let matches = condition_matches(&scope.borrow_names(), category, &e);
+    let cond = caught_condition(scope, e);
+
+    if matches {
+        Ok(Value::new_foreign(cond))
+    } else {
+        let found = intern_name(scope, cond.category);
+
+        Err(assertion_failed(
+            "assertion failed: error of unexpected category raised".to_owned(),
+            vec![Value::Keyword(category), Value::Keyword(found), cond.message.into()]))
+    }
+}
+
+/// `assert-error-matches/call` calls a function with no arguments and raises
+/// an error unless it raises an error whose message contains the given
+/// string. The caught error is returned. It is called by `assert-error-matches`.
+fn fn_assert_error_matches_call(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let pattern = match args[0] {
+        Value::String(ref s) => s.clone(),
+        ref v => return Err(From::from(ExecError::expected("string", v)))
+    };
+
+    let e = try!(call_expecting_error(scope, args[1].take(), pattern.clone().into()));
+    let cond = caught_condition(scope, e);
+
+    if cond.message.contains(&pattern[..]) {
+        Ok(Value::new_foreign(cond))
+    } else {
+        Err(assertion_failed(
+            "assertion failed: error message does not match".to_owned(),
+            vec![pattern.into(), cond.message.into()]))
+    }
+}
+
+/// `register-test` adds a named test function to the current scope,
+/// replacing any test of the same name. It is called by `define-test`.
+fn fn_register_test(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let name = match args[0] {
+        Value::Name(name) => name,
+        ref v => return Err(From::from(ExecError::expected("name", v)))
+    };
+    let thunk = args[1].take();
+
+    let key = scope.add_name(TESTS);
+    let mut tests = get_tests(scope);
+
+    match tests.iter().position(|&(n, _)| n == name) {
+        Some(pos) => tests[pos].1 = thunk,
+        None => tests.push((name, thunk))
+    }
+
+    let tests = tests.into_iter()
+        .map(|(name, thunk)| Value::from(vec![Value::Name(name), thunk]))
+        .collect::<Vec<_>>();
+
+    scope.add_value(key, tests.into());
+    Ok(Value::Name(name))
+}
+
+/// `run-tests` runs each test defined in the current scope or, if a module
+/// name is given, in the named module. A report is printed and a
+/// `test-summary` struct is returned.
+fn fn_run_tests(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let tests = match args.first() {
+        Some(&Value::Name(name)) => {
+            try!(scope.check_import_policy(name));
+            try!(scope.get_modules().get_module(name, scope)).scope
+        }
+        Some(v) => return Err(From::from(ExecError::expected("name", v))),
+        None => scope.clone()
+    };
+
+    let summary = try!(run_tests(scope, &tests));
+    summary.into_value(scope)
+}
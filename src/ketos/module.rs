@@ -1,26 +1,35 @@
 //! Implements loading named values from code modules.
 
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
 use std::fs::{File, Metadata};
-use std::io::{stderr, Read, Write};
+use std::io::{self, Read};
+use std::mem::replace;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
+use bytecode::Code;
 use compile::{compile, CompileError};
-use encode::{DecodeError, ModuleCode, read_bytecode_file, write_bytecode_file};
+use encode::{DecodeError, EncodeError, ModuleCode,
+    read_bytecode_file, write_bytecode_file};
 use error::Error;
 use exec::execute;
 use function::{Arity, Function, FunctionImpl, Lambda, SystemFn};
 use io::{IoError, IoMode};
 use lexer::Lexer;
-use name::{Name, NameMap};
+use name::{Name, NameDisplay, NameMap, NameSet, NameStore};
 use parser::Parser;
-use scope::{GlobalScope, Scope};
-use value::Value;
+use scope::{GlobalScope, LogLevel, Scope};
+use value::{StructDef, Value};
 
 use mod_code;
 use mod_math;
+use mod_os;
 use mod_random;
+use mod_string;
+use mod_test;
 
 /// Contains the values in a loaded module's namespace.
 #[derive(Clone)]
@@ -31,6 +40,78 @@ pub struct Module {
     pub scope: Scope,
 }
 
+/// Describes values imported from a module by a `use` declaration.
+///
+/// Imports are performed when `use` is compiled. They are recorded in
+/// compiled bytecode so that they may be repeated when the code is loaded.
+#[derive(Clone, Debug)]
+pub struct ImportSet {
+    /// Name of the module from which values are imported
+    pub module_name: Name,
+    /// Whether all exported values are imported
+    pub all: bool,
+    /// Whether values are imported by reference, using `(use module :ref ...)`.
+    /// References to such values are compiled as lookups within the module
+    /// scope, rather than as copies within the importing scope.
+    pub by_ref: bool,
+    /// Whether values are imported for use by macros, using `import-for-syntax`.
+    /// References to such values within macro bodies are compiled as lookups
+    /// within the module scope; the names are not visible to other code.
+    pub for_syntax: bool,
+    /// Local name of the module, using `(use module :as alias)`.
+    /// Qualified names of the form `alias:name` are compiled as lookups
+    /// within the module scope.
+    pub alias: Option<Name>,
+    /// Imported values; pairs of exported name and local name
+    pub names: Vec<(Name, Name)>,
+}
+
+/// Determines which modules may be imported within a scope.
+///
+/// The policy is checked when an import is compiled and again by the
+/// standard module loaders when a module is loaded.
+#[derive(Clone, Debug)]
+pub enum ImportPolicy {
+    /// Any module may be imported; this is the default
+    Full,
+    /// Only the named modules may be imported
+    AllowList(NameSet),
+    /// No module may be imported
+    DenyAll,
+}
+
+impl Default for ImportPolicy {
+    fn default() -> ImportPolicy {
+        ImportPolicy::Full
+    }
+}
+
+impl ImportPolicy {
+    /// Returns an error if the named module may not be imported.
+    pub fn check(&self, name: Name) -> Result<(), CompileError> {
+        match *self {
+            ImportPolicy::Full => Ok(()),
+            ImportPolicy::AllowList(ref names) if names.contains(name) => Ok(()),
+            ImportPolicy::AllowList(_) => Err(CompileError::ImportNotAllowed(name)),
+            ImportPolicy::DenyAll => Err(CompileError::ImportsDisabled),
+        }
+    }
+}
+
+impl ImportSet {
+    /// Creates an empty `ImportSet` for the named module.
+    pub fn new(module_name: Name) -> ImportSet {
+        ImportSet{
+            module_name: module_name,
+            all: false,
+            by_ref: false,
+            for_syntax: false,
+            alias: None,
+            names: Vec::new(),
+        }
+    }
+}
+
 impl Module {
     /// Creates a new module using the given scope.
     pub fn new(name: &str, scope: Scope) -> Module {
@@ -40,6 +121,34 @@ impl Module {
             scope: scope,
         }
     }
+
+    /// Returns the names exported by the module, including exported macros,
+    /// sorted by the string representation of each name.
+    pub fn exports(&self) -> Vec<Name> {
+        let mut exports = self.scope.get_exported_names().into_iter()
+            .map(|(name, _)| name).collect::<Vec<_>>();
+
+        let names = self.scope.borrow_names();
+        exports.sort_by(|&a, &b| names.get(a).cmp(names.get(b)));
+        exports.dedup();
+        exports
+    }
+
+    /// Returns the value of an exported name.
+    ///
+    /// Values which are not exported are private to the module;
+    /// for these, `None` is returned.
+    pub fn get_export(&self, name: Name) -> Option<Value> {
+        self.scope.get_export(name).and_then(|name| self.scope.get_value(name))
+    }
+
+    /// Returns the macro of an exported name.
+    ///
+    /// Macros which are not exported are private to the module;
+    /// for these, `None` is returned.
+    pub fn get_export_macro(&self, name: Name) -> Option<Lambda> {
+        self.scope.get_export(name).and_then(|name| self.scope.get_macro(name))
+    }
 }
 
 /// Helper to build modules in Rust code.
@@ -86,9 +195,17 @@ impl ModuleBuilder {
     }
 
     /// Consumes the builder and returns the new `Module`.
+    ///
+    /// All values and macros defined in the module scope are exported.
     pub fn finish(self) -> Module {
-        let exports = self.scope.with_values(
-            |v| v.iter().map(|&(name, _)| name).collect());
+        let mut exports = NameSet::new();
+
+        self.scope.with_values(
+            |v| for &(name, _) in v.iter() { exports.insert(name); });
+        self.scope.with_macros(
+            |m| for &(name, _) in m.iter() { exports.insert(name); });
+
+        let exports = exports.into_slice();
 
         self.scope.set_exports(exports);
 
@@ -103,6 +220,8 @@ impl ModuleBuilder {
 pub struct ModuleRegistry {
     loader: Box<ModuleLoader>,
     modules: RefCell<NameMap<Module>>,
+    /// Struct definitions decoded from compiled bytecode, by struct name
+    struct_defs: RefCell<NameMap<Vec<Rc<StructDef>>>>,
 }
 
 impl ModuleRegistry {
@@ -112,11 +231,16 @@ impl ModuleRegistry {
         ModuleRegistry{
             loader: loader,
             modules: RefCell::new(NameMap::new()),
+            struct_defs: RefCell::new(NameMap::new()),
         }
     }
 
     /// Returns a loaded module. If the module has not been loaded in this
     /// registry; the contained `ModuleLoader` instance will be used to load it.
+    ///
+    /// No borrow is held while a module is loaded, so a module may be loaded
+    /// while loading another; e.g. by macro expansion or by code which
+    /// compiles and executes values at runtime.
     pub fn get_module(&self, name: Name, scope: &Scope) -> Result<Module, Error> {
         // It's not necessary to borrow_mut here, but it means that this
         // function has consistent behavior with respect to existing borrows.
@@ -131,6 +255,60 @@ impl ModuleRegistry {
 
         Ok(m)
     }
+
+    /// Returns a module, if it has already been loaded in this registry.
+    pub fn get_loaded_module(&self, name: Name) -> Option<Module> {
+        self.modules.borrow().get(name).cloned()
+    }
+
+    /// Adds a module to the registry, replacing any module of the same name.
+    pub fn add_module(&self, name: Name, module: Module) {
+        self.modules.borrow_mut().insert(name, module);
+    }
+
+    /// Removes all loaded modules and interned struct definitions
+    /// from the registry.
+    ///
+    /// Lambdas defined within a removed module may no longer be called,
+    /// unless the module's scope is otherwise kept alive.
+    pub fn clear(&self) {
+        // Modules are dropped after the borrow is released,
+        // as dropping a module scope may drop other registries.
+        let modules = replace(&mut *self.modules.borrow_mut(), NameMap::new());
+        drop(modules);
+        self.struct_defs.borrow_mut().clear();
+    }
+
+    /// Returns a shared `StructDef` equivalent to the given definition.
+    ///
+    /// Each time a compiled module is decoded, its struct definitions are
+    /// created anew. Interning them here ensures that all decoded copies of
+    /// a definition, including copies embedded in other modules, refer to
+    /// the same `StructDef`, which is required for struct type checks to
+    /// succeed.
+    ///
+    /// Only definitions decoded from bytecode are interned. `define-struct`
+    /// compiled from source creates a new definition each time it is executed.
+    /// Interned definitions are held until the registry is cleared.
+    pub fn intern_struct_def(&self, def: StructDef) -> Rc<StructDef> {
+        let mut defs = self.struct_defs.borrow_mut();
+
+        if let Some(same_name) = defs.get_mut(def.name) {
+            if let Some(d) = same_name.iter().find(
+                    |d| d.fields == def.fields && d.variant_of == def.variant_of) {
+                return d.clone();
+            }
+
+            let def = Rc::new(def);
+            same_name.push(def.clone());
+            return def;
+        }
+
+        let name = def.name;
+        let def = Rc::new(def);
+        defs.insert(name, vec![def.clone()]);
+        def
+    }
 }
 
 /// Loads modules into separate namespaces
@@ -145,7 +323,11 @@ pub struct BuiltinModuleLoader;
 
 impl ModuleLoader for BuiltinModuleLoader {
     fn load_module(&self, name: Name, scope: &Scope) -> Result<Module, Error> {
-        load_builtin_module(name, GlobalScope::new_using(scope))
+        try!(scope.check_import_policy(name));
+
+        let new_scope = GlobalScope::new_using(scope);
+        new_scope.set_module_name(name);
+        load_builtin_module(name, new_scope)
     }
 }
 
@@ -153,7 +335,10 @@ fn get_loader(name: &str) -> Option<fn(Scope) -> Module> {
     match name {
         "code" => Some(mod_code::load),
         "math" => Some(mod_math::load),
+        "os" => Some(mod_os::load),
         "random" => Some(mod_random::load),
+        "string" => Some(mod_string::load),
+        "test" => Some(mod_test::load),
         _ => None
     }
 }
@@ -167,8 +352,56 @@ fn load_builtin_module(name: Name, scope: Scope) -> Result<Module, Error> {
     }
 }
 
+/// Error in reading the source or compiled bytecode file of a module
+#[derive(Debug)]
+pub struct ModuleLoadError {
+    /// Name of the module being loaded
+    pub module: Name,
+    /// Path of the file being read
+    pub path: PathBuf,
+    /// Error which occurred
+    pub cause: Box<Error>,
+}
+
+impl ModuleLoadError {
+    /// Creates a new `ModuleLoadError`.
+    pub fn new(module: Name, path: &Path, cause: Error) -> ModuleLoadError {
+        ModuleLoadError{
+            module: module,
+            path: path.to_owned(),
+            cause: Box::new(cause),
+        }
+    }
+}
+
+impl fmt::Display for ModuleLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to load module from file `{}`", self.path.display())
+    }
+}
+
+impl NameDisplay for ModuleLoadError {
+    fn fmt(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to load module `{}` from file `{}`",
+            names.get(self.module), self.path.display())
+    }
+}
+
+impl StdError for ModuleLoadError {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        Some(&*self.cause)
+    }
+}
+
 /// Loads modules from a file.
+///
+/// A module named `foo` is loaded from the file `foo.kts` or its compiled
+/// form, `foo.ktsc`, within one of the loader's search paths.
+/// Components of a dotted module name are translated into directories;
+/// e.g. `http.server` is loaded from `http/server.kts`.
 pub struct FileModuleLoader {
+    /// Directories searched, in order, for module files
+    paths: Vec<PathBuf>,
     /// Tracks import chains to prevent infinite recursion
     chain: RefCell<Vec<PathBuf>>,
 }
@@ -180,13 +413,53 @@ pub const FILE_EXTENSION: &'static str = "kts";
 pub const COMPILED_FILE_EXTENSION: &'static str = "ktsc";
 
 impl FileModuleLoader {
-    /// Creates a new `FileModuleLoader`.
+    /// Creates a new `FileModuleLoader` which searches for modules
+    /// in the current directory.
     pub fn new() -> FileModuleLoader {
+        FileModuleLoader::with_search_paths(vec![PathBuf::new()])
+    }
+
+    /// Creates a new `FileModuleLoader` which searches for modules
+    /// in the given directories, in order.
+    pub fn with_search_paths(paths: Vec<PathBuf>) -> FileModuleLoader {
         FileModuleLoader{
+            paths: paths,
             chain: RefCell::new(Vec::new()),
         }
     }
 
+    /// Adds a directory to the end of the list of search paths.
+    pub fn add_search_path(&mut self, path: PathBuf) {
+        self.paths.push(path);
+    }
+
+    /// Returns the source and compiled file paths of the first search path
+    /// containing either file. Returns `Ok(None)` if no file is found.
+    fn find_module(&self, name: Name, rel_path: &Path)
+            -> Result<Option<(PathBuf, PathBuf)>, Error> {
+        for dir in &self.paths {
+            let src_path = dir.join(rel_path.with_extension(FILE_EXTENSION));
+            let code_path = dir.join(rel_path.with_extension(COMPILED_FILE_EXTENSION));
+
+            let found = if src_path.exists() {
+                &src_path
+            } else if code_path.exists() {
+                &code_path
+            } else {
+                continue;
+            };
+
+            // Symbolic links may lead outside of the search path
+            if !try!(is_within(dir, found)) {
+                return Err(From::from(CompileError::InvalidModuleName(name)));
+            }
+
+            return Ok(Some((src_path, code_path)));
+        }
+
+        Ok(None)
+    }
+
     fn guard_import<F, T>(&self, name: Name, path: &Path, f: F) -> Result<T, Error>
             where F: FnOnce() -> Result<T, Error> {
         if self.chain.borrow().iter().any(|p| p == path) {
@@ -203,39 +476,216 @@ impl FileModuleLoader {
 
 impl ModuleLoader for FileModuleLoader {
     fn load_module(&self, name: Name, scope: &Scope) -> Result<Module, Error> {
-        let (src_path, code_path) = try!(scope.with_name(name, |name_str| {
-            if name_str.chars().any(|c| c == '.' || c == '/' || c == '\\') {
-                Err(CompileError::InvalidModuleName(name))
-            } else {
-                Ok((PathBuf::from(format!("{}.{}", name_str, FILE_EXTENSION)),
-                    PathBuf::from(format!("{}.{}", name_str, COMPILED_FILE_EXTENSION))))
-            }
+        try!(scope.check_import_policy(name));
+
+        let rel_path = try!(scope.with_name(name, |name_str| {
+            module_path(name_str).ok_or(CompileError::InvalidModuleName(name))
         }));
 
         let new_scope = GlobalScope::new_using(scope);
+        new_scope.set_module_name(name);
 
-        let use_code = try!(use_code_file(&code_path, &src_path));
-
-        if use_code {
-            self.guard_import(name, &src_path, || {
-                match read_bytecode_file(&code_path, &new_scope) {
-                    Ok(m) => {
-                        for &(name, ref code) in &m.macros {
-                            let mac = Lambda::new(code.clone(), scope);
-                            new_scope.add_macro(name, mac);
-                        }
-                        run_module_code(name, new_scope, m)
-                    }
-                    Err(Error::DecodeError(DecodeError::IncorrectVersion(_)))
-                            if src_path.exists() => {
-                        load_module_from_file(new_scope, name, &src_path, &code_path)
+        let (src_path, code_path) = match try!(self.find_module(name, &rel_path)) {
+            Some(paths) => paths,
+            None => return load_builtin_module(name, new_scope)
+        };
+
+        self.guard_import(name, &src_path, || {
+            // Source is checked in compile-only mode, even if bytecode is cached
+            let cached = if new_scope.is_compile_only() && src_path.exists() {
+                None
+            } else {
+                try!(read_cached_code(&code_path, &src_path, &new_scope)
+                    .map_err(|e| ModuleLoadError::new(name, &code_path, e)))
+            };
+
+            match cached {
+                Some(m) => {
+                    for &(name, ref code) in &m.macros {
+                        let mac = Lambda::new(code.clone(), &new_scope);
+                        new_scope.add_macro(name, mac);
                     }
-                    Err(e) => Err(e)
+                    run_module_code(name, new_scope, m)
                 }
+                None => load_module_from_file(new_scope, name, &src_path, &code_path)
+            }
+        })
+    }
+}
+
+/// Executes a source file within the given scope as the main program,
+/// rather than as a module; the file need not contain an `export` declaration.
+///
+/// As with modules loaded by `FileModuleLoader`, compiled bytecode is loaded
+/// from the file of the same name with extension `.ktsc`, if that file is
+/// newer than the source file. Otherwise, the source is compiled and executed
+/// and the resulting bytecode is written to the `.ktsc` file.
+pub fn run_main_file(scope: &Scope, src_path: &Path) -> Result<(), Error> {
+    let code_path = src_path.with_extension(COMPILED_FILE_EXTENSION);
+
+    if let Some(m) = try!(read_cached_code(&code_path, src_path, scope)) {
+        for &(name, ref code) in &m.macros {
+            let mac = Lambda::new(code.clone(), scope);
+            scope.add_macro(name, mac);
+        }
+
+        try!(import_all(scope, m.imports));
+
+        for code in m.code {
+            try!(execute(scope, code));
+        }
+
+        return Ok(());
+    }
+
+    let src = try!(read_source_file(src_path));
+
+    let exprs = {
+        let mut names = scope.borrow_names_mut();
+        let offset = scope.borrow_codemap_mut().add_source(&src,
+            Some(src_path.to_string_lossy().into_owned()));
+
+        let mut p = Parser::new(&mut names, Lexer::new(&src, offset));
+        p.skip_shebang();
+        try!(p.parse_exprs())
+    };
+
+    // All expressions are compiled before any are executed,
+    // as in `Interpreter::run_code`.
+    let code = try!(exprs.iter()
+        .map(|e| compile(scope, e).map(Rc::new)).collect::<Result<Vec<_>, _>>());
+
+    for code in &code {
+        try!(execute(scope, code.clone()));
+    }
+
+    write_code_file(&code_path, &ModuleCode::new(code, scope), scope);
+    Ok(())
+}
+
+/// Reads compiled bytecode from `code_path`, if it exists and is newer than
+/// the source file. Returns `Ok(None)` if the source file should be compiled
+/// instead; this includes bytecode compiled by another version of `ketos`.
+fn read_cached_code(code_path: &Path, src_path: &Path, scope: &Scope)
+        -> Result<Option<ModuleCode>, Error> {
+    if !try!(use_code_file(code_path, src_path)) {
+        return Ok(None);
+    }
+
+    match read_bytecode_file(code_path, scope) {
+        Ok(m) => Ok(Some(m)),
+        Err(Error::DecodeError(DecodeError::IncorrectVersion(_))) |
+        Err(Error::DecodeError(DecodeError::InvalidSystemFn(_))) |
+        Err(Error::DecodeError(DecodeError::MissingNameSnapshot{..}))
+                if src_path.exists() => Ok(None),
+        Err(e) => Err(e)
+    }
+}
+
+/// Translates a dotted module name into a relative file path, without extension.
+/// Returns `None` if the name is not a valid module name.
+fn module_path(name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+
+    for part in name.split('.') {
+        if part.is_empty() || part.chars().any(|c| c == '/' || c == '\\') {
+            return None;
+        }
+        path.push(part);
+    }
+
+    Some(path)
+}
+
+/// Returns whether `path` resolves to a location within the directory `dir`.
+fn is_within(dir: &Path, path: &Path) -> Result<bool, Error> {
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+
+    let dir = try!(dir.canonicalize()
+        .map_err(|e| IoError::new(IoMode::Stat, dir, e)));
+    let path = try!(path.canonicalize()
+        .map_err(|e| IoError::new(IoMode::Stat, path, e)));
+
+    Ok(path.starts_with(&dir))
+}
+
+/// Loads modules from source strings and compiled code held in memory.
+///
+/// Source modules are compiled when they are first imported.
+/// Compiled modules take precedence over source modules of the same name.
+/// Modules which are not found are loaded as builtin modules.
+pub struct MemoryModuleLoader {
+    sources: HashMap<String, String>,
+    compiled: HashMap<String, ModuleCode>,
+    /// Tracks import chains to prevent infinite recursion
+    chain: RefCell<Vec<Name>>,
+}
+
+impl MemoryModuleLoader {
+    /// Creates a new, empty `MemoryModuleLoader`.
+    pub fn new() -> MemoryModuleLoader {
+        MemoryModuleLoader{
+            sources: HashMap::new(),
+            compiled: HashMap::new(),
+            chain: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Adds a module to be compiled from source when it is imported.
+    pub fn add_module(&mut self, name: &str, source: &str) {
+        self.sources.insert(name.to_owned(), source.to_owned());
+    }
+
+    /// Adds a compiled module.
+    ///
+    /// Names contained within `code` must have been created by the same
+    /// `NameStore` used by the scope into which the module will be imported.
+    pub fn add_compiled(&mut self, name: &str, code: ModuleCode) {
+        self.compiled.insert(name.to_owned(), code);
+    }
+
+    fn guard_import<F, T>(&self, name: Name, f: F) -> Result<T, Error>
+            where F: FnOnce() -> Result<T, Error> {
+        if self.chain.borrow().iter().any(|&n| n == name) {
+            return Err(From::from(CompileError::ImportCycle(name)));
+        }
+
+        self.chain.borrow_mut().push(name);
+        let r = f();
+        self.chain.borrow_mut().pop();
+
+        r
+    }
+}
+
+impl ModuleLoader for MemoryModuleLoader {
+    fn load_module(&self, name: Name, scope: &Scope) -> Result<Module, Error> {
+        try!(scope.check_import_policy(name));
+
+        let new_scope = GlobalScope::new_using(scope);
+        new_scope.set_module_name(name);
+
+        let (compiled, source) = scope.with_name(name,
+            |name_str| (self.compiled.get(name_str), self.sources.get(name_str)));
+
+        if let Some(mcode) = compiled {
+            self.guard_import(name, || {
+                for &(name, ref code) in &mcode.macros {
+                    let mac = Lambda::new(code.clone(), &new_scope);
+                    new_scope.add_macro(name, mac);
+                }
+                run_module_code(name, new_scope.clone(), mcode.clone())
+            })
+        } else if let Some(src) = source {
+            self.guard_import(name, || {
+                let path = scope.with_name(name, |s| s.to_owned());
+                try!(run_module_source(&new_scope, name, src, Some(path)));
+
+                Ok(Module{
+                    name: name,
+                    scope: new_scope.clone(),
+                })
             })
-        } else if src_path.exists() {
-            self.guard_import(name, &src_path,
-                || load_module_from_file(new_scope, name, &src_path, &code_path))
         } else {
             load_builtin_module(name, new_scope)
         }
@@ -277,58 +727,96 @@ fn is_younger_impl(ma: &Metadata, mb: &Metadata) -> bool {
 
 fn load_module_from_file(scope: Scope, name: Name,
         src_path: &Path, code_path: &Path) -> Result<Module, Error> {
-    let mut file = try!(File::open(src_path)
-        .map_err(|e| IoError::new(IoMode::Open, src_path, e)));
+    let buf = try!(read_source_file(src_path)
+        .map_err(|e| ModuleLoadError::new(name, src_path, e)));
+
+    let code = try!(run_module_source(&scope, name, &buf,
+        Some(src_path.to_string_lossy().into_owned())));
+
+    if !scope.is_compile_only() {
+        write_code_file(code_path, &ModuleCode::new(code, &scope), &scope);
+    }
+
+    Ok(Module{
+        name: name,
+        scope: scope,
+    })
+}
+
+fn read_source_file(path: &Path) -> Result<String, Error> {
+    // Opening a directory may succeed, yielding an obscure error on reading
+    if path.is_dir() {
+        return Err(From::from(IoError::new(IoMode::Open, path,
+            io::Error::new(io::ErrorKind::Other, "path is a directory"))));
+    }
+
+    let mut file = try!(File::open(path)
+        .map_err(|e| IoError::new(IoMode::Open, path, e)));
     let mut buf = String::new();
 
     try!(file.read_to_string(&mut buf)
-        .map_err(|e| IoError::new(IoMode::Read, src_path, e)));
+        .map_err(|e| IoError::new(IoMode::Read, path, e)));
 
-    let exprs = {
-        let mut names = scope.borrow_names_mut();
-        let offset = scope.borrow_codemap_mut().add_source(&buf,
-            Some(src_path.to_string_lossy().into_owned()));
+    Ok(buf)
+}
 
-        try!(Parser::new(&mut names, Lexer::new(&buf, offset)).parse_exprs())
+/// Writes compiled bytecode, reporting any error without failing;
+/// code which could not be written is simply compiled again.
+fn write_code_file(code_path: &Path, mcode: &ModuleCode, scope: &Scope) {
+    let r = {
+        let names = scope.borrow_names();
+        write_bytecode_file(code_path, mcode, &names)
     };
 
-    let code = try!(exprs.iter()
-        .map(|e| compile(&scope, e).map(Rc::new)).collect::<Result<Vec<_>, _>>());
-
-    for code in &code {
-        try!(execute(&scope, code.clone()));
+    match r {
+        // Code defining an inline module is expected to be compiled each time
+        Ok(()) | Err(Error::EncodeError(EncodeError::DefineModule)) => (),
+        Err(e) => {
+            let msg = format!("failed to write compiled bytecode: {}", e);
+            scope.log(LogLevel::Warning, &msg);
+        }
     }
+}
 
-    try!(check_exports(&scope, name));
+/// Parses, compiles, and executes module source within the given scope.
+/// Returns the compiled code. In compile-only mode, code is not executed.
+fn run_module_source(scope: &Scope, name: Name, src: &str,
+        path: Option<String>) -> Result<Vec<Rc<Code>>, Error> {
+    let exprs = {
+        let mut names = scope.borrow_names_mut();
+        let offset = scope.borrow_codemap_mut().add_source(src, path);
 
-    let mcode = ModuleCode{
-        code: code.clone(),
-        macros: scope.with_macros(
-            |macros| macros.iter()
-                .map(|&(name, ref l)| (name, l.code.clone())).collect()),
-        exports: scope.with_exports(|e| e.cloned().unwrap()),
+        try!(Parser::new(&mut names, Lexer::new(src, offset)).parse_exprs())
     };
 
-    let r = {
-        let names = scope.borrow_names();
-        write_bytecode_file(code_path, &mcode, &names)
-    };
+    let code = try!(exprs.iter()
+        .map(|e| compile(scope, e).map(Rc::new)).collect::<Result<Vec<_>, _>>());
 
-    if let Err(e) = r {
-        let _ = writeln!(stderr(), "failed to write compiled bytecode: {}", e);
+    if !scope.is_compile_only() {
+        for code in &code {
+            try!(execute(scope, code.clone()));
+        }
     }
 
-    Ok(Module{
-        name: name,
-        scope: scope,
-    })
+    try!(check_exports(scope, name));
+
+    Ok(code)
 }
 
 fn run_module_code(name: Name, scope: Scope, mcode: ModuleCode) -> Result<Module, Error> {
     scope.set_exports(mcode.exports);
 
-    for code in mcode.code {
-        try!(execute(&scope, code));
+    if !mcode.export_renames.is_empty() {
+        scope.set_export_renames(mcode.export_renames);
+    }
+
+    // Compiled code has already been checked
+    if !scope.is_compile_only() {
+        try!(import_all(&scope, mcode.imports));
+
+        for code in mcode.code {
+            try!(execute(&scope, code));
+        }
     }
 
     Ok(Module{
@@ -337,21 +825,72 @@ fn run_module_code(name: Name, scope: Scope, mcode: ModuleCode) -> Result<Module
     })
 }
 
-fn check_exports(scope: &Scope, mod_name: Name) -> Result<(), CompileError> {
-    scope.with_exports(|exports| {
-        if let Some(exports) = exports {
-            for name in exports {
-                if !(scope.contains_value(name) || scope.contains_macro(name)) {
-                    return Err(CompileError::ExportError{
-                        module: mod_name,
-                        name: name,
-                    });
-                }
-            }
+/// Repeats the imports performed when compiled code was compiled,
+/// loading any modules if necessary.
+fn import_all(scope: &Scope, imports: Vec<ImportSet>) -> Result<(), Error> {
+    let mods = scope.get_modules();
+
+    for imports in imports {
+        let mod_name = imports.module_name;
+        try!(scope.check_import_policy(mod_name));
+        let m = try!(mods.get_module(mod_name, scope));
 
-            Ok(())
+        if imports.all {
+            m.scope.import_all_values(scope);
+        }
+
+        // Values imported by reference are found in the module when used
+        let names = if imports.by_ref || imports.for_syntax {
+            &[][..]
         } else {
-            Err(CompileError::MissingExport)
+            &imports.names[..]
+        };
+
+        for &(src, dest) in names {
+            let name = m.scope.get_export(src).unwrap_or(src);
+
+            match m.scope.get_value(name) {
+                Some(v) => scope.add_value(dest, v),
+                None => return Err(From::from(CompileError::ImportError{
+                    module: mod_name,
+                    name: src,
+                }))
+            }
+
+            if let Some(var) = m.scope.get_dynamic_var(name) {
+                scope.add_dynamic(dest, var);
+            }
         }
-    })
+
+        scope.add_imports(imports);
+    }
+
+    Ok(())
+}
+
+/// Checks that a module scope contains an `export` or `export-rename`
+/// declaration and that every exported name is defined within the module.
+/// In compile-only mode, exported names need not be defined.
+pub fn check_exports(scope: &Scope, mod_name: Name) -> Result<(), CompileError> {
+    let exports = scope.with_exports(|e| e.cloned());
+    let renames = scope.with_export_renames(|r| r.cloned());
+
+    if exports.is_none() && renames.is_none() {
+        return Err(CompileError::MissingExport);
+    }
+
+    let names = exports.iter().flat_map(|e| e.iter())
+        .chain(renames.iter().flat_map(|r| r.iter()).map(|&(_, name)| name));
+
+    for name in names {
+        if !(scope.contains_value(name) || scope.contains_macro(name) ||
+            scope.is_compile_only()) {
+            return Err(CompileError::ExportError{
+                module: mod_name,
+                name: name,
+            });
+        }
+    }
+
+    Ok(())
 }
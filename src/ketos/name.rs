@@ -9,6 +9,7 @@ use std::slice::Iter;
 use std::sync::Arc;
 
 use function::{SystemFn, SYSTEM_FNS};
+use scope::Scope;
 
 /// Represents a name interned within a `NameStore`.
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
@@ -133,101 +134,179 @@ standard_names!{
     "tail" => TAIL = 28,
     "list" => LIST = 29,
     "reverse" => REVERSE = 30,
-    "abs" => ABS = 31,
-    "ceil" => CEIL = 32,
-    "floor" => FLOOR = 33,
-    "round" => ROUND = 34,
-    "trunc" => TRUNC = 35,
-    "int" => INT = 36,
-    "float" => FLOAT = 37,
-    "inf" => INF = 38,
-    "nan" => NAN = 39,
-    "denom" => DENOM = 40,
-    "fract" => FRACT = 41,
-    "numer" => NUMER = 42,
-    "rat" => RAT = 43,
-    "recip" => RECIP = 44,
-    "chars" => CHARS = 45,
-    "string" => STRING = 46,
-    "id" => ID = 47,
-    "is" => IS = 48,
-    "is-instance" => IS_INSTANCE = 49,
-    "null" => NULL = 50,
-    "type-of" => TYPE_OF = 51,
-    "." => DOT = 52,
-    ".=" => DOT_EQ = 53,
-    "new" => NEW = 54,
-    "format" => FORMAT = 55,
-    "print" => PRINT = 56,
-    "println" => PRINTLN = 57,
-    "panic" => PANIC = 58,
-    "xor" => XOR = 59,
-    "not" => NOT = 60,
+    "apply-n" => APPLY_N = 31,
+    "apply-first" => APPLY_FIRST = 32,
+    "apply-rest" => APPLY_REST = 33,
+    "scan" => SCAN = 34,
+    "iterate" => ITERATE = 35,
+    "range" => RANGE = 36,
+    "range-list" => RANGE_LIST = 37,
+    "contains?" => CONTAINS = 38,
+    "vector" => VECTOR = 39,
+    "vec-ref" => VEC_REF = 40,
+    "vec-set" => VEC_SET = 41,
+    "vec-len" => VEC_LEN = 42,
+    "vec-slice" => VEC_SLICE = 43,
+    "vec->list" => VEC_TO_LIST = 44,
+    "list->vec" => LIST_TO_VEC = 45,
+    "abs" => ABS = 46,
+    "ceil" => CEIL = 47,
+    "floor" => FLOOR = 48,
+    "round" => ROUND = 49,
+    "trunc" => TRUNC = 50,
+    "int" => INT = 51,
+    "float" => FLOAT = 52,
+    "float-bits" => FLOAT_BITS = 53,
+    "float->bytes" => FLOAT_TO_BYTES = 54,
+    "bytes->float" => BYTES_TO_FLOAT = 55,
+    "integer-bits" => INTEGER_BITS = 56,
+    "integer->bytes" => INTEGER_TO_BYTES = 57,
+    "bytes->integer" => BYTES_TO_INTEGER = 58,
+    "inf" => INF = 59,
+    "nan" => NAN = 60,
+    "denom" => DENOM = 61,
+    "fract" => FRACT = 62,
+    "numer" => NUMER = 63,
+    "rat" => RAT = 64,
+    "recip" => RECIP = 65,
+    "set-float-mode" => SET_FLOAT_MODE = 66,
+    "chars" => CHARS = 67,
+    "string" => STRING = 68,
+    "string-from-chars" => STRING_FROM_CHARS = 69,
+    "map-string" => MAP_STRING = 70,
+    "string-len" => STRING_LEN = 71,
+    "string-byte-len" => STRING_BYTE_LEN = 72,
+    "string-escape" => STRING_ESCAPE = 73,
+    "string-unescape" => STRING_UNESCAPE = 74,
+    "string-escape-html" => STRING_ESCAPE_HTML = 75,
+    "string-ci=?" => STRING_CI_EQ = 76,
+    "starts-with?" => STARTS_WITH = 77,
+    "starts-with-ci?" => STARTS_WITH_CI = 78,
+    "id" => ID = 79,
+    "is" => IS = 80,
+    "is-instance" => IS_INSTANCE = 81,
+    "null" => NULL = 82,
+    "list?" => IS_LIST = 83,
+    "defined?" => IS_DEFINED = 84,
+    "macro-defined?" => IS_MACRO_DEFINED = 85,
+    "current-module" => CURRENT_MODULE = 86,
+    "exports-of" => EXPORTS_OF = 87,
+    "doc" => DOC = 88,
+    "keyword-namespace" => KEYWORD_NAMESPACE = 89,
+    "keyword-name" => KEYWORD_NAME = 90,
+    "error" => ERROR = 91,
+    "error-message" => ERROR_MESSAGE = 92,
+    "error-irritants" => ERROR_IRRITANTS = 93,
+    "error-category" => ERROR_CATEGORY = 94,
+    "error-kind" => ERROR_KIND = 95,
+    "type-of" => TYPE_OF = 96,
+    "." => DOT = 97,
+    ".=" => DOT_EQ = 98,
+    "new" => NEW = 99,
+    "struct?" => IS_STRUCT = 100,
+    "struct-type" => STRUCT_TYPE = 101,
+    "format" => FORMAT = 102,
+    "print" => PRINT = 103,
+    "println" => PRINTLN = 104,
+    "display" => DISPLAY = 105,
+    "write" => WRITE = 106,
+    "panic" => PANIC = 107,
+    "xor" => XOR = 108,
+    "not" => NOT = 109,
     // End of names referring to system functions.
     // The constant `NUM_SYSTEM_FNS` below should be one greater than
     // the value immediately above this comment.
 
     // Boolean names; the parser will replace these with boolean values.
     // These names must follow immediately after system function names.
-    "false" => FALSE = 61,
-    "true" => TRUE = 62,
+    "false" => FALSE = 110,
+    "true" => TRUE = 111,
     // End of names referring to standard values.
     // The constant `NUM_STANDARD_VALUES` below should be one6 greater than
     // the value immediately above this comment.
 
     // Special operators follow; these are not represented as values in global
     // scope. They are only handled by the compiler.
-    "apply" => APPLY = 63,
-    "do" => DO = 64,
-    "let" => LET = 65,
-    "define" => DEFINE = 66,
-    "macro" => MACRO = 67,
-    "struct" => STRUCT = 68,
-    "if" => IF = 69,
-    "and" => AND = 70,
-    "or" => OR = 71,
-    "case" => CASE = 72,
-    "cond" => COND = 73,
-    "lambda" => LAMBDA = 74,
-    "export" => EXPORT = 75,
-    "use" => USE = 76,
+    "apply" => APPLY = 112,
+    "do" => DO = 113,
+    "let" => LET = 114,
+    "define" => DEFINE = 115,
+    "macro" => MACRO = 116,
+    "struct" => STRUCT = 117,
+    "if" => IF = 118,
+    "and" => AND = 119,
+    "or" => OR = 120,
+    "case" => CASE = 121,
+    "cond" => COND = 122,
+    "lambda" => LAMBDA = 123,
+    "export" => EXPORT = 124,
+    "use" => USE = 125,
+    "defvar" => DEFVAR = 126,
+    "with-bindings" => WITH_BINDINGS = 127,
+    "export-rename" => EXPORT_RENAME = 128,
+    "guard" => GUARD = 129,
+    "try" => TRY = 130,
+    "apply/keyword" => APPLY_KEYWORD = 131,
+    "case-head" => CASE_HEAD = 132,
+    "define-type" => DEFINE_TYPE = 133,
+    "type-case" => TYPE_CASE = 134,
+    "define-module" => DEFINE_MODULE = 135,
+    "import-for-syntax" => IMPORT_FOR_SYNTAX = 136,
+    "with-output-to-string" => WITH_OUTPUT_TO_STRING = 137,
+    "unless-bound" => UNLESS_BOUND = 138,
+    "define-if-unbound" => DEFINE_IF_UNBOUND = 139,
+    "with-module" => WITH_MODULE = 140,
+    "let-fn" => LET_FN = 141,
+    "define-record" => DEFINE_RECORD = 142,
+    "while" => WHILE = 143,
+    "for" => FOR = 144,
+    "define-overload" => DEFINE_OVERLOAD = 145,
+    "let*" => LET_STAR = 146,
     // TODO: User-defined constants
     //"const" => CONST = N,
 
     // Just plain names follow; these are used by system functions or operators
     // to delineate syntactical constructs or just as name values.
-    "all" => ALL = 77,
-    "else" => ELSE = 78,
-    "optional" => OPTIONAL = 79,
-    "key" => KEY = 80,
-    "rest" => REST = 81,
-    "unbound" => UNBOUND = 82,
-    "unit" => UNIT = 83,
-    "bool" => BOOL = 84,
-    "char" => CHAR = 85,
-    "integer" => INTEGER = 86,
-    "ratio" => RATIO = 87,
-    "struct-def" => STRUCT_DEF = 88,
-    "keyword" => KEYWORD = 89,
-    "object" => OBJECT = 90,
-    "name" => NAME = 91,
-    "number" => NUMBER = 92,
-    "function" => FUNCTION = 93,
+    "all" => ALL = 147,
+    "else" => ELSE = 148,
+    "optional" => OPTIONAL = 149,
+    "key" => KEY = 150,
+    "rest" => REST = 151,
+    "unbound" => UNBOUND = 152,
+    "unit" => UNIT = 153,
+    "bool" => BOOL = 154,
+    "char" => CHAR = 155,
+    "integer" => INTEGER = 156,
+    "ratio" => RATIO = 157,
+    "struct-def" => STRUCT_DEF = 158,
+    "keyword" => KEYWORD = 159,
+    "object" => OBJECT = 160,
+    "any" => ANY = 161,
+    "name" => NAME = 162,
+    "number" => NUMBER = 163,
+    "function" => FUNCTION = 164,
+    "as" => AS = 165,
+    "catch" => CATCH = 166,
+    "ref" => REF = 167,
 }
 
 /// Number of standard names
-pub const NUM_STANDARD_NAMES: u32 = 94;
+pub const NUM_STANDARD_NAMES: u32 = 168;
+
+/// First `CallSys` operand which refers to a system function registered
+/// at runtime rather than a standard name.
+pub const REGISTERED_FNS_BEGIN: u32 = NUM_STANDARD_NAMES;
 
 /// Number of names, starting at `0`, which refer to system functions.
-pub const NUM_SYSTEM_FNS: usize = 61;
+pub const NUM_SYSTEM_FNS: usize = 110;
 
 /// Number of names, starting at `0`, which refer to standard values.
-pub const NUM_STANDARD_VALUES: u32 = 63;
+pub const NUM_STANDARD_VALUES: u32 = 112;
 
 /// First standard name which refers to a system operator.
 pub const SYSTEM_OPERATORS_BEGIN: u32 = NUM_STANDARD_VALUES;
 /// One-past-the-end of standard names which refer to system operators.
-pub const SYSTEM_OPERATORS_END: u32 = 77;
+pub const SYSTEM_OPERATORS_END: u32 = 147;
 
 /// Number of system operators, beginning at `SYSTEM_OPERATORS_BEGIN`.
 pub const NUM_SYSTEM_OPERATORS: usize =
@@ -302,21 +381,34 @@ impl_box!{ Box, Rc, Arc }
 #[derive(Clone, Debug)]
 pub struct NameInputConversion {
     map: HashMap<u32, Name>,
+    shared_end: u32,
     next_value: u32,
 }
 
 impl NameInputConversion {
     /// Creates a new `NameInputConversion` from a local-to-global mapping.
     pub fn new() -> NameInputConversion {
+        NameInputConversion::with_shared(0)
+    }
+
+    /// Creates a new `NameInputConversion` for bytecode which refers to
+    /// the first `shared` names of a `NameSnapshot` by their global values.
+    /// The interpreter `NameStore` must begin with the same names.
+    pub fn with_shared(shared: u32) -> NameInputConversion {
         NameInputConversion{
             map: HashMap::new(),
-            next_value: NUM_STANDARD_NAMES,
+            shared_end: NUM_STANDARD_NAMES + shared,
+            next_value: NUM_STANDARD_NAMES + shared,
         }
     }
 
     /// Returns the global name value for the given module-local value.
     pub fn get(&self, name: u32) -> Option<Name> {
-        get_standard_name(name).or_else(|| self.map.get(&name).cloned())
+        if name < self.shared_end {
+            Some(Name(name))
+        } else {
+            self.map.get(&name).cloned()
+        }
     }
 
     /// Insert a global name value into the map.
@@ -333,32 +425,42 @@ pub struct NameOutputConversion<'a> {
     /// Name strings, mapped to local name values
     names: Vec<&'a str>,
     map: HashMap<Name, u32>,
-    store: &'a NameStore
+    store: &'a NameStore,
+    shared_end: u32,
 }
 
 impl<'a> NameOutputConversion<'a> {
     /// Creates a new `NameOutputConversion`, using the given `NameStore`
     /// to lookup global name values.
     pub fn new(store: &NameStore) -> NameOutputConversion {
+        NameOutputConversion::with_shared(store, 0)
+    }
+
+    /// Creates a new `NameOutputConversion` which writes the first `shared`
+    /// names of the given `NameStore` as their global values.
+    /// Only the remaining names are collected.
+    pub fn with_shared(store: &NameStore, shared: u32) -> NameOutputConversion {
         NameOutputConversion{
             names: Vec::new(),
             map: HashMap::new(),
             store: store,
+            shared_end: NUM_STANDARD_NAMES + shared,
         }
     }
 
     /// Adds a name to the collection and returns a module-local value.
     pub fn add(&mut self, name: Name) -> u32 {
-        if is_standard_name(name) {
+        if name.0 < self.shared_end {
             name.0
         } else {
             let names = &mut self.names;
             let store = self.store;
+            let base = self.shared_end;
             *self.map.entry(name).or_insert_with(|| {
                 let name = store.get(name);
                 let n = names.len();
                 names.push(name);
-                n as u32 + NUM_STANDARD_NAMES
+                n as u32 + base
             })
         }
     }
@@ -379,6 +481,8 @@ impl<'a> NameOutputConversion<'a> {
 pub struct NameStore {
     /// Name string representation mapped to name values.
     names: Vec<String>,
+    /// Label and length of the snapshot from which the store was created
+    snapshot: Option<(String, u32)>,
 }
 
 impl NameStore {
@@ -386,9 +490,44 @@ impl NameStore {
     pub fn new() -> NameStore {
         NameStore{
             names: Vec::new(),
+            snapshot: None,
+        }
+    }
+
+    /// Constructs a `NameStore` containing the names of a snapshot,
+    /// such that each name has the same value as in the snapshotted store.
+    ///
+    /// Bytecode encoded using this store refers to the names of the snapshot
+    /// by value, rather than including them in its own name table.
+    pub fn from_snapshot(snapshot: &NameSnapshot) -> NameStore {
+        NameStore{
+            names: snapshot.names.clone(),
+            snapshot: Some((snapshot.label.clone(), snapshot.names.len() as u32)),
         }
     }
 
+    /// Returns a snapshot of all names currently in the store.
+    /// The label identifies the snapshot in error messages; e.g. the name
+    /// of the file to which it is written.
+    pub fn snapshot(&self, label: &str) -> NameSnapshot {
+        NameSnapshot{
+            label: label.to_owned(),
+            names: self.names.clone(),
+        }
+    }
+
+    /// Returns the label and number of names of the snapshot from which
+    /// the store was created, if any.
+    pub fn snapshot_info(&self) -> Option<(&str, u32)> {
+        self.snapshot.as_ref().map(|&(ref label, n)| (&label[..], n))
+    }
+
+    /// Returns a checksum of the first `n` names in the store,
+    /// or `None` if the store contains fewer names.
+    pub fn checksum(&self, n: u32) -> Option<u32> {
+        self.names.get(..n as usize).map(names_checksum)
+    }
+
     /// Adds a name to the `NameStore` if it is not present.
     /// Returns a `Name` value to refer to the new or existing name.
     pub fn add(&mut self, name: &str) -> Name {
@@ -428,6 +567,56 @@ impl NameStore {
     }
 }
 
+/// Names of a `NameStore`, which may be saved and used to create another
+/// `NameStore` in which each name has the same value.
+///
+/// See `encode::write_name_snapshot_file` and `NameStore::from_snapshot`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NameSnapshot {
+    label: String,
+    names: Vec<String>,
+}
+
+impl NameSnapshot {
+    /// Creates a snapshot from a label and a list of names,
+    /// in the order in which they were added to a `NameStore`.
+    pub fn new(label: String, names: Vec<String>) -> NameSnapshot {
+        NameSnapshot{
+            label: label,
+            names: names,
+        }
+    }
+
+    /// Returns the label identifying the snapshot.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Returns the names contained in the snapshot.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns a checksum of the names, which is written to bytecode
+    /// encoded against the snapshot.
+    pub fn checksum(&self) -> u32 {
+        names_checksum(&self.names)
+    }
+}
+
+/// Returns the 32-bit FNV-1a hash of a sequence of names.
+fn names_checksum(names: &[String]) -> u32 {
+    let mut hash = 0x811c9dc5_u32;
+
+    for name in names {
+        for &b in name.as_bytes().iter().chain(Some(&0)) {
+            hash = (hash ^ b as u32).wrapping_mul(0x01000193);
+        }
+    }
+
+    hash
+}
+
 /// Maps names to values in a sorted `Vec`
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct NameMap<T> {
@@ -440,6 +629,14 @@ impl<T> NameMap<T> {
         NameMap{values: Vec::new()}
     }
 
+    /// Creates a `NameMap` from pairs of string names and values,
+    /// interning each name in the given scope.
+    /// If a name occurs more than once, the last value is kept.
+    pub fn from_strs<'a, I>(scope: &Scope, iter: I) -> NameMap<T>
+            where I: IntoIterator<Item=(&'a str, T)> {
+        iter.into_iter().map(|(s, v)| (intern_name(scope, s), v)).collect()
+    }
+
     /// Lowers the map into a `NameMapSlice`, which may not receive new
     /// key-value pairs, but can overwrite existing values.
     pub fn into_slice(self) -> NameMapSlice<T> {
@@ -462,6 +659,14 @@ impl<T> NameMap<T> {
             .ok().map(|pos| &self.values[pos].1)
     }
 
+    /// Returns a mutable reference to the value corresponding to the given name.
+    pub fn get_mut(&mut self, name: Name) -> Option<&mut T> {
+        match self.values.binary_search_by(|&(ref n, _)| n.cmp(&name)) {
+            Ok(pos) => Some(&mut self.values[pos].1),
+            Err(_) => None
+        }
+    }
+
     /// Returns a slice of the contained names and values.
     pub fn get_values(&self) -> &[(Name, T)] {
         &self.values
@@ -496,11 +701,28 @@ impl<T> NameMap<T> {
     pub fn len(&self) -> usize {
         self.values.len()
     }
+
+    /// Removes a name from the map, returning its value, if present.
+    pub fn remove(&mut self, name: Name) -> Option<T> {
+        match self.values.binary_search_by(|&(ref n, _)| n.cmp(&name)) {
+            Ok(pos) => Some(self.values.remove(pos).1),
+            Err(_) => None
+        }
+    }
 }
 
 impl<T> FromIterator<(Name, T)> for NameMap<T> {
+    /// Collects name-value pairs, in any order, into a `NameMap`.
+    /// If a name occurs more than once, the last value is kept.
     fn from_iter<I>(iterator: I) -> Self where I: IntoIterator<Item=(Name, T)> {
-        let v = iterator.into_iter().collect();
+        let mut v = iterator.into_iter().collect::<Vec<_>>();
+
+        // Stable sort of the reversed pairs places the last value for each
+        // name first, which is the one retained by `dedup_by`.
+        v.reverse();
+        v.sort_by(|a, b| a.0.cmp(&b.0));
+        v.dedup_by(|a, b| a.0 == b.0);
+
         NameMap{values: v}
     }
 }
@@ -517,11 +739,17 @@ impl<'a, T> IntoIterator for &'a NameMap<T> {
 /// Maps names to values in a sorted boxed slice.
 ///
 /// Values may overwrite existing values, but new names cannot be inserted.
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct NameMapSlice<T> {
     values: Box<[(Name, T)]>,
 }
 
+impl<T> Default for NameMapSlice<T> {
+    fn default() -> NameMapSlice<T> {
+        NameMapSlice::new(Box::new([]))
+    }
+}
+
 impl<T> NameMapSlice<T> {
     /// Creates a `NameMapSlice` wrapping the given boxed slice,
     /// which must already be sorted by name.
@@ -604,6 +832,28 @@ impl NameSet {
         NameSet{map: NameMap::new()}
     }
 
+    /// Creates a `NameSet` from string names,
+    /// interning each name in the given scope.
+    pub fn from_strs<'a, I>(scope: &Scope, iter: I) -> NameSet
+            where I: IntoIterator<Item=&'a str> {
+        iter.into_iter().map(|s| intern_name(scope, s)).collect()
+    }
+
+    /// Returns a set of names contained in either this set or `other`.
+    pub fn union(&self, other: &NameSet) -> NameSet {
+        self.iter().chain(other.iter()).collect()
+    }
+
+    /// Returns a set of names contained in this set, but not in `other`.
+    pub fn difference(&self, other: &NameSet) -> NameSet {
+        self.iter().filter(|&n| !other.contains(n)).collect()
+    }
+
+    /// Returns a set of names contained in both this set and `other`.
+    pub fn intersection(&self, other: &NameSet) -> NameSet {
+        self.iter().filter(|&n| other.contains(n)).collect()
+    }
+
     /// Removes all names from the set.
     pub fn clear(&mut self) {
         self.map.clear();
@@ -639,6 +889,12 @@ impl NameSet {
     pub fn len(&self) -> usize {
         self.map.len()
     }
+
+    /// Removes the given name from the set.
+    /// Returns `true` if the name was contained.
+    pub fn remove(&mut self, name: Name) -> bool {
+        self.map.remove(name).is_some()
+    }
 }
 
 impl FromIterator<Name> for NameSet {
@@ -712,6 +968,12 @@ impl<'a> IntoIterator for &'a NameSetSlice {
     }
 }
 
+/// Interns a name in the given scope, using the standard `Name`
+/// value for standard names.
+pub fn intern_name(scope: &Scope, s: &str) -> Name {
+    get_standard_name_for(s).unwrap_or_else(|| scope.add_name(s))
+}
+
 /// Iterates over names in a `NameSet` or `NameSetSlice`.
 pub struct SetIter<'a>(Iter<'a, (Name, ())>);
 
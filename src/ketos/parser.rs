@@ -2,13 +2,15 @@
 
 use std::borrow::Cow::{self, Borrowed, Owned};
 use std::collections::HashMap;
+use std::error::Error as StdError;
 use std::fmt;
 
 use num::Num;
 
 use integer::{Integer, Ratio};
-use lexer::{Lexer, Span, Token};
+use lexer::{BytePos, Lexer, Span, Token};
 use name::{get_standard_name_for, Name, NameDisplay, NameStore};
+use rc_vec::RcVec;
 use string;
 use value::Value;
 
@@ -18,6 +20,10 @@ pub struct Parser<'a, 'lex> {
     names: &'a mut NameStore,
     name_cache: HashMap<&'lex str, Name>,
     cur_token: Option<(Span, Token<'lex>)>,
+    /// Span of the most recently consumed token
+    last_span: Span,
+    /// Whether input is read as data; see `set_data_mode`
+    data: bool,
 }
 
 /// Represents an error in parsing input.
@@ -45,6 +51,8 @@ impl fmt::Display for ParseError {
     }
 }
 
+impl StdError for ParseError {}
+
 impl NameDisplay for ParseError {
     fn fmt(&self, _names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Display::fmt(self, f)
@@ -54,6 +62,8 @@ impl NameDisplay for ParseError {
 /// Describes the kind of error encountered in parsing.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ParseErrorKind {
+    /// Boolean literal with unrecognized capitalization, e.g. `#TRUE`
+    InvalidBool,
     /// Error in parsing literal
     InvalidLiteral,
     /// Error in parsing token
@@ -79,6 +89,10 @@ pub enum ParseErrorKind {
     },
     /// Unrecognized character escape
     UnknownCharEscape(char),
+    /// Unrecognized character name, e.g. `#\foo`
+    UnknownCharName,
+    /// Unrecognized syntax beginning with `#`, e.g. `#x`
+    UnknownHashSyntax,
     /// Unmatched `)`
     UnmatchedParen,
     /// Unterminated character constant
@@ -92,6 +106,8 @@ pub enum ParseErrorKind {
 impl fmt::Display for ParseErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            ParseErrorKind::InvalidBool => f.write_str(
+                "invalid boolean literal; expected `#t`, `#f`, `#true`, or `#false`"),
             ParseErrorKind::InvalidLiteral => f.write_str("invalid numeric literal"),
             ParseErrorKind::InvalidToken => f.write_str("invalid token"),
             ParseErrorKind::InvalidChar(ch) =>
@@ -106,6 +122,10 @@ impl fmt::Display for ParseErrorKind {
                 write!(f, "expected {}; found {}", expected, found),
             ParseErrorKind::UnknownCharEscape(ch) =>
                 write!(f, "unknown char escape: {:?}", ch),
+            ParseErrorKind::UnknownCharName => f.write_str("unknown char name"),
+            ParseErrorKind::UnknownHashSyntax => f.write_str(
+                "unknown `#` syntax; expected `#t`, `#f`, `#true`, `#false`, \
+                 `#'c'`, `#\\name`, `#(...)`, or `#| comment |#`"),
             ParseErrorKind::UnmatchedParen => f.write_str("unmatched `)`"),
             ParseErrorKind::UnterminatedChar => f.write_str("unterminated char constant"),
             ParseErrorKind::UnterminatedComment => f.write_str("unterminated block comment"),
@@ -124,6 +144,8 @@ enum Group {
     Quotes(u32),
     /// Values in a parenthetical expression
     Parens(Vec<Value>),
+    /// Values in a vector literal `#(...)`
+    Vector(Vec<Value>),
 }
 
 impl<'a, 'lex> Parser<'a, 'lex> {
@@ -136,9 +158,19 @@ impl<'a, 'lex> Parser<'a, 'lex> {
             names: names,
             name_cache: HashMap::new(),
             cur_token: None,
+            last_span: Span::empty(0),
+            data: false,
         }
     }
 
+    /// Causes the `Parser` to read input as data rather than code.
+    /// Commas are treated as whitespace and backquotes are rejected,
+    /// as quasiquotation is meaningful only in code which is to be evaluated.
+    pub fn set_data_mode(&mut self) {
+        self.data = true;
+        self.lexer.skip_commas();
+    }
+
     /// Skips the "shebang" line of a source file.
     pub fn skip_shebang(&mut self) {
         self.lexer.skip_shebang();
@@ -153,17 +185,28 @@ impl<'a, 'lex> Parser<'a, 'lex> {
             let (sp, tok) = try!(self.next());
 
             let r = match tok {
-                Token::DocComment(_) => unreachable!(),
+                // Doc comments within an expression document nothing
+                Token::DocComment(_) => continue,
+                Token::BackQuote if self.data => Err(ParseError::new(sp,
+                    ParseErrorKind::UnexpectedToken{
+                        expected: "value",
+                        found: tok.name(),
+                    })),
                 Token::LeftParen => {
                     stack.push(Group::Parens(Vec::new()));
                     continue;
                 }
+                Token::HashParen => {
+                    stack.push(Group::Vector(Vec::new()));
+                    continue;
+                }
                 Token::RightParen => {
                     let group = try!(stack.pop().ok_or_else(
                         || ParseError::new(sp, ParseErrorKind::UnmatchedParen)));
 
                     match group {
                         Group::Parens(values) => Ok(values.into()),
+                        Group::Vector(values) => Ok(Value::Vector(RcVec::new(values))),
                         _ => Err(ParseError::new(sp,
                             ParseErrorKind::UnexpectedToken{
                                 expected: "expression",
@@ -171,6 +214,7 @@ impl<'a, 'lex> Parser<'a, 'lex> {
                             }))
                     }
                 }
+                Token::Bool(b) => Ok(Value::Bool(b)),
                 Token::Float(f) => parse_float(f)
                     .map(|f| Value::Float(f))
                     .map_err(|kind| ParseError::new(sp, kind)),
@@ -226,7 +270,7 @@ impl<'a, 'lex> Parser<'a, 'lex> {
                 Token::End => {
                     let any_paren = stack.iter().any(|group| {
                         match *group {
-                            Group::Parens(_) => true,
+                            Group::Parens(_) | Group::Vector(_) => true,
                             _ => false
                         }
                     });
@@ -246,7 +290,8 @@ impl<'a, 'lex> Parser<'a, 'lex> {
             loop {
                 match stack.last_mut() {
                     None => return Ok(v),
-                    Some(&mut Group::Parens(ref mut values)) => {
+                    Some(&mut Group::Parens(ref mut values)) |
+                    Some(&mut Group::Vector(ref mut values)) => {
                         values.push(v);
                         break;
                     }
@@ -291,6 +336,22 @@ impl<'a, 'lex> Parser<'a, 'lex> {
         }
     }
 
+    /// Parses the next expression from the input stream, if any remain.
+    ///
+    /// Returns the expression and the byte offset, relative to the beginning
+    /// of input, immediately following the expression. Any remaining input
+    /// is left unconsumed and may be parsed by further calls.
+    pub fn parse_next_expr(&mut self) -> Result<Option<(Value, usize)>, ParseError> {
+        if let (_, Token::End) = try!(self.peek()) {
+            return Ok(None);
+        }
+
+        let expr = try!(self.parse_expr());
+        let end = self.last_span.hi - self.lexer.code_offset();
+
+        Ok(Some((expr, end as usize)))
+    }
+
     /// Parse a series of expressions from the input stream.
     pub fn parse_exprs(&mut self) -> Result<Vec<Value>, ParseError> {
         let mut res = Vec::new();
@@ -334,6 +395,7 @@ impl<'a, 'lex> Parser<'a, 'lex> {
     fn next(&mut self) -> Result<(Span, Token<'lex>), ParseError> {
         let r = try!(self.peek_all());
         self.cur_token = None;
+        self.last_span = r.0;
         Ok(r)
     }
 
@@ -359,6 +421,28 @@ impl<'a, 'lex> Parser<'a, 'lex> {
     }
 }
 
+/// Parses a series of values from `input` in data mode, as described in
+/// `Parser::set_data_mode`, and returns them as a list.
+///
+/// Names and keywords are added to a new `NameStore`, which is returned
+/// with the values, so that input from an untrusted source cannot add names
+/// to a `NameStore` used by an interpreter.
+///
+/// `offset` is interpreted as in `Lexer::new`. If a `ParseError` is to be
+/// displayed by an `Interpreter`, it should be obtained by adding `input`
+/// to the interpreter's `CodeMap`.
+pub fn read_data(input: &str, offset: BytePos) -> Result<(Value, NameStore), ParseError> {
+    let mut names = NameStore::new();
+
+    let values = {
+        let mut p = Parser::new(&mut names, Lexer::new(input, offset));
+        p.set_data_mode();
+        try!(p.parse_exprs())
+    };
+
+    Ok((values.into(), names))
+}
+
 fn parse_char(s: &str) -> Result<char, ParseError> {
     let (ch, _) = try!(string::parse_char(s, 0));
     Ok(ch)
@@ -403,9 +487,9 @@ fn strip_underscores(s: &str) -> Cow<str> {
 
 #[cfg(test)]
 mod test {
-    use super::{ParseError, ParseErrorKind, Parser};
+    use super::{read_data, ParseError, ParseErrorKind, Parser};
     use lexer::{Span, Lexer};
-    use name::NameStore;
+    use name::{debug_names, NameStore};
     use value::Value;
 
     fn parse(s: &str) -> Result<Value, ParseError> {
@@ -414,6 +498,70 @@ mod test {
         p.parse_expr()
     }
 
+    #[test]
+    fn test_parse_bool() {
+        for &(s, b) in &[("#t", true), ("#f", false), ("#true", true),
+                ("#false", false), ("#True", true), ("#F", false)] {
+            match parse(s).unwrap() {
+                Value::Bool(v) => assert_eq!(v, b),
+                v => panic!("expected bool for {:?}; got {:?}", s, v)
+            }
+        }
+    }
+
+    #[test]
+    fn test_bool_round_trip() {
+        let names = NameStore::new();
+
+        for &(s, out) in &[("#t", "true"), ("#false", "false"),
+                ("(#t (#f) #true)", "(true (false) true)")] {
+            let v = parse(s).unwrap();
+            let printed = debug_names(&names, &v).to_string();
+            assert_eq!(printed, out);
+            assert!(parse(&printed).unwrap().is_identical(&v));
+        }
+    }
+
+    #[test]
+    fn test_parse_named_char() {
+        for &(s, ch) in &[
+                (r"#\space", 0x20), (r"#\newline", 0x0a), (r"#\tab", 0x09),
+                (r"#\return", 0x0d), (r"#\null", 0x00), (r"#\escape", 0x1b),
+                (r"#\delete", 0x7f), (r"#\alarm", 0x07), (r"#\backspace", 0x08),
+                (r"#\x41", 0x41), (r"#\x3bb", 0x3bb), (r"#\x", 0x78),
+                (r"#\a", 0x61), (r"#\(", 0x28), (r"#\λ", 0x3bb)] {
+            match parse(s).unwrap() {
+                Value::Char(v) => assert_eq!(v as u32, ch),
+                v => panic!("expected char for {:?}; got {:?}", s, v)
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_next_expr() {
+        let mut names = NameStore::new();
+        let input = "(+ 1 2)(- 3 4) ; end";
+        let mut p = Parser::new(&mut names, Lexer::new(input, 10));
+
+        let (a, end_a) = p.parse_next_expr().unwrap().unwrap();
+        assert_eq!(end_a, 7);
+        assert_eq!(&input[..end_a], "(+ 1 2)");
+
+        let (b, end_b) = p.parse_next_expr().unwrap().unwrap();
+        assert_eq!(end_b, 14);
+        assert_eq!(&input[end_a..end_b], "(- 3 4)");
+
+        assert!(p.parse_next_expr().unwrap().is_none());
+
+        match (a, b) {
+            (Value::List(a), Value::List(b)) => {
+                assert_eq!(a.len(), 3);
+                assert_eq!(b.len(), 3);
+            }
+            (a, b) => panic!("expected lists; got {:?} and {:?}", a, b)
+        }
+    }
+
     #[test]
     fn test_errors() {
         assert_eq!(parse("(foo").unwrap_err(), ParseError{
@@ -423,4 +571,59 @@ mod test {
         assert_eq!(parse("`(foo ,,bar)").unwrap_err(), ParseError{
             span: Span{lo: 7, hi: 8}, kind: ParseErrorKind::UnbalancedComma});
     }
+
+    #[test]
+    fn test_doc_comment_in_expr() {
+        let names = NameStore::new();
+        let v = parse("(list ;; doc\n 1)").unwrap();
+        assert_eq!(debug_names(&names, &v).to_string(), "(list 1)");
+    }
+
+    #[test]
+    fn test_read_data() {
+        let (v, names) = read_data("(1, 2,3) ,foo :bar ;; doc\n #(a, ,b)", 0).unwrap();
+        assert_eq!(debug_names(&names, &v).to_string(),
+            "((1 2 3) foo :bar #(a b))");
+
+        let (v, _) = read_data("", 0).unwrap();
+        assert!(v.is_identical(&Value::Unit));
+
+        assert_eq!(read_data("(a `b)", 0).unwrap_err(), ParseError{
+            span: Span{lo: 3, hi: 4}, kind: ParseErrorKind::UnexpectedToken{
+                expected: "value", found: "`"}});
+    }
+
+    /// Generates pseudo-random input; a fixed seed keeps failures reproducible.
+    fn random_input(seed: &mut u32, len: usize) -> String {
+        const CHARS: &'static [&'static str] = &[
+            "(", ")", "'", "`", ",", ",@", "#", "#(", "#\\", "\\", "\"", "|",
+            ";", ";;", "\n", " ", "0", "1.5", "-", "/", "e", "x", "a", "foo",
+            ":", "@", ".", "#t", "λ", "\u{10ffff}", "r#\"", "#\\x"];
+
+        let mut s = String::new();
+
+        for _ in 0..len {
+            *seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            s.push_str(CHARS[(*seed >> 16) as usize % CHARS.len()]);
+        }
+
+        s
+    }
+
+    #[test]
+    fn test_read_data_no_panic() {
+        let mut seed = 1;
+
+        for i in 0..5000 {
+            let input = random_input(&mut seed, i % 40);
+            let _ = read_data(&input, 0);
+        }
+
+        let valid = "(foo, \"bar\" #\\x41 #(1 2/3 -4.5e1) 'baz :quux) ; end";
+
+        for (i, _) in valid.char_indices() {
+            let _ = read_data(&valid[..i], 0);
+            let _ = read_data(&valid[i..], 0);
+        }
+    }
 }
@@ -0,0 +1,137 @@
+//! Implements lazily evaluated integer ranges.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use exec::ExecError;
+use name::NameStore;
+use value::{ForeignValue, Value};
+
+/// Represents a sequence of integers, from `start` up to, but not including,
+/// `end`, separated by `step`. Elements are computed as needed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Range {
+    /// First value in the range
+    pub start: i64,
+    /// End of the range; this value is not included
+    pub end: i64,
+    /// Difference between successive values; never zero
+    pub step: i64,
+}
+
+impl Range {
+    /// Creates a new `Range`. Returns `None` if `step` is zero.
+    pub fn new(start: i64, end: i64, step: i64) -> Option<Range> {
+        if step == 0 {
+            None
+        } else {
+            Some(Range{start: start, end: end, step: step})
+        }
+    }
+
+    /// Returns the value, if it is a `Range`.
+    pub fn from_value(v: &Value) -> Option<&Range> {
+        match *v {
+            Value::Foreign(ref fv) => fv.downcast_ref::<Range>(),
+            _ => None
+        }
+    }
+
+    /// Returns the number of values in the range.
+    pub fn len(&self) -> u64 {
+        // The difference of two `i64` values always fits in a `u64`.
+        let (diff, step) = if self.step > 0 && self.end > self.start {
+            (self.end.wrapping_sub(self.start) as u64, self.step as u64)
+        } else if self.step < 0 && self.end < self.start {
+            (self.start.wrapping_sub(self.end) as u64, self.step.wrapping_neg() as u64)
+        } else {
+            return 0;
+        };
+
+        (diff - 1) / step + 1
+    }
+
+    /// Returns whether the range contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the value at the given index, if it is within the range.
+    pub fn get(&self, idx: u64) -> Option<i64> {
+        if idx < self.len() {
+            Some((self.start as u64).wrapping_add(
+                idx.wrapping_mul(self.step as u64)) as i64)
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether the given value is contained in the range.
+    pub fn contains(&self, n: i64) -> bool {
+        if self.step > 0 {
+            n >= self.start && n < self.end &&
+                (n.wrapping_sub(self.start) as u64) % (self.step as u64) == 0
+        } else {
+            n <= self.start && n > self.end &&
+                (self.start.wrapping_sub(n) as u64) %
+                    (self.step.wrapping_neg() as u64) == 0
+        }
+    }
+
+    /// Returns an iterator over values in the range.
+    pub fn iter(&self) -> RangeIter {
+        RangeIter{
+            range: *self,
+            idx: 0,
+            len: self.len(),
+        }
+    }
+}
+
+impl ForeignValue for Range {
+    fn compare_to(&self, _rhs: &ForeignValue) -> Result<Ordering, ExecError> {
+        Err(ExecError::CannotCompare("range"))
+    }
+
+    fn is_equal_to(&self, rhs: &ForeignValue) -> Result<bool, ExecError> {
+        match rhs.downcast_ref::<Range>() {
+            Some(rhs) => Ok(self == rhs),
+            None => Err(ExecError::TypeMismatch{
+                lhs: self.type_name(),
+                rhs: rhs.type_name(),
+            })
+        }
+    }
+
+    fn fmt_debug(&self, _names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#<range {}..{} by {}>", self.start, self.end, self.step)
+    }
+
+    fn type_name(&self) -> &'static str { "range" }
+}
+
+/// Iterates over values in a `Range`.
+pub struct RangeIter {
+    range: Range,
+    idx: u64,
+    len: u64,
+}
+
+impl Iterator for RangeIter {
+    type Item = i64;
+
+    fn next(&mut self) -> Option<i64> {
+        if self.idx < self.len {
+            let v = self.range.get(self.idx);
+            self.idx += 1;
+            v
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = (self.len - self.idx) as usize;
+        (n, Some(n))
+    }
+}
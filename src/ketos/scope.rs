@@ -1,31 +1,145 @@
 //! Contains values associated with names in a given execution context.
 
-use std::cell::{Ref, RefMut, RefCell};
+use std::cell::{Cell, Ref, RefMut, RefCell};
+use std::collections::HashMap;
+use std::fmt::{self, Arguments};
 use std::io;
+use std::mem::size_of;
 use std::rc::{Rc, Weak};
 
+use bytecode::Code;
 use function::{Function, Lambda, SystemFn};
-use io::SharedWrite;
+use io::{IoError, SharedWrite};
 use lexer::CodeMap;
-use module::ModuleRegistry;
+use compile::CompileError;
+use module::{ImportPolicy, ImportSet, ModuleRegistry};
 use name::{get_standard_name, get_system_fn, is_system_operator,
-    is_standard_value, NUM_STANDARD_VALUES,
-    SYSTEM_OPERATORS_END, Name, NameMap, NameSetSlice, NameStore};
+    is_standard_value, NUM_STANDARD_VALUES, REGISTERED_FNS_BEGIN,
+    SYSTEM_OPERATORS_END, Name, NameMap, NameMapSlice, NameSet, NameSetSlice, NameStore};
 use value::Value;
 
 /// Represents the global namespace of an execution context.
+///
+/// # Reference cycles
+///
+/// A `Lambda` holds only a weak reference to the scope in which it was
+/// created, so values stored in a scope do not keep the scope alive.
+/// However, each scope holds a reference to the `ModuleRegistry`, which in
+/// turn holds the scopes of all loaded modules. This forms a cycle
+/// (scope → registry → module scope → registry) which keeps every loaded
+/// module, and any values defined within it, alive.
+///
+/// `break_cycles` breaks this cycle. `Interpreter` calls it automatically
+/// when the last reference to its scope is dropped.
 pub struct GlobalScope {
     namespace: RefCell<Namespace>,
     name_store: Rc<RefCell<NameStore>>,
     codemap: Rc<RefCell<CodeMap>>,
     modules: Rc<ModuleRegistry>,
     io: Rc<GlobalIo>,
+    strict: Cell<bool>,
+    strict_float: Cell<bool>,
+    strict_keywords: Cell<bool>,
+    compile_only: Cell<bool>,
+    reuse_struct_defs: Cell<bool>,
+    cache_run_file: Cell<bool>,
+    import_policy: RefCell<ImportPolicy>,
+    max_macro_expansion: Cell<usize>,
+    macro_trace: RefCell<Option<Rc<Fn(Name, usize)>>>,
+    log_handler: Rc<RefCell<Option<Rc<Fn(LogLevel, &str)>>>>,
+    unbound_name_handler: Rc<RefCell<Option<Rc<Fn(Name) -> Option<Value>>>>>,
+    yield_hook: Rc<RefCell<Option<YieldHook>>>,
+    dynamic: Rc<RefCell<DynamicVars>>,
+    code_tracker: Rc<RefCell<Option<CodeTracker>>>,
+    registered_fns: Rc<RefCell<Vec<(Name, SystemFn)>>>,
+    trace_depth: Rc<Cell<u32>>,
+    /// Scopes for which macros are currently being expanded, innermost last
+    expanding: Rc<RefCell<Vec<Scope>>>,
+    module_name: Cell<Option<Name>>,
+    parent: Option<Scope>,
+    /// Scope containing prelude definitions; inherited by scopes created
+    /// using `new_using` or `new_child`
+    prelude: Option<Scope>,
 }
 
+/// Weak references to the code objects of tracked lambdas, keyed by address
+type CodeTracker = HashMap<*const Code, Weak<Code>>;
+
+/// Statistics on the live code objects of tracked lambdas.
+///
+/// See `GlobalScope::set_track_code`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CodeStats {
+    /// Number of live `Code` objects
+    pub count: usize,
+    /// Total size, in bytes, of the const value arrays of live `Code` objects.
+    /// Memory referenced by const values, such as list elements, is not counted.
+    pub const_bytes: usize,
+    /// Number of `Code` objects held by the tracker, including any which
+    /// are no longer live and have not yet been discarded
+    pub tracked: usize,
+}
+
+/// Severity of a message reported to a log handler.
+///
+/// See `GlobalScope::set_log_handler`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LogLevel {
+    /// Informational message
+    Info,
+    /// Warning of a possible problem; e.g. a compile warning
+    Warning,
+}
+
+/// Action taken by the virtual machine after calling a yield hook.
+///
+/// See `GlobalScope::set_yield_hook`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum YieldAction {
+    /// Continue execution
+    Continue,
+    /// Abort execution with `ExecError::Interrupted`
+    Abort,
+}
+
+/// Function called periodically during execution, along with the number
+/// of instructions remaining until the next call.
+#[derive(Clone)]
+pub struct YieldHook {
+    interval: u32,
+    countdown: Rc<Cell<u32>>,
+    callback: Rc<Fn() -> YieldAction>,
+}
+
+impl YieldHook {
+    /// Counts the execution of one instruction, calling the hook function
+    /// when the interval has elapsed.
+    #[inline]
+    pub fn tick(&self) -> YieldAction {
+        let n = self.countdown.get();
+
+        if n > 1 {
+            self.countdown.set(n - 1);
+            YieldAction::Continue
+        } else {
+            self.countdown.set(self.interval);
+            (self.callback)()
+        }
+    }
+}
+
+/// Default maximum size, in nodes, of a single macro expansion
+pub const DEFAULT_MAX_MACRO_EXPANSION: usize = 100_000;
+
 /// Contains global shared I/O objects
+///
+/// Output written through the `SharedWrite` implementation of `GlobalIo`
+/// is sent to `stdout`, unless it is being captured by `begin_capture`.
 pub struct GlobalIo {
     /// Shared standard output writer
     pub stdout: Rc<SharedWrite>,
+    /// Buffers of captured output, innermost last
+    captures: RefCell<Vec<Vec<u8>>>,
 }
 
 impl GlobalIo {
@@ -33,6 +147,7 @@ impl GlobalIo {
     pub fn new(stdout: Rc<SharedWrite>) -> GlobalIo {
         GlobalIo{
             stdout: stdout,
+            captures: RefCell::new(Vec::new()),
         }
     }
 
@@ -40,13 +155,87 @@ impl GlobalIo {
     pub fn default() -> GlobalIo {
         GlobalIo::new(Rc::new(io::stdout()))
     }
+
+    /// Begins capturing output. Until the corresponding call to `end_capture`,
+    /// output is collected in a buffer rather than written to `stdout`.
+    pub fn begin_capture(&self) {
+        self.captures.borrow_mut().push(Vec::new());
+    }
+
+    /// Ends the innermost capture and returns the captured output.
+    /// Returns `None` if no output is being captured.
+    pub fn end_capture(&self) -> Option<String> {
+        self.captures.borrow_mut().pop().map(|buf| match String::from_utf8(buf) {
+            Ok(s) => s,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned()
+        })
+    }
+
+    /// Returns the number of active captures.
+    pub fn capture_depth(&self) -> usize {
+        self.captures.borrow().len()
+    }
+
+    /// Discards any captures begun after there were `depth` active captures.
+    pub fn end_captures(&self, depth: usize) {
+        self.captures.borrow_mut().truncate(depth);
+    }
+}
+
+impl SharedWrite for GlobalIo {
+    fn write_all(&self, buf: &[u8]) -> Result<(), IoError> {
+        match self.captures.borrow_mut().last_mut() {
+            Some(capture) => {
+                capture.extend_from_slice(buf);
+                Ok(())
+            }
+            None => self.stdout.write_all(buf)
+        }
+    }
+
+    fn write_fmt(&self, fmt: Arguments) -> Result<(), IoError> {
+        match self.captures.borrow_mut().last_mut() {
+            Some(capture) => {
+                capture.extend_from_slice(fmt::format(fmt).as_bytes());
+                Ok(())
+            }
+            None => self.stdout.write_fmt(fmt)
+        }
+    }
+
+    fn flush(&self) -> Result<(), IoError> {
+        if self.captures.borrow().is_empty() {
+            self.stdout.flush()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Contains the values of dynamic variables.
+///
+/// A dynamic variable is identified by the name with which it was declared.
+/// Values are shared among all scopes created using `new_using`. A scope created
+/// using `new_child` has its own values, falling back to those of its parent.
+struct DynamicVars {
+    /// Values assigned by `defvar`
+    values: NameMap<Value>,
+    /// Values bound by `with-bindings`; the last binding of a name is active
+    bindings: Vec<(Name, Value)>,
 }
 
 struct Namespace {
     macros: NameMap<Lambda>,
     values: NameMap<Value>,
+    /// Dynamic variables, mapped to the name with which each was declared
+    dynamic: NameMap<Name>,
     /// Exported names defined by an `export` declaration
     exports: Option<NameSetSlice>,
+    /// Names exported under another name by an `export-rename` declaration;
+    /// maps exported name to internal name
+    export_renames: Option<NameMapSlice<Name>>,
+    /// Values imported from other modules by `use` declarations
+    imports: Vec<ImportSet>,
 }
 
 /// Shared scope object
@@ -67,16 +256,571 @@ impl GlobalScope {
             codemap: codemap,
             modules: registry,
             io: io,
+            strict: Cell::new(false),
+            strict_float: Cell::new(false),
+            strict_keywords: Cell::new(false),
+            compile_only: Cell::new(false),
+            reuse_struct_defs: Cell::new(false),
+            cache_run_file: Cell::new(false),
+            import_policy: RefCell::new(ImportPolicy::default()),
+            max_macro_expansion: Cell::new(DEFAULT_MAX_MACRO_EXPANSION),
+            macro_trace: RefCell::new(None),
+            log_handler: Rc::new(RefCell::new(None)),
+            unbound_name_handler: Rc::new(RefCell::new(None)),
+            yield_hook: Rc::new(RefCell::new(None)),
+            dynamic: Rc::new(RefCell::new(DynamicVars{
+                values: NameMap::new(),
+                bindings: Vec::new(),
+            })),
+            code_tracker: Rc::new(RefCell::new(None)),
+            registered_fns: Rc::new(RefCell::new(Vec::new())),
+            trace_depth: Rc::new(Cell::new(0)),
+            expanding: Rc::new(RefCell::new(Vec::new())),
+            module_name: Cell::new(None),
+            parent: None,
+            prelude: None,
         }
     }
 
     /// Creates a new global scope using the shared data from the given scope.
     pub fn new_using(scope: &Scope) -> Scope {
-        Rc::new(GlobalScope::new(
+        Rc::new(GlobalScope::using(scope))
+    }
+
+    /// Creates a new global scope whose lookups fall back to the given scope.
+    ///
+    /// Values, macros, and dynamic variables defined in the child scope are
+    /// not visible to the parent, so compiled code may be executed in many
+    /// child scopes of one parent without each execution observing
+    /// the definitions of another.
+    pub fn new_child(parent: &Scope) -> Scope {
+        Rc::new(GlobalScope::child_of(parent))
+    }
+
+    /// Creates a new global scope whose lookups fall back to the given
+    /// prelude scope.
+    ///
+    /// Unlike a scope created by `new_child`, scopes created from the new
+    /// scope using `new_using`, such as module scopes, also fall back to the
+    /// prelude. Definitions in any of these scopes may shadow prelude values.
+    pub fn new_with_prelude(prelude: &Scope) -> Scope {
+        let mut new = GlobalScope::child_of(prelude);
+        new.prelude = Some(prelude.clone());
+        Rc::new(new)
+    }
+
+    fn child_of(parent: &Scope) -> GlobalScope {
+        let mut new = GlobalScope::using(parent);
+
+        new.dynamic = Rc::new(RefCell::new(DynamicVars{
+            values: NameMap::new(),
+            bindings: Vec::new(),
+        }));
+        new.module_name.set(parent.module_name());
+        new.parent = Some(parent.clone());
+        new
+    }
+
+    fn using(scope: &Scope) -> GlobalScope {
+        let mut new = GlobalScope::new(
             scope.name_store.clone(),
             scope.codemap.clone(),
             scope.modules.clone(),
-            scope.io.clone()))
+            scope.io.clone());
+
+        new.set_strict(scope.is_strict());
+        new.set_strict_float(scope.is_strict_float());
+        new.set_strict_keywords(scope.is_strict_keywords());
+        new.set_compile_only(scope.is_compile_only());
+        new.set_reuse_struct_defs(scope.reuse_struct_defs());
+        new.set_import_policy(scope.import_policy.borrow().clone());
+        new.set_max_macro_expansion(scope.max_macro_expansion());
+        new.set_macro_trace(scope.macro_trace.borrow().clone());
+        new.dynamic = scope.dynamic.clone();
+        new.code_tracker = scope.code_tracker.clone();
+        new.registered_fns = scope.registered_fns.clone();
+        new.log_handler = scope.log_handler.clone();
+        new.unbound_name_handler = scope.unbound_name_handler.clone();
+        new.yield_hook = scope.yield_hook.clone();
+        new.trace_depth = scope.trace_depth.clone();
+        new.expanding = scope.expanding.clone();
+        new.parent = scope.prelude.clone();
+        new.prelude = scope.prelude.clone();
+        new
+    }
+
+    /// Returns the name of the module whose definitions are contained
+    /// in this scope; `None` if the scope does not belong to a module.
+    ///
+    /// A scope created using `new_child` belongs to the module of its parent.
+    pub fn module_name(&self) -> Option<Name> {
+        self.module_name.get()
+    }
+
+    /// Sets the name of the module whose definitions are contained in this scope.
+    ///
+    /// Module loaders call this method for each new module scope.
+    pub fn set_module_name(&self, name: Name) {
+        self.module_name.set(Some(name));
+    }
+
+    /// Returns the parent scope, if this scope was created using `new_child`
+    /// or has a prelude.
+    pub fn parent(&self) -> Option<&Scope> {
+        self.parent.as_ref()
+    }
+
+    /// Returns the prelude scope, if this scope was created using
+    /// `new_with_prelude` or from such a scope.
+    pub fn prelude(&self) -> Option<&Scope> {
+        self.prelude.as_ref()
+    }
+
+    /// Returns whether the scope is in strict mode.
+    ///
+    /// In strict mode, the compiler will treat as errors some constructs which
+    /// would otherwise produce a warning; e.g. the use of `define` within
+    /// an expression rather than at top level.
+    pub fn is_strict(&self) -> bool {
+        self.strict.get()
+    }
+
+    /// Sets whether the scope is in strict mode.
+    ///
+    /// Scopes created using `new_using` inherit this setting.
+    pub fn set_strict(&self, strict: bool) {
+        self.strict.set(strict);
+    }
+
+    /// Returns whether the scope is in strict float mode.
+    ///
+    /// In strict float mode, an arithmetic function which produces `NaN` or
+    /// infinity from finite operands returns an error.
+    pub fn is_strict_float(&self) -> bool {
+        self.strict_float.get()
+    }
+
+    /// Sets whether the scope is in strict float mode.
+    ///
+    /// Scopes created using `new_using` inherit this setting.
+    pub fn set_strict_float(&self, strict: bool) {
+        self.strict_float.set(strict);
+    }
+
+    /// Returns whether the scope is in strict keyword mode.
+    ///
+    /// A function with a rest parameter and no keyword parameters collects
+    /// any keyword arguments into its rest list. In strict keyword mode,
+    /// passing a keyword value as a rest argument to such a function
+    /// returns an error naming the function and the keyword.
+    pub fn is_strict_keywords(&self) -> bool {
+        self.strict_keywords.get()
+    }
+
+    /// Sets whether the scope is in strict keyword mode.
+    ///
+    /// Scopes created using `new_using` inherit this setting.
+    pub fn set_strict_keywords(&self, strict: bool) {
+        self.strict_keywords.set(strict);
+    }
+
+    /// Returns whether the scope is in compile-only mode.
+    ///
+    /// In compile-only mode, modules imported by `use` or defined by
+    /// `define-module` are compiled, but their code is not executed.
+    /// Because values defined by such modules do not exist, exported names
+    /// are not required to be defined and imported names are not bound.
+    /// Macros are still defined and executed during compilation.
+    pub fn is_compile_only(&self) -> bool {
+        self.compile_only.get()
+    }
+
+    /// Sets whether the scope is in compile-only mode.
+    ///
+    /// Scopes created using `new_using` inherit this setting.
+    pub fn set_compile_only(&self, compile_only: bool) {
+        self.compile_only.set(compile_only);
+    }
+
+    /// Returns whether `define-struct` reuses an existing struct definition
+    /// with identical fields.
+    pub fn reuse_struct_defs(&self) -> bool {
+        self.reuse_struct_defs.get()
+    }
+
+    /// Sets whether `define-struct` reuses an existing struct definition.
+    ///
+    /// Each evaluation of `define-struct` ordinarily creates a new type,
+    /// distinct from any previous definition of the same name.
+    /// When this setting is enabled and the name is already bound to
+    /// a struct definition having identical fields, that definition is
+    /// bound again, so that values created before a redefinition remain
+    /// compatible with the new one. This is intended for interactive use.
+    ///
+    /// Scopes created using `new_using` inherit this setting.
+    pub fn set_reuse_struct_defs(&self, reuse: bool) {
+        self.reuse_struct_defs.set(reuse);
+    }
+
+    /// Returns whether `Interpreter::run_file` caches compiled bytecode.
+    pub fn cache_run_file(&self) -> bool {
+        self.cache_run_file.get()
+    }
+
+    /// Sets whether `Interpreter::run_file` caches compiled bytecode
+    /// in a `.ktsc` file alongside each `.kts` file it runs.
+    ///
+    /// This setting is disabled by default, so that running a script
+    /// does not write files beside it. Modules loaded by `FileModuleLoader`
+    /// are cached regardless of this setting.
+    pub fn set_cache_run_file(&self, cache: bool) {
+        self.cache_run_file.set(cache);
+    }
+
+    /// Sets the policy determining which modules may be imported.
+    ///
+    /// Scopes created using `new_using` inherit this setting,
+    /// so the policy also applies to imports made by loaded modules.
+    pub fn set_import_policy(&self, policy: ImportPolicy) {
+        *self.import_policy.borrow_mut() = policy;
+    }
+
+    /// Returns an error if the import policy of the scope does not permit
+    /// the named module to be imported.
+    pub fn check_import_policy(&self, name: Name) -> Result<(), CompileError> {
+        self.import_policy.borrow().check(name)
+    }
+
+    /// Returns the maximum size, in nodes, of a single macro expansion.
+    pub fn max_macro_expansion(&self) -> usize {
+        self.max_macro_expansion.get()
+    }
+
+    /// Sets the maximum size, in nodes, of a single macro expansion.
+    /// The size of a value is given by `Value::node_count`.
+    ///
+    /// Scopes created using `new_using` inherit this setting.
+    pub fn set_max_macro_expansion(&self, max: usize) {
+        self.max_macro_expansion.set(max);
+    }
+
+    /// Sets a function to be called after each macro expansion with the name
+    /// of the macro and the size, in nodes, of the expanded value.
+    /// Passing `None` disables tracing.
+    ///
+    /// Scopes created using `new_using` inherit this setting.
+    pub fn set_macro_trace(&self, trace: Option<Rc<Fn(Name, usize)>>) {
+        *self.macro_trace.borrow_mut() = trace;
+    }
+
+    /// Sets a function to receive warnings and other messages reported
+    /// by the interpreter, such as compile warnings. By default,
+    /// messages are discarded.
+    ///
+    /// Scopes created using `new_using` share the log handler with this scope.
+    pub fn set_log_handler(&self, handler: Box<Fn(LogLevel, &str)>) {
+        *self.log_handler.borrow_mut() = Some(Rc::from(handler));
+    }
+
+    /// Reports a message to the log handler, if one is set.
+    pub fn log(&self, level: LogLevel, msg: &str) {
+        let handler = self.log_handler.borrow().clone();
+
+        if let Some(handler) = handler {
+            handler(level, msg);
+        }
+    }
+
+    /// Sets a function to be called when executing code refers to a global
+    /// name which is not defined. If the function returns a value, the value
+    /// is defined in the scope in which the name was looked up, so that
+    /// the function is not called again for the same name; if it returns
+    /// `None`, the lookup fails with `ExecError::NameError`.
+    ///
+    /// Scopes created using `new_using` share the handler with this scope.
+    pub fn set_unbound_name_handler(&self, handler: Box<Fn(Name) -> Option<Value>>) {
+        *self.unbound_name_handler.borrow_mut() = Some(Rc::from(handler));
+    }
+
+    /// Sets a function to be called by the virtual machine after every
+    /// `interval` instructions executed, allowing an application to process
+    /// events during long computations. If the function returns
+    /// `YieldAction::Abort`, execution ends with `ExecError::Interrupted`,
+    /// which cannot be caught by `try` or `guard` expressions.
+    ///
+    /// Instructions executed by nested calls, such as a lambda passed to
+    /// a system function, count toward the same interval.
+    ///
+    /// Scopes created using `new_using` share the hook with this scope.
+    pub fn set_yield_hook(&self, interval: u32, hook: Box<Fn() -> YieldAction>) {
+        let interval = interval.max(1);
+
+        *self.yield_hook.borrow_mut() = Some(YieldHook{
+            interval: interval,
+            countdown: Rc::new(Cell::new(interval)),
+            callback: Rc::from(hook),
+        });
+    }
+
+    /// Removes the function set by `set_yield_hook`.
+    ///
+    /// Code which is currently executing continues to call the hook.
+    pub fn clear_yield_hook(&self) {
+        *self.yield_hook.borrow_mut() = None;
+    }
+
+    /// Returns the yield hook set by `set_yield_hook`, if any.
+    pub fn yield_hook(&self) -> Option<YieldHook> {
+        self.yield_hook.borrow().clone()
+    }
+
+    /// Calls the unbound name handler, if one is set, for a name which is
+    /// not defined. A value returned by the handler is added to this scope.
+    pub fn resolve_unbound_name(&self, name: Name) -> Option<Value> {
+        let handler = self.unbound_name_handler.borrow().clone();
+
+        handler.and_then(|handler| handler(name)).map(|v| {
+            self.add_value(name, v.clone());
+            v
+        })
+    }
+
+    /// Returns the name of a defined value whose spelling is closest to
+    /// the given name, if any is close enough to be a likely misspelling.
+    pub fn suggest_name(&self, name: Name) -> Option<Name> {
+        let names = self.name_store.borrow();
+        let target = names.get(name);
+        let max_distance = (target.chars().count() / 3).max(1);
+
+        let mut candidates: Vec<Name> = MasterScope::get_names().collect();
+        let mut scope = Some(self);
+
+        while let Some(s) = scope {
+            s.with_values(|values| candidates.extend(values.iter().map(|&(n, _)| n)));
+            scope = s.parent.as_ref().map(|p| &**p);
+        }
+
+        candidates.extend(self.registered_fns.borrow().iter().map(|&(n, _)| n));
+
+        candidates.into_iter()
+            .filter(|&n| n != name && !is_system_operator(n))
+            .filter_map(|n| {
+                let s = names.get(n);
+                let d = edit_distance(target, s);
+                if d <= max_distance { Some((d, s, n)) } else { None }
+            })
+            .min()
+            .map(|(_, _, n)| n)
+    }
+
+    /// Registers a system function which compiled code may call directly
+    /// using the `CallSys` and `CallSysArgs` instructions.
+    /// Returns the instruction operand which refers to the function.
+    ///
+    /// If a function is already registered with the same name,
+    /// it is replaced and retains its operand value.
+    ///
+    /// Scopes created using `new_using` share registered functions
+    /// with this scope.
+    pub fn register_system_fn(&self, name: Name, sys_fn: SystemFn) -> u32 {
+        let mut fns = self.registered_fns.borrow_mut();
+
+        let idx = match fns.iter().position(|&(n, _)| n == name) {
+            Some(idx) => {
+                fns[idx].1 = sys_fn;
+                idx
+            }
+            None => {
+                fns.push((name, sys_fn));
+                fns.len() - 1
+            }
+        };
+
+        REGISTERED_FNS_BEGIN + idx as u32
+    }
+
+    /// Returns the registered system function referred to by the given
+    /// `CallSys` operand.
+    pub fn get_registered_fn(&self, n: u32) -> Option<(Name, SystemFn)> {
+        if n < REGISTERED_FNS_BEGIN {
+            return None;
+        }
+
+        self.registered_fns.borrow()
+            .get((n - REGISTERED_FNS_BEGIN) as usize).cloned()
+    }
+
+    /// Returns the `CallSys` operand and implementation of the system function
+    /// registered with the given name.
+    pub fn find_registered_fn(&self, name: Name) -> Option<(u32, SystemFn)> {
+        self.registered_fns.borrow().iter().enumerate()
+            .find(|&(_, &(n, _))| n == name)
+            .map(|(idx, &(_, sys_fn))| (REGISTERED_FNS_BEGIN + idx as u32, sys_fn))
+    }
+
+    /// Sets whether the code objects of lambdas created within this scope
+    /// are tracked, to diagnose memory leaks.
+    /// Tracking holds only weak references to code objects.
+    ///
+    /// Scopes created using `new_using` share tracked code with this scope.
+    pub fn set_track_code(&self, track: bool) {
+        let mut tracker = self.code_tracker.borrow_mut();
+
+        if track {
+            if tracker.is_none() {
+                *tracker = Some(HashMap::new());
+            }
+        } else {
+            *tracker = None;
+        }
+    }
+
+    /// Adds the code object of a newly created lambda to the set of
+    /// tracked code, if tracking is enabled and it is not already tracked.
+    pub fn track_code(&self, code: &Rc<Code>) {
+        if let Some(ref mut tracked) = *self.code_tracker.borrow_mut() {
+            // A tracked `Weak` keeps its allocation, so the address of
+            // a tracked code object is not reused while it is held.
+            let key = &**code as *const Code;
+
+            if !tracked.contains_key(&key) {
+                if tracked.len() == tracked.capacity() {
+                    tracked.retain(|_, c| c.upgrade().is_some());
+                }
+                tracked.insert(key, Rc::downgrade(code));
+            }
+        }
+    }
+
+    /// Returns statistics on the live code objects of tracked lambdas.
+    /// Returns `None` if tracking is not enabled.
+    pub fn live_code_stats(&self) -> Option<CodeStats> {
+        let tracker = self.code_tracker.borrow();
+        let tracked = match *tracker {
+            Some(ref tracked) => tracked,
+            None => return None
+        };
+
+        let live = tracked.values()
+            .filter_map(|c| c.upgrade()).collect::<Vec<_>>();
+
+        Some(CodeStats{
+            count: live.len(),
+            const_bytes: live.iter()
+                .map(|c| c.consts.len() * size_of::<Value>()).sum(),
+            tracked: tracked.len(),
+        })
+    }
+
+    /// Breaks reference cycles between this scope and the scopes of
+    /// loaded modules by removing all modules from the module registry.
+    ///
+    /// This should only be called when the scope will no longer be used.
+    /// Lambdas defined within modules may no longer be called afterward.
+    pub fn break_cycles(&self) {
+        self.modules.clear();
+    }
+
+    /// Declares a name as a dynamic variable.
+    ///
+    /// `var` is the name with which the variable was originally declared;
+    /// this may differ from `name` if the variable was imported under
+    /// another name.
+    pub fn add_dynamic(&self, name: Name, var: Name) {
+        self.namespace.borrow_mut().dynamic.insert(name, var);
+    }
+
+    /// If the given name refers to a dynamic variable, returns the name with
+    /// which the variable was declared.
+    pub fn get_dynamic_var(&self, name: Name) -> Option<Name> {
+        self.namespace.borrow().dynamic.get(name).cloned()
+            .or_else(|| self.parent.as_ref().and_then(|p| p.get_dynamic_var(name)))
+    }
+
+    /// Assigns the global value of a dynamic variable.
+    /// Bindings created by `bind_dynamic` are unaffected.
+    ///
+    /// The name is declared as a dynamic variable, if it is not already.
+    pub fn set_dynamic(&self, name: Name, value: Value) {
+        let var = match self.get_dynamic_var(name) {
+            Some(var) => var,
+            None => {
+                self.add_dynamic(name, name);
+                name
+            }
+        };
+
+        self.add_value(name, value.clone());
+        self.dynamic.borrow_mut().values.insert(var, value);
+    }
+
+    /// Returns the active value of a dynamic variable, given the name
+    /// with which it was declared.
+    pub fn get_dynamic(&self, var: Name) -> Option<Value> {
+        let dynamic = self.dynamic.borrow();
+
+        dynamic.bindings.iter().rev()
+            .find(|&&(n, _)| n == var).map(|&(_, ref v)| v.clone())
+            .or_else(|| dynamic.values.get(var).cloned())
+            .or_else(|| self.parent.as_ref().and_then(|p| p.get_dynamic(var)))
+    }
+
+    /// Binds a new value to a dynamic variable, given the name with which
+    /// it was declared. The binding remains active until it is removed.
+    pub fn bind_dynamic(&self, var: Name, value: Value) {
+        self.dynamic.borrow_mut().bindings.push((var, value));
+    }
+
+    /// Returns the number of active dynamic variable bindings.
+    pub fn dynamic_depth(&self) -> usize {
+        self.dynamic.borrow().bindings.len()
+    }
+
+    /// Removes dynamic variable bindings until only `depth` bindings remain.
+    pub fn unbind_dynamic(&self, depth: usize) {
+        self.dynamic.borrow_mut().bindings.truncate(depth);
+    }
+
+    /// Reports a macro expansion to the trace function, if one is set.
+    pub fn trace_macro(&self, name: Name, size: usize) {
+        let trace = self.macro_trace.borrow().clone();
+
+        if let Some(trace) = trace {
+            trace(name, size);
+        }
+    }
+
+    /// Returns the nesting depth of calls to functions traced using
+    /// the `trace` function of the `code` module.
+    ///
+    /// Scopes created using `new_using` share this value with this scope.
+    pub fn trace_depth(&self) -> u32 {
+        self.trace_depth.get()
+    }
+
+    /// Sets the nesting depth of calls to traced functions.
+    pub fn set_trace_depth(&self, depth: u32) {
+        self.trace_depth.set(depth);
+    }
+
+    /// Returns the scope in which the innermost macro expansion is taking
+    /// place, if a macro is currently being expanded.
+    ///
+    /// A macro function executes within the scope in which it was defined,
+    /// which may differ from the scope of the code being compiled.
+    /// Scopes created using `new_using` share this value with this scope.
+    pub fn expansion_scope(&self) -> Option<Scope> {
+        self.expanding.borrow().last().cloned()
+    }
+
+    /// Calls a closure while reporting the given scope as the scope
+    /// of macro expansion. See `expansion_scope`.
+    pub fn with_expansion_scope<F, R>(&self, scope: &Scope, f: F) -> R
+            where F: FnOnce() -> R {
+        self.expanding.borrow_mut().push(scope.clone());
+        let r = f();
+        self.expanding.borrow_mut().pop();
+        r
     }
 
     /// Adds a macro function to the global scope.
@@ -150,22 +894,26 @@ impl GlobalScope {
 
     /// Returns whether the scope contains a macro for the given name.
     pub fn contains_macro(&self, name: Name) -> bool {
-        self.namespace.borrow().macros.contains_key(name)
+        self.namespace.borrow().macros.contains_key(name) ||
+            self.parent.as_ref().map_or(false, |p| p.contains_macro(name))
     }
 
     /// Returns whether the scope contains a value for the given name.
     pub fn contains_value(&self, name: Name) -> bool {
-        self.namespace.borrow().values.contains_key(name)
+        self.namespace.borrow().values.contains_key(name) ||
+            self.parent.as_ref().map_or(false, |p| p.contains_value(name))
     }
 
     /// Returns a macro function for the given name, if present.
     pub fn get_macro(&self, name: Name) -> Option<Lambda> {
         self.namespace.borrow().macros.get(name).cloned()
+            .or_else(|| self.parent.as_ref().and_then(|p| p.get_macro(name)))
     }
 
     /// Returns a `Value` for the given name, if present.
     pub fn get_value(&self, name: Name) -> Option<Value> {
         self.namespace.borrow().values.get(name).cloned()
+            .or_else(|| self.parent.as_ref().and_then(|p| p.get_value(name)))
     }
 
     /// Clones all exported values from this scope into another scope.
@@ -180,10 +928,16 @@ impl GlobalScope {
             .import_all_values(&mut other.namespace.borrow_mut())
     }
 
-    /// Returns whether the given name has been exported in this scope.
+    /// Returns whether the given name has been exported in this scope,
+    /// either by `export` or as the new name given by `export-rename`.
     pub fn is_exported(&self, name: Name) -> bool {
-        self.namespace.borrow().exports.as_ref()
-            .map_or(false, |e| e.contains(name))
+        self.get_export(name).is_some()
+    }
+
+    /// If the given name has been exported in this scope, returns the name
+    /// under which the exported value or macro is defined within the scope.
+    pub fn get_export(&self, name: Name) -> Option<Name> {
+        self.namespace.borrow().get_export(name)
     }
 
     /// Assigns a set of exported names for this scope.
@@ -191,6 +945,65 @@ impl GlobalScope {
         self.namespace.borrow_mut().exports = Some(names);
     }
 
+    /// Assigns the set of exported names for this scope to the given names,
+    /// replacing any previous set of exported names.
+    ///
+    /// Names exported under another name are not affected.
+    pub fn export_selective(&self, names: &[&str]) {
+        let mut exports = NameSet::new();
+
+        for name in names {
+            exports.insert(self.add_name(name));
+        }
+
+        self.set_exports(exports.into_slice());
+    }
+
+    /// Removes a name from the exported names of this scope,
+    /// including names exported under another name.
+    pub fn unexport(&self, name: &str) {
+        let name = self.add_name(name);
+        let mut ns = self.namespace.borrow_mut();
+
+        if let Some(exports) = ns.exports.take() {
+            let mut exports = exports.into_name_set();
+            exports.remove(name);
+            ns.exports = Some(exports.into_slice());
+        }
+
+        if let Some(renames) = ns.export_renames.take() {
+            let mut renames = renames.into_name_map();
+            renames.remove(name);
+            ns.export_renames = Some(renames.into_slice());
+        }
+    }
+
+    /// Assigns a set of names exported under another name for this scope.
+    /// The map is keyed by exported name; values are internal names.
+    pub fn set_export_renames(&self, renames: NameMapSlice<Name>) {
+        self.namespace.borrow_mut().export_renames = Some(renames);
+    }
+
+    /// Records a set of values imported into this scope from a module.
+    pub fn add_imports(&self, imports: ImportSet) {
+        self.namespace.borrow_mut().imports.push(imports);
+    }
+
+    /// Returns the name of the module imported under the given alias,
+    /// using `(use module :as alias)`.
+    pub fn get_module_alias(&self, alias: Name) -> Option<Name> {
+        self.with_imports(|imports| imports.iter().rev()
+            .find(|imp| imp.alias == Some(alias))
+            .map(|imp| imp.module_name))
+    }
+
+    /// Calls a closure with the sets of values imported into this scope.
+    pub fn with_imports<F, R>(&self, f: F) -> R
+            where F: FnOnce(&[ImportSet]) -> R {
+        let ns = self.namespace.borrow();
+        f(&ns.imports)
+    }
+
     /// Calls a closure with the borrowed string representation of a name.
     pub fn with_name<F, R>(&self, name: Name, f: F) -> R
             where F: FnOnce(&str) -> R {
@@ -205,6 +1018,19 @@ impl GlobalScope {
         f(ns.exports.as_ref())
     }
 
+    /// Returns all exported names, including names exported under another
+    /// name, paired with the corresponding internal names.
+    pub fn get_exported_names(&self) -> Vec<(Name, Name)> {
+        self.namespace.borrow().exported_names()
+    }
+
+    /// Calls a closure with the map of names exported under another name.
+    pub fn with_export_renames<F, R>(&self, f: F) -> R
+            where F: FnOnce(Option<&NameMapSlice<Name>>) -> R {
+        let ns = self.namespace.borrow();
+        f(ns.export_renames.as_ref())
+    }
+
     /// Calls a closure with the set of defined macros.
     pub fn with_macros<F, R>(&self, f: F) -> R
             where F: FnOnce(&NameMap<Lambda>) -> R {
@@ -225,25 +1051,45 @@ impl Namespace {
         Namespace{
             macros: NameMap::new(),
             values: NameMap::new(),
+            dynamic: NameMap::new(),
             exports: None,
+            export_renames: None,
+            imports: Vec::new(),
         }
     }
 
+    /// Returns the internal name of an exported name, if it is exported.
+    fn get_export(&self, name: Name) -> Option<Name> {
+        if self.exports.as_ref().map_or(false, |e| e.contains(name)) {
+            Some(name)
+        } else {
+            self.export_renames.as_ref().and_then(|r| r.get(name).cloned())
+        }
+    }
+
+    /// Returns all exported names, paired with the corresponding internal names.
+    fn exported_names(&self) -> Vec<(Name, Name)> {
+        let exports = self.exports.iter()
+            .flat_map(|e| e.iter()).map(|name| (name, name));
+        let renames = self.export_renames.iter()
+            .flat_map(|r| r.iter()).cloned();
+
+        exports.chain(renames).collect()
+    }
+
     fn import_all_macros(&self, other: &mut Namespace) {
-        if let Some(ref exports) = self.exports {
-            for name in exports {
-                self.macros.get(name).cloned()
-                    .map(|m| other.macros.insert(name, m));
-            }
+        for (name, src) in self.exported_names() {
+            self.macros.get(src).cloned()
+                .map(|m| other.macros.insert(name, m));
         }
     }
 
     fn import_all_values(&self, other: &mut Namespace) {
-        if let Some(ref exports) = self.exports {
-            for name in exports {
-                self.values.get(name).cloned()
-                    .map(|v| other.values.insert(name, v));
-            }
+        for (name, src) in self.exported_names() {
+            self.values.get(src).cloned()
+                .map(|v| other.values.insert(name, v));
+            self.dynamic.get(src).cloned()
+                .map(|var| other.dynamic.insert(name, var));
         }
     }
 }
@@ -379,3 +1225,23 @@ impl ExactSizeIterator for MasterValues {
         }
     }
 }
+
+/// Returns the number of single-character insertions, deletions,
+/// and substitutions required to change one string into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..b.len() + 1).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+
+        for (j, &cb) in b.iter().enumerate() {
+            let cost = if ca == cb { prev } else { prev + 1 };
+            prev = row[j + 1];
+            row[j + 1] = cost.min(prev + 1).min(row[j] + 1);
+        }
+    }
+
+    row[b.len()]
+}
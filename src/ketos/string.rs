@@ -1,10 +1,24 @@
-//! Parses string tokens from input.
+//! Parses string tokens from input, escapes strings for output,
+//! and folds case for case-insensitive comparison.
 
 use std::str::CharIndices;
 
 use lexer::{BytePos, Span};
 use parser::{ParseError, ParseErrorKind};
 
+/// Names of characters which may be written as `#\name`
+pub const CHAR_NAMES: &'static [(&'static str, char)] = &[
+    ("alarm", '\x07'),
+    ("backspace", '\x08'),
+    ("delete", '\x7f'),
+    ("escape", '\x1b'),
+    ("newline", '\n'),
+    ("null", '\0'),
+    ("return", '\r'),
+    ("space", ' '),
+    ("tab", '\t'),
+];
+
 /// Parses a character constant
 pub fn parse_char(s: &str, pos: BytePos) -> Result<(char, usize), ParseError> {
     let mut r = StringReader::new(s, pos, StringType::Char);
@@ -23,14 +37,101 @@ pub fn parse_raw_string(s: &str, pos: BytePos) -> Result<(String, usize), ParseE
     r.parse_string()
 }
 
+/// Processes escape sequences in a string, as they would be processed
+/// within a string literal. The string is not surrounded by quotes.
+pub fn unescape_string(s: &str) -> Result<String, ParseError> {
+    let mut r = StringReader::new(s, 0, StringType::Unquoted);
+    r.parse_unquoted()
+}
+
+/// Escapes a string so that it may be written within a string literal.
+/// The result is not surrounded by quotes.
+///
+/// The result of `unescape_string` on the escaped string is the original string.
+pub fn escape_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+
+    for ch in s.chars() {
+        match ch {
+            '\\' => res.push_str("\\\\"),
+            '"' => res.push_str("\\\""),
+            '\0' => res.push_str("\\0"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            '\'' => res.push(ch),
+            _ if ch.escape_debug().count() == 1 => res.push(ch),
+            _ => res.push_str(&format!("\\u{{{:x}}}", ch as u32))
+        }
+    }
+
+    res
+}
+
+/// Folds the case of a character for case-insensitive comparison.
+///
+/// Folding follows Unicode simple case folding: each character maps to
+/// exactly one character, so `ß` folds to itself rather than to `ss`.
+/// No locale-specific mappings are applied; Turkish dotless `ı` and
+/// dotted `İ` fold only to themselves.
+pub fn fold_char(ch: char) -> char {
+    if ch == '\u{131}' {
+        return ch;
+    }
+
+    let upper = single_char(ch.to_uppercase()).unwrap_or(ch);
+    single_char(upper.to_lowercase()).unwrap_or(upper)
+}
+
+fn single_char<I: Iterator<Item=char>>(mut iter: I) -> Option<char> {
+    match (iter.next(), iter.next()) {
+        (Some(ch), None) => Some(ch),
+        _ => None
+    }
+}
+
+/// Returns whether two strings are equal when case is folded.
+pub fn eq_folded(a: &str, b: &str) -> bool {
+    a.chars().map(fold_char).eq(b.chars().map(fold_char))
+}
+
+/// Returns whether `s` begins with `prefix` when case is folded.
+pub fn starts_with_folded(s: &str, prefix: &str) -> bool {
+    let mut chars = s.chars().map(fold_char);
+    prefix.chars().map(fold_char).all(|ch| chars.next() == Some(ch))
+}
+
+/// Finds the first occurrence of `pat` within `s`, beginning at byte offset
+/// `start`, when case is folded. Returns the byte range of the match in `s`.
+///
+/// Because each character folds to exactly one character, the matched range
+/// contains as many characters as `pat`, though not necessarily as many bytes.
+pub fn find_folded(s: &str, pat: &str, start: usize) -> Option<(usize, usize)> {
+    let pat = pat.chars().map(fold_char).collect::<Vec<_>>();
+    let text = &s[start..];
+
+    for (i, _) in text.char_indices().chain(Some((text.len(), ' '))) {
+        let mut chars = text[i..].char_indices();
+
+        if pat.iter().all(|&p| chars.next().map(|(_, ch)| fold_char(ch)) == Some(p)) {
+            let end = chars.next().map_or(text.len(), |(j, _)| i + j);
+            return Some((start + i, start + end));
+        }
+    }
+
+    None
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum StringType {
     Char,
     Normal,
     Raw,
+    Unquoted,
 }
 
 struct StringReader<'a> {
+    input: &'a str,
     chars: CharIndices<'a>,
     start: BytePos,
     last_index: usize,
@@ -41,6 +142,7 @@ struct StringReader<'a> {
 impl<'a> StringReader<'a> {
     fn new(input: &str, pos: BytePos, ty: StringType) -> StringReader {
         StringReader{
+            input: input,
             chars: input.char_indices(),
             start: pos,
             last_index: 0,
@@ -52,6 +154,13 @@ impl<'a> StringReader<'a> {
     fn parse_char(&mut self) -> Result<(char, usize), ParseError> {
         try!(self.expect('#', |slf, ch| ParseError::new(slf.span_one(),
             ParseErrorKind::InvalidChar(ch))));
+
+        if try!(self.peek_char()) == '\\' {
+            try!(self.consume_char());
+            let ch = try!(self.parse_named_char());
+            return Ok((ch, self.end_index));
+        }
+
         try!(self.expect('\'', |slf, ch| ParseError::new(slf.span_one(),
             ParseErrorKind::InvalidChar(ch))));
 
@@ -69,6 +178,40 @@ impl<'a> StringReader<'a> {
         Ok((ch, self.last_index + 1))
     }
 
+    /// Parses the remainder of a `#\` character literal: a single character,
+    /// a character name such as `space`, or a hexadecimal code such as `x7f`.
+    fn parse_named_char(&mut self) -> Result<char, ParseError> {
+        let first = try!(self.consume_char());
+        let start = self.last_index;
+
+        loop {
+            match self.chars.clone().next() {
+                Some((_, ch)) if ch.is_alphanumeric() => { try!(self.consume_char()); }
+                _ => break
+            }
+        }
+
+        if self.end_index == start + first.len_utf8() {
+            return Ok(first);
+        }
+
+        let name = &self.input[start..self.end_index];
+        let span = self.span_from(self.start + start as BytePos, name.len() as BytePos);
+
+        if let Some(&(_, ch)) = CHAR_NAMES.iter().find(|&&(n, _)| n == name) {
+            return Ok(ch);
+        }
+
+        if first == 'x' && name[1..].chars().all(|ch| ch.is_digit(16)) {
+            u32::from_str_radix(&name[1..], 16).ok()
+                .and_then(::std::char::from_u32)
+                .ok_or_else(|| ParseError::new(span,
+                    ParseErrorKind::InvalidNumericEscape('x')))
+        } else {
+            Err(ParseError::new(span, ParseErrorKind::UnknownCharName))
+        }
+    }
+
     fn parse_string(&mut self) -> Result<(String, usize), ParseError> {
         let mut res = String::new();
 
@@ -112,6 +255,23 @@ impl<'a> StringReader<'a> {
         Ok((res, self.last_index + 1))
     }
 
+    fn parse_unquoted(&mut self) -> Result<String, ParseError> {
+        let mut res = String::with_capacity(self.input.len());
+
+        while self.chars.clone().next().is_some() {
+            match try!(self.consume_char()) {
+                '\\' => {
+                    if let Some(ch) = try!(self.parse_string_escape()) {
+                        res.push(ch);
+                    }
+                }
+                ch => res.push(ch)
+            }
+        }
+
+        Ok(res)
+    }
+
     fn check_end(&mut self, n_hash: usize) -> Result<bool, ParseError> {
         let save_chars = self.chars.clone();
         let save_index = self.last_index;
@@ -279,7 +439,7 @@ impl<'a> StringReader<'a> {
 #[cfg(test)]
 mod test {
     use parser::ParseError;
-    use super::{StringReader, StringType};
+    use super::{escape_string, unescape_string, StringReader, StringType};
 
     fn parse_char(s: &str) -> Result<char, ParseError> {
         let mut r = StringReader::new(s, 0, StringType::Char);
@@ -305,4 +465,22 @@ mod test {
         assert_eq!(parse_string(r#"r"foo""#, r).unwrap(), "foo");
         assert_eq!(parse_string(r##"r#""foo""#"##, r).unwrap(), r#""foo""#);
     }
+
+    #[test]
+    fn test_escape_string() {
+        assert_eq!(escape_string("foo"), "foo");
+        assert_eq!(escape_string("a\"b\\c'd"), r#"a\"b\\c'd"#);
+        assert_eq!(escape_string("\0\n\r\t"), r"\0\n\r\t");
+        assert_eq!(escape_string("\x1b\u{e9}"), "\\u{1b}\u{e9}");
+
+        assert_eq!(unescape_string(r#"a\"b\\c\'d"#).unwrap(), "a\"b\\c'd");
+        assert_eq!(unescape_string(r"\x41\u{e9}\
+                                   b").unwrap(), "A\u{e9}b");
+        assert!(unescape_string(r"\q").is_err());
+        assert!(unescape_string(r"a\").is_err());
+
+        for s in &["", "foo", "\"\\\0\n\r\t'", "\x07\x7f\u{e9}\u{1f600}"] {
+            assert_eq!(unescape_string(&escape_string(s)).unwrap(), *s);
+        }
+    }
 }
@@ -3,6 +3,7 @@
 use std::ascii::AsciiExt;
 use std::borrow::Cow::{self, Borrowed, Owned};
 use std::cmp::max;
+use std::error::Error as StdError;
 use std::f64;
 use std::fmt::{self, Write};
 use std::iter::repeat;
@@ -79,6 +80,8 @@ impl FormatError {
     }
 }
 
+impl StdError for FormatError {}
+
 impl fmt::Display for FormatError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
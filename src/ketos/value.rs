@@ -1,18 +1,29 @@
 //! Represents any possible value type.
+//!
+//! # Truthiness
+//!
+//! Where a boolean condition is expected, e.g. in `if`, `and`, `or`, `not`,
+//! and `cond`, any value may be given. The value `false` is considered false;
+//! all other values, including `()`, `0`, and `""`, are considered true.
+//! Note that, unlike `nil` in some Lisp dialects, `()` is true.
+//!
+//! `Value::is_truthy` implements these semantics.
 
 use std::any::{Any, TypeId};
 use std::cmp::Ordering;
 use std::fmt::{self, Write};
-use std::mem::{replace, transmute};
+use std::hash::{Hash, Hasher};
+use std::mem::{discriminant, replace, transmute};
 use std::rc::Rc;
 
 use error::Error;
 use exec::ExecError;
-use function::{Function, Lambda};
+use function::{Function, Lambda, Overload};
 use integer::{Integer, Ratio};
 use name::{Name, NameDebug, NameDisplay, NameMapSlice, NameStore};
 use rc_vec::RcVec;
 use scope::Scope;
+use string::{escape_string, CHAR_NAMES};
 
 /// Represents a value.
 #[derive(Clone, Debug)]
@@ -53,10 +64,14 @@ pub enum Value {
     /// Series of one or more values.
     /// **MUST NEVER be of length zero.** Use `Unit` to represent empty lists.
     List(RcVec<Value>),
+    /// Series of values supporting constant-time indexing; may be empty.
+    Vector(RcVec<Value>),
     /// Function implemented in Rust
     Function(Function),
     /// Compiled bytecode function
     Lambda(Lambda),
+    /// Set of compiled bytecode functions, selected by number of arguments
+    Overload(Rc<Overload>),
     /// Boxed value of a foreign type
     Foreign(Rc<ForeignValue>),
 }
@@ -67,6 +82,11 @@ impl Value {
         Value::Foreign(Rc::new(t))
     }
 
+    /// Returns a vector value containing the given values.
+    pub fn new_vector<T: Into<Value>>(v: Vec<T>) -> Value {
+        Value::Vector(RcVec::new(v.into_iter().map(|v| v.into()).collect()))
+    }
+
     /// Returns a value containing a foreign function.
     pub fn new_foreign_fn<F>(name: Name, f: F) -> Value
             where F: Any + Fn(&Scope, &mut [Value]) -> Result<Value, Error> {
@@ -95,16 +115,15 @@ impl Value {
             (&Value::List(_), &Value::Unit) => Ordering::Greater,
             (&Value::List(ref a), &Value::List(ref b)) =>
                 try!(cmp_value_slice(a, b)),
+            (&Value::Vector(ref a), &Value::Vector(ref b)) =>
+                try!(cmp_value_slice(a, b)),
             (&Value::Struct(ref a), &Value::Struct(ref b)) => {
                 if a.def == b.def {
                     try!(cmp_value_iter(
                         a.fields.iter().map(|&(_, ref v)| v).zip(
                             b.fields.iter().map(|&(_, ref v)| v))))
                 } else {
-                    return Err(ExecError::StructMismatch{
-                        lhs: a.def.name,
-                        rhs: b.def.name,
-                    });
+                    return Err(struct_mismatch(&a.def, &b.def));
                 }
             }
 
@@ -139,7 +158,8 @@ impl Value {
                 return Err(ExecError::CannotCompare("struct-def")),
             (&Value::Function(_), &Value::Function(_)) =>
                 return Err(ExecError::CannotCompare("function")),
-            (&Value::Lambda(_), &Value::Lambda(_)) =>
+            (&Value::Lambda(_), &Value::Lambda(_)) |
+            (&Value::Overload(_), &Value::Overload(_)) =>
                 return Err(ExecError::CannotCompare("lambda")),
             (&Value::Quote(_, _), &Value::Quote(_, _)) =>
                 return Err(ExecError::CannotCompare("quote")),
@@ -167,104 +187,330 @@ impl Value {
     /// Tests two values for equality; returns an error if the values cannot be
     /// compared.
     pub fn is_equal(&self, rhs: &Value) -> Result<bool, ExecError> {
-        let eq = match (self, rhs) {
-            (&Value::Unit, &Value::Unit) => true,
-            (&Value::Bool(a), &Value::Bool(b)) => a == b,
-            (&Value::Float(a), &Value::Float(b)) => a == b,
-            (&Value::Integer(ref a), &Value::Integer(ref b)) => a == b,
-            (&Value::Ratio(ref a), &Value::Ratio(ref b)) => a == b,
-
-            (&Value::Float(a), &Value::Integer(ref b)) => {
-                let b = try!(b.to_f64().ok_or(ExecError::Overflow));
-                a == b
-            }
-            (&Value::Float(a), &Value::Ratio(ref b)) => {
-                let b = try!(b.to_f64().ok_or(ExecError::Overflow));
-                a == b
-            }
-            (&Value::Integer(ref a), &Value::Float(b)) => {
-                let a = try!(a.to_f64().ok_or(ExecError::Overflow));
-                a == b
-            }
-            (&Value::Ratio(ref a), &Value::Float(b)) => {
-                let a = try!(a.to_f64().ok_or(ExecError::Overflow));
-                a == b
+        // Nested values are compared using an explicit stack so that
+        // deeply nested lists do not overflow the call stack.
+        let mut stack = vec![(self, rhs)];
+
+        while let Some((a, b)) = stack.pop() {
+            if !try!(eq_shallow(a, b, &mut stack)) {
+                return Ok(false);
             }
+        }
 
-            (&Value::Integer(ref a), &Value::Ratio(ref b)) => a == b,
-            (&Value::Ratio(ref a), &Value::Integer(ref b)) => a == b,
+        Ok(true)
+    }
 
-            (&Value::Name(a), &Value::Name(b)) => a == b,
-            (&Value::Char(a), &Value::Char(b)) => a == b,
-            (&Value::String(ref a), &Value::String(ref b)) => a == b,
-            (&Value::Quote(ref a, na), &Value::Quote(ref b, nb)) =>
-                na == nb && try!(a.is_equal(&b)),
-            (&Value::Unit, &Value::List(_)) => false,
-            (&Value::List(_), &Value::Unit) => false,
-            (&Value::List(ref a), &Value::List(ref b)) =>
-                try!(eq_value_slice(a, b)),
-            (&Value::Struct(ref a), &Value::Struct(ref b)) => {
-                if a.def == b.def {
-                    try!(eq_value_iter(a.fields.iter().zip(b.fields.iter())
-                        .map(|(&(_, ref a), &(_, ref b))| (a, b))))
-                } else {
-                    return Err(ExecError::StructMismatch{
-                        lhs: a.def.name,
-                        rhs: b.def.name,
-                    });
-                }
-            }
-            (&Value::StructDef(ref a), &Value::StructDef(ref b)) => a == b,
-            (&Value::Function(ref a), &Value::Function(ref b)) => a == b,
-            (&Value::Lambda(ref a), &Value::Lambda(ref b)) => a == b,
+    /// Returns whether this value is considered true in a boolean context,
+    /// such as the condition of an `if` expression.
+    ///
+    /// Only `false` is considered false; all other values are considered true.
+    pub fn is_truthy(&self) -> bool {
+        match *self {
+            Value::Bool(false) => false,
+            _ => true
+        }
+    }
 
-            (&Value::Foreign(ref a), ref b) => try!(a.is_equal_to_value(b)),
-            (ref a, &Value::Foreign(ref b)) => try!(b.is_equal_to_value(a)),
+    /// Returns the value as an `i32`, if it is an integer within range.
+    pub fn to_i32(&self) -> Option<i32> {
+        match *self {
+            Value::Integer(ref i) => i.to_i32(),
+            _ => None
+        }
+    }
 
-            (a, b) => return Err(ExecError::TypeMismatch{
-                lhs: a.type_name(),
-                rhs: b.type_name(),
-            })
-        };
+    /// Returns the value as an `i64`, if it is an integer within range.
+    pub fn to_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Integer(ref i) => i.to_i64(),
+            _ => None
+        }
+    }
+
+    /// Returns the value as a `u32`, if it is an integer within range.
+    pub fn to_u32(&self) -> Option<u32> {
+        match *self {
+            Value::Integer(ref i) => i.to_u32(),
+            _ => None
+        }
+    }
+
+    /// Returns the value as a `u64`, if it is an integer within range.
+    pub fn to_u64(&self) -> Option<u64> {
+        match *self {
+            Value::Integer(ref i) => i.to_u64(),
+            _ => None
+        }
+    }
+
+    /// Returns the value as a `usize`, if it is an integer within range.
+    pub fn to_usize(&self) -> Option<usize> {
+        match *self {
+            Value::Integer(ref i) => i.to_usize(),
+            _ => None
+        }
+    }
+
+    /// Returns the number of nodes in the value tree.
+    ///
+    /// Each value counts as one node. Lists, vectors, structs, and quoted values
+    /// additionally count the nodes of each value they contain.
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack = vec![self];
+
+        while let Some(v) = stack.pop() {
+            count += 1;
+
+            match *v {
+                Value::List(ref li) |
+                Value::Vector(ref li) => stack.extend(li.iter()),
+                Value::Struct(ref s) =>
+                    stack.extend(s.fields.iter().map(|&(_, ref v)| v)),
+                Value::Quasiquote(ref v, _) |
+                Value::Comma(ref v, _) |
+                Value::CommaAt(ref v, _) |
+                Value::Quote(ref v, _) => stack.push(v),
+                _ => ()
+            }
+        }
 
-        Ok(eq)
+        count
     }
 
     /// Returns whether this value is identical to another.
     /// The notable difference between this and `eq` is that float `NaN` values
     /// will compare equal.
+    ///
+    /// Numbers, keywords, and names are compared by value. Integer
+    /// constructors may return copies of a shared preallocated value,
+    /// but this is not observable: equal integers are always identical.
     pub fn is_identical(&self, rhs: &Value) -> bool {
-        match (self, rhs) {
-            (&Value::Unit, &Value::Unit) => true,
-            (&Value::Unbound, &Value::Unbound) => true,
-            (&Value::Bool(a), &Value::Bool(b)) => a == b,
-            (&Value::Float(a), &Value::Float(b)) => float_is_identical(a, b),
-            (&Value::Integer(ref a), &Value::Integer(ref b)) => a == b,
-            (&Value::Ratio(ref a), &Value::Ratio(ref b)) => a == b,
-            (&Value::Struct(ref a), &Value::Struct(ref b)) =>
-                a.def == b.def &&
-                    a.fields.iter().zip(b.fields.iter())
-                        .all(|(&(_, ref a), &(_, ref b))| a.is_identical(b)),
-            (&Value::Name(a), &Value::Name(b)) => a == b,
-            (&Value::Keyword(a), &Value::Keyword(b)) => a == b,
-            (&Value::Char(a), &Value::Char(b)) => a == b,
-            (&Value::String(ref a), &Value::String(ref b)) => a == b,
-            (&Value::Quasiquote(ref a, na), &Value::Quasiquote(ref b, nb)) =>
-                na == nb && a.is_identical(b),
-            (&Value::Comma(ref a, na), &Value::Comma(ref b, nb)) =>
-                na == nb && a.is_identical(b),
-            (&Value::Quote(ref a, na), &Value::Quote(ref b, nb)) =>
-                na == nb && a.is_identical(b),
-            (&Value::List(ref a), &Value::List(ref b)) =>
-                list_is_identical(a, b),
-            (&Value::Function(ref a), &Value::Function(ref b)) => a == b,
-            (&Value::Lambda(ref a), &Value::Lambda(ref b)) => a == b,
+        let mut stack = vec![(self, rhs)];
 
-            (&Value::Foreign(ref a), &Value::Foreign(ref b)) =>
-                a.is_identical_to(&**b),
+        while let Some((a, b)) = stack.pop() {
+            if !identical_shallow(a, b, &mut stack) {
+                return false;
+            }
+        }
+
+        true
+    }
 
-            _ => false
+    /// Feeds the value into the given `Hasher`, consistently with
+    /// `is_identical`: identical values produce identical hashes.
+    ///
+    /// Returns `false` if the value is or contains a function, lambda,
+    /// or foreign value, none of which can be hashed.
+    pub fn hash_identical<H: Hasher>(&self, state: &mut H) -> bool {
+        let mut stack = vec![self];
+
+        while let Some(v) = stack.pop() {
+            discriminant(v).hash(state);
+
+            match *v {
+                Value::Unit | Value::Unbound => (),
+                Value::Bool(b) => b.hash(state),
+                Value::Float(f) => {
+                    // Signed zeroes are identical, as are all NaN values
+                    if f.is_nan() {
+                        0u64.hash(state);
+                    } else if f == 0.0 {
+                        1u64.hash(state);
+                    } else {
+                        f.to_bits().hash(state);
+                    }
+                }
+                Value::Integer(ref i) => i.hash(state),
+                Value::Ratio(ref r) => r.hash(state),
+                Value::Struct(ref s) => {
+                    (&*s.def as *const StructDef as usize).hash(state);
+                    stack.extend(s.fields.iter().rev().map(|&(_, ref v)| v));
+                }
+                Value::StructDef(ref def) =>
+                    (&**def as *const StructDef as usize).hash(state),
+                Value::Name(name) | Value::Keyword(name) => name.hash(state),
+                Value::Char(c) => c.hash(state),
+                Value::String(ref s) => s.hash(state),
+                Value::Quasiquote(ref v, n) |
+                Value::Comma(ref v, n) |
+                Value::CommaAt(ref v, n) |
+                Value::Quote(ref v, n) => {
+                    n.hash(state);
+                    stack.push(v);
+                }
+                Value::List(ref li) |
+                Value::Vector(ref li) => {
+                    li.len().hash(state);
+                    stack.extend(li.iter().rev());
+                }
+                Value::Function(_) |
+                Value::Lambda(_) |
+                Value::Overload(_) |
+                Value::Foreign(_) => return false
+            }
         }
+
+        true
+    }
+
+    /// Returns a structural hash of the value, consistent with `is_equal`:
+    /// values which compare equal produce equal hashes.
+    ///
+    /// Names and keywords are hashed by their string content, rather than
+    /// by `Name`, so the result is the same in any interpreter
+    /// and across program runs.
+    ///
+    /// Integers, ratios, and floats compare equal to one another when they
+    /// represent the same number, so all numbers are hashed by their value
+    /// as an `f64`. As a result, `1`, `1.0`, and `2/2` hash alike, as do
+    /// distinct integers too large to be represented exactly as floats.
+    /// Structs are hashed by the name of their definition and the values of
+    /// their fields.
+    ///
+    /// Returns `None` if the value is or contains a function, lambda,
+    /// struct definition, or foreign value, none of which can be hashed.
+    pub fn structural_hash(&self, names: &NameStore) -> Option<u64> {
+        let mut state = StableHasher::new();
+        let mut stack = vec![self];
+
+        while let Some(v) = stack.pop() {
+            match *v {
+                Value::Unit => state.write_u8(0),
+                Value::Unbound => state.write_u8(1),
+                Value::Bool(b) => {
+                    state.write_u8(2);
+                    state.write_u8(b as u8);
+                }
+                Value::Float(f) => hash_number(Some(f), || None, &mut state),
+                Value::Integer(ref i) => hash_number(i.to_f64(),
+                    || Some(Ratio::from_integer(i.clone())), &mut state),
+                Value::Ratio(ref r) => hash_number(r.to_f64(),
+                    || Some(r.clone()), &mut state),
+                Value::Struct(ref s) => {
+                    state.write_u8(4);
+                    state.write(names.get(s.def.name).as_bytes());
+                    state.write_u64(s.fields.len() as u64);
+                    stack.extend(s.fields.iter().rev().map(|&(_, ref v)| v));
+                }
+                Value::Name(name) => {
+                    state.write_u8(5);
+                    state.write(names.get(name).as_bytes());
+                    state.write_u8(0xff);
+                }
+                Value::Keyword(name) => {
+                    state.write_u8(6);
+                    state.write(names.get(name).as_bytes());
+                    state.write_u8(0xff);
+                }
+                Value::Char(c) => {
+                    state.write_u8(7);
+                    state.write_u32(c as u32);
+                }
+                Value::String(ref s) => {
+                    state.write_u8(8);
+                    state.write(s.as_bytes());
+                    state.write_u8(0xff);
+                }
+                Value::Quasiquote(ref inner, n) |
+                Value::Comma(ref inner, n) |
+                Value::CommaAt(ref inner, n) |
+                Value::Quote(ref inner, n) => {
+                    state.write_u8(match *v {
+                        Value::Quasiquote(..) => 9,
+                        Value::Comma(..) => 10,
+                        Value::CommaAt(..) => 11,
+                        _ => 12
+                    });
+                    state.write_u32(n);
+                    stack.push(inner);
+                }
+                Value::List(ref li) |
+                Value::Vector(ref li) => {
+                    state.write_u8(if let Value::List(_) = *v { 13 } else { 14 });
+                    state.write_u64(li.len() as u64);
+                    stack.extend(li.iter().rev());
+                }
+                Value::StructDef(_) |
+                Value::Function(_) |
+                Value::Lambda(_) |
+                Value::Overload(_) |
+                Value::Foreign(_) => return None
+            }
+        }
+
+        Some(state.finish())
+    }
+
+    /// Returns a copy of the value which shares no lists, vectors, or structs
+    /// with the original.
+    ///
+    /// Struct definitions, functions, lambdas, and foreign values are not
+    /// copied; the returned value refers to the same objects.
+    pub fn deep_clone(&self) -> Value {
+        enum Step<'a> {
+            Visit(&'a Value),
+            Build(&'a Value),
+        }
+
+        let mut steps = vec![Step::Visit(self)];
+        let mut done = Vec::new();
+
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(v) => match *v {
+                    Value::List(ref li) |
+                    Value::Vector(ref li) => {
+                        steps.push(Step::Build(v));
+                        steps.extend(li.iter().rev().map(Step::Visit));
+                    }
+                    Value::Struct(ref s) => {
+                        steps.push(Step::Build(v));
+                        steps.extend(s.fields.iter().rev()
+                            .map(|&(_, ref v)| Step::Visit(v)));
+                    }
+                    Value::Quasiquote(ref inner, _) |
+                    Value::Comma(ref inner, _) |
+                    Value::CommaAt(ref inner, _) |
+                    Value::Quote(ref inner, _) => {
+                        steps.push(Step::Build(v));
+                        steps.push(Step::Visit(inner));
+                    }
+                    _ => done.push(v.clone())
+                },
+                Step::Build(v) => {
+                    let built = match *v {
+                        Value::List(ref li) => {
+                            let start = done.len() - li.len();
+                            Value::List(RcVec::new(done.split_off(start)))
+                        }
+                        Value::Vector(ref li) => {
+                            let start = done.len() - li.len();
+                            Value::Vector(RcVec::new(done.split_off(start)))
+                        }
+                        Value::Struct(ref s) => {
+                            let start = done.len() - s.fields.len();
+                            let fields = s.fields.iter().map(|&(name, _)| name)
+                                .zip(done.split_off(start)).collect();
+                            Value::Struct(Rc::new(Struct::new(s.def.clone(), fields)))
+                        }
+                        Value::Quasiquote(_, n) =>
+                            Value::Quasiquote(Box::new(done.pop().unwrap()), n),
+                        Value::Comma(_, n) =>
+                            Value::Comma(Box::new(done.pop().unwrap()), n),
+                        Value::CommaAt(_, n) =>
+                            Value::CommaAt(Box::new(done.pop().unwrap()), n),
+                        Value::Quote(_, n) =>
+                            Value::Quote(Box::new(done.pop().unwrap()), n),
+                        _ => unreachable!()
+                    };
+
+                    done.push(built);
+                }
+            }
+        }
+
+        done.pop().unwrap()
     }
 
     /// Replaces the value with `Unit` and returns the old value.
@@ -324,10 +570,11 @@ impl Value {
             Value::CommaAt(_, _) |
             Value::Quote(_, _) => "object",
             Value::List(_) => "list",
+            Value::Vector(_) => "vector",
             Value::Struct(_) => "struct",
             Value::StructDef(_) => "struct-def",
             Value::Function(_) => "function",
-            Value::Lambda(_) => "lambda",
+            Value::Lambda(_) | Value::Overload(_) => "lambda",
             Value::Foreign(ref a) => a.type_name(),
         }
     }
@@ -535,100 +782,211 @@ macro_rules! ketos_fn {
 
 impl NameDebug for Value {
     fn fmt(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Value::Unit => write!(f, "()"),
-            Value::Unbound => write!(f, "<unbound>"),
-            Value::Bool(b) => write!(f, "{:?}", b),
-            Value::Float(fl) => write!(f, "{:?}", fl),
-            Value::Integer(ref i) => write!(f, "{}", i),
-            Value::Ratio(ref r) => write!(f, "{}", r),
-            Value::Char(ch) => write!(f, "#{:?}", ch),
-            Value::String(ref s) => write!(f, "{:?}", s),
-            Value::Name(name) => write!(f, "{}", names.get(name)),
-            Value::Keyword(name) => write!(f, ":{}", names.get(name)),
-            Value::Quasiquote(ref v, depth) => {
-                for _ in 0..depth { try!(write!(f, "`")); }
-                NameDebug::fmt(v, names, f)
+        fmt_value(self, names, None, f)
+    }
+}
+
+/// Displays a `Value` using `NameDebug` formatting, eliding the contents of
+/// lists, vectors, and structs nested more than `max_depth` levels deep.
+#[derive(Copy, Clone)]
+pub struct DepthDebugger<'a> {
+    value: &'a Value,
+    names: &'a NameStore,
+    max_depth: usize,
+}
+
+/// Returns a `DepthDebugger` wrapper around a value.
+///
+/// With a `max_depth` of `1`, `(1 (2 (3)))` is displayed as `(1 (...))`.
+pub fn debug_depth<'a>(names: &'a NameStore, value: &'a Value, max_depth: usize)
+        -> DepthDebugger<'a> {
+    DepthDebugger{
+        value: value,
+        names: names,
+        max_depth: max_depth,
+    }
+}
+
+impl<'a> fmt::Display for DepthDebugger<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_value(self.value, self.names, Some(self.max_depth), f)
+    }
+}
+
+/// Item remaining to be written by `fmt_value`
+enum FmtItem<'a> {
+    /// Value and its nesting depth
+    Value(&'a Value, usize),
+    /// Literal string
+    Str(&'static str),
+    /// Struct field name, preceded by a separator
+    Field(&'static str, Name),
+}
+
+/// Writes a value using `NameDebug` formatting.
+///
+/// Nested values are written using an explicit stack so that deeply nested
+/// lists do not overflow the call stack. If `max_depth` is given, the contents
+/// of lists, vectors, and structs nested more deeply are written as `...`.
+fn fmt_value(value: &Value, names: &NameStore, max_depth: Option<usize>,
+        f: &mut fmt::Formatter) -> fmt::Result {
+    let mut stack = vec![FmtItem::Value(value, 0)];
+
+    while let Some(item) = stack.pop() {
+        let (v, depth) = match item {
+            FmtItem::Value(v, depth) => (v, depth),
+            FmtItem::Str(s) => {
+                try!(f.write_str(s));
+                continue;
+            }
+            FmtItem::Field(sep, name) => {
+                try!(write!(f, "{}{}: ", sep, names.get(name)));
+                continue;
+            }
+        };
+
+        let elide = max_depth.map_or(false, |max| depth >= max);
+
+        match *v {
+            Value::Quasiquote(ref v, n) => {
+                for _ in 0..n { try!(write!(f, "`")); }
+                stack.push(FmtItem::Value(v, depth));
             }
-            Value::Comma(ref v, depth) => {
-                for _ in 0..depth { try!(write!(f, ",")); }
-                NameDebug::fmt(v, names, f)
+            Value::Comma(ref v, n) => {
+                for _ in 0..n { try!(write!(f, ",")); }
+                stack.push(FmtItem::Value(v, depth));
             }
-            Value::CommaAt(ref v, depth) => {
-                for _ in 0..depth { try!(write!(f, ",")); }
+            Value::CommaAt(ref v, n) => {
+                for _ in 0..n { try!(write!(f, ",")); }
                 try!(write!(f, "@"));
-                NameDebug::fmt(v, names, f)
+                stack.push(FmtItem::Value(v, depth));
             }
-            Value::Quote(ref v, depth) => {
-                for _ in 0..depth { try!(write!(f, "'")); }
-                NameDebug::fmt(v, names, f)
+            Value::Quote(ref v, n) => {
+                for _ in 0..n { try!(write!(f, "'")); }
+                stack.push(FmtItem::Value(v, depth));
             }
+            Value::List(_) if elide => try!(f.write_str("(...)")),
             Value::List(ref l) => {
                 try!(write!(f, "("));
-
-                let mut iter = l.iter();
-
-                if let Some(v) = iter.next() {
-                    try!(NameDebug::fmt(v, names, f));
-                }
-
-                for v in iter {
-                    try!(write!(f, " "));
-                    try!(NameDebug::fmt(v, names, f));
-                }
-
-                write!(f, ")")
+                stack.push(FmtItem::Str(")"));
+                push_value_seq(&mut stack, l, depth + 1);
+            }
+            Value::Vector(_) if elide => try!(f.write_str("#(...)")),
+            Value::Vector(ref v) => {
+                try!(write!(f, "#("));
+                stack.push(FmtItem::Str(")"));
+                push_value_seq(&mut stack, v, depth + 1);
             }
             // TODO: This output doesn't match the way structs are built.
             // Write out "(new 'name ...)"? Implement a shortcut syntax?
             Value::Struct(ref s) => {
                 if s.fields.is_empty() {
-                    write!(f, "{} {{}}", names.get(s.def.name))
+                    try!(write!(f, "{} {{}}", names.get(s.def.name)));
+                } else if elide {
+                    try!(write!(f, "{} {{ ... }}", names.get(s.def.name)));
                 } else {
                     try!(write!(f, "{} {{ ", names.get(s.def.name)));
+                    stack.push(FmtItem::Str(" }"));
 
-                    let mut iter = s.fields.iter();
-
-                    if let Some(&(name, ref value)) = iter.next() {
-                        try!(write!(f, "{}: ", names.get(name)));
-                        try!(NameDebug::fmt(value, names, f));
-                    }
-
-                    for &(name, ref value) in iter {
-                        try!(write!(f, ", {}: ", names.get(name)));
-                        try!(NameDebug::fmt(value, names, f));
+                    for (i, &(name, ref value)) in s.fields.iter().enumerate().rev() {
+                        stack.push(FmtItem::Value(value, depth + 1));
+                        stack.push(FmtItem::Field(if i == 0 { "" } else { ", " }, name));
                     }
-
-                    write!(f, " }}")
                 }
             }
-            Value::StructDef(ref d) => {
-                if d.fields.is_empty() {
-                    write!(f, "{} def {{}}", names.get(d.name))
-                } else {
-                    try!(write!(f, "{} def {{ ", names.get(d.name)));
+            ref v => try!(fmt_atom(v, names, f))
+        }
+    }
 
-                    let mut iter = d.fields.iter();
+    Ok(())
+}
 
-                    if let Some(&(name, ty)) = iter.next() {
-                        try!(write!(f, "{}: {}", names.get(name), names.get(ty)));
-                    }
+/// Pushes a sequence of values, separated by spaces, onto the `fmt_value`
+/// stack, in reverse order, so that they are written in order.
+fn push_value_seq<'a>(stack: &mut Vec<FmtItem<'a>>, values: &'a [Value], depth: usize) {
+    for (i, v) in values.iter().enumerate().rev() {
+        stack.push(FmtItem::Value(v, depth));
 
-                    for &(name, ty) in iter {
-                        try!(write!(f, ", {}: {}", names.get(name), names.get(ty)));
-                    }
+        if i != 0 {
+            stack.push(FmtItem::Str(" "));
+        }
+    }
+}
+
+/// Writes a value which contains no nested values.
+fn fmt_atom(v: &Value, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
+    match *v {
+        Value::Unit => write!(f, "()"),
+        Value::Unbound => write!(f, "<unbound>"),
+        Value::Bool(b) => write!(f, "{:?}", b),
+        Value::Float(fl) => write!(f, "{:?}", fl),
+        Value::Integer(ref i) => write!(f, "{}", i),
+        Value::Ratio(ref r) => write!(f, "{}", r),
+        Value::Char(ch) => f.write_str(&write_char(ch)),
+        Value::String(ref s) => write!(f, "\"{}\"", escape_string(s)),
+        Value::Name(name) => write!(f, "{}", names.get(name)),
+        Value::Keyword(name) => write!(f, ":{}", names.get(name)),
+        Value::StructDef(ref d) => {
+            if d.fields.is_empty() {
+                write!(f, "{} def {{}}", names.get(d.name))
+            } else {
+                try!(write!(f, "{} def {{ ", names.get(d.name)));
+
+                let mut iter = d.fields.iter();
+
+                if let Some(&(name, ty)) = iter.next() {
+                    try!(write!(f, "{}: {}", names.get(name), names.get(ty)));
+                }
 
-                    write!(f, " }}")
+                for &(name, ty) in iter {
+                    try!(write!(f, ", {}: {}", names.get(name), names.get(ty)));
+                }
+
+                write!(f, " }}")
+            }
+        }
+        Value::Function(ref fun) =>
+            write!(f, "<function {}>", names.get(fun.name)),
+        Value::Lambda(ref c) => match c.code.name {
+            Some(name) => write!(f, "<lambda {}>", names.get(name)),
+            None => write!(f, "<lambda>"),
+        },
+        Value::Overload(ref o) => {
+            try!(write!(f, "<lambda {} (", names.get(o.name)));
+
+            for (i, n) in o.arities().into_iter().enumerate() {
+                if i != 0 {
+                    try!(f.write_str(" "));
                 }
+                try!(write!(f, "{}", n));
             }
-            Value::Function(ref fun) =>
-                write!(f, "<function {}>", names.get(fun.name)),
-            Value::Lambda(ref c) => match c.code.name {
-                Some(name) => write!(f, "<lambda {}>", names.get(name)),
-                None => write!(f, "<lambda>"),
-            },
-            Value::Foreign(ref v) => v.fmt_debug(names, f),
+
+            f.write_str(")>")
         }
+        Value::Foreign(ref v) => v.fmt_debug(names, f),
+        _ => unreachable!()
+    }
+}
+
+/// Returns a character as displayed by `display`; i.e. the character itself.
+pub fn display_char(ch: char) -> String {
+    ch.to_string()
+}
+
+/// Returns a character as written by `write`, in `#\c` form.
+///
+/// Characters with a name, such as `#\space` or `#\newline`, are written
+/// using the name. Other non-printable characters are written using
+/// a hexadecimal code, such as `#\x1f`.
+pub fn write_char(ch: char) -> String {
+    if let Some(&(name, _)) = CHAR_NAMES.iter().find(|&&(_, c)| c == ch) {
+        return format!("#\\{}", name);
+    }
+
+    match ch {
+        '\\' | '\'' | '"' => format!("#\\{}", ch),
+        _ if ch.escape_debug().count() == 1 => format!("#\\{}", ch),
+        _ => format!("#\\x{:x}", ch as u32)
     }
 }
 
@@ -636,7 +994,7 @@ impl NameDisplay for Value {
     fn fmt(&self, names: &NameStore, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Value::Float(fl) => write!(f, "{}", fl),
-            Value::Char(ch) => write!(f, "{}", ch),
+            Value::Char(ch) => f.write_str(&display_char(ch)),
             Value::String(ref s) => write!(f, "{}", s),
             Value::Foreign(ref v) => v.fmt_display(names, f),
             ref v => NameDebug::fmt(v, names, f),
@@ -664,6 +1022,15 @@ fn cmp_value_iter<'a, I>(iter: I) -> Result<Ordering, ExecError>
     Ok(Ordering::Equal)
 }
 
+/// Returns the error for an operation on struct values of different types.
+fn struct_mismatch(a: &Rc<StructDef>, b: &Rc<StructDef>) -> ExecError {
+    if a.name == b.name {
+        ExecError::StructDefMismatch{lhs: a.clone(), rhs: b.clone()}
+    } else {
+        ExecError::StructMismatch{lhs: a.name, rhs: b.name}
+    }
+}
+
 fn cmp_value_slice(a: &[Value], b: &[Value]) -> Result<Ordering, ExecError> {
     for (a, b) in a.iter().zip(b) {
         match try!(a.compare(b)) {
@@ -675,29 +1042,184 @@ fn cmp_value_slice(a: &[Value], b: &[Value]) -> Result<Ordering, ExecError> {
     Ok(a.len().cmp(&b.len()))
 }
 
-fn eq_value_iter<'a, I>(iter: I) -> Result<bool, ExecError>
-        where I: IntoIterator<Item=(&'a Value, &'a Value)> {
-    for (a, b) in iter {
-        if !try!(a.is_equal(b)) {
-            return Ok(false);
+/// Tests two values for equality without comparing nested values.
+/// Pairs of nested values which must also be equal are pushed onto `stack`.
+fn eq_shallow<'a>(a: &'a Value, b: &'a Value, stack: &mut Vec<(&'a Value, &'a Value)>)
+        -> Result<bool, ExecError> {
+    let eq = match (a, b) {
+        (&Value::Unit, &Value::Unit) => true,
+        (&Value::Bool(a), &Value::Bool(b)) => a == b,
+        (&Value::Float(a), &Value::Float(b)) => a == b,
+        (&Value::Integer(ref a), &Value::Integer(ref b)) => a == b,
+        (&Value::Ratio(ref a), &Value::Ratio(ref b)) => a == b,
+
+        (&Value::Float(a), &Value::Integer(ref b)) => {
+            let b = try!(b.to_f64().ok_or(ExecError::Overflow));
+            a == b
+        }
+        (&Value::Float(a), &Value::Ratio(ref b)) => {
+            let b = try!(b.to_f64().ok_or(ExecError::Overflow));
+            a == b
+        }
+        (&Value::Integer(ref a), &Value::Float(b)) => {
+            let a = try!(a.to_f64().ok_or(ExecError::Overflow));
+            a == b
+        }
+        (&Value::Ratio(ref a), &Value::Float(b)) => {
+            let a = try!(a.to_f64().ok_or(ExecError::Overflow));
+            a == b
         }
+
+        (&Value::Integer(ref a), &Value::Ratio(ref b)) => a == b,
+        (&Value::Ratio(ref a), &Value::Integer(ref b)) => a == b,
+
+        (&Value::Name(a), &Value::Name(b)) => a == b,
+        (&Value::Keyword(a), &Value::Keyword(b)) => a == b,
+        (&Value::Char(a), &Value::Char(b)) => a == b,
+        (&Value::String(ref a), &Value::String(ref b)) => a == b,
+        (&Value::Quasiquote(ref a, na), &Value::Quasiquote(ref b, nb)) |
+        (&Value::Comma(ref a, na), &Value::Comma(ref b, nb)) |
+        (&Value::CommaAt(ref a, na), &Value::CommaAt(ref b, nb)) |
+        (&Value::Quote(ref a, na), &Value::Quote(ref b, nb)) => {
+            stack.push((a, b));
+            na == nb
+        }
+        // Quoted forms of different kinds are all of type `object`
+        (a, b) if is_quoted_form(a) && is_quoted_form(b) => false,
+        (&Value::Unit, &Value::List(_)) => false,
+        (&Value::List(_), &Value::Unit) => false,
+        (&Value::List(ref a), &Value::List(ref b)) |
+        (&Value::Vector(ref a), &Value::Vector(ref b)) =>
+            push_value_pairs(a, b, stack),
+        (&Value::Struct(ref a), &Value::Struct(ref b)) => {
+            if a.def == b.def {
+                stack.extend(a.fields.iter().zip(b.fields.iter()).rev()
+                    .map(|(&(_, ref a), &(_, ref b))| (a, b)));
+                true
+            } else {
+                return Err(struct_mismatch(&a.def, &b.def));
+            }
+        }
+        (&Value::StructDef(ref a), &Value::StructDef(ref b)) => a == b,
+        (&Value::Function(ref a), &Value::Function(ref b)) => a == b,
+        (&Value::Lambda(ref a), &Value::Lambda(ref b)) => a == b,
+        (&Value::Overload(ref a), &Value::Overload(ref b)) => a == b,
+
+        (&Value::Foreign(ref a), ref b) => try!(a.is_equal_to_value(b)),
+        (ref a, &Value::Foreign(ref b)) => try!(b.is_equal_to_value(a)),
+
+        (a, b) => return Err(ExecError::TypeMismatch{
+            lhs: a.type_name(),
+            rhs: b.type_name(),
+        })
+    };
+
+    Ok(eq)
+}
+
+/// Returns whether a value is a quoted, quasiquoted, or comma'd form.
+fn is_quoted_form(v: &Value) -> bool {
+    match *v {
+        Value::Quasiquote(..) | Value::Comma(..) |
+        Value::CommaAt(..) | Value::Quote(..) => true,
+        _ => false
     }
+}
 
-    Ok(true)
+/// Hashes a number by its value as an `f64`, so that numbers of different
+/// types hash alike when they compare equal. Numbers which cannot be
+/// represented as an `f64` are hashed as a `Ratio`.
+fn hash_number<F>(f: Option<f64>, ratio: F, state: &mut StableHasher)
+        where F: FnOnce() -> Option<Ratio> {
+    state.write_u8(3);
+
+    match f {
+        // Signed zeroes compare equal
+        Some(f) if f == 0.0 => state.write_u64(0),
+        Some(f) => state.write_u64(f.to_bits()),
+        None => {
+            state.write_u8(0xff);
+            if let Some(r) = ratio() {
+                r.hash(state);
+            }
+        }
+    }
 }
 
-fn eq_value_slice(a: &[Value], b: &[Value]) -> Result<bool, ExecError> {
-    if a.len() != b.len() {
-        return Ok(false);
+/// 64-bit FNV-1a hasher, whose output does not vary between program runs
+/// or Rust releases.
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> StableHasher {
+        StableHasher(0xcbf29ce484222325)
     }
+}
 
-    for (a, b) in a.iter().zip(b.iter()) {
-        if !try!(a.is_equal(b)) {
-            return Ok(false);
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
         }
     }
 
-    Ok(true)
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Tests whether two values are identical without comparing nested values.
+/// Pairs of nested values which must also be identical are pushed onto `stack`.
+fn identical_shallow<'a>(a: &'a Value, b: &'a Value,
+        stack: &mut Vec<(&'a Value, &'a Value)>) -> bool {
+    match (a, b) {
+        (&Value::Unit, &Value::Unit) => true,
+        (&Value::Unbound, &Value::Unbound) => true,
+        (&Value::Bool(a), &Value::Bool(b)) => a == b,
+        (&Value::Float(a), &Value::Float(b)) => float_is_identical(a, b),
+        (&Value::Integer(ref a), &Value::Integer(ref b)) => a == b,
+        (&Value::Ratio(ref a), &Value::Ratio(ref b)) => a == b,
+        (&Value::Struct(ref a), &Value::Struct(ref b)) => {
+            stack.extend(a.fields.iter().zip(b.fields.iter()).rev()
+                .map(|(&(_, ref a), &(_, ref b))| (a, b)));
+            a.def == b.def
+        }
+        (&Value::Name(a), &Value::Name(b)) => a == b,
+        (&Value::Keyword(a), &Value::Keyword(b)) => a == b,
+        (&Value::Char(a), &Value::Char(b)) => a == b,
+        (&Value::String(ref a), &Value::String(ref b)) => a == b,
+        (&Value::Quasiquote(ref a, na), &Value::Quasiquote(ref b, nb)) |
+        (&Value::Comma(ref a, na), &Value::Comma(ref b, nb)) |
+        (&Value::CommaAt(ref a, na), &Value::CommaAt(ref b, nb)) |
+        (&Value::Quote(ref a, na), &Value::Quote(ref b, nb)) => {
+            stack.push((a, b));
+            na == nb
+        }
+        (&Value::List(ref a), &Value::List(ref b)) |
+        (&Value::Vector(ref a), &Value::Vector(ref b)) =>
+            push_value_pairs(a, b, stack),
+        (&Value::Function(ref a), &Value::Function(ref b)) => a == b,
+        (&Value::Lambda(ref a), &Value::Lambda(ref b)) => a == b,
+        (&Value::Overload(ref a), &Value::Overload(ref b)) => a == b,
+
+        (&Value::Foreign(ref a), &Value::Foreign(ref b)) =>
+            a.is_identical_to(&**b),
+
+        _ => false
+    }
+}
+
+/// Pushes pairs of corresponding elements onto `stack`, in reverse order,
+/// so that they are popped in order. Returns `false` if the slices
+/// differ in length.
+fn push_value_pairs<'a>(a: &'a [Value], b: &'a [Value],
+        stack: &mut Vec<(&'a Value, &'a Value)>) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    stack.extend(a.iter().zip(b.iter()).rev());
+    true
 }
 
 fn float_is_identical(a: f64, b: f64) -> bool {
@@ -708,11 +1230,6 @@ fn float_is_identical(a: f64, b: f64) -> bool {
     }
 }
 
-fn list_is_identical(a: &[Value], b: &[Value]) -> bool {
-    a.len() == b.len() &&
-        a.iter().zip(b.iter()).all(|(a, b)| a.is_identical(b))
-}
-
 /// Borrows a Rust value from a `Value`
 pub trait FromValueRef<'a>: Sized {
     /// Returns the borrowed value
@@ -737,7 +1254,11 @@ macro_rules! integer_from_ref {
         impl<'a> FromValueRef<'a> for $ty {
             fn from_value_ref(v: &'a Value) -> Result<$ty, ExecError> {
                 match *v {
-                    Value::Integer(ref i) => i.$meth().ok_or(ExecError::Overflow),
+                    Value::Integer(ref i) => i.$meth().ok_or_else(
+                        || ExecError::IntegerOutOfRange{
+                            expected: stringify!($ty),
+                            value: i.clone(),
+                        }),
                     ref v => Err(ExecError::expected("integer", v))
                 }
             }
@@ -786,11 +1307,13 @@ integer_from_ref!{ i8 to_i8 }
 integer_from_ref!{ i16 to_i16 }
 integer_from_ref!{ i32 to_i32 }
 integer_from_ref!{ i64 to_i64 }
+integer_from_ref!{ i128 to_i128 }
 integer_from_ref!{ isize to_isize }
 integer_from_ref!{ u8 to_u8 }
 integer_from_ref!{ u16 to_u16 }
 integer_from_ref!{ u32 to_u32 }
 integer_from_ref!{ u64 to_u64 }
+integer_from_ref!{ u128 to_u128 }
 integer_from_ref!{ usize to_usize }
 
 impl<'a> FromValueRef<'a> for &'a str {
@@ -824,6 +1347,7 @@ impl<'a> FromValueRef<'a> for &'a [Value] {
     fn from_value_ref(v: &'a Value) -> Result<&'a [Value], ExecError> {
         match *v {
             Value::Unit => Ok(&[]),
+            Value::Vector(ref li) |
             Value::List(ref li) => Ok(li),
             ref v => Err(ExecError::expected("list", v))
         }
@@ -834,6 +1358,7 @@ impl<'a, T: FromValueRef<'a>> FromValueRef<'a> for Vec<T> {
     fn from_value_ref(v: &'a Value) -> Result<Vec<T>, ExecError> {
         match *v {
             Value::Unit => Ok(Vec::new()),
+            Value::Vector(ref li) |
             Value::List(ref li) => li.iter()
                 .map(|v| T::from_value_ref(v)).collect(),
             ref v => Err(ExecError::expected("list", v))
@@ -881,7 +1406,13 @@ macro_rules! integer_from_value {
         impl FromValue for $ty {
             fn from_value(v: Value) -> Result<$ty, ExecError> {
                 match v {
-                    Value::Integer(ref i) => i.$meth().ok_or(ExecError::Overflow),
+                    Value::Integer(i) => match i.$meth() {
+                        Some(n) => Ok(n),
+                        None => Err(ExecError::IntegerOutOfRange{
+                            expected: stringify!($ty),
+                            value: i,
+                        })
+                    },
                     ref v => Err(ExecError::expected("integer", v))
                 }
             }
@@ -902,11 +1433,13 @@ integer_from_value!{ i8 to_i8 }
 integer_from_value!{ i16 to_i16 }
 integer_from_value!{ i32 to_i32 }
 integer_from_value!{ i64 to_i64 }
+integer_from_value!{ i128 to_i128 }
 integer_from_value!{ isize to_isize }
 integer_from_value!{ u8 to_u8 }
 integer_from_value!{ u16 to_u16 }
 integer_from_value!{ u32 to_u32 }
 integer_from_value!{ u64 to_u64 }
+integer_from_value!{ u128 to_u128 }
 integer_from_value!{ usize to_usize }
 
 impl FromValue for Value {
@@ -929,6 +1462,7 @@ impl<T: FromValue> FromValue for Vec<T> {
     fn from_value(v: Value) -> Result<Vec<T>, ExecError> {
         match v {
             Value::Unit => Ok(Vec::new()),
+            Value::Vector(li) |
             Value::List(li) => li.into_vec().into_iter()
                 .map(|v| T::from_value(v)).collect(),
             ref v => Err(ExecError::expected("list", v))
@@ -1011,11 +1545,13 @@ from_integer!{ i8 from_i8 }
 from_integer!{ i16 from_i16 }
 from_integer!{ i32 from_i32 }
 from_integer!{ i64 from_i64 }
+from_integer!{ i128 from_i128 }
 from_integer!{ isize from_isize }
 from_integer!{ u8 from_u8 }
 from_integer!{ u16 from_u16 }
 from_integer!{ u32 from_u32 }
 from_integer!{ u64 from_u64 }
+from_integer!{ u128 from_u128 }
 from_integer!{ usize from_usize }
 
 /// Represents a structure value containing named fields
@@ -1051,6 +1587,9 @@ pub struct StructDef {
     // TODO: Name-based type-checking prevents a StructDef from requiring
     // a specific class of Struct value for a field.
     pub fields: NameMapSlice<Name>,
+    /// Name of the type defined by `define-type` of which this struct
+    /// is a variant, if any
+    pub variant_of: Option<Name>,
 }
 
 impl PartialEq for StructDef {
@@ -1065,6 +1604,16 @@ impl StructDef {
         StructDef{
             name: name,
             fields: fields,
+            variant_of: None,
+        }
+    }
+
+    /// Creates a new `StructDef` which is a variant of the named type.
+    pub fn new_variant(name: Name, fields: NameMapSlice<Name>, type_name: Name) -> StructDef {
+        StructDef{
+            name: name,
+            fields: fields,
+            variant_of: Some(type_name),
         }
     }
 }
@@ -0,0 +1,116 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Returns the path of the `ketos` executable built alongside the tests.
+fn ketos_path() -> PathBuf {
+    let mut path = env::current_exe().unwrap();
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.push("ketos");
+    path
+}
+
+/// Creates an empty temporary directory for a single test.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("ketos-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_file(path: PathBuf, src: &str) {
+    File::create(path).unwrap().write_all(src.as_bytes()).unwrap();
+}
+
+fn ketos(dir: &PathBuf, args: &[&str]) -> Output {
+    Command::new(ketos_path())
+        .args(&["--no-rc"])
+        .args(args)
+        .current_dir(dir)
+        .env_remove("KETOS_PATH")
+        .output().unwrap()
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8(out.stdout.clone()).unwrap()
+}
+
+fn stderr(out: &Output) -> String {
+    String::from_utf8(out.stderr.clone()).unwrap()
+}
+
+#[test]
+fn test_exprs() {
+    let dir = temp_dir("exprs");
+
+    let out = ketos(&dir, &["-e", "(define x 2)", "-e", "(* x 3)"]);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "x\n6\n");
+
+    let out = ketos(&dir, &["-q", "-e", "(define x 2)", "-e", "(println \"~a\" x)"]);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "2\n");
+
+    // Evaluation stops at the first error
+    let out = ketos(&dir, &["-e", "1", "-e", "(foo", "-e", "2"]);
+    assert!(!out.status.success());
+    assert_eq!(stdout(&out), "1\n");
+    assert!(stderr(&out).starts_with("<input>:1:4:parse error"), "{}", stderr(&out));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_module_main() {
+    let dir = temp_dir("module");
+    let lib = dir.join("lib");
+    fs::create_dir(&lib).unwrap();
+
+    write_file(dir.join("greet.kts"), "
+        (use helper (twice))
+        (export (main))
+        (define (main) (println \"~a ~a\" (twice 21) argv))
+        ");
+    write_file(lib.join("helper.kts"), "
+        (export (twice))
+        (define (twice n) (* n 2))
+        ");
+    write_file(dir.join("nomain.kts"), "
+        (export ())
+        (println \"loaded\")
+        ");
+    write_file(dir.join("broken.kts"), "
+        (export (main))
+        (define (main) (/ 1 0))
+        ");
+
+    // helper is found only through KETOS_PATH
+    let out = ketos(&dir, &["-m", "greet", "a", "b"]);
+    assert!(!out.status.success());
+
+    let out = Command::new(ketos_path())
+        .args(&["--no-rc", "-m", "greet", "a", "b"])
+        .current_dir(&dir)
+        .env("KETOS_PATH", &lib)
+        .output().unwrap();
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stdout(&out), "42 (\"a\" \"b\")\n");
+
+    let out = ketos(&dir, &["-m", "nomain"]);
+    assert!(out.status.success());
+    assert_eq!(stdout(&out), "loaded\n");
+
+    let out = ketos(&dir, &["-m", "broken"]);
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("divide by zero"), "{}", stderr(&out));
+
+    let out = ketos(&dir, &["-m", "missing"]);
+    assert!(!out.status.success());
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -1,8 +1,14 @@
 extern crate ketos;
 
-use ketos::{Error, Interpreter, Value};
+use std::rc::Rc;
+
+use ketos::{Arity, CompileError, Error, ExecError, Interpreter, Value};
+use ketos::function::Arity::{Exact, Min, Range};
+use ketos::bytecode::{Code, CodeReader, Instruction};
+use ketos::function::Lambda;
 use ketos::bytecode::opcodes::*;
-use ketos::name::standard_names;
+use ketos::name::{get_standard_name_for, is_system_fn, is_system_operator, standard_names,
+    NUM_SYSTEM_FNS, NUM_SYSTEM_OPERATORS, SYSTEM_OPERATORS_BEGIN};
 
 fn lambda(s: &str) -> Result<Vec<u8>, Error> {
     let interp = Interpreter::new();
@@ -14,6 +20,39 @@ fn lambda(s: &str) -> Result<Vec<u8>, Error> {
     }
 }
 
+#[test]
+fn test_case_jump_table() {
+    fn instructions(code: &[u8]) -> Vec<Instruction> {
+        let mut r = CodeReader::new(code, 0);
+        let mut res = Vec::new();
+
+        while r.get_offset() < code.len() {
+            res.push(r.read_instruction().unwrap());
+        }
+
+        res
+    }
+
+    let is_table = |i: &Instruction| match *i {
+        Instruction::JumpTable(_) => true,
+        _ => false
+    };
+
+    let code = lambda(r#"(define (foo s)
+        (case s (("a" "b") 1) (("c") 2) (("d") 3) (else 4)))"#).unwrap();
+    assert!(instructions(&code).iter().any(&is_table));
+
+    // Too few patterns
+    let code = lambda(r#"(define (foo s)
+        (case s (("a" "b") 1) (("c") 2)))"#).unwrap();
+    assert!(!instructions(&code).iter().any(&is_table));
+
+    // Patterns which are not strings
+    let code = lambda(r#"(define (foo s)
+        (case s (("a" "b") 1) (("c") 2) ((:d) 3)))"#).unwrap();
+    assert!(!instructions(&code).iter().any(&is_table));
+}
+
 #[test]
 fn test_if() {
     assert_eq!(lambda("(define (foo a b c) (if a b c))").unwrap(), [
@@ -50,8 +89,7 @@ fn test_call_self() {
     assert_eq!(lambda("(define (foo a) (do (foo a) ()))").unwrap(), [
         LOAD_PUSH_0,
         CALL_SELF, 1,
-        UNIT,
-        RETURN,
+        RETURN_UNIT,
     ]);
 
     // Prevents self-call in case one wants to do something tricky.
@@ -59,7 +97,46 @@ fn test_call_self() {
         GET_DEF_PUSH, 0,
         LOAD_PUSH_0,
         CALL, 1,
-        UNIT,
+        RETURN_UNIT,
+    ]);
+}
+
+#[test]
+fn test_return_unit() {
+    assert_eq!(lambda("(define (foo) ())").unwrap(), [
+        RETURN_UNIT,
+    ]);
+
+    assert_eq!(lambda("(define (foo a) (if a 1))").unwrap(), [
+        LOAD_0,
+        JUMP_IF_NOT, 5,
+        CONST_0,
+        RETURN,
+        RETURN_UNIT,
+    ]);
+
+    assert_eq!(lambda("(define (foo a) (let ((b a)) ()))").unwrap(), [
+        LOAD_PUSH_0,
+        RETURN_UNIT,
+    ]);
+}
+
+#[test]
+fn test_let() {
+    assert_eq!(lambda("(define (foo a) (list (let () a) a))").unwrap(), [
+        LOAD_PUSH_0,
+        LOAD_PUSH_0,
+        LIST, 2,
+        RETURN,
+    ]);
+
+    assert_eq!(lambda("(define (foo a) (list (let ((b a)) b) a))").unwrap(), [
+        LOAD_PUSH_0,
+        LOAD_1,
+        SKIP, 1,
+        PUSH,
+        LOAD_PUSH_0,
+        LIST, 2,
         RETURN,
     ]);
 }
@@ -71,6 +148,26 @@ fn test_tail_recursion() {
         TAIL_CALL, 1,
     ]);
 
+    assert_eq!(lambda("(define (foo a) (let () (foo a)))").unwrap(), [
+        LOAD_PUSH_0,
+        TAIL_CALL, 1,
+    ]);
+
+    assert_eq!(lambda("(define (foo a) (let ((b a)) (foo b)))").unwrap(), [
+        LOAD_PUSH_0,
+        LOAD_PUSH_1,
+        TAIL_CALL, 1,
+    ]);
+
+    let interp = Interpreter::new();
+
+    interp.run_code("
+        (define (count n acc)
+          (let ((m (- n 1)))
+            (if (< m 0) acc (count m (+ acc 1)))))", None).unwrap();
+    let v = interp.run_code("(count 100000 0)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "100000");
+
     assert_eq!(lambda("(define (foo a)
                          (if (something a)
                             (foo 1)
@@ -96,3 +193,443 @@ fn test_tail_recursion() {
         TAIL_CALL, 0,
     ]);
 }
+
+#[test]
+fn test_tail_call() {
+    // Calls to other functions in tail position also reuse the stack frame,
+    // so that mutually recursive functions do not grow the stack.
+    assert_eq!(lambda("(define (foo a) (bar a))").unwrap(), [
+        LOAD_PUSH_0,
+        TAIL_CALL_CONST, 0, 1,
+    ]);
+
+    assert_eq!(lambda("(define (foo f) (f 1))").unwrap(), [
+        LOAD_PUSH_0,
+        CONST_PUSH_0,
+        TAIL_CALL_FUNCTION, 1,
+    ]);
+
+    assert_eq!(lambda("(define (foo a) (do (bar a) a))").unwrap(), [
+        LOAD_PUSH_0,
+        CALL_CONST_0, 1,
+        LOAD_0,
+        RETURN,
+    ]);
+}
+
+#[test]
+fn test_while() {
+    assert_eq!(lambda("(define (foo) (while (a) (b)))").unwrap(), [
+        CALL_CONST_0, 0,
+        JUMP_IF_NOT, 8,
+        CALL_CONST_1, 0,
+        JUMP, 0,
+        RETURN_UNIT,
+    ]);
+
+    assert_eq!(lambda("(define (foo x) (do (while (a x)) x))").unwrap(), [
+        LOAD_PUSH_0,
+        CALL_CONST_0, 1,
+        JUMP_IF_NOT, 7,
+        JUMP, 0,
+        UNIT,
+        LOAD_0,
+        RETURN,
+    ]);
+}
+
+#[test]
+fn test_while_backward_jump() {
+    // A jump back to the loop condition must not be treated as a return,
+    // so the last call within the loop body is not a tail call.
+    assert_eq!(lambda("(define (foo x) (while (a) (b x)))").unwrap(), [
+        CALL_CONST_0, 0,
+        JUMP_IF_NOT, 9,
+        LOAD_PUSH_0,
+        CALL_CONST_1, 1,
+        JUMP, 0,
+        RETURN_UNIT,
+    ]);
+}
+
+#[test]
+fn test_for() {
+    assert_eq!(lambda("(define (foo li) (for x li (a x)))").unwrap(), [
+        LOAD_0,
+        ITER_START,
+        PUSH,
+        LOAD_1,
+        JUMP_IF_NULL, 19,
+        LOAD_1,
+        ITER_FIRST,
+        PUSH,
+        LOAD_PUSH_2,
+        CALL_CONST_0, 1,
+        SKIP, 1,
+        LOAD_1,
+        ITER_REST,
+        STORE_1,
+        JUMP, 3,
+        SKIP, 1,
+        RETURN_UNIT,
+    ]);
+}
+
+fn run_bytecode(consts: Vec<Value>, code: &[u8]) -> Result<String, Error> {
+    let interp = Interpreter::new();
+
+    let v = try!(interp.execute(Code{
+        name: None,
+        consts: consts.into_boxed_slice(),
+        code: code.to_vec().into_boxed_slice(),
+        param_names: Box::new([]),
+        kw_params: Box::new([]),
+        n_params: 0,
+        req_params: 0,
+        flags: 0,
+    }));
+
+    Ok(interp.format_value(&v))
+}
+
+#[test]
+fn test_dup_swap() {
+    let mut r = CodeReader::new(&[DUP, SWAP], 0);
+    assert_eq!(r.read_instruction().unwrap(), Instruction::Dup);
+    assert_eq!(r.read_instruction().unwrap(), Instruction::Swap);
+
+    assert_eq!(run_bytecode(vec![Value::from(1), Value::from(2)], &[
+        CONST_PUSH_0,
+        CONST_PUSH_1,
+        SWAP,
+        DUP,
+        LIST, 3,
+        RETURN,
+    ]).unwrap(), "(2 1 1)");
+
+    match run_bytecode(vec![], &[DUP, RETURN]).unwrap_err() {
+        Error::ExecError(ExecError::InvalidStack(_)) => (),
+        e => panic!("expected InvalidStack; got {:?}", e)
+    }
+
+    match run_bytecode(vec![], &[UNIT_PUSH, SWAP, RETURN]).unwrap_err() {
+        Error::ExecError(ExecError::InvalidStack(_)) => (),
+        e => panic!("expected InvalidStack; got {:?}", e)
+    }
+}
+
+#[test]
+fn test_jump_if_eq_const() {
+    let consts = || vec![Value::from(1), Value::from(2), Value::from(3)];
+
+    // Yields 2 if the jump is not taken; otherwise, 3
+    for &(op, first, expected) in &[
+            (JUMP_IF_EQ_CONST, CONST_0, "3"),
+            (JUMP_IF_EQ_CONST, CONST_1, "2"),
+            (JUMP_IF_NOT_EQ_CONST, CONST_0, "2"),
+            (JUMP_IF_NOT_EQ_CONST, CONST_1, "3")] {
+        assert_eq!(run_bytecode(consts(), &[
+            first,
+            op, 6, 0,
+            CONST_1,
+            RETURN,
+            CONST_2,
+            RETURN,
+        ]).unwrap(), expected);
+    }
+
+    let interp = Interpreter::new();
+    assert_eq!(interp.run_code("(let ((n 0)) (if (= n 0) 'zero 'other))", None)
+        .map(|v| interp.format_value(&v)).unwrap(), "zero");
+}
+
+fn padded_if(n: usize) -> String {
+    let items = (0..n).map(|i| i.to_string()).collect::<Vec<_>>();
+    format!("(define (foo a) (if a (list {}) ()))", items.join(" "))
+}
+
+fn run_padded_if(n: usize) -> String {
+    let interp = Interpreter::new();
+    interp.run_code(&padded_if(n), None).unwrap();
+    let v = interp.call("foo", vec![Value::Bool(true)]).unwrap();
+    interp.format_value(&v)
+}
+
+#[test]
+fn test_short_jump_boundary() {
+    // Estimated size exceeds MAX_SHORT_OPERAND, but assembled size does not.
+    let code = lambda(&padded_if(64)).unwrap();
+    assert_eq!(code.len(), 127);
+    assert_eq!(&code[..3], &[LOAD_0, JUMP_IF_NOT, 126]);
+
+    // Actual offset exceeds MAX_SHORT_OPERAND; long encoding is used.
+    let code = lambda(&padded_if(65)).unwrap();
+    assert_eq!(code.len(), 130);
+    assert_eq!(&code[..4], &[LOAD_0, JUMP_IF_NOT, 0x80, 129]);
+
+    let expected = |n: usize| format!("({})",
+        (0..n).map(|i| i.to_string()).collect::<Vec<_>>().join(" "));
+
+    assert_eq!(run_padded_if(64), expected(64));
+    assert_eq!(run_padded_if(65), expected(65));
+}
+
+#[test]
+fn test_closure_value_bounds() {
+    let interp = Interpreter::new();
+
+    interp.run_code("(define (make-closure a) (lambda () a))", None).unwrap();
+
+    let lambda = match interp.call("make-closure", vec![Value::from(1)]).unwrap() {
+        Value::Lambda(l) => l,
+        v => panic!("expected lambda; got {}", v.type_name())
+    };
+
+    assert_eq!(&*lambda.code.code, &[LOADC_0, RETURN]);
+
+    let mut code = (*lambda.code).clone();
+    code.code = vec![LOADC, 5, RETURN].into_boxed_slice();
+
+    let bad = Lambda{
+        code: Rc::new(code),
+        scope: lambda.scope.clone(),
+        values: lambda.values.clone(),
+    };
+
+    match interp.call_value(Value::Lambda(bad), vec![]).unwrap_err() {
+        Error::ExecError(ExecError::InvalidClosureValue(5)) => (),
+        e => panic!("expected InvalidClosureValue; got {:?}", e)
+    }
+
+    // LoadC outside of any closure
+    match run_bytecode(vec![], &[LOADC_0, RETURN]).unwrap_err() {
+        Error::ExecError(ExecError::InvalidClosureValue(0)) => (),
+        e => panic!("expected InvalidClosureValue; got {:?}", e)
+    }
+}
+
+/// Arities of all system functions, in the order of standard names
+static SYSTEM_FN_ARITIES: &'static [(&'static str, Arity)] = &[
+        ("+", Min(0)),
+        ("-", Min(1)),
+        ("*", Min(0)),
+        ("^", Exact(2)),
+        ("/", Min(2)),
+        ("//", Min(2)),
+        ("rem", Exact(2)),
+        ("<<", Exact(2)),
+        (">>", Exact(2)),
+        ("=", Min(2)),
+        ("/=", Min(2)),
+        ("<", Min(2)),
+        (">", Min(2)),
+        ("<=", Min(2)),
+        (">=", Min(2)),
+        ("zero", Min(1)),
+        ("max", Min(1)),
+        ("min", Min(1)),
+        ("append", Min(1)),
+        ("elt", Exact(2)),
+        ("concat", Min(1)),
+        ("join", Min(1)),
+        ("len", Exact(1)),
+        ("slice", Exact(3)),
+        ("first", Exact(1)),
+        ("second", Exact(1)),
+        ("last", Exact(1)),
+        ("init", Exact(1)),
+        ("tail", Exact(1)),
+        ("list", Min(0)),
+        ("reverse", Exact(1)),
+        ("apply-n", Exact(3)),
+        ("apply-first", Exact(2)),
+        ("apply-rest", Exact(2)),
+        ("scan", Exact(3)),
+        ("iterate", Exact(3)),
+        ("range", Range(1, 3)),
+        ("range-list", Exact(1)),
+        ("contains?", Exact(2)),
+        ("vector", Min(0)),
+        ("vec-ref", Exact(2)),
+        ("vec-set", Exact(3)),
+        ("vec-len", Exact(1)),
+        ("vec-slice", Exact(3)),
+        ("vec->list", Exact(1)),
+        ("list->vec", Exact(1)),
+        ("abs", Exact(1)),
+        ("ceil", Exact(1)),
+        ("floor", Exact(1)),
+        ("round", Exact(1)),
+        ("trunc", Exact(1)),
+        ("int", Exact(1)),
+        ("float", Exact(1)),
+        ("float-bits", Exact(1)),
+        ("float->bytes", Exact(2)),
+        ("bytes->float", Exact(2)),
+        ("integer-bits", Exact(2)),
+        ("integer->bytes", Exact(2)),
+        ("bytes->integer", Exact(2)),
+        ("inf", Min(0)),
+        ("nan", Min(0)),
+        ("denom", Exact(1)),
+        ("fract", Exact(1)),
+        ("numer", Exact(1)),
+        ("rat", Range(1, 2)),
+        ("recip", Exact(1)),
+        ("set-float-mode", Exact(1)),
+        ("chars", Exact(1)),
+        ("string", Exact(1)),
+        ("string-from-chars", Exact(1)),
+        ("map-string", Exact(2)),
+        ("string-len", Exact(1)),
+        ("string-byte-len", Exact(1)),
+        ("string-escape", Exact(1)),
+        ("string-unescape", Exact(1)),
+        ("string-escape-html", Exact(1)),
+        ("string-ci=?", Exact(2)),
+        ("starts-with?", Exact(2)),
+        ("starts-with-ci?", Exact(2)),
+        ("id", Exact(1)),
+        ("is", Exact(2)),
+        ("is-instance", Exact(2)),
+        ("null", Exact(1)),
+        ("list?", Exact(1)),
+        ("defined?", Exact(1)),
+        ("macro-defined?", Exact(1)),
+        ("current-module", Exact(0)),
+        ("exports-of", Range(1, 2)),
+        ("doc", Exact(1)),
+        ("keyword-namespace", Exact(1)),
+        ("keyword-name", Exact(1)),
+        ("error", Min(1)),
+        ("error-message", Exact(1)),
+        ("error-irritants", Exact(1)),
+        ("error-category", Exact(1)),
+        ("error-kind", Exact(1)),
+        ("type-of", Exact(1)),
+        (".", Exact(2)),
+        (".=", Min(1)),
+        ("new", Min(1)),
+        ("struct?", Exact(2)),
+        ("struct-type", Exact(1)),
+        ("format", Min(1)),
+        ("print", Min(1)),
+        ("println", Min(1)),
+        ("display", Exact(1)),
+        ("write", Exact(1)),
+        ("panic", Range(0, 1)),
+        ("xor", Exact(2)),
+        ("not", Exact(1)),
+];
+
+/// Arities of all system operators, in the order of standard names
+static SYSTEM_OPERATOR_ARITIES: &'static [(&'static str, Arity)] = &[
+        ("apply", Min(2)),
+        ("do", Min(1)),
+        ("let", Exact(2)),
+        ("define", Exact(2)),
+        ("macro", Exact(2)),
+        ("struct", Exact(2)),
+        ("if", Range(2, 3)),
+        ("and", Min(1)),
+        ("or", Min(1)),
+        ("case", Min(2)),
+        ("cond", Min(1)),
+        ("lambda", Exact(2)),
+        ("export", Exact(1)),
+        ("use", Min(2)),
+        ("defvar", Exact(2)),
+        ("with-bindings", Min(2)),
+        ("export-rename", Min(1)),
+        ("guard", Min(2)),
+        ("try", Min(2)),
+        ("apply/keyword", Min(2)),
+        ("case-head", Min(2)),
+        ("define-type", Min(2)),
+        ("type-case", Min(3)),
+        ("define-module", Min(1)),
+        ("import-for-syntax", Exact(2)),
+        ("with-output-to-string", Min(0)),
+        ("unless-bound", Exact(2)),
+        ("define-if-unbound", Exact(2)),
+        ("with-module", Min(2)),
+        ("let-fn", Exact(2)),
+        ("define-record", Exact(2)),
+        ("while", Min(1)),
+        ("for", Min(2)),
+        ("define-overload", Exact(2)),
+        ("let*", Exact(2)),
+];
+
+/// Compiles a call with `n` integer arguments and returns the arity
+/// reported by the resulting `ArityError`, if any.
+fn arity_error(name: &str, n: u32) -> Option<Arity> {
+    let interp = Interpreter::new();
+    let mut expr = format!("({}", name);
+
+    for _ in 0..n {
+        expr.push_str(" 0");
+    }
+    expr.push(')');
+
+    match interp.compile_single_expr(&expr, None) {
+        Err(Error::CompileError(CompileError::ArityError{expected, found, ..})) => {
+            assert_eq!(found, n);
+            Some(expected)
+        }
+        _ => None
+    }
+}
+
+fn check_arities(table: &[(&str, Arity)]) {
+    for &(name, arity) in table {
+        let min = arity.min();
+
+        if min > 0 {
+            assert_eq!(arity_error(name, min - 1), Some(arity), "`{}` with {}", name, min - 1);
+        }
+        assert_eq!(arity_error(name, min), None, "`{}` with {}", name, min);
+
+        if let Some(max) = arity.max() {
+            assert_eq!(arity_error(name, max), None, "`{}` with {}", name, max);
+            assert_eq!(arity_error(name, max + 1), Some(arity), "`{}` with {}", name, max + 1);
+        }
+    }
+}
+
+#[test]
+fn test_arity_table() {
+    assert_eq!(SYSTEM_FN_ARITIES.len(), NUM_SYSTEM_FNS);
+    assert_eq!(SYSTEM_OPERATOR_ARITIES.len(), NUM_SYSTEM_OPERATORS);
+
+    for (i, &(name, _)) in SYSTEM_FN_ARITIES.iter().enumerate() {
+        let n = get_standard_name_for(name).expect("missing standard name");
+        assert_eq!(n.get(), i as u32, "`{}`", name);
+        assert!(is_system_fn(n), "`{}`", name);
+    }
+
+    for (i, &(name, _)) in SYSTEM_OPERATOR_ARITIES.iter().enumerate() {
+        let n = get_standard_name_for(name).expect("missing standard name");
+        assert_eq!(n.get(), SYSTEM_OPERATORS_BEGIN + i as u32, "`{}`", name);
+        assert!(is_system_operator(n), "`{}`", name);
+    }
+
+    check_arities(SYSTEM_FN_ARITIES);
+    check_arities(SYSTEM_OPERATOR_ARITIES);
+}
+
+#[test]
+fn test_arity_display() {
+    assert_eq!(Arity::Exact(0).to_string(), "exactly 0 arguments");
+    assert_eq!(Arity::Exact(1).to_string(), "exactly 1 argument");
+    assert_eq!(Arity::Exact(2).to_string(), "exactly 2 arguments");
+    assert_eq!(Arity::Min(1).to_string(), "at least 1 argument");
+    assert_eq!(Arity::Min(2).to_string(), "at least 2 arguments");
+    assert_eq!(Arity::Range(2, 3).to_string(), "between 2 and 3 arguments");
+
+    assert!(Arity::Range(2, 3).contains(3));
+    assert!(!Arity::Range(2, 3).contains(4));
+    assert_eq!(Arity::Range(2, 3).min(), 2);
+    assert_eq!(Arity::Range(2, 3).max(), Some(3));
+    assert_eq!(Arity::Min(1).max(), None);
+}
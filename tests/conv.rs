@@ -1,6 +1,6 @@
 extern crate ketos;
 
-use ketos::{ExecError, FromValue, FromValueRef, Value};
+use ketos::{ExecError, FromValue, FromValueRef, Integer, Value};
 
 macro_rules! assert_matches {
     ( $e:expr, $pat:pat ) => {
@@ -31,6 +31,10 @@ fn into<T: Into<Value>>(t: T) -> Value {
     t.into()
 }
 
+fn integer(v: Value) -> Integer {
+    from(v).unwrap()
+}
+
 #[test]
 fn test_from_value() {
     assert_eq!(from::<()>(Value::Unit).unwrap(), ());
@@ -61,3 +65,133 @@ fn test_into_value() {
     assert_matches!(into(Vec::<i32>::new()), Value::Unit);
     assert_matches!(into(Vec::<Value>::new()), Value::Unit);
 }
+
+#[test]
+fn test_value_to_integer() {
+    assert_eq!(into(123).to_i32(), Some(123));
+    assert_eq!(into(-123).to_i64(), Some(-123));
+    assert_eq!(into(123).to_u32(), Some(123));
+    assert_eq!(into(123).to_usize(), Some(123));
+
+    assert_eq!(into(-1).to_u32(), None);
+    assert_eq!(into(-1).to_usize(), None);
+
+    assert_eq!(into(i32::max_value() as i64 + 1).to_i32(), None);
+    assert_eq!(into(u32::max_value() as u64 + 1).to_u32(), None);
+    assert_eq!(into(u64::max_value()).to_i64(), None);
+
+    assert_eq!(into(1.0).to_i32(), None);
+    assert_eq!(into("1").to_usize(), None);
+    assert_eq!(Value::Unit.to_i64(), None);
+}
+
+#[test]
+fn test_small_integers() {
+    // Values at and beyond the bounds of preallocated integers
+    for &i in &[-129_i64, -128, -1, 0, 1, 1024, 1025] {
+        assert_eq!(into(i).to_i64(), Some(i));
+        assert!(into(i).is_identical(&Value::Integer(
+            Integer::from_str_radix(&i.to_string(), 10).unwrap())));
+    }
+
+    for &u in &[0_u64, 1024, 1025, u64::max_value()] {
+        assert_eq!(Integer::from_u64(u).to_u64(), Some(u));
+        assert_eq!(Integer::from_usize(u as usize).to_u64(), Some(u));
+    }
+
+    assert_eq!(into(-128_i8).to_i64(), Some(-128));
+    assert_eq!(into(u32::max_value()).to_i64(), Some(u32::max_value() as i64));
+}
+
+/// Asserts that the bounds of an integer type convert to and from `Value`,
+/// and that values just beyond them produce an error naming the type.
+macro_rules! check_bounds {
+    ( $( $ty:ident ),* ) => { $( {
+        let min = $ty::min_value();
+        let max = $ty::max_value();
+
+        for &n in &[min, 0, max] {
+            assert_eq!(from::<$ty>(into(n)).unwrap(), n);
+            assert_eq!(from_ref::<$ty>(&into(n)).unwrap(), n);
+        }
+
+        let below = integer(into(min)) - Integer::one();
+        let above = integer(into(max)) + Integer::one();
+
+        for i in vec![below, above] {
+            let v = Value::Integer(i.clone());
+
+            assert_matches!(from_ref::<$ty>(&v),
+                Err(ExecError::IntegerOutOfRange{expected, ref value})
+                    if expected == stringify!($ty) && *value == i);
+            assert_matches!(from::<$ty>(v),
+                Err(ExecError::IntegerOutOfRange{expected, ref value})
+                    if expected == stringify!($ty) && *value == i);
+        }
+    } )* }
+}
+
+#[test]
+fn test_integer_bounds() {
+    check_bounds!(i8, i16, i32, i64, i128, isize,
+        u8, u16, u32, u64, u128, usize);
+
+    assert_eq!(from::<u8>(into(-1)).unwrap_err().to_string(),
+        "integer -1 out of range for `u8`");
+    assert_matches!(from::<u8>(into(1.0)), Err(ExecError::TypeError{..}));
+}
+
+#[test]
+fn test_value_to_u64() {
+    assert_eq!(into(u64::max_value()).to_u64(), Some(u64::max_value()));
+    assert_eq!(into(-1).to_u64(), None);
+    assert_eq!(into(u64::max_value() as u128 + 1).to_u64(), None);
+    assert_eq!(into(1.0).to_u64(), None);
+}
+
+#[test]
+fn test_large_integers() {
+    let cases: &[(i128, &str)] = &[
+        (i128::min_value(), "-170141183460469231731687303715884105728"),
+        (i64::min_value() as i128 - 1, "-9223372036854775809"),
+        (-1, "-1"),
+        (u64::max_value() as i128 + 1, "18446744073709551616"),
+        (i128::max_value(), "170141183460469231731687303715884105727"),
+    ];
+
+    for &(n, s) in cases {
+        let i = Integer::from_i128(n);
+        assert_eq!(i.to_string(), s);
+        assert_eq!(i.to_i128(), Some(n));
+        assert_eq!(Integer::from_str_radix(s, 10).unwrap(), i);
+    }
+
+    assert_eq!(Integer::from_u128(u128::max_value()).to_u128(), Some(u128::max_value()));
+    assert_eq!(Integer::from_u128(u128::max_value()).to_i128(), None);
+    assert_eq!(Integer::from_i128(-1).to_u128(), None);
+}
+
+#[test]
+fn test_integer_from_f64_exact() {
+    let cases: &[(f64, Option<&str>)] = &[
+        (0.0, Some("0")),
+        (-0.0, Some("0")),
+        (-2.0, Some("-2")),
+        (1e20, Some("100000000000000000000")),
+        (0.5, None),
+        (-1.5, None),
+        (1e20 + 0.5, Some("100000000000000000000")),
+        (::std::f64::INFINITY, None),
+        (::std::f64::NEG_INFINITY, None),
+        (::std::f64::NAN, None),
+    ];
+
+    for &(f, s) in cases {
+        assert_eq!(Integer::from_f64_exact(f).map(|i| i.to_string()),
+            s.map(|s| s.to_owned()), "{}", f);
+    }
+
+    // Range checks for a specific width follow conversion to `Integer`
+    assert_eq!(Integer::from_f64_exact(255.0).and_then(|i| i.to_u8()), Some(255));
+    assert_eq!(Integer::from_f64_exact(256.0).and_then(|i| i.to_u8()), None);
+}
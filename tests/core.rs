@@ -1,6 +1,8 @@
 extern crate ketos;
 
-use ketos::{CompileError, Error, ExecError, Interpreter, FromValue, Value};
+use ketos::{CompileError, Error, ExecError, Interpreter, FromValue, ParseError, ParseErrorKind,
+    Value, YieldAction};
+use ketos::name::display_names;
 
 macro_rules! assert_matches {
     ( $e:expr, $pat:pat ) => {
@@ -52,6 +54,20 @@ fn test_integer() {
     assert_eq!(eval("0b101101").unwrap(), "45");
 }
 
+#[test]
+fn test_large_integer() {
+    let digits = format!("9{}1", "0".repeat(4998));
+
+    assert_eq!(eval(&digits).unwrap(), digits);
+    assert_eq!(eval(&format!("-{}", digits)).unwrap(), format!("-{}", digits));
+    assert_eq!(eval(&format!("(= {} (+ (^ 10 4999) (* 8 (^ 10 4999)) 1))", digits))
+        .unwrap(), "true");
+    assert_eq!(eval("(^ 10 3000)").unwrap(), format!("1{}", "0".repeat(3000)));
+
+    let hex = eval_str("(format \"~x\" (^ 7 5000))").unwrap();
+    assert_eq!(eval(&format!("(= 0x{} (^ 7 5000))", hex)).unwrap(), "true");
+}
+
 #[test]
 fn test_quasiquote() {
     assert_eq!(eval("`(foo ,(id 1))").unwrap(), "(foo 1)");
@@ -80,6 +96,27 @@ fn test_struct() {
         ").unwrap(),
         ["foo", "bar", "my-foo", "true", "false"]);
 
+    assert_eq!(run("
+        (struct Point ((x integer) (y integer)))
+        (struct Size ((x integer) (y integer)))
+        (define p (new Point :x 1 :y 2))
+        (define s (new Size :x 1 :y 2))
+        (struct? p 'Point)
+        (struct? p 'Size)
+        (struct? s 'Size)
+        (struct? 1 'Point)
+        (struct-type p)
+        (struct-type s)
+        (struct-type 1)
+        ").unwrap(),
+        ["Point", "Size", "p", "s",
+            "true", "false", "true", "false", "Point", "Size", "()"]);
+
+    assert_matches!(eval("(struct? 1 'Nothing)").unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+    assert_matches!(run("(define x 1) (struct? 1 'x)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "struct-def", ..}));
+
     assert_eq!(run("
         (struct foo ((a integer)
                      (b list)
@@ -152,6 +189,144 @@ fn test_struct() {
         ["foo", "true"]);
 }
 
+#[test]
+fn test_struct_redefinition() {
+    let interp = Interpreter::new();
+
+    let e = interp.run_code("
+        (struct foo ((a integer) (b string)))
+        (define x (new foo :a 1 :b \"b\"))
+        (struct foo ((a number) (c char)))
+        (= x (new foo :a 1 :c #'c'))
+        ", None).unwrap_err();
+    assert_matches!(e, Error::ExecError(ExecError::StructDefMismatch{..}));
+    assert_eq!(interp.format_error(&e), "\
+execution error: struct type mismatch: values of `foo` have different definitions; \
+field `a` has type `integer` and `number`; \
+field `b` is only in the left-hand definition; \
+field `c` is only in the right-hand definition
+");
+
+    let e = interp.run_code("
+        (struct foo ((a integer)))
+        (define y (new foo :a 1))
+        (struct foo ((a integer)))
+        (= y (new foo :a 1))
+        ", None).unwrap_err();
+    assert_eq!(interp.format_error(&e), "\
+execution error: struct type mismatch: values of `foo` have different definitions; \
+the definitions have identical fields, but were defined at different times
+");
+
+    let interp = Interpreter::new();
+    interp.get_scope().set_reuse_struct_defs(true);
+
+    // Each expression is compiled after the previous one is executed,
+    // as in the REPL.
+    interp.run_code("(struct foo ((a integer)))", None).unwrap();
+    interp.run_code("(define x (new foo :a 1))", None).unwrap();
+    interp.run_code("(struct foo ((a integer)))", None).unwrap();
+    assert_matches!(interp.run_code("(= x (new foo :a 1))", None).unwrap(),
+        Value::Bool(true));
+
+    interp.run_code("(struct foo ((a number)))", None).unwrap();
+    assert_matches!(interp.run_code("(= x (new foo :a 1))", None).unwrap_err(),
+        Error::ExecError(ExecError::StructDefMismatch{..}));
+}
+
+#[test]
+fn test_define_type() {
+    assert_eq!(run("
+        (define-type Shape
+          (Circle radius)
+          (Rectangle width height)
+          (Triangle (base number) (height number)))
+
+        (define (area s)
+          (shape-case s
+            ((Circle r) (* r r 3))
+            ((Rectangle w h) (* w h))
+            ((Triangle b h) (/ (* b h) 2))))
+
+        (list (area (Circle 2)) (area (Rectangle 3 4)) (area (Triangle 5 6)))
+        (list (Circle? (Circle 2)) (Circle? (Rectangle 3 4)) (Circle? 1))
+        (list (Shape? (Circle 2)) (Shape? (Triangle 5 6)) (Shape? 1))
+        (shape-case (Rectangle 1 2)
+          ((Circle r) 'circle)
+          (else 'other))
+        (shape-case (Circle 1) ((Rectangle w h) 'rectangle))
+        (struct-type (Triangle 1 2))
+        (Circle 1)
+        ").unwrap(),
+        ["Shape", "area", "(12 12 15)", "(true false false)",
+            "(true true false)", "other", "()", "Triangle",
+            "Circle { radius: 1 }"]);
+
+    assert_eq!(run("
+        (define-type BinaryTree (Leaf) (Node left right))
+        (struct Holder ((tree BinaryTree)))
+        (define (depth t)
+          (binary-tree-case t
+            ((Leaf) 0)
+            ((Node l r) (+ 1 (max (depth l) (depth r))))))
+        (depth (Node (Leaf) (Node (Leaf) (Leaf))))
+        (. (new Holder :tree (Leaf)) :tree)
+        ").unwrap(),
+        ["BinaryTree", "Holder", "depth", "2", "Leaf {}"]);
+
+    assert_matches!(run("
+        (define-type Shape (Circle radius))
+        (Triangle 1 2)
+        ").unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+    assert_matches!(run("
+        (define-type Shape (Triangle (base number)))
+        (Triangle 'a)
+        ").unwrap_err(),
+        Error::ExecError(ExecError::FieldTypeError{..}));
+    assert_matches!(run("
+        (define-type Shape (Circle radius))
+        (shape-case (Circle 1) ((Square s) s))
+        ").unwrap_err(),
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
+    assert_matches!(run("
+        (define-type Shape (Circle radius))
+        (shape-case (Circle 1) ((Circle) 0))
+        ").unwrap_err(),
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
+}
+
+#[test]
+fn test_define_record() {
+    assert_eq!(run("
+        (define-record point (x (y integer)))
+        (define p (make-point 1 2))
+        (list (point-x p) (point-y p))
+        (list (point? p) (point? 1))
+        (struct-type p)
+        (is-instance point p)
+        p
+        ").unwrap(),
+        ["point", "p", "(1 2)", "(true false)", "point", "true",
+            "point { x: 1, y: 2 }"]);
+
+    assert_eq!(run("
+        (define-record empty ())
+        (empty? (make-empty))
+        ").unwrap(),
+        ["empty", "true"]);
+
+    assert_matches!(run("
+        (define-record point (x (y integer)))
+        (make-point 1 'a)
+        ").unwrap_err(),
+        Error::ExecError(ExecError::FieldTypeError{..}));
+    assert_matches!(run("
+        (define-record vec (len))
+        ").unwrap_err(),
+        Error::CompileError(CompileError::CannotDefine(_)));
+}
+
 #[test]
 fn test_format() {
     assert_eq!(eval_str(r#"(format "foo")"#).unwrap(), "foo");
@@ -172,7 +347,9 @@ fn test_format() {
     assert_eq!(eval_str(r#"(format "~s" 123)"#).unwrap(), "123");
     assert_eq!(eval_str(r#"(format "~s" 'foo)"#).unwrap(), "foo");
     assert_eq!(eval_str(r#"(format "~a" #'a')"#).unwrap(), "a");
-    assert_eq!(eval_str(r#"(format "~s" #'a')"#).unwrap(), "#'a'");
+    assert_eq!(eval_str(r#"(format "~s" #'a')"#).unwrap(), r"#\a");
+    assert_eq!(eval_str(r#"(format "~s ~s ~s ~s" #\newline #\\ #\x1f #\x3bb)"#).unwrap(),
+        r"#\newline #\\ #\x1f #\λ");
 
     assert_eq!(eval_str(r#"(format "foo~c~c~c" #'b' #'a' #'r')"#).unwrap(), "foobar");
 
@@ -504,6 +681,42 @@ fn test_rem() {
         Error::ExecError(ExecError::DivideByZero));
 }
 
+#[test]
+fn test_strict_float() {
+    assert_eq!(run("(/ 0.0 0.0) (set-float-mode :strict) (/ 1.0 2.0)").unwrap(),
+        ["NaN", "()", "0.5"]);
+
+    assert_matches!(run("(set-float-mode :strict) (/ 0.0 0.0)").unwrap_err(),
+        Error::ExecError(ExecError::NonFiniteFloat{operation: "/", ..}));
+    assert_matches!(run("(set-float-mode :strict) (* 1e300 1e300)").unwrap_err(),
+        Error::ExecError(ExecError::NonFiniteFloat{operation: "*", ..}));
+    assert_matches!(run("(set-float-mode :strict) (+ 1.0 1.7e308 1.7e308)").unwrap_err(),
+        Error::ExecError(ExecError::NonFiniteFloat{operation: "+", ..}));
+    assert_matches!(run("(set-float-mode :strict) (^ 0 -1)").unwrap_err(),
+        Error::ExecError(ExecError::NonFiniteFloat{operation: "^", ..}));
+    assert_matches!(run("(set-float-mode :strict) (rem 1.0 0)").unwrap_err(),
+        Error::ExecError(ExecError::NonFiniteFloat{operation: "rem", ..}));
+
+    assert_eq!(error_message("(set-float-mode :strict) (- 2 (/ 1.0 0.0))"),
+        "`/` of 1.0 and 0.0 produced inf");
+
+    assert_eq!(run("
+        (set-float-mode :strict)
+        (+ (inf) 1.0)
+        (nan (- (inf) (inf)))
+        (/ 1 0.5)
+        (* 100000000000000000000 100000000000000000000)
+        (/ 1/3 2/3)
+        (set-float-mode :default)
+        (/ 0.0 0.0)
+        ").unwrap(),
+        ["()", "inf", "true", "2.0", "10000000000000000000000000000000000000000",
+            "1/2", "()", "NaN"]);
+
+    assert_matches!(eval("(set-float-mode :loose)").unwrap_err(),
+        Error::ExecError(ExecError::UnrecognizedKeyword(_)));
+}
+
 #[test]
 fn test_shift() {
     assert_eq!(eval("(<< 1 10)").unwrap(), "1024");
@@ -617,11 +830,9 @@ fn test_and() {
     assert_eq!(eval("(and false true)").unwrap(), "false");
     assert_eq!(eval("(and false (panic))").unwrap(), "false");
 
-    assert_matches!(eval("(and () true)").unwrap_err(), Error::ExecError(
-        ExecError::TypeError{
-            expected: "bool",
-            found: "unit",
-        }));
+    assert_eq!(eval("(and () true)").unwrap(), "true");
+    assert_eq!(eval("(and 1 2)").unwrap(), "2");
+    assert_eq!(eval("(and 1 false (panic))").unwrap(), "false");
 }
 
 #[test]
@@ -631,11 +842,9 @@ fn test_or() {
     assert_eq!(eval("(or false true)").unwrap(), "true");
     assert_eq!(eval("(or true (panic))").unwrap(), "true");
 
-    assert_matches!(eval("(or () true)").unwrap_err(), Error::ExecError(
-        ExecError::TypeError{
-            expected: "bool",
-            found: "unit",
-        }));
+    assert_eq!(eval("(or () true)").unwrap(), "()");
+    assert_eq!(eval("(or false 0 (panic))").unwrap(), "0");
+    assert_eq!(eval("(or false false ())").unwrap(), "()");
 }
 
 #[test]
@@ -657,11 +866,35 @@ fn test_not() {
     assert_eq!(eval("(not true)").unwrap(), "false");
     assert_eq!(eval("(not false)").unwrap(), "true");
 
-    assert_matches!(eval("(not ())").unwrap_err(), Error::ExecError(
-        ExecError::TypeError{
-            expected: "bool",
-            found: "unit",
-        }));
+    assert_eq!(eval("(not ())").unwrap(), "false");
+    assert_eq!(eval("(not 0)").unwrap(), "false");
+    assert_eq!(eval("(apply not '(false))").unwrap(), "true");
+    assert_eq!(eval("(apply not '(\"\"))").unwrap(), "false");
+}
+
+#[test]
+fn test_truthiness() {
+    let interp = Interpreter::new();
+
+    interp.run_code("
+        (struct Foo ())
+        (define (foo) ())
+        ", None).unwrap();
+
+    let truthy = [
+        "()", "true", "0", "0.0", "1/2", "(new Foo)", "Foo",
+        "'foo", ":foo", "#'a'", "\"\"", "'`foo", "'`,foo", "'`(,@foo)",
+        "''foo", "'(false)", "foo", "(lambda () ())", "+",
+    ];
+
+    for s in &truthy {
+        let v = interp.run_single_expr(s, None).unwrap();
+        assert!(v.is_truthy(), "{} should be truthy", s);
+    }
+
+    assert!(Value::Unit.is_truthy());
+    assert!(Value::Unbound.is_truthy());
+    assert!(!Value::Bool(false).is_truthy());
 }
 
 #[test]
@@ -669,11 +902,14 @@ fn test_if() {
     assert_eq!(eval("(if true 1 (panic))").unwrap(), "1");
     assert_eq!(eval("(if false (panic) 1)").unwrap(), "1");
 
-    assert_matches!(eval("(if 0 () ())").unwrap_err(), Error::ExecError(
-        ExecError::TypeError{
-            expected: "bool",
-            found: "integer",
-        }));
+    assert_eq!(eval("(if 0 1 (panic))").unwrap(), "1");
+    assert_eq!(eval("(if () 1 (panic))").unwrap(), "1");
+    assert_eq!(eval("(if \"\" 1 (panic))").unwrap(), "1");
+    assert_eq!(eval("(if (not ()) (panic) 1)").unwrap(), "1");
+    assert_eq!(eval("(cond (() 1) (else (panic)))").unwrap(), "1");
+
+    assert_eq!(eval("(let ((n 0)) (if (= n 0) 1 (panic)))").unwrap(), "1");
+    assert_eq!(eval("(let ((n 1)) (if (= n 0) (panic) 1))").unwrap(), "1");
 }
 
 #[test]
@@ -700,6 +936,130 @@ fn test_case() {
         Error::CompileError(_));
 }
 
+#[test]
+fn test_case_strings() {
+    let interp = Interpreter::new();
+
+    interp.run_code(r#"
+        (define (dispatch s)
+          (case s
+            (("add" "plus") 'add)
+            (("sub")        'sub)
+            (("mul")        'mul)
+            (("div" "add")  'div)))
+
+        (define (dispatch-else s)
+          (case s
+            (("a" "b") 1)
+            (("c" "d") 2)
+            (else      3)))
+        "#, None).unwrap();
+
+    let call = |s: &str| interp.run_single_expr(s, None)
+        .map(|v| interp.format_value(&v));
+
+    assert_eq!(call(r#"(dispatch "add")"#).unwrap(), "add");
+    assert_eq!(call(r#"(dispatch "plus")"#).unwrap(), "add");
+    assert_eq!(call(r#"(dispatch "div")"#).unwrap(), "div");
+    assert_eq!(call(r#"(dispatch "mul")"#).unwrap(), "mul");
+    assert_eq!(call(r#"(dispatch "")"#).unwrap(), "()");
+    assert_eq!(call(r#"(dispatch "addx")"#).unwrap(), "()");
+
+    assert_eq!(call(r#"(dispatch-else "a")"#).unwrap(), "1");
+    assert_eq!(call(r#"(dispatch-else "d")"#).unwrap(), "2");
+    assert_eq!(call(r#"(dispatch-else "e")"#).unwrap(), "3");
+
+    // Values other than strings are compared to each pattern
+    assert_matches!(call("(dispatch 1)").unwrap_err(),
+        Error::ExecError(ExecError::TypeMismatch{..}));
+    assert_matches!(call("(dispatch-else #'a')").unwrap_err(),
+        Error::ExecError(ExecError::TypeMismatch{..}));
+
+    // Duplicate patterns are a warning, except in strict mode
+    assert_matches!(run_strict(r#"(case "a" (("a") 1) (("b" "a") 2))"#).unwrap_err(),
+        Error::CompileError(CompileError::DuplicateCasePattern(_)));
+    assert_matches!(run_strict("(case 1 ((1 2) 1) ((3 4 2) 2))").unwrap_err(),
+        Error::CompileError(CompileError::DuplicateCasePattern(_)));
+}
+
+#[test]
+fn test_case_head() {
+    assert_eq!(eval("(case-head '(:add 1 2)
+                           ((:add a b) (+ a b))
+                           ((:neg a)   (- a)))").unwrap(), "3");
+
+    assert_eq!(eval("(case-head '(:neg 1)
+                           ((:add a b) (+ a b))
+                           ((:neg a)   (- a)))").unwrap(), "-1");
+
+    assert_eq!(eval("(case-head '(:quit)
+                           ((:add a b) (+ a b))
+                           ((:quit)    'quit))").unwrap(), "quit");
+
+    assert_eq!(eval("(case-head '(:mul 2 3)
+                           ((:add a b) (+ a b)))").unwrap(), "()");
+
+    assert_eq!(eval("(case-head '(:mul 2 3)
+                           ((:add a b) (+ a b))
+                           (else       'other))").unwrap(), "other");
+
+    assert_eq!(eval("(let ((x 10))
+                       (case-head (list :sub x 4)
+                         ((:sub a b) (list x a b (- a b)))))").unwrap(), "(10 10 4 6)");
+
+    assert_eq!(error_message("(case-head '(:add 1)
+                                ((:add a b) (+ a b)))"),
+        "`case-head` pattern `:add` expected exactly 2 values; found 1");
+    assert_eq!(error_message("(case-head '(:neg 1 2)
+                                ((:neg a) (- a)))"),
+        "`case-head` pattern `:neg` expected exactly 1 value; found 2");
+    assert_eq!(error_message("(case-head '(:quit 1)
+                                ((:quit) 'quit))"),
+        "`case-head` pattern `:quit` expected exactly 0 values; found 1");
+    assert_eq!(error_message("(case-head '(\"~a\")
+                                ((\"~a\" a) a))"),
+        "`case-head` pattern `\"~a\"` expected exactly 1 value; found 0");
+
+    assert_matches!(eval("(case-head '(:add 1 2)
+                                (else 'b)
+                                ((:add a b) 'c))").unwrap_err(),
+        Error::CompileError(_));
+}
+
+#[test]
+fn test_stack_balance() {
+    // Compiled code is verified when debug assertions are enabled;
+    // bindings from each arm must be removed before arms merge.
+    assert_eq!(run("
+        (define (f n)
+          (case n
+            ((0) (let ((a 1) (b 2)) (list a b)))
+            ((1) (let ((a 3))
+                   (cond
+                     ((< a n) 'less)
+                     (else (let ((b (+ a n))) (list a b))))))
+            (else (case-head (list :x n)
+                    ((:x v) (let ((w (* v 2))) (list v w)))))))
+        (f 0)
+        (f 1)
+        (f 5)
+        ").unwrap(),
+        ["f", "(1 2)", "(3 4)", "(5 10)"]);
+}
+
+#[test]
+fn test_append_stack() {
+    // Append consumes the list pushed onto the stack;
+    // bindings which follow it must be found at the right offset.
+    assert_eq!(run("
+        (let ((a (append '(1) 2)) (b 3)) (list a b))
+        (let ((x 0) (a (append '(1) 2)) (b 3)) (list x a b))
+        (define (f x y) (let ((a (append x y)) (b y)) (list a b)))
+        (f '(1 2) 3)
+        ").unwrap(),
+        ["((1 2) 3)", "(0 (1 2) 3)", "f", "((1 2 3) 3)"]);
+}
+
 #[test]
 fn test_cond() {
     assert_eq!(eval("(cond (false 'a)
@@ -721,6 +1081,62 @@ fn test_cond() {
         Error::CompileError(_));
 }
 
+#[test]
+fn test_doc_comment_in_expr() {
+    assert_eq!(run("(list 1 ;; doc\n 2)").unwrap(), ["(1 2)"]);
+    assert_eq!(run("
+        (define (f x)
+          ;; Not the documentation of f
+          (+ x 1))
+        (f 1)
+        ").unwrap(), ["f", "2"]);
+}
+
+#[test]
+fn test_reserved_names() {
+    // Names which became system functions or operators after earlier
+    // versions; these lists match those in docs/functions.md.
+    let functions = [
+        "apply-n", "apply-first", "apply-rest", "scan", "iterate", "range",
+        "range-list", "contains?", "vector", "vec-ref", "vec-set", "vec-len",
+        "vec-slice", "vec->list", "list->vec", "float-bits", "float->bytes",
+        "bytes->float", "integer-bits", "integer->bytes", "bytes->integer",
+        "set-float-mode", "string-from-chars", "map-string", "string-len",
+        "string-byte-len", "string-escape", "string-unescape",
+        "string-escape-html", "string-ci=?", "starts-with?", "starts-with-ci?",
+        "list?", "defined?", "macro-defined?", "current-module", "exports-of",
+        "doc", "keyword-namespace", "keyword-name", "error", "error-message",
+        "error-irritants", "error-category", "error-kind", "struct?",
+        "struct-type", "display", "write",
+    ];
+    let operators = [
+        "defvar", "with-bindings", "export-rename", "guard", "try",
+        "apply/keyword", "case-head", "define-type", "type-case",
+        "define-module", "import-for-syntax", "with-output-to-string",
+        "unless-bound", "define-if-unbound", "with-module", "let-fn",
+        "define-record", "while", "for", "define-overload", "let*",
+    ];
+
+    // Names of system functions cannot be defined globally,
+    // but may be bound locally
+    for name in &functions {
+        assert_matches!(run(&format!("(define {} 1)", name)).unwrap_err(),
+            Error::CompileError(CompileError::CannotDefine(_)));
+        assert_matches!(run(&format!("(define ({} a) a)", name)).unwrap_err(),
+            Error::CompileError(CompileError::CannotDefine(_)));
+        assert_eq!(eval(&format!("(let (({0} 1)) {0})", name)).unwrap(), "1");
+        assert_eq!(run(&format!("(define (f {0}) {0}) (f 2)", name)).unwrap(),
+            ["f", "2"]);
+    }
+
+    for name in &operators {
+        assert_matches!(run(&format!("(define {} 1)", name)).unwrap_err(),
+            Error::CompileError(CompileError::CannotDefine(_)));
+        assert_matches!(run(&format!("(define ({} a) a)", name)).unwrap_err(),
+            Error::CompileError(CompileError::CannotDefine(_)));
+    }
+}
+
 #[test]
 fn test_let() {
     assert_eq!(eval("
@@ -731,14 +1147,98 @@ fn test_let() {
 
     // Standard names CAN be overriden with let
     assert_eq!(eval("(let ((id 0)) id)").unwrap(), "0");
+
+    // Each binding is visible to those which follow it
+    assert_eq!(eval("(let ((a 1) (b (+ a 1))) (list a b))").unwrap(), "(1 2)");
+    assert_eq!(eval("(let ((a 1) (b (+ a 1)) (a (* b 10))) (list a b))").unwrap(),
+        "(20 2)");
+    assert_eq!(eval("(let ((a 1)) (let ((a (+ a 1)) (b a)) b))").unwrap(), "2");
+
+    // A bound lambda does not see its own name; an outer binding is used
+    assert_eq!(eval("(let ((f (lambda (a) a))) f)").unwrap(), "<lambda>");
+    assert_eq!(eval("
+        (let ((f (lambda (x) (* x 10))))
+          (let ((f (lambda (x) (f (+ x 1)))))
+            (f 1)))
+        ").unwrap(), "20");
+    assert_eq!(run("
+        (define (f x) x)
+        (let ((f (lambda (x) (f (+ x 1))))) (f 1))
+        ").unwrap(), ["f", "2"]);
+
+    // `let*` is an alias for `let`
+    assert_eq!(eval("(let* ((a 1) (b (+ a 1))) (list a b))").unwrap(), "(1 2)");
+    assert_eq!(eval("(let* () 1)").unwrap(), "1");
+}
+
+#[test]
+fn test_tail_call() {
+    assert_eq!(eval("
+        (let ((ping (lambda (n a b) (if (= n 0) 'ping (b (- n 1) a b))))
+              (pong (lambda (n a b) (if (= n 0) 'pong (a (- n 1) a b)))))
+          (ping 100001 ping pong))
+        ").unwrap(), "pong");
+
+    assert_eq!(run("
+        (define (my-even n) (if (= n 0) true (my-odd (- n 1))))
+        (define (my-odd n) (if (= n 0) false (my-even (- n 1))))
+        (my-even 100001)
+        ").unwrap(), ["my-even", "my-odd", "false"]);
+
+    // Functions which are not lambdas are called normally
+    assert_eq!(eval("(let ((f (lambda (a b) (max a b)))) (f 1 2))").unwrap(), "2");
+    assert_eq!(eval("(let ((f (lambda (a b) (a b)))) (f id 1))").unwrap(), "1");
+}
+
+#[test]
+fn test_let_fn() {
+    assert_eq!(eval("
+        (let-fn ((fact (n) (if (< n 1) 1 (* n (fact (- n 1))))))
+          (fact 20))
+        ").unwrap(), "2432902008176640000");
+
+    assert_eq!(eval("
+        (let-fn ((depth (n) (if (< n 1) 0 (+ 1 (depth (- n 1))))))
+          (depth 1000))
+        ").unwrap(), "1000");
+
+    // Self calls in tail position do not grow the stack
+    assert_eq!(eval("
+        (let-fn ((count (n acc) (if (< n 1) acc (count (- n 1) (+ acc 1)))))
+          (count 100000 0))
+        ").unwrap(), "100000");
+
+    // Functions may capture outer values and call earlier siblings
+    assert_eq!(eval("
+        (let ((k 3))
+          (let-fn ((add-k (x) (+ x k))
+                   (twice (x) (add-k (add-k x))))
+            (twice 1)))
+        ").unwrap(), "7");
+
+    // Local functions shadow global values
+    assert_eq!(run("
+        (define (f) 'global)
+        (let-fn ((f () 'local)) (f))
+        ").unwrap(), ["f", "local"]);
+
+    assert_eq!(eval("(let-fn () 1)").unwrap(), "1");
+    assert_eq!(eval("(let-fn ((f (a) a)) f)").unwrap(), "<lambda f>");
+
+    assert_eq!(error_message("(let-fn ((f (a) (f a 1))) (f 1))"),
+        "`f` expected exactly 1 argument; found 2");
+    assert_eq!(error_message("(let-fn ((f (a) a) (g (x) (f x x))) (g 1))"),
+        "`f` expected exactly 1 argument; found 2");
+    assert_matches!(eval("(let-fn ((f a a)) 1)").unwrap_err(),
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
 }
 
 #[test]
 fn test_chars() {
     assert_eq!(eval(r#"(chars "")"#).unwrap(), "()");
-    assert_eq!(eval(r#"(chars "foo")"#).unwrap(), "(#'f' #'o' #'o')");
+    assert_eq!(eval(r#"(chars "foo")"#).unwrap(), r"(#\f #\o #\o)");
     assert_eq!(eval(r#"(chars "halo thar")"#).unwrap(),
-        "(#'h' #'a' #'l' #'o' #' ' #'t' #'h' #'a' #'r')");
+        r"(#\h #\a #\l #\o #\space #\t #\h #\a #\r)");
 }
 
 #[test]
@@ -747,6 +1247,74 @@ fn test_string() {
     assert_eq!(eval(r#"(string "foo")"#).unwrap(), r#""foo""#);
 }
 
+#[test]
+fn test_string_chars() {
+    assert_eq!(eval(r#"(chars "h\u{e9}\u{1f600}")"#).unwrap(),
+        "(#\\h #\\\u{e9} #\\\u{1f600})");
+
+    assert_eq!(eval_str(r#"(string-from-chars ())"#).unwrap(), "");
+    assert_eq!(eval_str(r#"(string-from-chars (chars "h\u{e9}\u{1f600}"))"#).unwrap(),
+        "h\u{e9}\u{1f600}");
+    assert_eq!(eval_str(r#"(string-from-chars (vector #'a' #'b'))"#).unwrap(), "ab");
+    assert_matches!(eval(r#"(string-from-chars '(#'a' "b"))"#).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "char", found: "string"}));
+
+    assert_eq!(eval_str(r#"(map-string id "h\u{e9}\u{1f600}")"#).unwrap(),
+        "h\u{e9}\u{1f600}");
+    assert_eq!(eval_str(r#"(map-string (lambda (c) (concat c c)) "a\u{1f600}")"#).unwrap(),
+        "aa\u{1f600}\u{1f600}");
+    assert_matches!(eval(r#"(map-string (lambda (c) 1) "a")"#).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "char or string", ..}));
+
+    assert_eq!(eval(r#"(string-len "")"#).unwrap(), "0");
+    assert_eq!(eval(r#"(string-len "h\u{e9}\u{1f600}")"#).unwrap(), "3");
+    assert_eq!(eval(r#"(string-byte-len "h\u{e9}\u{1f600}")"#).unwrap(), "7");
+}
+
+#[test]
+fn test_string_escape() {
+    assert_eq!(eval(r#"(string-escape "hello\nworld")"#).unwrap(),
+        r#""hello\\nworld""#);
+    assert_eq!(eval_str(r#"(string-escape "a\"b\\c\td\x7f")"#).unwrap(),
+        r#"a\"b\\c\td\u{7f}"#);
+
+    assert_eq!(eval_str(r#"(string-unescape "a\\nb\\u{e9}")"#).unwrap(), "a\nb\u{e9}");
+    assert_matches!(eval(r#"(string-unescape "\\q")"#).unwrap_err(),
+        Error::ParseError(_));
+
+    assert_eq!(eval_str(r#"(string-unescape (string-escape "\"\\\0\n\r\t'\x1b\u{1f600}"))"#)
+        .unwrap(), "\"\\\0\n\r\t'\x1b\u{1f600}");
+
+    assert_eq!(eval_str(r#"(string-escape-html "<a href=\"x\">'&'</a>")"#).unwrap(),
+        "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;");
+}
+
+#[test]
+fn test_string_ci() {
+    assert_eq!(eval(r#"(string-ci=? "Hello" "hELLO")"#).unwrap(), "true");
+    assert_eq!(eval(r#"(string-ci=? "Hello" "Hell")"#).unwrap(), "false");
+    assert_eq!(eval(r#"(string-ci=? "\u{3a3}\u{3c3}" "\u{3c3}\u{3c2}")"#).unwrap(), "true");
+
+    // Simple case folding without locale: Turkish dotless and dotted `i`
+    // fold only to themselves, while `I` folds to `i`.
+    assert_eq!(eval(r#"(string-ci=? "I" "i")"#).unwrap(), "true");
+    assert_eq!(eval(r#"(string-ci=? "\u{131}" "i")"#).unwrap(), "false");
+    assert_eq!(eval(r#"(string-ci=? "\u{131}" "I")"#).unwrap(), "false");
+    assert_eq!(eval(r#"(string-ci=? "\u{130}" "i")"#).unwrap(), "false");
+    assert_eq!(eval(r#"(string-ci=? "\u{130}" "I")"#).unwrap(), "false");
+
+    // Simple case folding maps one char to one char: `ß` does not match `ss`,
+    // but capital sharp s folds to `ß`.
+    assert_eq!(eval(r#"(string-ci=? "stra\u{df}e" "STRASSE")"#).unwrap(), "false");
+    assert_eq!(eval(r#"(string-ci=? "\u{1e9e}" "\u{df}")"#).unwrap(), "true");
+
+    assert_eq!(eval(r#"(starts-with? "Hello" "He")"#).unwrap(), "true");
+    assert_eq!(eval(r#"(starts-with? "Hello" "he")"#).unwrap(), "false");
+    assert_eq!(eval(r#"(starts-with-ci? "Hello" "he")"#).unwrap(), "true");
+    assert_eq!(eval(r#"(starts-with-ci? "He" "hello")"#).unwrap(), "false");
+    assert_eq!(eval(r#"(starts-with-ci? "abc" "")"#).unwrap(), "true");
+}
+
 #[test]
 fn test_slice() {
     assert_eq!(eval("(slice () 0 0)").unwrap(), "()");
@@ -774,7 +1342,7 @@ fn test_elt() {
     assert_matches!(eval("(elt '(1 2) 2)").unwrap_err(),
         Error::ExecError(ExecError::OutOfBounds(2)));
     assert_matches!(eval("(elt () -1)").unwrap_err(),
-        Error::ExecError(ExecError::Overflow));
+        Error::ExecError(ExecError::IntegerOutOfRange{expected: "usize", ..}));
 }
 
 #[test]
@@ -828,6 +1396,61 @@ fn test_list_fns() {
     assert_eq!(eval("(tail '(1))").unwrap(), "()");
 }
 
+#[test]
+fn test_constant_arg_types() {
+    for s in &[
+            "(first 1)",
+            "(tail 1.0)",
+            "(init #'a')",
+            "(last :foo)",
+            "(append true 1)",
+            "(first 'foo)",
+            "(tail ''(1 2))",
+            ] {
+        assert_matches!(eval(s).unwrap_err(),
+            Error::CompileError(CompileError::ConstantTypeError{..}));
+    }
+
+    // Strings and vectors are only a warning, outside of strict mode
+    for s in &[
+            "(first \"abc\")",
+            "(tail '#(1 2))",
+            ] {
+        assert_matches!(eval(s).unwrap_err(),
+            Error::ExecError(ExecError::TypeError{..}));
+        assert_matches!(run_strict(s).unwrap_err(),
+            Error::CompileError(CompileError::ConstantTypeError{..}));
+    }
+
+    // Quoted constants are checked as the values they produce
+    assert_eq!(eval("(first '(1 2))").unwrap(), "1");
+    assert_eq!(eval("(append () 1)").unwrap(), "(1)");
+    assert_matches!(eval("(first ())").unwrap_err(),
+        Error::ExecError(_));
+
+    // Non-constant arguments are only checked at runtime
+    assert_matches!(eval("(let ((a 1)) (first a))").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{..}));
+    assert_matches!(eval("(init (id 1))").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{..}));
+}
+
+#[test]
+fn test_shared_list_slices() {
+    // `tail` and `init` share the buffer of a list, leaving it unchanged.
+    assert_eq!(run("
+        (define li (range-list (range 100000)))
+        (define (walk li acc)
+          (if (null li) acc (walk (tail li) (+ acc (first li)))))
+        (walk li 0)
+        (first li)
+        (last (init li))
+        (= (tail (init li)) (init (tail li)))
+        (len li)
+        ").unwrap(),
+        ["li", "walk", "4999950000", "0", "99998", "true", "100000"]);
+}
+
 #[test]
 fn test_list() {
     assert_eq!(eval("(list 1 2 (+ 1 2))").unwrap(), "(1 2 3)");
@@ -839,6 +1462,147 @@ fn test_reverse() {
     assert_eq!(eval("(reverse '(1 2 3))").unwrap(), "(3 2 1)");
 }
 
+#[test]
+fn test_vector() {
+    assert_eq!(eval("(vector)").unwrap(), "#()");
+    assert_eq!(eval("(vector 1 2 (+ 1 2))").unwrap(), "#(1 2 3)");
+    assert_eq!(eval("(type-of (vector))").unwrap(), "vector");
+
+    assert_eq!(eval("(vec-ref (vector 1 2 3) 2)").unwrap(), "3");
+    assert_matches!(eval("(vec-ref (vector 1 2 3) 3)").unwrap_err(),
+        Error::ExecError(ExecError::OutOfBounds(3)));
+    assert_eq!(eval("(vec-len (vector))").unwrap(), "0");
+    assert_eq!(eval("(vec-len (vector 1 2 3))").unwrap(), "3");
+
+    assert_eq!(eval("(vec-slice (vector 1 2 3) 1 3)").unwrap(), "#(2 3)");
+    assert_eq!(eval("(vec-slice (vector 1 2 3) 1 1)").unwrap(), "#()");
+    assert_matches!(eval("(vec-slice (vector 1 2 3) 0 4)").unwrap_err(),
+        Error::ExecError(ExecError::OutOfBounds(4)));
+
+    assert_eq!(eval("(vec->list (vector 1 2 3))").unwrap(), "(1 2 3)");
+    assert_eq!(eval("(vec->list (vector))").unwrap(), "()");
+    assert_eq!(eval("(list->vec '(1 2 3))").unwrap(), "#(1 2 3)");
+    assert_eq!(eval("(list->vec ())").unwrap(), "#()");
+
+    assert_eq!(eval("(= (vector 1 2) (list->vec '(1 2)))").unwrap(), "true");
+    assert_eq!(eval("(= (vector 1 2) (vector 1 3))").unwrap(), "false");
+    assert_eq!(eval("(< (vector 1 2) (vector 1 3))").unwrap(), "true");
+    assert_matches!(eval("(= (vector 1 2) '(1 2))").unwrap_err(),
+        Error::ExecError(ExecError::TypeMismatch{..}));
+
+    assert_eq!(eval("(len (vector 1 2))").unwrap(), "2");
+    assert_eq!(eval("(elt (vector 1 2) 1)").unwrap(), "2");
+    assert_eq!(eval("(slice (vector 1 2 3) 0 2)").unwrap(), "#(1 2)");
+}
+
+#[test]
+fn test_vector_literal() {
+    assert_eq!(eval("#()").unwrap(), "#()");
+    assert_eq!(eval("#(1 foo (bar :baz) #(\"x\"))").unwrap(),
+        r#"#(1 foo (bar :baz) #("x"))"#);
+    assert_eq!(eval("(type-of #(1))").unwrap(), "vector");
+    assert_eq!(eval("(= #(1 2) (vector 1 2))").unwrap(), "true");
+    assert_eq!(eval("'#(a 'b)").unwrap(), "#(a 'b)");
+
+    assert_eq!(eval("(let ((x 1) (xs '(2 3))) `#(,x ,@xs y))").unwrap(), "#(1 2 3 y)");
+    assert_eq!(eval("(let ((xs ())) `#(,@xs))").unwrap(), "#()");
+    assert_eq!(eval("`#()").unwrap(), "#()");
+    assert_eq!(eval("`(a #(,(+ 1 2)))").unwrap(), "(a #(3))");
+    assert_eq!(eval("``#(,,(+ 1 2) ,x)").unwrap(), "`#(3 ,x)");
+
+    // Printed vectors read back as equal values
+    for src in &["#()", "#(1 2/3 4.5 #\\a)", "'#(a #(b (c)) :d)", "`#(1 ,(vector 2))"] {
+        let interp = Interpreter::new();
+        let v = interp.run_single_expr(src, None).unwrap();
+        let printed = interp.format_value(&v);
+        let w = interp.run_single_expr(&format!("'{}", printed), None).unwrap();
+        assert!(v.is_identical(&w), "{} printed as {}", src, printed);
+    }
+
+    assert_matches!(eval("#(1 2").unwrap_err(),
+        Error::ParseError(ParseError{kind: ParseErrorKind::MissingCloseParen, ..}));
+}
+
+#[test]
+fn test_quote_round_trip() {
+    let interp = Interpreter::new();
+
+    // Quoted forms are printed as they are written
+    for src in &[
+            "'a", "''a", "'(a 'b ''c)", "`(a ,b ,@c)", "``(a ,,b ,,@c ,@,d)",
+            "'`(a ,b)", "`',a", "`(a #(,b ,@c))", "'(:a #\\a \"b\" ())",
+            ] {
+        let v = interp.parse_single_expr(src, None).unwrap();
+        assert_eq!(interp.format_value(&v), *src);
+    }
+
+    // Expansions built by nested quasiquotes are printed as readable source
+    for src in &[
+            "(let ((x 'y)) ``(a ,,x ,@,x))",
+            "(let ((xs '(1 2))) ``(f ,@,@xs ',x))",
+            "(let ((d '(1 2))) `(a `(b ,(c ,@d))))",
+            "(let ((name 'wrap) (f 'car)) `(macro (,name x) `(,',f ,x ,@(list x))))",
+            ] {
+        let v = interp.run_single_expr(src, None).unwrap();
+        let printed = interp.format_value(&v);
+        let w = interp.parse_single_expr(&printed, None).unwrap();
+
+        assert!(v.is_identical(&w), "{} printed as {}", src, printed);
+        assert!(v.is_equal(&w).unwrap(), "{} printed as {}", src, printed);
+    }
+
+    assert_eq!(eval("(let ((f 'car)) `(macro (m x) `(,',f ,x)))").unwrap(),
+        "(macro (m x) `(,'car ,x))");
+    assert_eq!(eval("(= '`(a ,@b) '`(a ,@b))").unwrap(), "true");
+    assert_eq!(eval("(= '`(a ,@b) '`(a ,b))").unwrap(), "false");
+}
+
+fn vector_ptr(v: &Value) -> *const Value {
+    match *v {
+        Value::Vector(ref v) => v.as_ptr(),
+        ref v => panic!("expected vector; got {}", v.type_name())
+    }
+}
+
+#[test]
+fn test_vec_set() {
+    assert_eq!(eval("(vec-set (vector 1 2 3) 0 4)").unwrap(), "#(4 2 3)");
+    assert_matches!(eval("(vec-set (vector 1 2 3) 3 4)").unwrap_err(),
+        Error::ExecError(ExecError::OutOfBounds(3)));
+
+    // Shared vectors are never observably modified
+    assert_eq!(run("
+        (define a (vector 1 2 3))
+        (define b (vec-set a 0 4))
+        a
+        b
+        (vec-set (vec-set a 1 5) 2 6)
+        a
+        ").unwrap(),
+        ["a", "b", "#(1 2 3)", "#(4 2 3)", "#(1 5 6)", "#(1 2 3)"]);
+
+    let interp = Interpreter::new();
+    let vec_set = interp.run_single_expr("vec-set", None).unwrap();
+
+    let mut v = Value::new_vector(vec![0; 10000]);
+    let shared = v.clone();
+
+    // Updating a shared vector copies it
+    v = interp.call_value(vec_set.clone(), vec![v, 0.into(), 1.into()]).unwrap();
+    assert!(vector_ptr(&v) != vector_ptr(&shared));
+
+    // Updating a uniquely held vector modifies it in place
+    let ptr = vector_ptr(&v);
+
+    for i in 0..10000 {
+        v = interp.call_value(vec_set.clone(), vec![v, i.into(), 2.into()]).unwrap();
+        assert_eq!(vector_ptr(&v), ptr);
+    }
+
+    assert!(Vec::<i32>::from_value(shared).unwrap().iter().all(|&n| n == 0));
+    assert!(Vec::<i32>::from_value(v).unwrap().iter().all(|&n| n == 2));
+}
+
 /* TODO: These are commented out until standard library stuff is figured out.
  * Mainly, whether a standard library will exist and where the interpreter
  * will look for it by default.
@@ -958,6 +1722,60 @@ fn test_float() {
     assert_eq!(eval("(type-of (float 123/1))").unwrap(), "float");
 }
 
+#[test]
+fn test_float_bits() {
+    assert_eq!(eval("(= (float-bits 1.0) 0x3FF0000000000000)").unwrap(), "true");
+    assert_eq!(eval("(float-bits -0.0)").unwrap(), "9223372036854775808");
+
+    assert_eq!(eval("(float->bytes 1.0 :big)").unwrap(), "(63 240 0 0 0 0 0 0)");
+    assert_eq!(eval("(float->bytes 1.0 :little)").unwrap(), "(0 0 0 0 0 0 240 63)");
+
+    for x in &["0.0", "-1.5", "1e300", "3.141592653589793", "5e-324", "(inf)"] {
+        for endian in &[":big", ":little"] {
+            assert_eq!(eval(&format!("(= (bytes->float (float->bytes {0} {1}) {1}) {0})",
+                x, endian)).unwrap(), "true");
+        }
+    }
+
+    assert_eq!(eval("(nan (bytes->float (float->bytes (nan) :little) :little))").unwrap(),
+        "true");
+    assert_eq!(eval("(= (bytes->float (vector 0 0 0 0 0 0 240 63) :little) 1.0)").unwrap(),
+        "true");
+
+    assert_matches!(eval("(float->bytes 1.0 :middle)").unwrap_err(),
+        Error::ExecError(ExecError::UnrecognizedKeyword(_)));
+    assert_matches!(eval("(bytes->float '(1 2 3) :big)").unwrap_err(),
+        Error::ExecError(ExecError::ByteCount{expected: 8, found: 3}));
+    assert_matches!(eval("(bytes->float '(0 0 0 0 0 0 0 256) :big)").unwrap_err(),
+        Error::ExecError(ExecError::Overflow));
+}
+
+#[test]
+fn test_integer_bits() {
+    assert_eq!(eval("(integer-bits 5 :little)").unwrap(),
+        "(1 0 1 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 \
+          0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0)");
+    assert_eq!(eval("(slice (integer-bits 5 :big) 60 64)").unwrap(), "(0 1 0 1)");
+    assert_eq!(eval("(first (integer-bits -1 :big))").unwrap(), "1");
+    assert_eq!(eval("(len (integer-bits 0 :big))").unwrap(), "64");
+
+    assert_eq!(eval("(integer->bytes 258 :big)").unwrap(), "(0 0 0 0 0 0 1 2)");
+    assert_eq!(eval("(integer->bytes -2 :little)").unwrap(),
+        "(254 255 255 255 255 255 255 255)");
+
+    for n in &["0", "1", "-1", "258", "-9223372036854775808", "9223372036854775807"] {
+        for endian in &[":big", ":little"] {
+            assert_eq!(eval(&format!("(bytes->integer (integer->bytes {} {1}) {1})",
+                n, endian)).unwrap(), *n);
+        }
+    }
+
+    assert_matches!(eval("(integer->bytes 9223372036854775808 :big)").unwrap_err(),
+        Error::ExecError(ExecError::Overflow));
+    assert_matches!(eval("(integer-bits 1 :foo)").unwrap_err(),
+        Error::ExecError(ExecError::UnrecognizedKeyword(_)));
+}
+
 #[test]
 fn test_inf() {
     assert_eq!(eval("(inf)").unwrap(), "inf");
@@ -1013,7 +1831,7 @@ fn test_ratio() {
 #[test]
 fn test_id() {
     assert_eq!(eval("(id 1)").unwrap(), "1");
-    assert_eq!(eval("(id #'a')").unwrap(), "#'a'");
+    assert_eq!(eval("(id #'a')").unwrap(), r"#\a");
     assert_eq!(eval("(id \"a\")").unwrap(), "\"a\"");
     assert_eq!(eval("(id '(1 2 3))").unwrap(), "(1 2 3)");
 }
@@ -1047,6 +1865,34 @@ fn test_null() {
     assert_eq!(eval("(null 1)").unwrap(), "false");
 }
 
+#[test]
+fn test_keyword() {
+    assert_eq!(eval(":foo/bar").unwrap(), ":foo/bar");
+    assert_eq!(eval("(= :foo/bar :foo/bar)").unwrap(), "true");
+    assert_eq!(eval("(= :foo/bar :bar)").unwrap(), "false");
+    assert_eq!(eval("(= :foo/bar :baz/bar)").unwrap(), "false");
+    assert_eq!(eval("(format \"~a\" :foo/bar)").unwrap(), r#"":foo/bar""#);
+
+    assert_eq!(eval("(keyword-namespace :foo/bar)").unwrap(), r#""foo""#);
+    assert_eq!(eval("(keyword-namespace :foo)").unwrap(), "()");
+    assert_eq!(eval("(keyword-namespace :/)").unwrap(), "()");
+    assert_eq!(eval("(keyword-name :foo/bar)").unwrap(), r#""bar""#);
+    assert_eq!(eval("(keyword-name :foo/bar/baz)").unwrap(), r#""bar/baz""#);
+    assert_eq!(eval("(keyword-name :foo)").unwrap(), r#""foo""#);
+    assert_matches!(eval("(keyword-name 'foo)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{..}));
+}
+
+#[test]
+fn test_is_list() {
+    assert_eq!(eval("(list? '())").unwrap(), "true");
+    assert_eq!(eval("(list? '(1 2))").unwrap(), "true");
+    assert_eq!(eval("(list? (list 1 2))").unwrap(), "true");
+    assert_eq!(eval("(list? 42)").unwrap(), "false");
+    assert_eq!(eval("(list? 'a)").unwrap(), "false");
+    assert_eq!(eval("(list? \"ab\")").unwrap(), "false");
+}
+
 #[test]
 fn test_type_of() {
     assert_eq!(eval("(type-of ())").unwrap(), "unit");
@@ -1161,55 +2007,1128 @@ fn test_define() {
     assert_matches!(run("
         (define (foo a :optional b :key c) ())
         ").unwrap_err(),
-        Error::CompileError(CompileError::SyntaxError(_)));
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
 
     assert_matches!(run("
         (define (foo a :key b :optional c) ())
         ").unwrap_err(),
-        Error::CompileError(CompileError::SyntaxError(_)));
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
 
     assert_matches!(run("
         (define (foo a :key b :rest rest) ())
         ").unwrap_err(),
-        Error::CompileError(CompileError::SyntaxError(_)));
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
 }
 
 #[test]
-fn test_lambda() {
-    assert_eq!(eval("((lambda (n) n) 1)").unwrap(), "1");
-    assert_eq!(eval("((lambda (:rest rest) rest) 1 2 3)").unwrap(), "(1 2 3)");
-}
+fn test_param_defaults() {
+    assert_eq!(run("
+        (define (double n) (* n 2))
+        (define (foo :optional (a 1) (b (double a)) (c (list a b)))
+          (list a b c))
+        (foo)
+        (foo 2)
+        (foo 2 3)
+        ").unwrap(),
+        ["double", "foo", "(1 2 (1 2))", "(2 4 (2 4))", "(2 3 (2 3))"]);
 
-#[test]
-fn test_do() {
-    assert_eq!(eval("(do 1 2 3)").unwrap(), "3");
-}
+    assert_eq!(run("
+        (define (double n) (* n 2))
+        (define (foo :key (a 1) (b (double a)) (c (list a b)))
+          (list a b c))
+        (foo)
+        (foo :a 2)
+        (foo :b 3)
+        ").unwrap(),
+        ["double", "foo", "(1 2 (1 2))", "(2 4 (2 4))", "(1 3 (1 3))"]);
 
-#[test]
-fn test_macro() {
     assert_eq!(run("
-        (macro (quote a) `',a)
-        (quote foo)
+        (define (foo a :optional (b (let ((x (* a 2))) (+ x 1)))
+                                 (c ((lambda () (+ a b)))))
+          (list a b c))
+        (foo 1)
+        (foo 1 10)
         ").unwrap(),
-        ["quote", "foo"]);
+        ["foo", "(1 3 4)", "(1 10 11)"]);
 
-    assert_matches!(eval("(define (foo a) (macro (bar) a))").unwrap_err(),
-        Error::CompileError(_));
+    let interp = Interpreter::new();
 
-    assert_matches!(run("
-        (macro (foo) '(bar))
-        (macro (bar) '(foo))
+    let c = interp.compile_exprs("
+        (define (fail) (panic 'boom))
+        (define (foo a :optional (b (fail))) (list a b))
+        (define (bar :key (c (fail))) c)
+        ").unwrap();
+    interp.execute_program(c).unwrap();
+
+    assert_eq!(interp.format_value(
+        &interp.run_single_expr("(foo 1 2)", None).unwrap()), "(1 2)");
+
+    let e = interp.run_single_expr("(foo 1)", None).unwrap_err();
+
+    match e {
+        Error::ExecError(ExecError::DefaultError{function, param, ref error}) => {
+            let names = interp.get_scope().borrow_names();
+            assert_eq!(function.map(|n| names.get(n)), Some("foo"));
+            assert_eq!(names.get(param), "b");
+            assert_matches!(&**error, &Error::ExecError(ExecError::Panic(_)));
+        }
+        e => panic!("expected default error; got {:?}", e)
+    }
+
+    let e = interp.run_single_expr("(bar)", None).unwrap_err();
+
+    match e {
+        Error::ExecError(ExecError::DefaultError{function, param, ..}) => {
+            let names = interp.get_scope().borrow_names();
+            assert_eq!(function.map(|n| names.get(n)), Some("bar"));
+            assert_eq!(names.get(param), "c");
+        }
+        e => panic!("expected default error; got {:?}", e)
+    }
+
+    // Errors raised within the function body are not wrapped
+    assert_matches!(run("
+        (define (foo :optional (a 1)) (panic a))
+        (foo)
+        ").unwrap_err(),
+        Error::ExecError(ExecError::Panic(_)));
+}
+
+#[test]
+fn test_param_supplied() {
+    assert_eq!(run("
+        (define (foo a :optional (b () b-supplied) (c b c-supplied))
+          (list a b b-supplied c c-supplied))
+        (foo 1)
+        (foo 1 ())
+        (foo 1 2)
+        (foo 1 2 ())
+        ").unwrap(),
+        ["foo",
+         "(1 () false () false)",
+         "(1 () true () false)",
+         "(1 2 true 2 false)",
+         "(1 2 true () true)"]);
+
+    assert_eq!(run("
+        (define (foo :key (a 1 a-supplied) (b (if a-supplied a 0) b-supplied))
+          (list a a-supplied b b-supplied))
+        (foo)
+        (foo :a ())
+        (foo :b ())
+        (foo :b 2 :a 3)
+        ").unwrap(),
+        ["foo",
+         "(1 false 0 false)",
+         "(() true () false)",
+         "(1 false () true)",
+         "(3 true 2 true)"]);
+
+    assert_eq!(eval("((lambda (:optional (a 1 s)) (list a s)) ())").unwrap(),
+        "(() true)");
+
+    assert_matches!(eval("((lambda (a :optional (b 1 a)) a) 1)").unwrap_err(),
+        Error::CompileError(CompileError::DuplicateParameter(_)));
+    assert_matches!(eval("((lambda ((a 1 s)) a) 1)").unwrap_err(),
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
+}
+
+#[test]
+fn test_rest_keywords() {
+    // Keywords are collected into the rest list by default.
+    assert_eq!(run(r#"
+        (define (foo a :rest args) (list a args))
+        (foo 1 :color "red")
+        (apply foo '(1 :color "red"))
+        "#).unwrap(),
+        ["foo", r#"(1 (:color "red"))"#, r#"(1 (:color "red"))"#]);
+
+    let interp = Interpreter::new();
+    interp.get_scope().set_strict(true);
+
+    interp.run_code("(define (foo a :rest args) args)", None).unwrap();
+    assert_matches!(interp.run_code("(foo :a :b)", None).unwrap_err(),
+        Error::CompileError(CompileError::UnexpectedKeyword{..}));
+    let e = interp.run_code(r#"(foo 1 :color "red")"#, None).unwrap_err();
+    assert_eq!(interp.format_error(&e), "\
+compile error: keyword argument `:color` passed to `foo`, which accepts no \
+keyword parameters; it will be collected into the rest list
+");
+
+    let interp = Interpreter::new();
+    interp.get_scope().set_strict_keywords(true);
+
+    interp.run_code("
+        (define (foo a :rest args) args)
+        (define (bar :key a) a)
+        (define (count n :rest args)
+          (if (zero n) args (count (- n 1) (first (first args)))))
+        ", None).unwrap();
+
+    let v = interp.run_code("(foo :a 1 2)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "(1 2)");
+    let v = interp.run_code("(bar :a 1)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "1");
+
+    let e = interp.run_code("(apply foo '(1 :color 2))", None).unwrap_err();
+    assert_matches!(e, Error::ExecError(ExecError::UnexpectedKeyword{..}));
+    assert_eq!(interp.format_error(&e), "\
+execution error: keyword argument `:color` passed to `foo`, \
+which accepts no keyword parameters
+");
+
+    assert_matches!(interp.run_code("((lambda (:rest r) r) :x)", None).unwrap_err(),
+        Error::ExecError(ExecError::UnexpectedKeyword{name: None, ..}));
+    assert_matches!(interp.run_code("(count 1 :x)", None).unwrap_err(),
+        Error::ExecError(ExecError::UnexpectedKeyword{..}));
+    // The keyword is passed by a tail call.
+    assert_matches!(interp.run_code("(count 1 (list :x))", None).unwrap_err(),
+        Error::ExecError(ExecError::UnexpectedKeyword{..}));
+}
+
+#[test]
+fn test_define_overload() {
+    assert_eq!(run("
+        (define (area r) (* r r))
+        (define-overload (area w h) (* w h))
+        (area 3)
+        (area 2 5)
+        area
+        ").unwrap(),
+        ["area", "area", "9", "10", "<lambda area (1 2)>"]);
+
+    // Overloading a function with the same arity replaces only that function
+    assert_eq!(run("
+        (define (foo a) 1)
+        (define-overload (foo a b) 2)
+        (define-overload (foo a) 3)
+        (list (foo 0) (foo 0 0))
+        ").unwrap(),
+        ["foo", "foo", "foo", "(3 2)"]);
+
+    // A plain `define` replaces all definitions
+    let interp = Interpreter::new();
+    assert_eq!(interp.run_code("
+        (define (foo a) 1)
+        (define-overload (foo a b) 2)
+        (define (foo a b) 3)
+        (foo 0 0)
+        ", None).map(|v| interp.format_value(&v)).unwrap(), "3");
+    assert_matches!(interp.run_code("(foo 0)", None).unwrap_err(),
+        Error::ExecError(ExecError::ArityError{..}));
+    assert_eq!(run("(define (foo a) 1) (define (foo a b) 2) foo").unwrap(),
+        ["foo", "foo", "<lambda foo>"]);
+
+    // Overloaded functions may call one another by name
+    assert_eq!(run("
+        (define (sum li) (sum li 0))
+        (define-overload (sum li n)
+          (if (null li) n (sum (tail li) (+ n (first li)))))
+        (sum '(1 2 3 4))
+        ").unwrap(),
+        ["sum", "sum", "10"]);
+
+    let interp = Interpreter::new();
+    let e = interp.run_code("
+        (define (foo a) 1)
+        (define-overload (foo a b c) 3)
+        (foo 1 2)
+        ", None).unwrap_err();
+    assert_matches!(e, Error::ExecError(ExecError::OverloadArityError{
+        ref expected, found: 2, ..}) if expected == &[1, 3]);
+    assert_eq!(display_names(&interp.get_scope().borrow_names(), &e).to_string(),
+        "`foo` expected 1 or 3 arguments; found 2");
+
+    // Functions with optional, keyword, or rest parameters cannot be overloaded
+    assert_matches!(interp.run_code("(define-overload (foo :rest r) r)", None).unwrap_err(),
+        Error::CompileError(CompileError::InvalidOverload(_)));
+    assert_matches!(interp.run_code("(define-overload (foo a :key b) b)", None).unwrap_err(),
+        Error::CompileError(CompileError::InvalidOverload(_)));
+    assert_matches!(interp.run_code("(define-overload foo 1)", None).unwrap_err(),
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
+}
+
+#[test]
+fn test_lambda() {
+    assert_eq!(eval("((lambda (n) n) 1)").unwrap(), "1");
+    assert_eq!(eval("((lambda (:rest rest) rest) 1 2 3)").unwrap(), "(1 2 3)");
+}
+
+#[test]
+fn test_do() {
+    assert_eq!(eval("(do 1 2 3)").unwrap(), "3");
+}
+
+#[test]
+fn test_macro() {
+    assert_eq!(run("
+        (macro (quote a) `',a)
+        (quote foo)
+        ").unwrap(),
+        ["quote", "foo"]);
+
+    assert_matches!(eval("(define (foo a) (macro (bar) a))").unwrap_err(),
+        Error::CompileError(_));
+
+    assert_matches!(run("
+        (macro (foo) '(bar))
+        (macro (bar) '(foo))
         (foo)
         ").unwrap_err(),
         Error::CompileError(CompileError::MacroRecursionExceeded));
 }
 
+#[test]
+fn test_dynamic_variables() {
+    assert_eq!(run("
+        (defvar *indent* 0)
+        (define (indent) *indent*)
+        (with-bindings ((*indent* (+ *indent* 2)))
+          (with-bindings ((*indent* (+ *indent* 2)))
+            (indent)))
+        (with-bindings ((*indent* 1)) (indent) *indent*)
+        (indent)
+        ").unwrap(),
+        ["*indent*", "indent", "4", "1", "0"]);
+
+    // Bindings are sequential
+    assert_eq!(run("
+        (defvar *a* 1)
+        (defvar *b* 2)
+        (with-bindings ((*a* 10) (*b* (+ *a* 1))) (list *a* *b*))
+        (list *a* *b*)
+        ").unwrap(),
+        ["*a*", "*b*", "(10 11)", "(1 2)"]);
+
+    // Closures see the binding active when called, not when created
+    assert_eq!(run("
+        (defvar *x* 1)
+        (define f (with-bindings ((*x* 2)) (lambda () *x*)))
+        (f)
+        (with-bindings ((*x* 3)) (f))
+        ").unwrap(),
+        ["*x*", "f", "1", "3"]);
+
+    // Local bindings shadow dynamic variables
+    assert_eq!(run("
+        (defvar *x* 1)
+        (let ((*x* 2)) (with-bindings ((*x* 3)) *x*))
+        ").unwrap(),
+        ["*x*", "2"]);
+
+    // Dynamic variables may hold functions
+    assert_eq!(run("
+        (defvar *op* +)
+        (define (calc a b) (*op* a b))
+        (with-bindings ((*op* *)) (calc 3 4))
+        (calc 3 4)
+        ").unwrap(),
+        ["*op*", "calc", "12", "7"]);
+
+    // `define` assigns the global value
+    assert_eq!(run("
+        (defvar *x* 1)
+        (define *x* 2)
+        (with-bindings ((*x* 3)) *x*)
+        *x*
+        ").unwrap(),
+        ["*x*", "*x*", "3", "2"]);
+
+    assert_matches!(run("
+        (define x 1)
+        (with-bindings ((x 2)) x)
+        ").unwrap_err(),
+        Error::CompileError(CompileError::NotDynamic(_)));
+
+    assert_matches!(run_strict("(let () (defvar *x* 1))").unwrap_err(),
+        Error::CompileError(CompileError::NotTopLevel(_)));
+}
+
+#[test]
+fn test_dynamic_variables_error() {
+    let interp = Interpreter::new();
+
+    interp.run_code("
+        (defvar *x* 1)
+        (define (get-x) *x*)
+        (define (fail) (with-bindings ((*x* 2)) (panic (get-x))))
+        ", None).unwrap();
+
+    assert_matches!(interp.run_code("(with-bindings ((*x* 3)) (fail))", None),
+        Err(Error::ExecError(ExecError::Panic(Some(Value::Integer(_))))));
+
+    // Bindings are removed when an error is raised
+    let v = interp.run_code("(get-x)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "1");
+}
+
+#[test]
+fn test_unless_bound() {
+    let interp = Interpreter::new();
+
+    interp.run_code("(define retries 5) (defvar *level* 1)", None).unwrap();
+
+    let v = interp.run_code("
+        (unless-bound retries (panic ()))
+        (unless-bound timeout 30)
+        (unless-bound *level* 2)
+        (list retries timeout *level*)
+        ", None).unwrap();
+    assert_eq!(interp.format_value(&v), "(5 30 1)");
+
+    // Values are checked when the expression is compiled
+    assert_eq!(run("(define a 1) (unless-bound a 2) a").unwrap(),
+        ["a", "a", "2"]);
+}
+
+#[test]
+fn test_define_if_unbound() {
+    assert_eq!(run("
+        (define a 1)
+        (define-if-unbound a (panic ()))
+        (define-if-unbound b (+ a 1))
+        (define-if-unbound b (panic ()))
+        (list a b)
+        ").unwrap(),
+        ["a", "a", "b", "b", "(1 2)"]);
+
+    assert_eq!(run("
+        (defvar *level* 1)
+        (define-if-unbound *level* 2)
+        *level*
+        ").unwrap(),
+        ["*level*", "*level*", "1"]);
+}
+
+#[test]
+fn test_with_output_to_string() {
+    assert_eq!(eval(r#"(with-output-to-string (println "a") (println "b"))"#).unwrap(),
+        r#""a\nb\n""#);
+    assert_eq!(eval("(with-output-to-string)").unwrap(), r#""""#);
+    assert_eq!(eval("(with-output-to-string 1 2)").unwrap(), r#""""#);
+    assert_eq!(eval(r#"
+        (with-output-to-string
+          (display 1)
+          (display (with-output-to-string (display 2)))
+          (write "x"))
+        "#).unwrap(),
+        r#""12\"x\"""#);
+
+    // Output is no longer captured when an error is raised
+    assert_eq!(eval(r#"
+        (with-output-to-string
+          (print "a")
+          (guard (e :error (print "c"))
+            (with-output-to-string (print "b") (error "oops"))))
+        "#).unwrap(),
+        r#""ac""#);
+
+    let interp = Interpreter::new();
+
+    assert_matches!(interp.run_code(r#"(with-output-to-string (print "a") (/ 1 0))"#, None),
+        Err(Error::ExecError(ExecError::DivideByZero)));
+    assert_eq!(interp.get_scope().get_io().capture_depth(), 0);
+}
+
+fn node_count(s: &str) -> usize {
+    let interp = Interpreter::new();
+    interp.run_single_expr(s, None).unwrap().node_count()
+}
+
+#[test]
+fn test_node_count() {
+    assert_eq!(node_count("1"), 1);
+    assert_eq!(node_count("()"), 1);
+    assert_eq!(node_count("'foo"), 1);
+    assert_eq!(node_count("''foo"), 2);
+    assert_eq!(node_count("'(1 2 3)"), 4);
+    assert_eq!(node_count("'(1 (2 3) (4 (5)))"), 9);
+    assert_eq!(node_count("'(a `(b ,c ,@d))"), 9);
+
+    let interp = Interpreter::new();
+    interp.run_code("(struct Foo ((a list) (b integer)))", None).unwrap();
+    let v = interp.run_single_expr("(new Foo :a '(1 2) :b 3)", None).unwrap();
+    assert_eq!(v.node_count(), 5);
+}
+
+/// Returns a random expression built from a small set of atoms,
+/// so that a corpus of such expressions contains many equal values.
+fn random_expr(seed: &mut u64, depth: u32) -> String {
+    const ATOMS: &'static [&'static str] = &[
+        "0", "1", "2", "0.0", "-0.0", "1.0", "2.0", "0.5", "(/ 1 2)", "(/ 4 2)",
+        "'a", "'b", ":a", "#'a'", r#""a""#, r#""""#, "true", "()",
+    ];
+
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 7;
+    *seed ^= *seed << 17;
+
+    let n = (*seed >> 32) as usize;
+
+    if depth == 0 || n % 3 != 0 {
+        return ATOMS[n % ATOMS.len()].to_owned();
+    }
+
+    let a = random_expr(seed, depth - 1);
+    let b = random_expr(seed, depth - 1);
+
+    match n % 4 {
+        0 => format!("(list {} {})", a, b),
+        1 => format!("(vector {} {})", a, b),
+        2 => format!("(new Pair :a {} :b {})", a, b),
+        _ => format!("(list {})", a),
+    }
+}
+
+#[test]
+fn test_structural_hash() {
+    use ketos::{Integer, Ratio};
+
+    let interp = Interpreter::new();
+    interp.run_code("(struct Pair ((a any) (b any)))", None).unwrap();
+
+    let mut seed = 0x2545f4914f6cdd1d;
+    let mut values = vec![Value::Ratio(Ratio::from_integer(Integer::from_i64(2)))];
+
+    for _ in 0..300 {
+        let expr = random_expr(&mut seed, 3);
+        values.push(interp.run_single_expr(&expr, None).unwrap());
+    }
+
+    let hash = |v: &Value| v.structural_hash(&interp.get_scope().borrow_names());
+    let hashes = values.iter().map(|v| hash(v).unwrap()).collect::<Vec<_>>();
+    let mut equal_pairs = 0;
+
+    for (i, a) in values.iter().enumerate() {
+        for (j, b) in values.iter().enumerate().skip(i + 1) {
+            if let Ok(true) = a.is_equal(b) {
+                equal_pairs += 1;
+                assert!(hashes[i] == hashes[j], "{} = {}, but hashes differ",
+                    interp.format_value(a), interp.format_value(b));
+            }
+        }
+    }
+
+    assert!(equal_pairs > values.len());
+
+    let other = Interpreter::new();
+    let v = other.run_single_expr("'(c a :d)", None).unwrap();
+    let w = interp.run_single_expr("'(c a :d)", None).unwrap();
+    assert_eq!(v.structural_hash(&other.get_scope().borrow_names()), hash(&w));
+
+    let eval_hash = |s: &str| hash(&interp.run_single_expr(s, None).unwrap());
+
+    assert_eq!(eval_hash("'(1 2)"), eval_hash("'(1.0 2/1)"));
+    assert_eq!(eval_hash("(/ 1 2)"), eval_hash("0.5"));
+    assert!(eval_hash("'(1 2)") != eval_hash("(vector 1 2)"));
+    assert!(eval_hash("(lambda () ())").is_none());
+    assert!(eval_hash("(list 1 (lambda () ()))").is_none());
+}
+
+#[test]
+fn test_deep_clone() {
+    use std::rc::Rc;
+
+    let interp = Interpreter::new();
+    interp.run_code("(struct Pair ((a any) (b any)))", None).unwrap();
+
+    let v = interp.run_single_expr(
+        r#"(new Pair :a '(1 (2 "three") `(a ,b)) :b (vector :c 4.0))"#, None).unwrap();
+    let c = v.deep_clone();
+
+    assert!(v.is_identical(&c));
+    assert_eq!(interp.format_value(&c), interp.format_value(&v));
+
+    match (&v, &c) {
+        (&Value::Struct(ref a), &Value::Struct(ref b)) => {
+            assert!(!Rc::ptr_eq(a, b));
+            assert!(Rc::ptr_eq(&a.def, &b.def));
+        }
+        _ => panic!("expected struct values")
+    }
+
+    let f = interp.run_single_expr("(lambda () ())", None).unwrap();
+    assert!(f.deep_clone().is_identical(&f));
+}
+
+#[test]
+fn test_macro_expansion_size() {
+    let interp = Interpreter::new();
+    interp.get_scope().set_max_macro_expansion(10);
+
+    let v = interp.run_code("
+        (macro (small) '(+ 1 2 3))
+        (small)
+        ", None).unwrap();
+    assert_eq!(interp.format_value(&v), "6");
+
+    // Each macro expansion is limited, not the final expression
+    let v = interp.run_code("
+        (macro (nested n) (if (< n 3) `(+ 1 2 (nested ,(+ n 1))) 0))
+        (nested 0)
+        ", None).unwrap();
+    assert_eq!(interp.format_value(&v), "9");
+
+    let err = interp.run_code("
+        (macro (big) '(list 1 2 3 4 5 6 7 8 9 10))
+        (big)
+        ", None).unwrap_err();
+
+    assert_matches!(err,
+        Error::CompileError(CompileError::MacroExpansionExceeded(_)));
+    assert_eq!(display_names(&interp.get_scope().borrow_names(), &err).to_string(),
+        "expansion of macro `big` exceeded maximum size");
+}
+
+#[test]
+fn test_macro_trace() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let interp = Interpreter::new();
+    let trace = Rc::new(RefCell::new(Vec::new()));
+    let t = trace.clone();
+
+    interp.get_scope().set_macro_trace(Some(Rc::new(
+        move |name, size| t.borrow_mut().push((name, size)))));
+
+    let v = interp.run_code("
+        (macro (one) 1)
+        (macro (two) '(+ (one) (one)))
+        (two)
+        ", None).unwrap();
+    assert_eq!(interp.format_value(&v), "2");
+
+    let trace = trace.borrow().iter()
+        .map(|&(name, size)| interp.get_scope()
+            .with_name(name, |s| (s.to_owned(), size)))
+        .collect::<Vec<_>>();
+
+    assert_eq!(trace, [
+        ("two".to_owned(), 6),
+        ("one".to_owned(), 1),
+        ("one".to_owned(), 1),
+    ]);
+}
+
+fn run_strict(s: &str) -> Result<Vec<String>, Error> {
+    let interp = Interpreter::new();
+    interp.get_scope().set_strict(true);
+
+    let mut res = Vec::new();
+
+    for c in try!(interp.compile_exprs(s)) {
+        let v = try!(interp.execute(c));
+        res.push(interp.format_value(&v));
+    }
+
+    Ok(res)
+}
+
+#[test]
+fn test_top_level() {
+    assert_eq!(run_strict("
+        (do (define a 1) (define (b) a))
+        (do (do (struct Foo ()) (macro (c) 'a)))
+        (b)
+        (c)
+        ").unwrap(),
+        ["b", "c", "1", "1"]);
+
+    // Macros may expand to top level operators
+    assert_eq!(run_strict("
+        (macro (define-two a b) `(do (define ,a 1) (define ,b 2)))
+        (define-two foo bar)
+        (+ foo bar)
+        ").unwrap(),
+        ["define-two", "bar", "3"]);
+
+    for s in &[
+            "(let ((a 1)) (define foo a))",
+            "(if true (define foo 1))",
+            "(define foo (define bar 1))",
+            "(define (foo) (define bar 1))",
+            "(lambda () (export (foo)))",
+            "(id (use math :all))",
+            "(do 1 (let () (struct Foo ())))",
+            ] {
+        assert_matches!(run_strict(s).unwrap_err(),
+            Error::CompileError(CompileError::NotTopLevel(_)));
+    }
+
+    // Macro expansion within an expression is not at top level
+    assert_matches!(run_strict("
+        (macro (define-one a) `(define ,a 1))
+        (let () (define-one foo))
+        ").unwrap_err(),
+        Error::CompileError(CompileError::NotTopLevel(_)));
+
+    // Outside of strict mode, only a warning is emitted
+    assert_eq!(run("
+        (define (foo) (define bar 1))
+        (foo)
+        bar
+        ").unwrap(),
+        ["foo", "bar", "1"]);
+}
+
 #[test]
 fn test_apply() {
     assert_eq!(eval("(apply + '(1 2 3))").unwrap(), "6");
     assert_eq!(eval("(apply + 1 2 3 '(4 5 6))").unwrap(), "21");
 }
 
+#[test]
+fn test_scan() {
+    assert_eq!(eval("(scan + 0 '(1 2 3))").unwrap(), "(0 1 3 6)");
+    assert_eq!(eval("(scan + 0 ())").unwrap(), "(0)");
+    assert_eq!(eval("(scan (lambda (acc x) (concat acc x)) \"\" '(\"a\" \"b\"))").unwrap(),
+        r#"("" "a" "ab")"#);
+    assert_matches!(eval("(scan + 0 1)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "list", found: "integer"}));
+
+    let e = eval("(scan + 0 '(1 2 \"x\" 4))").unwrap_err();
+    assert_matches!(e, Error::ContextError(_));
+    assert_eq!(e.to_string(), "`scan` failed at index 2");
+    assert_matches!(e.cause(), Some(&Error::ExecError(ExecError::TypeError{..})));
+}
+
+#[test]
+fn test_range() {
+    assert_eq!(eval("(range 0 10)").unwrap(), "#<range 0..10 by 1>");
+    assert_eq!(eval("(range 5)").unwrap(), "#<range 0..5 by 1>");
+    assert_eq!(eval("(range-list (range 0 10 3))").unwrap(), "(0 3 6 9)");
+    assert_eq!(eval("(range-list (range 10 0 -3))").unwrap(), "(10 7 4 1)");
+    assert_eq!(eval("(range-list (range 3 3))").unwrap(), "()");
+    assert_eq!(eval("(range-list (range 3 0))").unwrap(), "()");
+
+    assert_eq!(eval("(len (range 0 10 3))").unwrap(), "4");
+    assert_eq!(eval("(len (range 0 10000000000000))").unwrap(), "10000000000000");
+    assert_eq!(eval("(len (range -9223372036854775808 9223372036854775807))").unwrap(),
+        "18446744073709551615");
+    assert_eq!(eval("(elt (range 0 10 3) 3)").unwrap(), "9");
+    assert_matches!(eval("(elt (range 0 10 3) 4)").unwrap_err(),
+        Error::ExecError(ExecError::OutOfBounds(4)));
+
+    assert_eq!(eval("(contains? (range 0 100 3) 42)").unwrap(), "true");
+    assert_eq!(eval("(contains? (range 0 100 3) 43)").unwrap(), "false");
+    assert_eq!(eval("(contains? (range 0 100 3) 99)").unwrap(), "true");
+    assert_eq!(eval("(contains? (range 0 100 3) 102)").unwrap(), "false");
+    assert_eq!(eval("(contains? (range 10 0 -2) 2)").unwrap(), "true");
+    assert_eq!(eval("(contains? (range 10 0 -2) 0)").unwrap(), "false");
+    assert_eq!(eval("(contains? (range 10) 1.0)").unwrap(), "false");
+    assert_eq!(eval("(contains? '(1 \"a\") \"a\")").unwrap(), "true");
+    assert_eq!(eval("(contains? () 1)").unwrap(), "false");
+
+    assert_eq!(eval("(= (range 0 3) (range 0 3))").unwrap(), "true");
+    assert_eq!(eval("(= (range 0 3) (range 0 3 2))").unwrap(), "false");
+    assert_eq!(eval("(last (scan + 0 (range 0 100001)))").unwrap(), "5000050000");
+
+    assert_matches!(eval("(range 0 1 0)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "nonzero integer", ..}));
+    assert_matches!(eval("(range-list '(1 2))").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "range", ..}));
+}
+
+#[test]
+fn test_scan_fold() {
+    // The last value produced by `scan` is the result of a left fold,
+    // and one value is produced for each element, plus the initial value.
+    let fold = "
+        (define (fold f acc li)
+          (if (null li)
+            acc
+            (fold f (f acc (first li)) (tail li))))";
+
+    for n in 0..12 {
+        let li = (0..n).map(|i| ((i * 7) % 11 - 5).to_string())
+            .collect::<Vec<_>>().join(" ");
+
+        for f in &["+", "-", "*", "max", "(lambda (a b) (+ (* a 2) b))"] {
+            let code = format!("{}
+                (define s (scan {1} 1 '({2})))
+                (= (last s) (fold {1} 1 '({2})))
+                (= (len s) {3})",
+                fold, f, li, n + 1);
+
+            assert_eq!(run(&code).unwrap(), ["fold", "s", "true", "true"],
+                "f = {}, li = ({})", f, li);
+        }
+    }
+}
+
+#[test]
+fn test_iterate() {
+    assert_eq!(eval("(iterate (lambda (x) (* x 2)) 1 5)").unwrap(), "(1 2 4 8 16)");
+    assert_eq!(eval("(iterate (lambda (x) (* x 2)) 1 1)").unwrap(), "(1)");
+    assert_eq!(eval("(iterate (lambda (x) (* x 2)) 1 0)").unwrap(), "()");
+    assert_eq!(eval("(iterate (lambda (x) (tail x)) '(a b c) 3)").unwrap(),
+        "((a b c) (b c) (c))");
+
+    let e = eval("(iterate (lambda (x) (tail x)) '(a b) 4)").unwrap_err();
+    assert_eq!(e.to_string(), "`iterate` failed at index 3");
+    assert_matches!(e.cause(), Some(&Error::ExecError(ExecError::TypeError{..})));
+
+    // Values are produced without recursion
+    assert_eq!(eval("(last (iterate (lambda (x) (+ x 1)) 0 100000))").unwrap(), "99999");
+}
+
+#[test]
+fn test_apply_n() {
+    assert_eq!(eval("(apply-n + 1 '(1 2 3))").unwrap(), "5");
+    assert_eq!(eval("(apply-n list 2 '(a b c d))").unwrap(), "(c d)");
+    assert_eq!(eval("(apply-n list 3 '(a b c))").unwrap(), "()");
+    assert_eq!(eval("(apply-n list 0 ())").unwrap(), "()");
+    assert_matches!(eval("(apply-n list 4 '(a b c))").unwrap_err(),
+        Error::ExecError(ExecError::OutOfBounds(4)));
+
+    assert_eq!(eval("(apply-first list '(1 2 3))").unwrap(), "(1 (2 3))");
+    assert_eq!(eval("(apply-first list '(1))").unwrap(), "(1 ())");
+    assert_matches!(eval("(apply-first list ())").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "list", found: "unit"}));
+
+    assert_eq!(eval("(apply-rest + '(1 2 3))").unwrap(), "5");
+    assert_eq!(eval("(apply-rest list '(1))").unwrap(), "()");
+    assert_eq!(eval("(let ((f (lambda (g) (lambda (li) (apply-rest g li)))))
+                       ((f *) '(1 2 3 4)))").unwrap(), "24");
+}
+
+#[test]
+fn test_apply_keyword() {
+    assert_eq!(run("
+        (define (foo a b :key c (d 10)) (list a b c d))
+        (apply/keyword foo :c 3 :d 4 '(1 2))
+        (apply/keyword foo 1 :c 3 '(2))
+        (apply/keyword foo 1 2 ())
+        (define (call-foo k v) (apply/keyword foo :c 0 k v '(1 2)))
+        (call-foo :d 5)
+        ").unwrap(),
+        ["foo", "(1 2 3 4)", "(1 2 3 10)", "(1 2 () 10)",
+            "call-foo", "(1 2 0 5)"]);
+
+    assert_matches!(run("
+        (define (foo a b :key c (d 10)) (list a b c d))
+        (apply/keyword foo :c 3 'd 4 '(1 2))
+        ").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "keyword", ..}));
+    assert_matches!(run("
+        (define (foo a b :key c (d 10)) (list a b c d))
+        (apply/keyword foo :e 3 '(1 2))
+        ").unwrap_err(),
+        Error::ExecError(ExecError::UnrecognizedKeyword(_)));
+    assert_matches!(run("
+        (define (foo a b :key c (d 10)) (list a b c d))
+        (apply/keyword foo :c 3 (id :c) 4 '(1 2))
+        ").unwrap_err(),
+        Error::ExecError(ExecError::DuplicateKeyword(_)));
+    assert_matches!(eval("(apply/keyword list :c '(1 2))").unwrap_err(),
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
+}
+
+fn error_message(s: &str) -> String {
+    let interp = Interpreter::new();
+    let e = interp.run_code(s, None).unwrap_err();
+    let names = interp.get_scope().borrow_names();
+    display_names(&names, &e).to_string()
+}
+
+#[test]
+fn test_format_error() {
+    let interp = Interpreter::new();
+
+    let e = interp.run_code("(let ((a)) a)", None).unwrap_err();
+    assert_eq!(interp.format_error(&e), "\
+compile error: `let` expected list of 2 elements; \
+usage: (let ( [ ( name expression ) ... ] ) body)
+");
+
+    let e = interp.run_code("(define (f n) (+ n 1))\n(f \"x\")", None).unwrap_err();
+    assert_eq!(interp.format_error(&e),
+        "execution error: type error: expected number; found string\n");
+
+    let e = interp.run_code("(foo\n  (bar #x))", Some("test.kts".to_owned())).unwrap_err();
+    assert_eq!(interp.format_error(&e), "\
+test.kts:2:7:parse error: unknown `#` syntax; expected `#t`, `#f`, `#true`, `#false`, \
+`#'c'`, `#\\name`, `#(...)`, or `#| comment |#`
+      (bar #x))
+           ^
+");
+    assert_eq!(interp.format_error_ansi(&e), "\
+test.kts:2:7:\x1b[1;31mparse error\x1b[0m: unknown `#` syntax; expected `#t`, `#f`, \
+`#true`, `#false`, `#'c'`, `#\\name`, `#(...)`, or `#| comment |#`
+      (bar #x))
+    \x1b[1;31m       ^\x1b[0m
+");
+}
+
+#[test]
+fn test_call_errors() {
+    assert_matches!(eval("(1 2 3)").unwrap_err(),
+        Error::CompileError(CompileError::InvalidCallExpression(Value::Integer(_))));
+    assert_matches!(run("(define config '(1 2)) (config 1)").unwrap_err(),
+        Error::ExecError(ExecError::NotCallable{name: Some(_), ..}));
+    assert_matches!(eval("((id 1) 2)").unwrap_err(),
+        Error::ExecError(ExecError::NotCallable{name: None, ..}));
+
+    assert_eq!(error_message("(1 2 3)"),
+        "invalid call expression `1` of type `integer`; \
+         to create a list, use `'(...)` or `(list ...)`");
+    assert_eq!(error_message("(\"foo\" 2 3)"),
+        "invalid call expression `\"foo\"` of type `string`; \
+         to create a list, use `'(...)` or `(list ...)`");
+    assert_eq!(error_message("(:foo bar)"),
+        "invalid call expression `:foo` of type `keyword`; \
+         to get a struct field, use `(. value :foo)`");
+    assert_eq!(error_message("(`(1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17) 1)"),
+        "invalid call expression of type `object`");
+
+    assert_eq!(error_message("(define config '(1 2)) (config 1)"),
+        "`config` is a list, not a function");
+    assert_eq!(error_message("(define n 1) (n)"),
+        "`n` is an integer, not a function");
+    assert_eq!(error_message("(define u ()) (u)"),
+        "`u` is a unit, not a function");
+    assert_eq!(error_message("((id 1) 2)"),
+        "cannot call value of type `integer`");
+    assert_eq!(error_message("(struct Foo ()) (Foo)"),
+        "`Foo` is a struct-def, not a function; \
+         to create a struct, use `(new Foo ...)`");
+    assert_eq!(error_message("((id :foo) 1)"),
+        "cannot call value of type `keyword`; \
+         to get a struct field, use `(. value :foo)`");
+}
+
+#[test]
+fn test_operator_usage() {
+    assert_eq!(error_message("(let ((a 1)) a a)"),
+        "`let` expected exactly 2 arguments; found 3; \
+         usage: (let ( [ ( name expression ) ... ] ) body)");
+    assert_eq!(error_message("(let (a) a)"),
+        "`let` expected list of 2 elements; \
+         usage: (let ( [ ( name expression ) ... ] ) body)");
+    assert_eq!(error_message("(let* (a) a)"),
+        "`let*` expected list of 2 elements; \
+         usage: (let* ( [ ( name expression ) ... ] ) body)");
+    assert_eq!(error_message("(if)"),
+        "`if` expected between 2 and 3 arguments; found 0; \
+         usage: (if condition then-expression [ else-expression ])");
+    assert_eq!(error_message("(define (foo a) a) (foo)"),
+        "`foo` expected exactly 1 argument; found 0");
+
+    assert_eq!(eval("(doc 'with-bindings)").unwrap(),
+        r#""(with-bindings ( [ ( name expression ) ... ] ) expression ...)""#);
+    assert_eq!(eval("(doc 'list)").unwrap(), "()");
+}
+
+#[test]
+fn test_error_chain() {
+    let interp = Interpreter::new();
+
+    interp.run_code("(define (foo :optional (a (panic 'boom))) a)", None).unwrap();
+
+    let e = interp.run_code("(foo)", None).unwrap_err()
+        .context("loading configuration")
+        .context("starting application");
+
+    assert_eq!(interp.format_error_chain(&e), "\
+starting application
+  loading configuration
+    execution error: error in default value of parameter `a` of `foo`
+      execution error: panic: boom
+");
+
+    let e = interp.run_code("(bar)", None).unwrap_err();
+
+    assert_eq!(interp.format_error_chain(&e),
+        "execution error: name not found in global scope: bar\n");
+}
+
+#[test]
+fn test_unbound_name_handler() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let interp = Interpreter::new();
+    let scope = interp.get_scope();
+
+    let timeout = scope.add_name("config-timeout");
+    let lookups = Rc::new(RefCell::new(Vec::new()));
+    let l = lookups.clone();
+
+    scope.set_unbound_name_handler(Box::new(move |name| {
+        l.borrow_mut().push(name);
+        if name == timeout { Some(Value::from(30)) } else { None }
+    }));
+
+    let v = interp.run_code("(+ config-timeout config-timeout)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "60");
+    let v = interp.run_code("(* config-timeout 2)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "60");
+    // The resolved value is cached in the scope
+    assert_eq!(*lookups.borrow(), [timeout]);
+    assert!(scope.get_value(timeout).is_some());
+
+    interp.run_code("(define (get-later) later)", None).unwrap();
+    interp.run_code("(define later 1)", None).unwrap();
+    let v = interp.run_code("(get-later)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "1");
+    assert_eq!(*lookups.borrow(), [timeout]);
+
+    let e = interp.run_code("config-timeot", None).unwrap_err();
+    assert_matches!(e, Error::ExecError(ExecError::NameError(_)));
+    assert_eq!(lookups.borrow().len(), 2);
+    assert_eq!(interp.format_error(&e), "execution error: \
+        name not found in global scope: config-timeot; did you mean `config-timeout`?\n");
+}
+
+#[test]
+fn test_while() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let interp = Interpreter::new();
+    let count = Rc::new(Cell::new(0));
+    let c = count.clone();
+
+    interp.get_scope().add_value_with_name("tick",
+        move |name| Value::new_foreign_fn(name, move |_scope, _args| {
+            c.set(c.get() + 1);
+            Ok(c.get().into())
+        }));
+
+    let run = |s: &str| {
+        count.set(0);
+        let v = interp.run_code(s, None).unwrap();
+        interp.format_value(&v)
+    };
+
+    // Looping does not grow the stack
+    assert_eq!(run("(while (< (tick) 100000))"), "()");
+    assert_eq!(count.get(), 100000);
+
+    assert_eq!(run("(while false (panic))"), "()");
+    assert_eq!(run("(with-output-to-string
+                      (while (< (tick) 3) (print \"a\") (print \"b\")))"), r#""abab""#);
+    assert_eq!(run("(let ((a 1) (b 2)) (do (while (< (tick) 5) (+ a b)) (list a b)))"),
+        "(1 2)");
+    assert_eq!(count.get(), 5);
+
+    assert_eq!(run("(define (f n) (do (while (< (tick) n) (tick)) (tick))) (f 10)"), "12");
+
+    // The jump back to the condition is not a return from the function
+    assert_eq!(run("(define (g) (while (< (tick) 5) (tick))) (g)"), "()");
+    assert_eq!(count.get(), 5);
+}
+
+#[test]
+fn test_for() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let interp = Interpreter::new();
+    let sum = Rc::new(Cell::new(0));
+    let s = sum.clone();
+
+    interp.get_scope().add_value_with_name("add",
+        move |name| Value::new_foreign_fn(name, move |_scope, args| {
+            let n = try!(i64::from_value(args[0].clone()));
+            s.set(s.get() + n);
+            Ok(Value::Unit)
+        }));
+
+    let run = |s: &str| {
+        sum.set(0);
+        let v = interp.run_code(s, None).unwrap();
+        interp.format_value(&v)
+    };
+
+    assert_eq!(run("(for x (list 1 2 3 4 5) (add x))"), "()");
+    assert_eq!(sum.get(), 15);
+
+    assert_eq!(run("(define (sum li) (for x li (add x))) (sum (list 10 20))"), "()");
+    assert_eq!(sum.get(), 30);
+
+    assert_eq!(run("(for x () (panic))"), "()");
+    assert_eq!(run("(with-output-to-string
+                      (for x '(a b) (print \"~a\" x) (print \"-\")))"), r#""a-b-""#);
+
+    // Nested loops bind their own names; an inner binding shadows an outer one
+    assert_eq!(run("(with-output-to-string
+                      (for x '(1 2) (for y '(a b) (print \"~a~a \" x y))))"),
+        r#""1a 1b 2a 2b ""#);
+    assert_eq!(run("(with-output-to-string
+                      (for x '(1 2) (for x (list x (* x 10)) (print \"~a \" x)) (print \"~a \" x)))"),
+        r#""1 10 1 2 20 2 ""#);
+    assert_eq!(run("(let ((x 0) (li '(1 2))) (do (for x li (add x)) (list x li)))"),
+        "(0 (1 2))");
+    assert_eq!(sum.get(), 3);
+
+    // Ranges and vectors are iterated without building a list
+    assert_eq!(run("(for x (range 0 3) (add x))"), "()");
+    assert_eq!(sum.get(), 3);
+    assert_eq!(run("(with-output-to-string
+                      (for x (range 10 0 -3) (print \"~a \" x)))"), r#""10 7 4 1 ""#);
+    assert_eq!(run("(for x (range 3 3) (panic))"), "()");
+
+    assert_eq!(run("(for x (vector 4 5 6) (add x))"), "()");
+    assert_eq!(sum.get(), 15);
+    assert_eq!(run("(for x (vector) (panic))"), "()");
+    assert_eq!(run("(let ((v (vector 1 2))) (do (for x v (add x)) v))"), "#(1 2)");
+    assert_eq!(sum.get(), 3);
+
+    assert_matches!(interp.run_code("(for x 1 x)", None).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{..}));
+    assert_matches!(interp.run_code("(for 1 () x)", None).unwrap_err(),
+        Error::CompileError(_));
+}
+
+#[test]
+fn test_yield_hook() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let interp = Interpreter::new();
+    let scope = interp.get_scope();
+
+    interp.run_code("
+        (define (spin n) (if (zero n) 'done (spin (- n 1))))
+        (defvar level 0)
+        ", None).unwrap();
+
+    let calls = Rc::new(Cell::new(0));
+    let c = calls.clone();
+
+    scope.set_yield_hook(100, Box::new(move || {
+        c.set(c.get() + 1);
+        YieldAction::Continue
+    }));
+
+    let v = interp.run_code("(spin 1000)", None).unwrap();
+    assert_eq!(interp.format_value(&v), "done");
+    assert!(calls.get() >= 10);
+
+    let c = calls.clone();
+    calls.set(0);
+
+    scope.set_yield_hook(100, Box::new(move || {
+        c.set(c.get() + 1);
+        if c.get() == 3 { YieldAction::Abort } else { YieldAction::Continue }
+    }));
+
+    // Interruption cannot be caught and leaves no dynamic bindings behind
+    let e = interp.run_code("
+        (with-bindings ((level 1))
+          (try (spin 100000) (catch else e 'caught)))
+        ", None).unwrap_err();
+    assert_matches!(e, Error::ExecError(ExecError::Interrupted));
+    assert_eq!(calls.get(), 3);
+
+    scope.clear_yield_hook();
+
+    let v = interp.run_code("(list level (spin 1000))", None).unwrap();
+    assert_eq!(interp.format_value(&v), "(0 done)");
+}
+
 #[test]
 fn test_panic() {
     assert_matches!(eval("(panic)").unwrap_err(),
@@ -1222,6 +3141,105 @@ fn test_panic() {
             if s == "foo");
 }
 
+#[test]
+fn test_error() {
+    assert_matches!(eval("(error \"bad value\" 1 2)").unwrap_err(),
+        Error::ExecError(ExecError::UserError(ref c))
+            if c.message == "bad value" && c.irritants.len() == 2);
+
+    assert_eq!(run("
+        (define x 42)
+        (guard (e :error (error-message e)) (error \"bad value\" x))
+        (guard (e :error (error-irritants e)) (error \"bad value\" x))
+        (guard (e :error (error-irritants e)) (error \"no irritants\"))
+        ").unwrap(), ["x", r#""bad value""#, "(42)", "()"]);
+}
+
+#[test]
+fn test_guard() {
+    assert_eq!(eval("(guard (e :error 'caught) (+ 1 2))").unwrap(), "3");
+    assert_eq!(eval("(guard (e :error (error-message e)) (/ 1 0))").unwrap(),
+        r#""attempt to divide by zero""#);
+
+    // Errors raised within function calls are caught
+    // and the stack is restored to its state in the guarding function.
+    assert_eq!(run("
+        (define (count-down n)
+          (if (> n 0)
+            (+ 1 (count-down (- n 1)))
+            (error \"done\" n)))
+        (define (catch-count n offset)
+          (let ((a offset))
+            (guard (e :error (+ a (first (error-irritants e))))
+              (count-down n))))
+        (catch-count 10 100)
+        ").unwrap(), ["count-down", "catch-count", "100"]);
+
+    // Inner handlers catch errors before outer handlers
+    assert_eq!(eval("
+        (guard (e :error 'outer)
+          (list (guard (e :error 'inner) (error \"foo\")) 'after))
+        ").unwrap(), "(inner after)");
+
+    // Errors raised within a handler propagate to the enclosing handler
+    assert_eq!(eval("
+        (guard (e :error (error-message e))
+          (guard (e :error (error \"from handler\")) (error \"foo\")))
+        ").unwrap(), r#""from handler""#);
+
+    // Dynamic bindings are restored when an error is caught
+    assert_eq!(run("
+        (defvar *depth* 0)
+        (guard (e :error *depth*)
+          (with-bindings ((*depth* 1)) (error \"foo\")))
+        ").unwrap(), ["*depth*", "0"]);
+
+    assert_matches!(eval("(guard (e :foo 1) 2)").unwrap_err(),
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
+    assert_matches!(eval("(guard (e :error 1) (error \"foo\" 1))").unwrap(),
+        ref s if s == "1");
+}
+
+#[test]
+fn test_try() {
+    assert_eq!(eval("(try (+ 1 2) (catch :arith e 0))").unwrap(), "3");
+    assert_eq!(eval("(try (/ 1 0) (catch :arith e (error-kind e)))").unwrap(),
+        ":divide-by-zero");
+    assert_eq!(eval("(try (/ 1 0) (catch :type e 'type) (catch :arith e 'arith))").unwrap(),
+        "arith");
+    assert_eq!(eval("(try (+ 1 'a) (catch :arith e 'arith) (catch :type e 'type))").unwrap(),
+        "type");
+    assert_eq!(eval("(try (/ 1 0) (catch else e (error-category e)))").unwrap(),
+        ":arith");
+    assert_eq!(eval("(try (error \"foo\") (catch :custom e (error-message e)))").unwrap(),
+        r#""foo""#);
+    assert_eq!(eval("(try (elt '(1 2) 5) (catch :index e (error-irritants e)))").unwrap(),
+        "(5)");
+
+    // The first matching clause handles the error
+    assert_eq!(eval("(try (/ 1 0) (catch :arith e 1) (catch :error e 2))").unwrap(), "1");
+
+    // Errors not matching any clause propagate to enclosing handlers
+    assert_eq!(eval("
+        (guard (e :arith 'outer)
+          (try (/ 1 0) (catch :type e 'inner)))
+        ").unwrap(), "outer");
+    assert_matches!(eval("(try (/ 1 0) (catch :type e 'inner))").unwrap_err(),
+        Error::ExecError(ExecError::DivideByZero));
+
+    // Remaining handlers are removed after an error is handled
+    assert_eq!(eval("
+        (guard (e :type 'outer)
+          (list (try (/ 1 0) (catch :arith e 'inner) (catch :type e 'unused))
+                (+ 1 'a)))
+        ").unwrap(), "outer");
+
+    assert_matches!(eval("(try 1 (catch :foo e 2))").unwrap_err(),
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
+    assert_matches!(eval("(try 1 (foo :error e 2))").unwrap_err(),
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
+}
+
 #[test]
 fn test_use() {
     assert_eq!(run("
@@ -0,0 +1,2292 @@
+extern crate ketos;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Arguments;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use std::time::{Duration, SystemTime};
+
+use ketos::{BuiltinModuleLoader, CompileError, EncodeError, Error, FileModuleLoader,
+    GlobalScope, ImportPolicy, Interpreter, IoError,
+    Integer, MemoryModuleLoader, Module, ModuleBuilder, ModuleLoader, Name, NameMap,
+    NameSet, NameStore, LogLevel, ParseError, ParseErrorKind, Scope, Value};
+use ketos::bytecode::Code;
+use ketos::condition::error_kind;
+use ketos::function::Arity;
+use ketos::io::{IoMode, SharedWrite};
+use ketos::lexer::CodeMap;
+use ketos::module::ModuleRegistry;
+use ketos::name::get_standard_name_for;
+use ketos::scope::GlobalIo;
+use ketos::compile::compile;
+use ketos::encode::{read_bytecode, read_name_snapshot_file, write_bytecode,
+    write_name_snapshot_file, DecodeError, ModuleCode, SHARED_NAMES_MAGIC_NUMBER};
+use ketos::exec::execute;
+use ketos::exec::ExecError;
+use ketos::function::Lambda;
+use ketos::lexer::Lexer;
+use ketos::parser::Parser;
+use ketos::value::{debug_depth, StructDef};
+
+macro_rules! assert_matches {
+    ( $e:expr, $pat:pat ) => {
+        match $e {
+            $pat => (),
+            e => panic!("assertion failed: `{:?}` does not match `{}`",
+                e, stringify!($pat))
+        }
+    }
+}
+
+type Bytecode = Rc<RefCell<HashMap<String, Vec<u8>>>>;
+
+/// Compiles modules from source and stores the resulting bytecode.
+struct SourceLoader {
+    sources: HashMap<String, String>,
+    bytecode: Bytecode,
+}
+
+impl ModuleLoader for SourceLoader {
+    fn load_module(&self, name: Name, scope: &Scope) -> Result<Module, Error> {
+        let name_str = scope.with_name(name, |s| s.to_owned());
+        let src = &self.sources[&name_str];
+        let scope = GlobalScope::new_using(scope);
+
+        let exprs = {
+            let mut names = scope.borrow_names_mut();
+            try!(Parser::new(&mut names, Lexer::new(src, 0)).parse_exprs())
+        };
+
+        let mut code = Vec::new();
+
+        for e in &exprs {
+            let c = Rc::new(try!(compile(&scope, e)));
+            try!(execute(&scope, c.clone()));
+            code.push(c);
+        }
+
+        let mcode = ModuleCode::new(code, &scope);
+
+        let mut buf = Vec::new();
+        try!(write_bytecode(&mut buf, Path::new(&name_str), &mcode,
+            &scope.borrow_names()));
+        self.bytecode.borrow_mut().insert(name_str, buf);
+
+        Ok(Module{
+            name: name,
+            scope: scope,
+        })
+    }
+}
+
+/// Loads modules from previously compiled bytecode.
+struct BytecodeLoader {
+    bytecode: Bytecode,
+}
+
+impl ModuleLoader for BytecodeLoader {
+    fn load_module(&self, name: Name, scope: &Scope) -> Result<Module, Error> {
+        let name_str = scope.with_name(name, |s| s.to_owned());
+        let buf = self.bytecode.borrow()[&name_str].clone();
+        let scope = GlobalScope::new_using(scope);
+
+        let mcode = try!(read_bytecode(&mut &buf[..],
+            Path::new(&name_str), &scope));
+
+        for (name, code) in mcode.macros {
+            scope.add_macro(name, Lambda::new(code, &scope));
+        }
+
+        scope.set_exports(mcode.exports);
+        scope.set_export_renames(mcode.export_renames);
+
+        for code in mcode.code {
+            try!(execute(&scope, code));
+        }
+
+        Ok(Module{
+            name: name,
+            scope: scope,
+        })
+    }
+}
+
+fn run(interp: &Interpreter, s: &str) -> Result<Vec<String>, Error> {
+    let c = try!(interp.compile_exprs(s));
+    c.into_iter().map(|c| interp.execute(c)
+        .map(|v| interp.format_value(&v))).collect()
+}
+
+#[test]
+fn test_struct_def_bytecode() {
+    let mut sources = HashMap::new();
+
+    sources.insert("geometry".to_owned(), "
+        (export (Point))
+        (struct Point ((x integer) (y integer)))
+        ".to_owned());
+
+    // `point-def` expands to the `StructDef` value itself,
+    // so the definition is encoded into this module's bytecode, too.
+    sources.insert("shapes".to_owned(), "
+        (use geometry (Point))
+        (export (make-origin))
+        (macro (point-def) Point)
+        (define (make-origin) (new (point-def) :x 0 :y 0))
+        ".to_owned());
+
+    let bytecode = Rc::new(RefCell::new(HashMap::new()));
+
+    let interp = Interpreter::with_loader(Box::new(SourceLoader{
+        sources: sources,
+        bytecode: bytecode.clone(),
+    }));
+
+    run(&interp, "(use shapes :all)").unwrap();
+
+    assert!(bytecode.borrow().contains_key("geometry"));
+    assert!(bytecode.borrow().contains_key("shapes"));
+
+    let interp = Interpreter::with_loader(Box::new(BytecodeLoader{
+        bytecode: bytecode,
+    }));
+
+    assert_eq!(run(&interp, "
+        (use shapes (make-origin))
+        (use geometry (Point))
+        (is-instance Point (make-origin))
+        (= (make-origin) (new Point :x 0 :y 0))
+        ").unwrap(),
+        ["()", "()", "true", "true"]);
+
+    let point = match interp.get_value("Point") {
+        Some(Value::StructDef(def)) => def,
+        _ => panic!("expected struct def")
+    };
+
+    let mods = interp.get_scope().get_modules();
+    let copy = || StructDef::new(point.name, point.fields.clone());
+
+    assert!(Rc::ptr_eq(&mods.intern_struct_def(copy()), &point));
+    assert!(!Rc::ptr_eq(&mods.intern_struct_def(
+        StructDef::new(point.name, NameMap::new().into_slice())), &point));
+
+    // Interned definitions are released when the registry is cleared
+    mods.clear();
+    assert!(!Rc::ptr_eq(&mods.intern_struct_def(copy()), &point));
+}
+
+#[test]
+fn test_overload_bytecode() {
+    let mut sources = HashMap::new();
+
+    sources.insert("shapes".to_owned(), "
+        (export (area))
+        (define (area r) (* r r))
+        (define-overload (area w h) (* w h))
+        ".to_owned());
+
+    let bytecode = Rc::new(RefCell::new(HashMap::new()));
+
+    let interp = Interpreter::with_loader(Box::new(SourceLoader{
+        sources: sources,
+        bytecode: bytecode.clone(),
+    }));
+
+    run(&interp, "(use shapes :all)").unwrap();
+
+    let interp = Interpreter::with_loader(Box::new(BytecodeLoader{
+        bytecode: bytecode,
+    }));
+
+    assert_eq!(run(&interp, "
+        (use shapes (area))
+        (area 3)
+        (area 2 5)
+        area
+        ").unwrap(),
+        ["()", "9", "10", "<lambda area (1 2)>"]);
+}
+
+#[test]
+fn test_import_policy() {
+    let interp = Interpreter::new();
+    let scope = interp.get_scope();
+
+    // Full access is the default
+    assert_eq!(run(&interp, "(use math ())").unwrap(), ["()"]);
+
+    scope.set_import_policy(ImportPolicy::DenyAll);
+
+    let e = run(&interp, "(use math ())").unwrap_err();
+    assert_matches!(e, Error::CompileError(CompileError::ImportsDisabled));
+    assert_eq!(interp.format_error(&e), "compile error: module imports are disabled\n");
+
+    // Modules already loaded may not be imported, either
+    assert_matches!(run(&interp, "(use math :as m)").unwrap_err(),
+        Error::CompileError(CompileError::ImportsDisabled));
+    assert_matches!(run(&interp, "(with-module math (define x 1))").unwrap_err(),
+        Error::CompileError(CompileError::ImportsDisabled));
+    assert_matches!(run(&interp, "(import-for-syntax math (sqrt))").unwrap_err(),
+        Error::CompileError(CompileError::ImportsDisabled));
+
+    scope.set_import_policy(ImportPolicy::AllowList(
+        NameSet::from_strs(scope, vec!["math"])));
+
+    assert_eq!(run(&interp, "(use math ())").unwrap(), ["()"]);
+
+    let e = run(&interp, "(use code ())").unwrap_err();
+    assert_matches!(e, Error::CompileError(CompileError::ImportNotAllowed(_)));
+    assert_eq!(interp.format_error(&e),
+        "compile error: import of module `code` is not allowed\n");
+
+    // The policy is inherited by module scopes
+    let mut loader = MemoryModuleLoader::new();
+    loader.add_module("util", "(use code ()) (export ())");
+
+    let interp = Interpreter::with_loader(Box::new(loader));
+    let scope = interp.get_scope();
+    scope.set_import_policy(ImportPolicy::AllowList(
+        NameSet::from_strs(scope, vec!["util"])));
+
+    assert_matches!(run(&interp, "(use util ())").unwrap_err(),
+        Error::CompileError(CompileError::ImportNotAllowed(_)));
+
+    // Loaders also check the policy
+    let interp = Interpreter::new();
+    let scope = interp.get_scope();
+    scope.set_import_policy(ImportPolicy::DenyAll);
+
+    let math = scope.add_name("math");
+
+    assert_matches!(BuiltinModuleLoader.load_module(math, scope).err(),
+        Some(Error::CompileError(CompileError::ImportsDisabled)));
+    assert_matches!(FileModuleLoader::new().load_module(math, scope).err(),
+        Some(Error::CompileError(CompileError::ImportsDisabled)));
+}
+
+#[test]
+fn test_import_policy_runtime() {
+    let mut loader = MemoryModuleLoader::new();
+    loader.add_module("arith", "
+        (use test :all :macro :all)
+        (export (double))
+        (define (double n) (* n 2))
+        (define-test doubles (assert-eq (double 2) 4))
+        ");
+
+    let interp = Interpreter::with_loader(Box::new(loader));
+    let scope = interp.get_scope();
+
+    // Compiled while imports are permitted, run after they are not
+    assert_eq!(run(&interp, "
+        (use test (run-tests))
+        (use arith :as a)
+        (define (twice n) (a:double n))
+        (define (reset) (with-module arith (define double 0)))
+        (define (check) (run-tests 'arith))
+        (twice 3)
+        ").unwrap(),
+        ["()", "()", "twice", "reset", "check", "6"]);
+
+    scope.set_import_policy(ImportPolicy::DenyAll);
+
+    assert_matches!(run(&interp, "(twice 3)").unwrap_err(),
+        Error::CompileError(CompileError::ImportsDisabled));
+    assert_matches!(run(&interp, "(reset)").unwrap_err(),
+        Error::CompileError(CompileError::ImportsDisabled));
+    assert_matches!(run(&interp, "(check)").unwrap_err(),
+        Error::CompileError(CompileError::ImportsDisabled));
+
+    scope.set_import_policy(ImportPolicy::AllowList(
+        NameSet::from_strs(scope, vec!["test"])));
+
+    assert_matches!(run(&interp, "(twice 3)").unwrap_err(),
+        Error::CompileError(CompileError::ImportNotAllowed(_)));
+    assert_matches!(run(&interp, "(check)").unwrap_err(),
+        Error::CompileError(CompileError::ImportNotAllowed(_)));
+
+    // The module itself is unchanged
+    scope.set_import_policy(ImportPolicy::AllowList(
+        NameSet::from_strs(scope, vec!["arith", "test"])));
+    assert_eq!(run(&interp, "(twice 3)").unwrap(), ["6"]);
+}
+
+#[test]
+fn test_exports_of() {
+    let mut loader = MemoryModuleLoader::new();
+    loader.add_module("plugin", r#"
+        (export (run version twice))
+        (export-rename (run :as start))
+        (define (run) 'running)
+        (define version "1.0")
+        (define (helper) 'private)
+        (macro (twice x) `(do ,x ,x))
+        (println "loaded")
+        "#);
+
+    let interp = Interpreter::with_loader(Box::new(loader));
+    let scope = interp.get_scope();
+
+    // Loading the module executes it, as `use` does
+    assert_eq!(run(&interp, "(with-output-to-string (exports-of 'plugin))").unwrap(),
+        [r#""loaded\n""#]);
+    assert_eq!(run(&interp, "(exports-of 'plugin)").unwrap(),
+        ["(run start twice version)"]);
+
+    assert_eq!(run(&interp, "(exports-of 'plugin :values)").unwrap(),
+        ["(run start version)"]);
+    assert_eq!(run(&interp, "(exports-of 'plugin :macros)").unwrap(),
+        ["(twice)"]);
+
+    // Nothing is imported
+    assert_matches!(run(&interp, "(run)").unwrap_err(),
+        Error::ExecError(_));
+    // The module is loaded only once
+    assert_eq!(run(&interp, "(with-output-to-string (exports-of 'plugin))").unwrap(),
+        [r#""""#]);
+
+    assert_matches!(run(&interp, "(exports-of 'plugin :functions)").unwrap_err(),
+        Error::ExecError(ExecError::UnrecognizedKeyword(_)));
+    assert_matches!(run(&interp, "(exports-of 'no-such-module)").unwrap_err(),
+        Error::CompileError(CompileError::ModuleError(_)));
+
+    let plugin = scope.get_modules()
+        .get_loaded_module(scope.add_name("plugin")).unwrap();
+
+    let exports = plugin.exports().into_iter()
+        .map(|n| scope.with_name(n, |s| s.to_owned())).collect::<Vec<_>>();
+    assert_eq!(exports, ["run", "start", "twice", "version"]);
+
+    let get = |name| plugin.get_export(scope.add_name(name))
+        .map(|v| interp.format_value(&v));
+
+    assert_eq!(get("version").unwrap(), r#""1.0""#);
+    assert!(get("start").is_some());
+    assert!(get("helper").is_none());
+    assert!(get("twice").is_none());
+    assert!(plugin.get_export_macro(scope.add_name("twice")).is_some());
+    assert!(plugin.get_export_macro(scope.add_name("run")).is_none());
+
+    // The import policy applies
+    scope.set_import_policy(ImportPolicy::DenyAll);
+    assert_matches!(run(&interp, "(exports-of 'plugin)").unwrap_err(),
+        Error::CompileError(CompileError::ImportsDisabled));
+}
+
+#[test]
+fn test_use_alias() {
+    let mut sources = HashMap::new();
+
+    sources.insert("very-long-name".to_owned(), "
+        (export (parse limit))
+        (define (parse s) (concat \"parsed \" s))
+        (define limit 10)
+        (define hidden 1)
+        ".to_owned());
+    sources.insert("client".to_owned(), "
+        (use very-long-name :as v)
+        (export (run parse))
+        (define (parse) 'local)
+        (define (run) (list (v:parse \"x\") v:limit (parse)))
+        ".to_owned());
+
+    let bytecode = Rc::new(RefCell::new(HashMap::new()));
+
+    let interp = Interpreter::with_loader(Box::new(SourceLoader{
+        sources: sources,
+        bytecode: bytecode.clone(),
+    }));
+
+    assert_eq!(run(&interp, "
+        (use client (run))
+        (run)
+        (use very-long-name :as list)
+        (list:parse \"y\")
+        (list 1 2)
+        ").unwrap(),
+        ["()", r#"("parsed x" 10 local)"#, "()", r#""parsed y""#, "(1 2)"]);
+
+    assert_matches!(run(&interp, "list:hidden").unwrap_err(),
+        Error::CompileError(CompileError::PrivacyError{..}));
+    assert_matches!(run(&interp, "list:missing").unwrap_err(),
+        Error::CompileError(CompileError::ImportError{..}));
+    assert_matches!(run(&interp, "(use very-long-name :as)").unwrap_err(),
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
+    // An unknown alias is an ordinary name
+    assert_matches!(run(&interp, "other:parse").unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+
+    // Qualified names are resolved when compiled code is loaded
+    let interp = Interpreter::with_loader(Box::new(BytecodeLoader{
+        bytecode: bytecode,
+    }));
+
+    assert_eq!(run(&interp, "
+        (use client (run))
+        (run)
+        ").unwrap(),
+        ["()", r#"("parsed x" 10 local)"#]);
+}
+
+#[test]
+fn test_range_bytecode() {
+    let interp = Interpreter::new();
+
+    let encode = |src: &str| {
+        let code = try!(interp.compile_exprs(src));
+        let mcode = ModuleCode::new(code.into_iter().map(Rc::new).collect(),
+            interp.get_scope());
+        let mut buf = Vec::new();
+        write_bytecode(&mut buf, Path::new("range"), &mcode,
+            &interp.get_scope().borrow_names())
+    };
+
+    // A range may not be a const value, unless it is materialized as a list
+    assert_matches!(encode("(macro (r) (range 0 3)) (define (f) (r))").unwrap_err(),
+        Error::EncodeError(EncodeError::UnencodableType(_)));
+    encode("(macro (rl) `',(range-list (range 0 3))) (define (g) (rl))").unwrap();
+}
+
+#[test]
+fn test_vector_bytecode() {
+    let mut sources = HashMap::new();
+
+    sources.insert("consts".to_owned(), "
+        (export (primes))
+        (macro (primes-vec) (vector 2 3 5 7))
+        (define (primes) (primes-vec))
+        ".to_owned());
+
+    let bytecode = Rc::new(RefCell::new(HashMap::new()));
+
+    let interp = Interpreter::with_loader(Box::new(SourceLoader{
+        sources: sources,
+        bytecode: bytecode.clone(),
+    }));
+
+    run(&interp, "(use consts :all)").unwrap();
+
+    let interp = Interpreter::with_loader(Box::new(BytecodeLoader{
+        bytecode: bytecode,
+    }));
+
+    assert_eq!(run(&interp, "
+        (use consts (primes))
+        (primes)
+        (vec-ref (primes) 3)
+        ").unwrap(),
+        ["()", "#(2 3 5 7)", "7"]);
+}
+
+/// Builds a value nested `depth` levels deep, alternating lists, vectors,
+/// and quotes; e.g. `('[(1)])` for a depth of 4.
+fn deep_value(depth: usize) -> Value {
+    let mut v = Value::from(1);
+
+    for i in 0..depth {
+        v = match i % 3 {
+            0 => vec![v].into(),
+            1 => Value::new_vector(vec![v]),
+            _ => v.quote(1),
+        };
+    }
+
+    v
+}
+
+/// Takes apart a nested value without recursing through `Drop`.
+fn drop_deep(mut v: Value) {
+    loop {
+        v = match v {
+            Value::List(li) | Value::Vector(li) => li.into_vec().pop().unwrap(),
+            Value::Quote(v, _) => *v,
+            _ => break
+        };
+    }
+}
+
+fn take_const(mut mcode: ModuleCode) -> Value {
+    let code = Rc::try_unwrap(mcode.code.pop().unwrap()).ok().unwrap();
+    code.consts.into_vec().pop().unwrap()
+}
+
+#[test]
+fn test_deep_values() {
+    const DEPTH: usize = 100_000;
+
+    let interp = Interpreter::new();
+    let scope = interp.get_scope();
+
+    let a = deep_value(DEPTH);
+    let b = deep_value(DEPTH);
+
+    assert!(a.is_equal(&b).unwrap());
+    assert!(a.is_identical(&b));
+
+    let s = interp.format_value(&a);
+    assert!(s.starts_with("('#(('#(") && s.contains("('#((1)))"));
+
+    assert_eq!(debug_depth(&scope.borrow_names(), &a, 2).to_string(),
+        "('#((...)))");
+
+    let code = Code{
+        name: None,
+        consts: vec![a].into_boxed_slice(),
+        code: Box::new([]),
+        param_names: Box::new([]),
+        kw_params: Box::new([]),
+        n_params: 0,
+        req_params: 0,
+        flags: 0,
+    };
+
+    let mcode = ModuleCode::new(vec![Rc::new(code)], scope);
+    let mut buf = Vec::new();
+
+    write_bytecode(&mut buf, Path::new("deep"), &mcode,
+        &scope.borrow_names()).unwrap();
+    drop_deep(take_const(mcode));
+
+    let mcode = read_bytecode(&mut &buf[..], Path::new("deep"), scope).unwrap();
+    let a = take_const(mcode);
+
+    assert!(a.is_equal(&b).unwrap());
+
+    drop_deep(a);
+    drop_deep(b);
+}
+
+#[test]
+fn test_name_collections() {
+    let interp = Interpreter::new();
+    let scope = interp.get_scope();
+
+    let map = NameMap::from_strs(scope, vec![("b", 2), ("a", 1), ("b", 3), ("define", 4)]);
+    let a = scope.add_name("a");
+    let b = scope.add_name("b");
+
+    assert_eq!(map.len(), 3);
+    assert_eq!(map.get(a), Some(&1));
+    assert_eq!(map.get(b), Some(&3));
+    assert!(map.contains_key(get_standard_name_for("define").unwrap()));
+    assert!(map.iter().zip(map.iter().skip(1)).all(|(x, y)| x.0 < y.0));
+
+    let mut map = map;
+    *map.get_mut(a).unwrap() += 10;
+    assert_eq!(map.into_slice().get(a), Some(&11));
+
+    let x = NameSet::from_strs(scope, vec!["a", "b", "c"]);
+    let y = NameSet::from_strs(scope, vec!["c", "d"]);
+    let c = scope.add_name("c");
+    let d = scope.add_name("d");
+
+    assert_eq!(x.union(&y), NameSet::from_strs(scope, vec!["a", "b", "c", "d"]));
+    assert_eq!(x.difference(&y), vec![a, b].into_iter().collect::<NameSet>());
+    assert_eq!(x.intersection(&y), vec![c].into_iter().collect::<NameSet>());
+    assert!(!x.contains(d));
+}
+
+#[test]
+fn test_run_module_source() {
+    let interp = Interpreter::new();
+
+    interp.run_module_source("foo", "
+        (export (foo))
+        (define (foo a) (* a 2))
+        (define (bar) 1)
+        ").unwrap();
+
+    assert_eq!(run(&interp, "
+        (use foo (foo))
+        (foo 3)
+        ").unwrap(),
+        ["()", "6"]);
+
+    assert_matches!(run(&interp, "(use foo (bar))").unwrap_err(),
+        Error::CompileError(CompileError::PrivacyError{..}));
+
+    assert_matches!(interp.run_module_source("bar", "(define x 1)").err(),
+        Some(Error::CompileError(CompileError::MissingExport)));
+}
+
+#[test]
+fn test_expansion_scope() {
+    let interp = Interpreter::new();
+
+    interp.run_module_source("defs", "
+        (export (define-once module-of))
+        (define helper 1)
+        (macro (define-once name value)
+          (if (defined? name)
+            ''already-defined
+            `(define ,name ,value)))
+        (macro (module-of)
+          `(list ',(current-module) ,(defined? 'helper)
+                 ,(macro-defined? 'define-once)))
+        ").unwrap();
+
+    assert_eq!(run(&interp, "
+        (current-module)
+        (defined? 'foo)
+        (defined? 'println)
+        (macro-defined? 'define-once)
+        ").unwrap(),
+        ["()", "false", "true", "false"]);
+
+    interp.run_code("(use defs :all :macro :all)", None).unwrap();
+    interp.run_code("(define-once foo 1)", None).unwrap();
+
+    assert_eq!(run(&interp, "
+        (define-once foo 2)
+        foo
+        (module-of)
+        (defined? 'foo)
+        (macro-defined? 'define-once)
+        ").unwrap(),
+        ["already-defined", "1", "(() false true)", "true", "true"]);
+
+    interp.run_module_source("user", "
+        (use defs () :macro (module-of))
+        (export (result))
+        (define helper 2)
+        (define result (module-of))
+        ").unwrap();
+
+    assert_eq!(run(&interp, "(use user (result)) result").unwrap(),
+        ["()", "(user true false)"]);
+}
+
+fn triple(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    match args[0] {
+        Value::Integer(ref i) => Ok(Value::Integer(i * &Integer::from_i32(3))),
+        ref v => Err(From::from(ExecError::expected("integer", v)))
+    }
+}
+
+fn read_file(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let path = match args[0] {
+        Value::String(ref s) => PathBuf::from(s),
+        ref v => return Err(From::from(ExecError::expected("string", v)))
+    };
+
+    let mut buf = String::new();
+
+    try!(File::open(&path)
+        .and_then(|mut f| f.read_to_string(&mut buf))
+        .map_err(|e| IoError::new(IoMode::Open, &path, e)));
+
+    Ok(buf.into())
+}
+
+#[test]
+fn test_memory_loader_compiled() {
+    let names = Rc::new(RefCell::new(NameStore::new()));
+    let codemap = Rc::new(RefCell::new(CodeMap::new()));
+    let io = Rc::new(GlobalIo::default());
+
+    let build_scope = Rc::new(GlobalScope::new(names.clone(), codemap.clone(),
+        Rc::new(ModuleRegistry::new(Box::new(BuiltinModuleLoader))), io.clone()));
+
+    let m = ModuleBuilder::new("util", build_scope)
+        .add_function("triple", triple, Arity::Exact(1))
+        .add_value("answer", Value::Integer(Integer::from_i32(42)))
+        .finish();
+
+    let mut loader = MemoryModuleLoader::new();
+
+    loader.add_compiled("util", ModuleCode::from_module(&m));
+    loader.add_module("main", "
+        (use util (triple answer))
+        (export (run))
+        (define (run) (triple answer))
+        ");
+
+    let scope = Rc::new(GlobalScope::new(names, codemap,
+        Rc::new(ModuleRegistry::new(Box::new(loader))), io));
+    let interp = Interpreter::with_scope(scope);
+
+    assert_eq!(run(&interp, "
+        (use main (run))
+        (run)
+        (use util :all)
+        (triple 5)
+        ").unwrap(),
+        ["()", "126", "()", "15"]);
+
+    assert_matches!(run(&interp, "(use nothing :all)").unwrap_err(),
+        Error::CompileError(CompileError::ModuleError(_)));
+}
+
+#[test]
+fn test_export_selective() {
+    let names = Rc::new(RefCell::new(NameStore::new()));
+    let codemap = Rc::new(RefCell::new(CodeMap::new()));
+    let io = Rc::new(GlobalIo::default());
+
+    let build_scope = Rc::new(GlobalScope::new(names.clone(), codemap.clone(),
+        Rc::new(ModuleRegistry::new(Box::new(BuiltinModuleLoader))), io.clone()));
+
+    let m = ModuleBuilder::new("util", build_scope)
+        .add_value("a", Value::Integer(Integer::from_i32(1)))
+        .add_value("b", Value::Integer(Integer::from_i32(2)))
+        .add_value("c", Value::Integer(Integer::from_i32(3)))
+        .finish();
+
+    m.scope.export_selective(&["c", "a", "b"]);
+    m.scope.unexport("b");
+
+    let mut loader = MemoryModuleLoader::new();
+    loader.add_compiled("util", ModuleCode::from_module(&m));
+
+    let scope = Rc::new(GlobalScope::new(names, codemap,
+        Rc::new(ModuleRegistry::new(Box::new(loader))), io));
+    let interp = Interpreter::with_scope(scope);
+
+    assert_eq!(run(&interp, "
+        (use util :all)
+        (list a c)
+        ").unwrap(),
+        ["()", "(1 3)"]);
+
+    assert_matches!(run(&interp, "b").unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+    assert_matches!(run(&interp, "(use util (b))").unwrap_err(),
+        Error::CompileError(CompileError::PrivacyError{..}));
+}
+
+fn write_file(path: &Path, src: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    File::create(path).unwrap().write_all(src.as_bytes()).unwrap();
+}
+
+/// Sets the modification time of a file to the given number of seconds
+/// before the present.
+fn set_file_age(path: &Path, secs: u64) {
+    let time = SystemTime::now() - Duration::from_secs(secs);
+    File::open(path).unwrap().set_modified(time).unwrap();
+}
+
+#[test]
+fn test_file_loader_subdirectories() {
+    let root = env::temp_dir().join(format!("ketos-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+
+    write_file(&root.join("lib/http/server.kts"), "
+        (use http.status (ok))
+        (export (serve))
+        (define (serve) (ok))
+        ");
+    write_file(&root.join("lib/http/status.kts"), "
+        (export (ok))
+        (define (ok) 200)
+        ");
+    write_file(&root.join("lib/http.kts"), "
+        (export (version))
+        (define version \"1.1\")
+        ");
+    write_file(&root.join("secret.kts"), "
+        (export (secret))
+        (define secret 1)
+        ");
+
+    let paths: Vec<PathBuf> = vec![root.join("empty"), root.join("lib")];
+    let interp = Interpreter::with_loader(
+        Box::new(FileModuleLoader::with_search_paths(paths)));
+
+    assert_eq!(run(&interp, "
+        (use http.server (serve))
+        (use http (version))
+        (serve)
+        version
+        ").unwrap(),
+        ["()", "()", "200", "\"1.1\""]);
+
+    assert!(root.join("lib/http/server.ktsc").exists());
+
+    for name in &[".http", "http.", "http..server", "..secret", "lib/http"] {
+        assert_matches!(run(&interp, &format!("(use {} :all)", name)).unwrap_err(),
+            Error::CompileError(CompileError::InvalidModuleName(_)));
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_file_loader_macro_scope() {
+    let root = env::temp_dir().join(format!("ketos-test-macro-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+
+    write_file(&root.join("lib.kts"), "
+        (export (twice))
+        (define (double-form x) `(list ,x ,x))
+        (macro (twice x) (double-form x))
+        ");
+
+    // Compiled code must be newer than the source to be used
+    set_file_age(&root.join("lib.kts"), 60);
+
+    // Macros loaded from source and from compiled bytecode
+    // both see private values of their module
+    for _ in 0..2 {
+        let interp = Interpreter::with_loader(
+            Box::new(FileModuleLoader::with_search_paths(vec![root.clone()])));
+
+        assert_eq!(run(&interp, "(use lib () :macro (twice)) (twice 1)").unwrap(),
+            ["()", "(1 1)"]);
+        assert!(root.join("lib.ktsc").exists());
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_compile_only() {
+    let root = env::temp_dir().join(format!("ketos-test-check-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+
+    write_file(&root.join("lib.kts"), "
+        (export (greet twice))
+        (panic \"lib executed\")
+        (define (greet name) (concat \"hello \" name))
+        (macro (twice e) `(list ,e ,e))
+        ");
+    write_file(&root.join("main.kts"), "
+        (use lib (greet) :macro (twice))
+        (define-module inner
+          (export (f))
+          (panic \"inner executed\"))
+        (use inner (f))
+        (panic \"main executed\")
+        (twice (greet (f)))
+        ");
+    write_file(&root.join("private.kts"), "
+        (use lib (secret))
+        ");
+
+    let interp = Interpreter::with_loader(
+        Box::new(FileModuleLoader::with_search_paths(vec![root.clone()])));
+    interp.get_scope().set_compile_only(true);
+
+    assert_eq!(interp.compile_file(&root.join("main.kts")).unwrap().len(), 5);
+    assert!(!root.join("lib.ktsc").exists());
+
+    assert_matches!(interp.compile_file(&root.join("private.kts")).unwrap_err(),
+        Error::CompileError(CompileError::PrivacyError{..}));
+
+    let interp = Interpreter::with_loader(
+        Box::new(FileModuleLoader::with_search_paths(vec![root.clone()])));
+
+    assert_matches!(interp.compile_file(&root.join("main.kts")).unwrap_err(),
+        Error::ExecError(ExecError::Panic(_)));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_file_loader_errors() {
+    let root = env::temp_dir().join(format!("ketos-test-errors-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+
+    fs::create_dir_all(root.join("dir.kts")).unwrap();
+    fs::create_dir_all(&root).unwrap();
+    File::create(root.join("bytes.kts")).unwrap()
+        .write_all(b"(export (x)) (define x \"\xff\xfe\")").unwrap();
+    write_file(&root.join("outer.kts"), "
+        (use bytes (x))
+        (export (x))
+        ");
+
+    let interp = Interpreter::with_loader(
+        Box::new(FileModuleLoader::with_search_paths(vec![root.clone()])));
+
+    let e = run(&interp, "(use dir :all)").unwrap_err();
+
+    match e {
+        Error::ModuleLoadError(ref e) => {
+            assert_eq!(e.path, root.join("dir.kts"));
+            assert_matches!(&*e.cause, &Error::IoError(IoError{mode: IoMode::Open, ..}));
+        }
+        ref e => panic!("unexpected error: {:?}", e)
+    }
+
+    assert_eq!(interp.format_error_chain(&e), format!("\
+failed to load module `dir` from file `{0}`
+  io error: failed to open file `{0}`: path is a directory
+", root.join("dir.kts").display()));
+
+    let e = run(&interp, "(use outer :all)").unwrap_err();
+
+    match e {
+        Error::ModuleLoadError(ref e) => {
+            assert_eq!(interp.get_scope().with_name(e.module, |s| s.to_owned()), "bytes");
+            assert_eq!(e.path, root.join("bytes.kts"));
+            assert_matches!(&*e.cause, &Error::IoError(IoError{mode: IoMode::Read, ..}));
+        }
+        ref e => panic!("unexpected error: {:?}", e)
+    }
+
+    assert_eq!(error_kind(&e), ("io", "invalid-data"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_std_error_source() {
+    use std::error::Error as StdError;
+    use std::io;
+
+    let interp = Interpreter::new();
+
+    let e = interp.run_file(Path::new("tests/does-not-exist.kts")).unwrap_err();
+    let boxed: Box<StdError> = Box::new(e);
+    let source = boxed.source().unwrap();
+    assert_eq!(source.downcast_ref::<io::Error>().unwrap().kind(), io::ErrorKind::NotFound);
+    assert!(source.source().is_none());
+
+    let root = env::temp_dir().join(format!("ketos-test-source-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("dir.kts")).unwrap();
+
+    let interp = Interpreter::with_loader(
+        Box::new(FileModuleLoader::with_search_paths(vec![root.clone()])));
+
+    // ModuleLoadError -> IoError -> io::Error
+    let e = run(&interp, "(use dir :all)").unwrap_err().context("loading dir");
+    let chain = {
+        let mut chain = Vec::new();
+        let mut cur: Option<&StdError> = Some(&e);
+
+        while let Some(err) = cur {
+            chain.push(err.to_string());
+            cur = err.source();
+        }
+        chain
+    };
+
+    assert_eq!(chain.len(), 4);
+    assert_eq!(chain[0], "loading dir");
+    assert!(chain[1].starts_with("failed to load module from file"));
+    assert!(chain[2].starts_with("failed to open file"));
+    assert_matches!(e.source().and_then(|e| e.downcast_ref::<Error>()),
+        Some(&Error::ModuleLoadError(_)));
+
+    fs::remove_dir_all(&root).unwrap();
+
+    let e = run(&interp, "(undefined-fn)").unwrap_err();
+    assert_eq!(e.to_string(), "name not found in global scope");
+    assert_eq!(e.display_with(&interp.get_scope().borrow_names()).to_string(),
+        "name not found in global scope: undefined-fn");
+}
+
+#[test]
+fn test_log_handler() {
+    let root = env::temp_dir().join(format!("ketos-test-log-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+
+    // A directory in place of the compiled file makes writing fail,
+    // even where permissions would not.
+    fs::create_dir_all(root.join("foo.ktsc")).unwrap();
+    write_file(&root.join("foo.kts"), "
+        (export (foo))
+        (define (foo) 1)
+        ");
+    set_file_age(&root.join("foo.ktsc"), 60);
+
+    let interp = Interpreter::with_loader(
+        Box::new(FileModuleLoader::with_search_paths(vec![root.clone()])));
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let log_ref = log.clone();
+
+    interp.get_scope().set_log_handler(Box::new(move |level, msg| {
+        log_ref.borrow_mut().push((level, msg.to_owned()));
+    }));
+
+    assert_eq!(run(&interp, "(use foo (foo)) (foo)").unwrap(), ["()", "1"]);
+
+    {
+        let log = log.borrow();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].0, LogLevel::Warning);
+        assert!(log[0].1.starts_with("failed to write compiled bytecode: "));
+    }
+
+    log.borrow_mut().clear();
+    run(&interp, "(let ((a 1)) (define b a))").unwrap();
+
+    assert_eq!(*log.borrow(), [(LogLevel::Warning,
+        "`define` operator used outside of top level".to_owned())]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+fn run_file_value(root: &Path, file: &str, cache: bool) -> Result<String, Error> {
+    let interp = Interpreter::with_loader(
+        Box::new(FileModuleLoader::with_search_paths(vec![root.to_owned()])));
+    interp.get_scope().set_cache_run_file(cache);
+
+    try!(interp.run_file(&root.join(file)));
+    Ok(interp.format_value(&interp.get_value("value").unwrap()))
+}
+
+#[test]
+fn test_run_file_cached() {
+    let root = env::temp_dir().join(format!("ketos-test-run-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+
+    write_file(&root.join("util.kts"), "
+        (use math (sqrt))
+        (export (four))
+        (define (four) (int (sqrt 16.0)))
+        ");
+    write_file(&root.join("app.kts"), "
+        (use util (four))
+        (define value (four))
+        ");
+
+    // Caching is disabled by default; imported modules are still cached
+    assert_eq!(run_file_value(&root, "app.kts", false).unwrap(), "4");
+    assert!(!root.join("app.ktsc").exists());
+    assert!(root.join("util.ktsc").exists());
+
+    set_file_age(&root.join("app.kts"), 60);
+    assert_eq!(run_file_value(&root, "app.kts", true).unwrap(), "4");
+    assert!(root.join("app.ktsc").exists());
+
+    // Imports are repeated when compiled code is loaded
+    assert_eq!(run_file_value(&root, "app.kts", true).unwrap(), "4");
+
+    // Compiled code newer than the source is used
+    write_file(&root.join("app.kts"), "(define value");
+    set_file_age(&root.join("app.kts"), 60);
+
+    assert_eq!(run_file_value(&root, "app.kts", true).unwrap(), "4");
+
+    // Unless caching is disabled
+    assert_matches!(run_file_value(&root, "app.kts", false).unwrap_err(),
+        Error::ParseError(_));
+
+    // Source newer than compiled code is compiled again
+    set_file_age(&root.join("app.ktsc"), 60);
+    write_file(&root.join("app.kts"), "(define value 5)");
+
+    assert_eq!(run_file_value(&root, "app.kts", true).unwrap(), "5");
+    set_file_age(&root.join("app.kts"), 60);
+    assert_eq!(run_file_value(&root, "app.kts", true).unwrap(), "5");
+
+    set_file_age(&root.join("app.ktsc"), 60);
+    write_file(&root.join("app.kts"), "(define value");
+
+    assert_matches!(run_file_value(&root, "app.kts", true).unwrap_err(),
+        Error::ParseError(_));
+
+    // Code containing `define-module` is run without writing compiled code
+    fs::remove_file(&root.join("app.ktsc")).unwrap();
+    write_file(&root.join("app.kts"), "
+        (define-module local (export (six)) (define (six) 6))
+        (use local (six))
+        (define value (six))
+        ");
+
+    let interp = Interpreter::with_loader(
+        Box::new(FileModuleLoader::with_search_paths(vec![root.clone()])));
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let log_ref = log.clone();
+
+    interp.get_scope().set_cache_run_file(true);
+    interp.get_scope().set_log_handler(Box::new(move |_level, msg| {
+        log_ref.borrow_mut().push(msg.to_owned());
+    }));
+
+    interp.run_file(&root.join("app.kts")).unwrap();
+    assert_eq!(interp.format_value(&interp.get_value("value").unwrap()), "6");
+    assert!(log.borrow().is_empty());
+    assert!(!root.join("app.ktsc").exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_use_ref() {
+    let interp = Interpreter::new();
+
+    interp.run_module_source("config", "
+        (export (threshold scale))
+        (define threshold 10)
+        (define (scale n) (* n threshold))
+        ").unwrap();
+
+    assert_eq!(run(&interp, "
+        (use config (threshold))
+        (use config :ref (:live-threshold threshold scale))
+        (define (get-live) live-threshold)
+        (list threshold live-threshold (get-live) (scale 2))
+        ").unwrap(),
+        ["()", "()", "get-live", "(10 10 10 20)"]);
+
+    interp.run_module_source("config", "
+        (export (threshold scale))
+        (define threshold 20)
+        (define (scale n) (+ n threshold))
+        ").unwrap();
+
+    // Values imported by reference are found in the reloaded module
+    assert_eq!(run(&interp, "(list threshold live-threshold (get-live) (scale 2))").unwrap(),
+        ["(10 20 20 22)"]);
+
+    assert_matches!(run(&interp, "(use config :ref (missing))").unwrap_err(),
+        Error::CompileError(CompileError::ImportError{..}));
+    assert_matches!(run(&interp, "(use config :ref :all)").unwrap_err(),
+        Error::CompileError(CompileError::OperatorSyntaxError{..}));
+
+    // Redefining an imported name is a warning; in strict mode, an error
+    assert_eq!(run(&interp, "(define threshold 30) threshold").unwrap(),
+        ["threshold", "30"]);
+
+    interp.get_scope().set_strict(true);
+
+    assert_matches!(run(&interp, "(define threshold 30)").unwrap_err(),
+        Error::CompileError(CompileError::DefineImport{..}));
+    assert_matches!(run(&interp, "(define (live-threshold) 30)").unwrap_err(),
+        Error::CompileError(CompileError::DefineImport{..}));
+    assert_matches!(run(&interp, "(use math :all) (define (sqrt x) x)").unwrap_err(),
+        Error::CompileError(CompileError::DefineImport{..}));
+}
+
+#[test]
+fn test_with_module() {
+    let interp = Interpreter::new();
+
+    interp.run_module_source("config", "
+        (export (threshold scale))
+        (define threshold 10)
+        (define secret 1)
+        (define (scale n) (* n threshold))
+        ").unwrap();
+
+    assert_eq!(run(&interp, "
+        (with-module config
+          (define threshold 20)
+          (define secret (+ secret 1))
+          (list secret (scale 2)))
+        ").unwrap(),
+        ["(2 40)"]);
+
+    // `use` imports values when it is compiled
+    assert_eq!(run(&interp, "
+        (use config (threshold scale))
+        (list threshold (scale 3))
+        ").unwrap(),
+        ["()", "(20 60)"]);
+
+    // Values defined within the module are not defined in the outer scope
+    assert_matches!(run(&interp, "secret").unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+
+    assert_matches!(run(&interp, "(with-module missing 1)").unwrap_err(),
+        Error::CompileError(CompileError::ModuleError(_)));
+}
+
+#[test]
+fn test_with_module_bytecode() {
+    let mut sources = HashMap::new();
+
+    sources.insert("config".to_owned(), "
+        (export (threshold))
+        (define threshold 10)
+        ".to_owned());
+    sources.insert("tuning".to_owned(), "
+        (export (raise))
+        (with-module config (define threshold 20))
+        (define (raise)
+          (with-module config
+            (define threshold (+ threshold 1))
+            threshold))
+        ".to_owned());
+
+    let bytecode = Rc::new(RefCell::new(HashMap::new()));
+
+    let interp = Interpreter::with_loader(Box::new(SourceLoader{
+        sources: sources,
+        bytecode: bytecode.clone(),
+    }));
+
+    assert_eq!(run(&interp, "(use tuning (raise)) (raise)").unwrap(), ["()", "21"]);
+
+    // Loaded from bytecode, the body is still evaluated in the module scope
+    let interp = Interpreter::with_loader(Box::new(BytecodeLoader{
+        bytecode: bytecode,
+    }));
+
+    assert_eq!(run(&interp, "(use tuning (raise)) (raise) (raise)").unwrap(),
+        ["()", "21", "22"]);
+    assert_eq!(run(&interp, "(use config (threshold)) threshold").unwrap(),
+        ["()", "22"]);
+    assert_matches!(run(&interp, "(use tuning (threshold))").unwrap_err(),
+        Error::CompileError(CompileError::ImportError{..}));
+}
+
+#[test]
+fn test_define_module() {
+    let interp = Interpreter::new();
+
+    assert_eq!(run(&interp, "
+        (define-module shapes
+          (export (area perimeter))
+          (define pi 3.0)
+          (define (area r) (* pi r r))
+          (define (perimeter r) (* 2 pi r)))
+        (use shapes (area))
+        (area 2)
+        ").unwrap(),
+        ["shapes", "()", "12.0"]);
+
+    assert_matches!(run(&interp, "(perimeter 2)").unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+    assert_matches!(run(&interp, "pi").unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+
+    assert_matches!(run(&interp, "(define-module empty (define a 1))").unwrap_err(),
+        Error::CompileError(CompileError::MissingExport));
+    assert_matches!(run(&interp, "(define-module bad (export (b)))").unwrap_err(),
+        Error::CompileError(CompileError::ExportError{..}));
+}
+
+#[test]
+fn test_define_module_bytecode() {
+    let mut sources = HashMap::new();
+
+    sources.insert("geometry".to_owned(), "
+        (export (square))
+        (define-module shapes
+          (export (area))
+          (define (area r) (* r r)))
+        (use shapes (area))
+        (define (square r) (area r))
+        ".to_owned());
+
+    let interp = Interpreter::with_loader(Box::new(SourceLoader{
+        sources: sources,
+        bytecode: Rc::new(RefCell::new(HashMap::new())),
+    }));
+
+    // The inline module would be missing when the bytecode is loaded
+    let e = run(&interp, "(use geometry (square))").unwrap_err();
+    assert_matches!(e, Error::EncodeError(EncodeError::DefineModule));
+    assert_eq!(interp.format_error(&e),
+        "encode error: cannot encode `define-module`; \
+         the module is defined only when compiled\n");
+
+    // A module file using `define-module` is loaded from source
+    // and its compiled code is not written
+    let root = env::temp_dir().join(format!("ketos-test-inline-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+
+    write_file(&root.join("geometry.kts"), "
+        (export (square))
+        (define-module shapes
+          (export (area))
+          (define (area r) (* r r)))
+        (use shapes (area))
+        (define (square r) (area r))
+        ");
+
+    let interp = Interpreter::with_loader(
+        Box::new(FileModuleLoader::with_search_paths(vec![root.clone()])));
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let log_ref = log.clone();
+
+    interp.get_scope().set_log_handler(Box::new(move |_level, msg| {
+        log_ref.borrow_mut().push(msg.to_owned());
+    }));
+
+    assert_eq!(run(&interp, "(use geometry (square)) (square 3)").unwrap(),
+        ["()", "9"]);
+    assert!(log.borrow().is_empty());
+    assert!(!root.join("geometry.ktsc").exists());
+
+    let interp = Interpreter::with_loader(
+        Box::new(FileModuleLoader::with_search_paths(vec![root.clone()])));
+    assert_eq!(run(&interp, "(use geometry (square)) (square 4)").unwrap(),
+        ["()", "16"]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_import_for_syntax() {
+    let interp = Interpreter::new();
+
+    interp.run_module_source("helpers", "
+        (export (double-form))
+        (define (double-form x) `(* 2 ,x))
+        ").unwrap();
+
+    assert_eq!(run(&interp, "
+        (import-for-syntax helpers (double-form :quad-form double-form))
+        (macro (twice x) (double-form x))
+        (macro (quad x) (quad-form (double-form x)))
+        (macro (twice-first :rest xs)
+          (let ((f (lambda (x) (double-form x))))
+            (f (first xs))))
+        (twice 21)
+        (quad 5)
+        (twice-first 4 5)
+        ").unwrap(),
+        ["()", "twice", "quad", "twice-first", "42", "20", "8"]);
+
+    // Names imported for syntax are not visible outside of macro bodies
+    assert_matches!(run(&interp, "(double-form 1)").unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+
+    interp.get_scope().set_strict(true);
+
+    assert_eq!(run(&interp, "(define (double-form x) x) (double-form 1)").unwrap(),
+        ["double-form", "1"]);
+
+    assert_matches!(run(&interp, "(import-for-syntax helpers (missing))").unwrap_err(),
+        Error::CompileError(CompileError::ImportError{..}));
+}
+
+#[test]
+fn test_dynamic_variable_import() {
+    let mut loader = MemoryModuleLoader::new();
+
+    loader.add_module("indent", "
+        (export (*indent* current-indent))
+        (defvar *indent* 0)
+        (define (current-indent) *indent*)
+        ");
+
+    let interp = Interpreter::with_loader(Box::new(loader));
+
+    assert_eq!(run(&interp, "
+        (use indent (*indent* current-indent))
+        (with-bindings ((*indent* 4)) (current-indent))
+        (use indent (:*depth* *indent*))
+        (with-bindings ((*depth* (+ *depth* 2))) (list *indent* (current-indent)))
+        (current-indent)
+        ").unwrap(),
+        ["()", "4", "()", "(2 2)", "0"]);
+}
+
+#[test]
+fn test_export_rename() {
+    let mut sources = HashMap::new();
+
+    sources.insert("helpers".to_owned(), "
+        (export (public))
+        (export-rename (internal-helper :as helper)
+                       (internal-macro :as helper-macro))
+        (define (internal-helper a) (* a 2))
+        (define (public) (internal-helper 5))
+        (macro (internal-macro a) `(helper ,a))
+        ".to_owned());
+
+    let bytecode = Rc::new(RefCell::new(HashMap::new()));
+
+    let interp = Interpreter::with_loader(Box::new(SourceLoader{
+        sources: sources,
+        bytecode: bytecode.clone(),
+    }));
+
+    assert_eq!(run(&interp, "
+        (use helpers (helper public) :macro (helper-macro))
+        (helper 3)
+        (public)
+        (helper-macro 4)
+        ").unwrap(),
+        ["()", "6", "10", "8"]);
+
+    assert_matches!(run(&interp, "(use helpers (internal-helper))").unwrap_err(),
+        Error::CompileError(CompileError::PrivacyError{..}));
+
+    // Renamed exports are preserved in compiled bytecode
+    let interp = Interpreter::with_loader(Box::new(BytecodeLoader{
+        bytecode: bytecode,
+    }));
+
+    assert_eq!(run(&interp, "
+        (use helpers :all)
+        (helper 4)
+        (public)
+        ").unwrap(),
+        ["()", "8", "10"]);
+
+    assert_matches!(run(&interp, "internal-helper").unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+}
+
+#[test]
+fn test_export_rename_errors() {
+    let interp = Interpreter::new();
+
+    assert_matches!(interp.run_module_source("a", "
+        (export-rename (foo :as bar))
+        ").err(),
+        Some(Error::CompileError(CompileError::ExportError{..})));
+
+    assert_matches!(interp.run_module_source("b", "
+        (export (bar))
+        (export-rename (foo :as bar))
+        ").err(),
+        Some(Error::CompileError(CompileError::DuplicateExport(_))));
+
+    assert_matches!(interp.run_module_source("c", "
+        (export-rename (foo :as bar) (baz :as bar))
+        ").err(),
+        Some(Error::CompileError(CompileError::DuplicateExport(_))));
+
+    assert_matches!(interp.run_module_source("d", "
+        (export-rename (foo bar))
+        ").err(),
+        Some(Error::CompileError(CompileError::OperatorSyntaxError{..})));
+
+    interp.run_module_source("e", "
+        (export-rename (foo :as bar))
+        (define foo 1)
+        ").unwrap();
+
+    assert_eq!(run(&interp, "(use e (bar)) bar").unwrap(), ["()", "1"]);
+}
+
+fn lambda_code(v: Option<Value>) -> Weak<Code> {
+    match v {
+        Some(Value::Lambda(ref l)) => Rc::downgrade(&l.code),
+        v => panic!("expected lambda; found {:?}", v)
+    }
+}
+
+#[test]
+fn test_drop_frees_lambdas() {
+    let mut loader = MemoryModuleLoader::new();
+
+    loader.add_module("closures", "
+        (export (make-closure))
+        (define (make-closure big) (lambda () (len big)))
+        ");
+
+    let interp = Interpreter::with_loader(Box::new(loader));
+
+    run(&interp, "
+        (use closures (make-closure))
+        (use math :all)
+        (define f (make-closure '(1 2 3)))
+        (define (g) f)
+        ").unwrap();
+
+    let f_code = lambda_code(interp.get_value("f"));
+    let g_code = lambda_code(interp.get_value("g"));
+
+    assert_eq!(run(&interp, "(f)").unwrap(), ["3"]);
+
+    drop(interp);
+
+    assert!(f_code.upgrade().is_none());
+    assert!(g_code.upgrade().is_none());
+}
+
+#[test]
+fn test_live_code_stats() {
+    let interp = Interpreter::new();
+
+    assert_eq!(interp.live_code_stats(), None);
+
+    interp.get_scope().set_track_code(true);
+
+    run(&interp, "
+        (define (foo) '(1 2 3))
+        (define bar (lambda (x) (+ x 1)))
+        ").unwrap();
+
+    let stats = interp.live_code_stats().unwrap();
+    assert_eq!(stats.count, 2);
+    assert!(stats.const_bytes > 0);
+
+    // Overwriting a global frees the lambda
+    run(&interp, "(define foo ())").unwrap();
+    assert_eq!(interp.live_code_stats().unwrap().count, 1);
+
+    // Closures sharing a code object track it once
+    run(&interp, "
+        (define (adder n) (lambda (x) (+ x n)))
+        (define add-one (adder 1))
+        (for i (range 1000) ((adder i) i))
+        ").unwrap();
+
+    let stats = interp.live_code_stats().unwrap();
+    assert_eq!(stats.count, 3);
+    assert!(stats.tracked <= 4);
+
+    interp.get_scope().set_track_code(false);
+    assert_eq!(interp.live_code_stats(), None);
+}
+
+thread_local!{
+    static REGISTERED: RefCell<Vec<String>> = RefCell::new(Vec::new())
+}
+
+fn register(_scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let name = match args[0] {
+        Value::String(ref s) => s.to_string(),
+        ref v => return Err(From::from(ExecError::expected("string", v)))
+    };
+
+    REGISTERED.with(|r| r.borrow_mut().push(name));
+    Ok(Value::Unit)
+}
+
+#[test]
+fn test_use_empty_list() {
+    let names = Rc::new(RefCell::new(NameStore::new()));
+    let codemap = Rc::new(RefCell::new(CodeMap::new()));
+    let io = Rc::new(GlobalIo::default());
+
+    let build_scope = Rc::new(GlobalScope::new(names.clone(), codemap.clone(),
+        Rc::new(ModuleRegistry::new(Box::new(BuiltinModuleLoader))), io.clone()));
+
+    let m = ModuleBuilder::new("registry", build_scope)
+        .add_function("register", register, Arity::Exact(1))
+        .finish();
+
+    let mut loader = MemoryModuleLoader::new();
+
+    loader.add_compiled("registry", ModuleCode::from_module(&m));
+    loader.add_module("handlers", "
+        (use registry (register))
+        (export (handler))
+        (define (handler) 1)
+        (register \"handler\")
+        ");
+
+    let scope = Rc::new(GlobalScope::new(names, codemap,
+        Rc::new(ModuleRegistry::new(Box::new(loader))), io));
+    let interp = Interpreter::with_scope(scope);
+
+    assert_eq!(run(&interp, "
+        (use handlers ())
+        (use handlers ())
+        ").unwrap(),
+        ["()", "()"]);
+
+    // Module code is run once, when the module is first loaded
+    REGISTERED.with(|r| assert_eq!(*r.borrow(), ["handler"]));
+
+    assert_matches!(run(&interp, "(handler)").unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+}
+
+/// Parses, compiles, and executes a string of code in the calling scope.
+fn eval_str(scope: &Scope, args: &mut [Value]) -> Result<Value, Error> {
+    let src = match args[0] {
+        Value::String(ref s) => s.to_string(),
+        ref v => panic!("expected string; got {}", v.type_name())
+    };
+
+    let exprs = {
+        let offset = scope.borrow_codemap_mut().add_source(&src, None);
+        let mut names = scope.borrow_names_mut();
+        try!(Parser::new(&mut names, Lexer::new(&src, offset)).parse_exprs())
+    };
+
+    let mut last = Value::Unit;
+
+    for e in &exprs {
+        let code = try!(compile(scope, e));
+        last = try!(execute(scope, Rc::new(code)));
+    }
+
+    Ok(last)
+}
+
+#[test]
+fn test_nested_module_loading() {
+    let mut loader = MemoryModuleLoader::new();
+
+    loader.add_module("answer", "(export (x)) (define x 42)");
+    loader.add_module("loader", "
+        (export (load-answer eval-in))
+        (macro (load-answer) `(use answer (x)))
+        (define (eval-in s) (eval-str s))
+        ");
+    loader.add_module("cycle", "
+        (export (y))
+        (use loader (eval-in))
+        (define y (eval-in \"(use cycle (y)) y\"))
+        ");
+
+    let interp = Interpreter::with_loader(Box::new(loader));
+    interp.register_system_fn("eval-str", Arity::Exact(1), eval_str);
+
+    // Module loaded by macro expansion
+    assert_eq!(run(&interp, "(use loader () :macro (load-answer))").unwrap(), ["()"]);
+    assert_eq!(run(&interp, "(load-answer)").unwrap(), ["()"]);
+    assert_eq!(run(&interp, "x").unwrap(), ["42"]);
+
+    // Module loaded during execution
+    assert_eq!(run(&interp, "
+        (eval-str \"(use answer (:z x)) (+ z 1)\")
+        (macro (add-two) (eval-str \"(use answer (:w x)) `(+ w 2)\"))
+        (add-two)
+        ").unwrap(),
+        ["43", "add-two", "44"]);
+
+    // A module which loads itself during its own execution
+    assert_matches!(run(&interp, "(use cycle (y))").unwrap_err(),
+        Error::CompileError(CompileError::ImportCycle(_)));
+}
+
+#[test]
+fn test_register_system_fn() {
+    let interp = Interpreter::new();
+
+    interp.register_system_fn("triple", Arity::Exact(1), triple);
+
+    assert_eq!(run(&interp, "
+        (triple 4)
+        (define (nine) (triple 3))
+        (nine)
+        (apply triple '(5))
+        ").unwrap(),
+        ["12", "nine", "9", "15"]);
+
+    assert_matches!(run(&interp, "(triple 1 2)").unwrap_err(),
+        Error::CompileError(CompileError::ArityError{..}));
+}
+
+#[test]
+fn test_registered_fn_bytecode() {
+    let mut sources = HashMap::new();
+
+    sources.insert("util".to_owned(), "
+        (export (six))
+        (define (six) (triple 2))
+        ".to_owned());
+
+    let bytecode = Rc::new(RefCell::new(HashMap::new()));
+
+    let interp = Interpreter::with_loader(Box::new(SourceLoader{
+        sources: sources,
+        bytecode: bytecode.clone(),
+    }));
+
+    interp.register_system_fn("triple", Arity::Exact(1), triple);
+
+    assert_eq!(run(&interp, "(use util (six)) (six)").unwrap(), ["()", "6"]);
+
+    let interp = Interpreter::with_loader(Box::new(BytecodeLoader{
+        bytecode: bytecode.clone(),
+    }));
+
+    interp.register_system_fn("triple", Arity::Exact(1), triple);
+
+    assert_eq!(run(&interp, "(use util (six)) (six)").unwrap(), ["()", "6"]);
+
+    let interp = Interpreter::with_loader(Box::new(BytecodeLoader{
+        bytecode: bytecode,
+    }));
+
+    assert_matches!(run(&interp, "(use util (six))").unwrap_err(),
+        Error::DecodeError(DecodeError::InvalidSystemFn(_)));
+}
+
+#[test]
+fn test_prelude() {
+    let mut loader = MemoryModuleLoader::new();
+    loader.add_module("util", "
+        (export (add-two))
+        (define (add-two n) (add-one (add-one n)))
+        ");
+
+    let base = Interpreter::with_loader(Box::new(loader));
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let log_ref = log.clone();
+
+    base.get_scope().set_log_handler(Box::new(move |level, msg| {
+        log_ref.borrow_mut().push((level, msg.to_owned()));
+    }));
+
+    let interp = base.with_prelude("
+        (define (add-one n) (+ n 1))
+        (define greeting \"hello\")
+        (macro (flip f a b) `(,f ,b ,a))
+        ", None).unwrap();
+
+    assert!(Rc::ptr_eq(interp.get_scope().prelude().unwrap(), base.get_scope()));
+
+    assert_eq!(run(&interp, "(add-one 1) greeting (flip - 1 10)").unwrap(),
+        ["2", r#""hello""#, "9"]);
+
+    // Module scopes also inherit prelude definitions
+    assert_eq!(run(&interp, "(use util (add-two)) (add-two 1)").unwrap(),
+        ["()", "3"]);
+
+    // User definitions shadow the prelude without affecting it
+    assert_eq!(run(&interp, "
+        (define (add-one n) (+ n 100))
+        (define greeting \"hi\")
+        (add-one 1)
+        greeting
+        (add-two 1)
+        ").unwrap(),
+        ["add-one", "greeting", "101", r#""hi""#, "3"]);
+    assert_eq!(run(&base, "(add-one 1) greeting").unwrap(), ["2", r#""hello""#]);
+    assert!(log.borrow().is_empty());
+
+    let m = ModuleBuilder::new("helpers", GlobalScope::new_using(base.get_scope()))
+        .add_function("triple", triple, Arity::Exact(1))
+        .finish();
+
+    let interp = base.with_prelude_module(&m);
+    assert_eq!(run(&interp, "(triple (add-one 1))").unwrap(), ["6"]);
+
+    assert_matches!(base.with_prelude("(undefined-fn)", None).err().unwrap(),
+        Error::ExecError(ExecError::NameError(_)));
+}
+
+#[test]
+fn test_name_snapshot_bytecode() {
+    let src = "
+        (export (greet))
+        (define (greet name) (concat greeting-prefix name))
+        (define greeting-prefix \"hello, \")
+        ";
+
+    let interp = Interpreter::new();
+    run(&interp, "(define (greet name) name) (define greeting-prefix ())").unwrap();
+
+    let path = env::temp_dir().join(format!("ketos-names-{}.ktsn", std::process::id()));
+    let snapshot = interp.get_scope().borrow_names().snapshot("names.ktsn");
+    write_name_snapshot_file(&path, &snapshot).unwrap();
+
+    let read = read_name_snapshot_file(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(read.names(), snapshot.names());
+    assert_eq!(read.checksum(), snapshot.checksum());
+
+    let compile_util = |interp: &Interpreter| {
+        let bytecode = Rc::new(RefCell::new(HashMap::new()));
+        let mut sources = HashMap::new();
+        sources.insert("util".to_owned(), src.to_owned());
+
+        let interp = match interp.get_scope().borrow_names().snapshot_info() {
+            Some(_) => Interpreter::with_name_snapshot(&snapshot, Box::new(SourceLoader{
+                sources: sources,
+                bytecode: bytecode.clone(),
+            })),
+            None => Interpreter::with_loader(Box::new(SourceLoader{
+                sources: sources,
+                bytecode: bytecode.clone(),
+            }))
+        };
+
+        run(&interp, "(use util (greet))").unwrap();
+        let buf = bytecode.borrow()["util"].clone();
+        buf
+    };
+
+    let plain = compile_util(&Interpreter::new());
+    let seeded = Interpreter::with_name_snapshot(&snapshot,
+        Box::new(MemoryModuleLoader::new()));
+    let shared = compile_util(&seeded);
+
+    assert_eq!(&shared[..4], SHARED_NAMES_MAGIC_NUMBER);
+    assert!(shared.len() < plain.len());
+
+    let bytecode = Rc::new(RefCell::new(HashMap::new()));
+    bytecode.borrow_mut().insert("util".to_owned(), shared);
+
+    let interp = Interpreter::with_name_snapshot(&read, Box::new(BytecodeLoader{
+        bytecode: bytecode.clone(),
+    }));
+
+    assert_eq!(run(&interp, "(use util (greet)) (greet \"world\")").unwrap(),
+        ["()", r#""hello, world""#]);
+    assert_eq!(interp.get_scope().borrow_names().get_name("greeting-prefix"),
+        seeded.get_scope().borrow_names().get_name("greeting-prefix"));
+
+    let interp = Interpreter::with_loader(Box::new(BytecodeLoader{
+        bytecode: bytecode,
+    }));
+
+    let e = run(&interp, "(use util (greet))").unwrap_err();
+    assert_matches!(e, Error::DecodeError(DecodeError::MissingNameSnapshot{..}));
+    assert_eq!(interp.format_error(&e), "decode error: bytecode requires an interpreter \
+        created from name snapshot `names.ktsn` (2 names)\n");
+}
+
+#[test]
+fn test_catch_io_error() {
+    let interp = Interpreter::new();
+
+    interp.register_system_fn("read-file", Arity::Exact(1), read_file);
+
+    assert_eq!(run(&interp, r#"
+        (try (read-file "tests/does-not-exist.ket")
+          (catch :arith e 'arith)
+          (catch :io e (list (error-kind e) (error-irritants e))))
+        "#).unwrap(),
+        [r#"(:not-found ("tests/does-not-exist.ket" open))"#]);
+
+    assert_eq!(run(&interp, r#"
+        (guard (e :io (error-category e))
+          (read-file "tests/does-not-exist.ket"))
+        "#).unwrap(),
+        [":io"]);
+}
+
+#[test]
+fn test_os_path() {
+    let interp = Interpreter::new();
+
+    assert_eq!(run(&interp, r#"
+        (use os :all)
+        (path-file-name "foo/bar.txt")
+        (path-file-name "foo/..")
+        (path-extension "foo/bar.tar.gz")
+        (path-extension "foo/bar")
+        (path-parent "foo/bar")
+        (path-parent "foo")
+        (path-parent "")
+        (path-absolute? "foo/bar")
+        "#).unwrap(),
+        ["()", r#""bar.txt""#, "()", r#""gz""#, "()",
+         r#""foo""#, r#""""#, "()", "false"]);
+
+    let cwd = env::current_dir().unwrap().canonicalize().unwrap();
+    let canon = cwd.join("tests").join("module.rs");
+
+    assert_eq!(run(&interp, r#"(path-canonicalize "tests/../tests/module.rs")"#).unwrap(),
+        [format!("{:?}", canon.to_str().unwrap())]);
+    assert_eq!(run(&interp, r#"(path-absolute? (path-canonicalize "tests"))"#).unwrap(),
+        ["true"]);
+
+    match run(&interp, r#"(path-canonicalize "tests/does-not-exist")"#).unwrap_err() {
+        Error::IoError(ref e) => {
+            assert_eq!(e.mode, IoMode::Stat);
+            assert_eq!(e.path, Path::new("tests/does-not-exist"));
+        }
+        e => panic!("expected IoError; found {:?}", e)
+    }
+    assert_matches!(run(&interp, "(path-join \"a\" 1)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "string", ..}));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_os_path_unix() {
+    let interp = Interpreter::new();
+
+    assert_eq!(run(&interp, r#"
+        (use os :all)
+        (path-join "foo" "bar" "baz.txt")
+        (path-join "foo" "/bar")
+        (path-parent "/")
+        (path-absolute? "/foo")
+        (path-file-name "foo\\bar")
+        "#).unwrap(),
+        ["()", r#""foo/bar/baz.txt""#, r#""/bar""#, "()", "true", r#""foo\\bar""#]);
+}
+
+#[cfg(windows)]
+#[test]
+fn test_os_path_windows() {
+    let interp = Interpreter::new();
+
+    assert_eq!(run(&interp, r#"
+        (use os :all)
+        (path-join "foo" "bar" "baz.txt")
+        (path-join "foo" "C:\\bar")
+        (path-join "C:\\" "foo")
+        (path-file-name "C:\\foo\\bar.txt")
+        (path-file-name "C:/foo/bar.txt")
+        (path-parent "C:\\foo")
+        (path-parent "C:\\")
+        (path-absolute? "C:\\foo")
+        (path-absolute? "C:foo")
+        (path-absolute? "\\foo")
+        "#).unwrap(),
+        ["()", r#""foo\\bar\\baz.txt""#, r#""C:\\bar""#, r#""C:\\foo""#,
+         r#""bar.txt""#, r#""bar.txt""#, r#""C:\\""#, "()",
+         "true", "false", "false"]);
+
+    // UNC paths
+    assert_eq!(run(&interp, r#"
+        (path-join "\\\\server\\share" "foo")
+        (path-parent "\\\\server\\share\\foo")
+        (path-parent "\\\\server\\share\\")
+        (path-file-name "\\\\server\\share\\foo.txt")
+        (path-absolute? "\\\\server\\share\\foo")
+        "#).unwrap(),
+        [r#""\\\\server\\share\\foo""#, r#""\\\\server\\share\\""#, "()",
+         r#""foo.txt""#, "true"]);
+}
+
+struct Buffer(RefCell<Vec<u8>>);
+
+impl SharedWrite for Buffer {
+    fn write_all(&self, buf: &[u8]) -> Result<(), IoError> {
+        self.0.borrow_mut().extend(buf);
+        Ok(())
+    }
+
+    fn write_fmt(&self, fmt: Arguments) -> Result<(), IoError> {
+        self.0.borrow_mut().write_fmt(fmt).unwrap();
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), IoError> { Ok(()) }
+}
+
+#[test]
+fn test_trace() {
+    let out = Rc::new(Buffer(RefCell::new(Vec::new())));
+
+    let names = Rc::new(RefCell::new(NameStore::new()));
+    let codemap = Rc::new(RefCell::new(CodeMap::new()));
+    let io = Rc::new(GlobalIo::new(out.clone()));
+
+    let scope = Rc::new(GlobalScope::new(names, codemap,
+        Rc::new(ModuleRegistry::new(Box::new(BuiltinModuleLoader))), io));
+    let interp = Interpreter::with_scope(scope);
+
+    // Calls compiled before `trace` is called are also traced
+    assert_eq!(run(&interp, "
+        (use code (trace untrace))
+        (define (double n) (* n 2))
+        (define (quad n) (double (double n)))
+        (define (run) (quad 3))
+        (trace 'double 'quad)
+        (run)
+        (trace)
+        (untrace 'double)
+        (run)
+        (untrace)
+        (trace)
+        (run)
+        double
+        ").unwrap(),
+        ["()", "double", "quad", "run", "(double quad)", "12",
+         "(double quad)", "()", "12", "()", "()", "12", "<lambda double>"]);
+
+    assert_eq!(String::from_utf8(out.0.borrow().clone()).unwrap(), "\
+(quad 3)
+  (double 3)
+  double => 6
+  (double 6)
+  double => 12
+quad => 12
+(quad 3)
+quad => 12
+");
+
+    run(&interp, "(trace 'double)").unwrap();
+
+    assert_matches!(run(&interp, "(double 1 2)").unwrap_err(),
+        Error::ExecError(ExecError::ArityError{name: Some(_), found: 2, ..}));
+    assert_matches!(run(&interp, "(trace 'list)").unwrap_err(),
+        Error::ExecError(ExecError::CannotDefine(_)));
+    assert_matches!(run(&interp, "(define foo 1) (trace 'foo)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{..}));
+}
+
+#[test]
+fn test_memoize() {
+    let interp = Interpreter::new();
+
+    assert_eq!(run(&interp, "
+        (use code (memoize))
+        (define (slow n) (do (display n) (* n 2)))
+        (define fast (memoize slow 2))
+        (with-output-to-string (fast 1) (fast 1) (fast 2) (fast 1))
+        (fast 2)
+        (with-output-to-string (fast 3) (fast 1) (fast 2))
+        (with-output-to-string (fast 1) (fast 2))
+        ").unwrap(),
+        ["()", "slow", "fast", r#""12""#, "4", r#""312""#, r#""""#]);
+
+    // Arguments are compared by value
+    assert_eq!(run(&interp, r#"
+        (define (identity v) (do (display "x") v))
+        (define vec-fast (memoize identity))
+        (with-output-to-string
+          (vec-fast (vector 1 '(2 "a")))
+          (vec-fast (vector 1 '(2 "a")))
+          (vec-fast (vector 1 '(2 "b"))))
+        "#).unwrap(),
+        ["identity", "vec-fast", r#""xx""#]);
+
+    // Functions in arguments bypass the cache
+    assert_eq!(run(&interp, r#"
+        (define (one) 1)
+        (define (call-fn f) (do (display "x") (f)))
+        (define call (memoize call-fn))
+        (with-output-to-string (call one) (call one))
+        "#).unwrap(),
+        ["one", "call-fn", "call", r#""xx""#]);
+
+    // Errors are not cached
+    assert_eq!(run(&interp, r#"
+        (define fail true)
+        (define (check n) (if fail (panic "failed") n))
+        (define check-fast (memoize check))
+        "#).unwrap(),
+        ["fail", "check", "check-fast"]);
+
+    assert_matches!(run(&interp, "(check-fast 1)").unwrap_err(),
+        Error::ExecError(ExecError::Panic(_)));
+    assert_eq!(run(&interp, "(define fail false) (check-fast 1)").unwrap(),
+        ["fail", "1"]);
+
+    assert_matches!(run(&interp, "(memoize 1)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{..}));
+    assert_matches!(run(&interp, "(memoize slow 0)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{..}));
+}
+
+#[test]
+fn test_string_module() {
+    let interp = Interpreter::new();
+
+    // Names in the `string` module may still be defined by other code
+    assert_eq!(run(&interp, "(define (find li) li) (find 1)").unwrap(), ["find", "1"]);
+
+    let interp = Interpreter::new();
+    let eval = |s: &str| interp.run_single_expr(s, None).map(|v| interp.format_value(&v));
+
+    assert_eq!(run(&interp, "(use string :all)").unwrap(), ["()"]);
+
+    assert_eq!(eval(r#"(find "h\u{e9}llo" "l")"#).unwrap(), "2");
+    assert_eq!(eval(r#"(find "hello" "L")"#).unwrap(), "()");
+    assert_eq!(eval(r#"(find-ci "hello" "L")"#).unwrap(), "2");
+    assert_eq!(eval(r#"(find-ci "\u{e9}\u{212a}ELVIN" "kelvin")"#).unwrap(), "1");
+    assert_eq!(eval(r#"(find-ci "abc" "")"#).unwrap(), "0");
+
+    assert_eq!(eval(r#"(split "a,b,,c" ",")"#).unwrap(), r#"("a" "b" "" "c")"#);
+    assert_eq!(eval(r#"(split "aXbxc" "x")"#).unwrap(), r#"("aXb" "c")"#);
+    assert_eq!(eval(r#"(split "aXbxc" "x" :ci true)"#).unwrap(), r#"("a" "b" "c")"#);
+    assert_eq!(eval(r#"(split "aXbxc" "x" :ci false)"#).unwrap(), r#"("aXb" "c")"#);
+    assert_eq!(eval(r#"(split "\u{212a}\u{e9}k" "K" :ci true)"#).unwrap(),
+        r#"("" "é" "")"#);
+
+    assert_eq!(eval(r#"(replace "a-b-c" "-" "+")"#).unwrap(), r#""a+b+c""#);
+    assert_eq!(eval(r#"(replace "Hello, hello" "hello" "bye")"#).unwrap(),
+        r#""Hello, bye""#);
+    assert_eq!(eval(r#"(replace "Hello, hello" "HELLO" "bye" :ci true)"#).unwrap(),
+        r#""bye, bye""#);
+
+    assert_matches!(eval(r#"(split "abc" "")"#).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "non-empty string", ..}));
+    assert_matches!(eval(r#"(split "abc" "b" :ci)"#).unwrap_err(),
+        Error::ExecError(ExecError::OddKeywordParams));
+    assert_matches!(eval(r#"(split "abc" "b" :case true)"#).unwrap_err(),
+        Error::ExecError(ExecError::UnrecognizedKeyword(_)));
+    assert_matches!(eval(r#"(replace "abc" "b" "d" :ci 1)"#).unwrap_err(),
+        Error::ExecError(ExecError::TypeError{expected: "bool", ..}));
+}
+
+#[test]
+fn test_read_data() {
+    let interp = Interpreter::new();
+
+    assert_eq!(run(&interp, r#"
+        (use code (read-data))
+        (read-data "(1, 2, 3) #(\"a\", b) :c 'd")
+        (read-data "; nothing")
+        "#).unwrap(),
+        ["()", r#"((1 2 3) #("a" "b") ":c" '"d")"#, "()"]);
+
+    // Names read as data are not added to the interpreter's `NameStore`
+    assert!(interp.get_scope().borrow_names().get_name("some-unusual-name").is_none());
+    run(&interp, r#"(read-data "some-unusual-name")"#).unwrap();
+    assert!(interp.get_scope().borrow_names().get_name("some-unusual-name").is_none());
+
+    assert_matches!(run(&interp, r#"(read-data "`(a ,b)")"#).unwrap_err(),
+        Error::ParseError(ParseError{
+            kind: ParseErrorKind::UnexpectedToken{expected: "value", found: "`"}, ..}));
+    assert_matches!(run(&interp, r#"(read-data "(a")"#).unwrap_err(),
+        Error::ParseError(ParseError{kind: ParseErrorKind::MissingCloseParen, ..}));
+    assert_matches!(run(&interp, "(read-data 1)").unwrap_err(),
+        Error::ExecError(ExecError::TypeError{..}));
+}
+
+#[test]
+fn test_test_module() {
+    let out = Rc::new(Buffer(RefCell::new(Vec::new())));
+
+    let names = Rc::new(RefCell::new(NameStore::new()));
+    let codemap = Rc::new(RefCell::new(CodeMap::new()));
+    let io = Rc::new(GlobalIo::new(out.clone()));
+
+    let mut loader = MemoryModuleLoader::new();
+
+    loader.add_module("arith", "
+        (use test :all :macro :all)
+        (export (double))
+
+        (define (double n) (* n 2))
+
+        (define-test double-int (assert-eq (double 2) 4))
+        (define-test double-float (assert-eq (double 0.5) 2.0))
+        ");
+
+    let scope = Rc::new(GlobalScope::new(names, codemap,
+        Rc::new(ModuleRegistry::new(Box::new(loader))), io));
+    let interp = Interpreter::with_scope(scope);
+
+    assert_eq!(run(&interp, "
+        (use test :all :macro :all)
+        (define-test passes (assert (= 1 1)))
+        (define-test fails (assert (= 1 2) \"one is two\"))
+        (define-test raises (assert-raises :arith (/ 1 0)))
+        (define s (run-tests))
+        (. s :passed)
+        (. s :failed)
+        (. (first (. s :failures)) :name)
+        (. (first (. s :failures)) :message)
+        ").unwrap(),
+        ["()", "passes", "fails", "raises", "s", "2", "1", "fails",
+            "\"assertion failed: one is two\""]);
+
+    assert_eq!(String::from_utf8(out.0.borrow().clone()).unwrap(), "\
+running 3 tests
+test passes ... ok
+test fails ... FAILED
+test raises ... ok
+
+failures:
+    fails: assertion failed: one is two
+
+test result: FAILED. 2 passed; 1 failed
+");
+
+    out.0.borrow_mut().clear();
+
+    assert_eq!(run(&interp, "
+        (define s (run-tests 'arith))
+        (. (first (. s :failures)) :irritants)
+        ").unwrap(),
+        ["s", "(1.0 2.0)"]);
+
+    assert_eq!(String::from_utf8(out.0.borrow().clone()).unwrap(), "\
+running 2 tests
+test double-int ... ok
+test double-float ... FAILED
+
+failures:
+    double-float: assertion failed: values are not equal 1.0 2.0
+
+test result: FAILED. 1 passed; 1 failed
+");
+
+    assert_eq!(run(&interp, "
+        (guard (e :error (error-message e))
+          (assert-eq '(1 2) '(1 3)))
+        (guard (e :error (error-irritants e))
+          (assert-raises :index (/ 1 0)))
+        (guard (e :error (error-irritants e))
+          (assert-raises :error))
+        (error-category (assert-raises :error (error \"oops\")))
+        (error-category (assert-error-matches \"by zero\" (/ 1 0)))
+        (guard (e :error (error-irritants e))
+          (assert-error-matches \"out of bounds\" (/ 1 0)))
+        (guard (e :error (error-irritants e))
+          (assert-error-matches \"out of bounds\" 1))
+        ").unwrap(),
+        ["\"assertion failed: values are not equal\"",
+            "(:index :arith \"attempt to divide by zero\")",
+            "(:error)", ":custom", ":arith",
+            "(\"out of bounds\" \"attempt to divide by zero\")",
+            "(\"out of bounds\")"]);
+}
+
+fn run_in(scope: &Scope, code: &[Rc<Code>]) -> Result<Value, Error> {
+    let mut last = Value::Unit;
+
+    for c in code {
+        last = try!(execute(scope, c.clone()));
+    }
+
+    Ok(last)
+}
+
+#[test]
+fn test_child_scope() {
+    let interp = Interpreter::new();
+
+    run(&interp, "
+        (define base 10)
+        (define (double x) (* x 2))
+        (defvar *offset* 100)
+        ").unwrap();
+
+    let rules = interp.compile_exprs("
+        (define total (+ (double (+ base fact)) *offset*))
+        total
+        ").unwrap().into_iter().map(Rc::new).collect::<Vec<_>>();
+
+    let parent = interp.get_scope();
+    let a = GlobalScope::new_child(parent);
+    let b = GlobalScope::new_child(parent);
+
+    a.add_named_value("fact", 1.into());
+    b.add_named_value("fact", 5.into());
+
+    assert_eq!(interp.format_value(&run_in(&a, &rules).unwrap()), "122");
+    assert_eq!(interp.format_value(&run_in(&b, &rules).unwrap()), "130");
+
+    let total = parent.add_name("total");
+    assert_eq!(interp.format_value(&a.get_value(total).unwrap()), "122");
+    assert_eq!(interp.format_value(&b.get_value(total).unwrap()), "130");
+    assert!(!parent.contains_value(total));
+
+    let only_a = interp.compile_exprs("
+        (define only-a 1)
+        (defvar *offset* 0)
+        ").unwrap().into_iter().map(Rc::new).collect::<Vec<_>>();
+    run_in(&a, &only_a).unwrap();
+
+    let get_only_a = interp.compile_exprs("only-a").unwrap()
+        .into_iter().map(Rc::new).collect::<Vec<_>>();
+
+    assert_eq!(interp.format_value(&run_in(&a, &get_only_a).unwrap()), "1");
+    assert_matches!(run_in(&b, &get_only_a).unwrap_err(),
+        Error::ExecError(ExecError::NameError(_)));
+
+    assert_eq!(interp.format_value(&run_in(&a, &rules).unwrap()), "22");
+    assert_eq!(interp.format_value(&run_in(&b, &rules).unwrap()), "130");
+    assert_eq!(run(&interp, "*offset*").unwrap(), ["100"]);
+}
+
+#[test]
+fn test_display_write() {
+    let out = Rc::new(Buffer(RefCell::new(Vec::new())));
+
+    let names = Rc::new(RefCell::new(NameStore::new()));
+    let codemap = Rc::new(RefCell::new(CodeMap::new()));
+    let io = Rc::new(GlobalIo::new(out.clone()));
+
+    let scope = Rc::new(GlobalScope::new(names, codemap,
+        Rc::new(ModuleRegistry::new(Box::new(BuiltinModuleLoader))), io));
+    let interp = Interpreter::with_scope(scope);
+
+    assert_eq!(run(&interp, r#"
+        (display #\a)
+        (write #\a)
+        (display #\space)
+        (write #\space)
+        (display "foo")
+        (write '("foo" #\tab))
+        "#).unwrap(),
+        ["()", "()", "()", "()", "()", "()"]);
+
+    assert_eq!(String::from_utf8(out.0.borrow().clone()).unwrap(),
+        r#"a#\a #\spacefoo("foo" #\tab)"#);
+}